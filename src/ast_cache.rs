@@ -0,0 +1,183 @@
+use std::rc::Rc;
+
+use crate::ast_json::{program_from_json, program_to_json};
+use crate::error::ParserError;
+use crate::expr::{Expr, ExprId};
+use crate::json::Json;
+use crate::stmt::Stmt;
+use crate::symbol::Symbol;
+
+/// Bumped whenever a `.loxc` file written by an older build could be
+/// read wrong.
+const VERSION: i64 = 3;
+
+/// The variables a function captures, with how many scopes out each is
+/// declared.
+pub type Captures = Vec<(Symbol, u32)>;
+
+/// What the resolver decided about a program, kept for it to run later
+/// without being resolved again: how far out each local variable is, and
+/// what each function captures.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub locals: Vec<(ExprId, u32)>,
+    pub captures: Vec<(ExprId, Captures)>,
+}
+
+/// Something the resolver records a decision for, in the order a walk of
+/// the program meets them.
+enum Resolvable {
+    Variable(ExprId),
+    Function(ExprId),
+}
+
+/// Serializes a resolved program as a `.loxc` file. `local` and
+/// `captures` look up what the resolver decided; a variable with no depth
+/// is global, and a function with no captures closes over everything.
+/// Fails if the program is nested too deeply to serialize.
+pub fn write(
+    stmts: &[Stmt],
+    local: impl Fn(ExprId) -> Option<u32>,
+    captures: impl Fn(ExprId) -> Option<Rc<[(Symbol, u32)]>>,
+) -> Result<String, ParserError> {
+    // Serialized first: a program nested too deeply for that is too deep
+    // for the shallower walk that finds the resolvables.
+    let program = program_to_json(stmts)?;
+    let resolution = resolvables(stmts)
+        .into_iter()
+        .map(|resolvable| match resolvable {
+            Resolvable::Variable(id) => local(id).map(|depth| Json::Int(depth as i64)).into(),
+            Resolvable::Function(id) => captures(id)
+                .map(|captures| {
+                    let captures = captures.iter().map(|&(name, distance)| {
+                        Json::Array(vec![Json::from(name.as_str()), Json::Int(distance as i64)])
+                    });
+                    Json::Array(captures.collect())
+                })
+                .into(),
+        })
+        .collect();
+    let compiled = Json::object([
+        ("loxc", Json::Int(VERSION)),
+        ("program", program),
+        ("resolution", Json::Array(resolution)),
+    ]);
+    Ok(compiled.to_string())
+}
+
+/// Loads a `.loxc` file written by [`write`].
+pub fn read(contents: &str) -> Result<(Vec<Stmt>, Resolution), String> {
+    let json = Json::parse(contents)?;
+    match json.get("loxc").and_then(Json::as_i64) {
+        Some(VERSION) => (),
+        Some(version) => return Err(format!("Compiled with an unsupported format version {}.", version)),
+        None => return Err("Not a compiled Lox program.".to_string()),
+    }
+    let stmts = program_from_json(json.get("program").ok_or("Missing the compiled program.")?)?;
+    let entries = json
+        .get("resolution")
+        .and_then(Json::as_array)
+        .ok_or("Missing the compiled program's resolution.")?;
+    let resolvables = resolvables(&stmts);
+    if resolvables.len() != entries.len() {
+        return Err("The resolution doesn't match the compiled program.".to_string());
+    }
+
+    let mut resolution = Resolution::default();
+    for (resolvable, entry) in resolvables.into_iter().zip(entries) {
+        match (resolvable, entry) {
+            (_, Json::Null) => (),
+            (Resolvable::Variable(id), Json::Int(depth)) => resolution.locals.push((id, *depth as u32)),
+            (Resolvable::Function(id), Json::Array(captures)) => {
+                let captures = captures
+                    .iter()
+                    .map(|capture| match capture.as_array() {
+                        Some([Json::String(name), Json::Int(distance)]) => {
+                            Ok((Symbol::intern(name), *distance as u32))
+                        }
+                        _ => Err("Invalid captured variable.".to_string()),
+                    })
+                    .collect::<Result<_, _>>()?;
+                resolution.captures.push((id, captures));
+            }
+            _ => return Err("The resolution doesn't match the compiled program.".to_string()),
+        }
+    }
+    Ok((stmts, resolution))
+}
+
+fn resolvables(stmts: &[Stmt]) -> Vec<Resolvable> {
+    let mut found = vec![];
+    stmts.iter().for_each(|stmt| walk_stmt(stmt, &mut found));
+    found
+}
+
+fn walk_stmt(stmt: &Stmt, found: &mut Vec<Resolvable>) {
+    match stmt {
+        Stmt::Block(stmts) => stmts.iter().for_each(|stmt| walk_stmt(stmt, found)),
+        Stmt::Function(_, id, _, body, ..) => {
+            found.push(Resolvable::Function(*id));
+            body.iter().for_each(|stmt| walk_stmt(stmt, found));
+        }
+        Stmt::Expression(expr) => walk_expr(expr, found),
+        Stmt::Print(values) => values.iter().for_each(|expr| walk_expr(expr, found)),
+        Stmt::Return(_, value) => value.iter().for_each(|expr| walk_expr(expr, found)),
+        Stmt::Var(_, _, initializer) => initializer.iter().for_each(|expr| walk_expr(expr, found)),
+        Stmt::If(arms, else_branch) => {
+            for (_, condition, branch) in arms.iter() {
+                walk_expr(condition, found);
+                walk_stmt(branch, found);
+            }
+            if let Some(else_branch) = else_branch {
+                walk_stmt(else_branch, found);
+            }
+        }
+        Stmt::While(_, condition, body) => {
+            walk_expr(condition, found);
+            walk_stmt(body, found);
+        }
+        Stmt::For(_, initializer, condition, increment, body) => {
+            if let Some(initializer) = initializer {
+                walk_stmt(initializer, found);
+            }
+            condition.iter().chain(increment).for_each(|expr| walk_expr(expr, found));
+            walk_stmt(body, found);
+        }
+        Stmt::Labeled(_, body) => walk_stmt(body, found),
+        Stmt::ForIn(_, initializer, body) | Stmt::With(_, _, initializer, body) => {
+            walk_expr(initializer, found);
+            walk_stmt(body, found);
+        }
+        Stmt::Export(declaration) => walk_stmt(declaration, found),
+        Stmt::Break(..) | Stmt::Continue(..) | Stmt::Enum(..) | Stmt::Import(..) => (),
+    }
+}
+
+fn walk_expr(expr: &Expr, found: &mut Vec<Resolvable>) {
+    match expr {
+        Expr::Variable(id, _) => found.push(Resolvable::Variable(*id)),
+        Expr::Assign(id, _, value) => {
+            found.push(Resolvable::Variable(*id));
+            walk_expr(value, found);
+        }
+        Expr::Lambda(id, _, body, _) => {
+            found.push(Resolvable::Function(*id));
+            body.iter().for_each(|stmt| walk_stmt(stmt, found));
+        }
+        Expr::Logical(left, _, right)
+        | Expr::Binary(left, _, right)
+        | Expr::Index(left, _, right)
+        | Expr::Set(left, _, right) => {
+            walk_expr(left, found);
+            walk_expr(right, found);
+        }
+        Expr::Unary(_, operand) | Expr::Grouping(operand) | Expr::Get(operand, _) => walk_expr(operand, found),
+        Expr::Call(callee, _, arguments, named) => {
+            walk_expr(callee, found);
+            arguments.iter().for_each(|argument| walk_expr(argument, found));
+            named.iter().for_each(|(_, value)| walk_expr(value, found));
+        }
+        Expr::Record(_, fields) => fields.iter().for_each(|(_, value)| walk_expr(value, found)),
+        Expr::Literal(_) | Expr::Empty => (),
+    }
+}
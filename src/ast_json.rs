@@ -0,0 +1,517 @@
+use std::rc::Rc;
+
+use crate::error::ParserError;
+use crate::expr::{next_expr_id, Expr};
+use crate::json::Json;
+use crate::stack::{self, DepthLimit, StackGuard};
+use crate::stmt::{Signature, Stmt};
+use crate::symbol::Symbol;
+use crate::token::{Literal, Token, TokenType};
+
+/// Serializes a parsed program. Expression IDs are left out: a loaded
+/// program gets fresh ones and is resolved again before it runs.
+/// Fails if the program is nested too deeply to serialize.
+pub fn program_to_json(stmts: &[Stmt]) -> Result<Json, ParserError> {
+    let writer = ToJson::new();
+    let json = writer.stmts(stmts);
+    writer.depth.finish(json)
+}
+
+pub fn program_from_json(json: &Json) -> Result<Vec<Stmt>, String> {
+    FromJson::new().stmts(Some(json))
+}
+
+fn token_to_json(token: &Token) -> Json {
+    Json::object([
+        ("type", Json::from(token.token_type.to_string())),
+        ("lexeme", Json::from(&*token.lexeme)),
+        ("line", Json::Int(token.line as i64)),
+        ("column", Json::Int(token.column as i64)),
+    ])
+}
+
+fn tokens_to_json(tokens: &[Token]) -> Json {
+    Json::Array(tokens.iter().map(token_to_json).collect())
+}
+
+fn signature_to_json(signature: &Signature) -> Json {
+    let params = signature.params.iter().map(|param| param.as_ref().map(token_to_json).into());
+    Json::object([
+        ("params", Json::Array(params.collect())),
+        ("returns", signature.returns.as_ref().map(token_to_json).into()),
+    ])
+}
+
+pub fn literal_to_json(literal: &Literal) -> Json {
+    match literal {
+        Literal::Nil => Json::Null,
+        Literal::True => Json::Bool(true),
+        Literal::False => Json::Bool(false),
+        Literal::Int(i) => Json::Int(*i),
+        Literal::Number(n) if n.is_finite() => Json::Number(*n),
+        Literal::Number(n) => Json::object([("float", Json::from(n.to_string()))]),
+        Literal::String(s) => Json::from(&**s),
+        Literal::NativeFunction(_)
+        | Literal::LoxFunction(_)
+        | Literal::Map(_)
+        | Literal::Record(_)
+        | Literal::EnumCase(_)
+        | Literal::Bytes(_)
+        | Literal::Bound(_)
+        | Literal::Channel(_)
+        | Literal::Thread(_) => {
+            Json::Null
+        }
+    }
+}
+
+/// Serializes statements and expressions, giving up where they are nested
+/// too deeply for the stack.
+struct ToJson {
+    depth: DepthLimit,
+}
+
+impl ToJson {
+    fn new() -> Self {
+        Self {
+            depth: DepthLimit::default(),
+        }
+    }
+
+    fn stmts(&self, stmts: &[Stmt]) -> Json {
+        Json::Array(stmts.iter().map(|stmt| self.stmt(stmt)).collect())
+    }
+
+    fn stmt(&self, stmt: &Stmt) -> Json {
+        if self.depth.exhausted(None, || stmt.line()) {
+            return Json::Null;
+        }
+        match stmt {
+            Stmt::Block(stmts) => Json::object([
+                ("stmt", Json::from("Block")),
+                ("body", self.stmts(stmts)),
+            ]),
+            Stmt::Expression(expr) => Json::object([
+                ("stmt", Json::from("Expression")),
+                ("expr", self.expr(expr)),
+            ]),
+            Stmt::Function(name, _, params, body, signature, doc) => Json::object([
+                ("stmt", Json::from("Function")),
+                ("name", token_to_json(name)),
+                ("params", tokens_to_json(params)),
+                ("body", self.stmts(body)),
+                ("signature", signature.as_deref().map(signature_to_json).into()),
+                ("doc", doc.as_deref().map(Json::from).into()),
+            ]),
+            Stmt::Print(values) => Json::object([
+                ("stmt", Json::from("Print")),
+                ("values", Json::Array(values.iter().map(|expr| self.expr(expr)).collect())),
+            ]),
+            Stmt::Return(keyword, value) => Json::object([
+                ("stmt", Json::from("Return")),
+                ("keyword", token_to_json(keyword)),
+                ("value", value.as_ref().map(|expr| self.expr(expr)).into()),
+            ]),
+            Stmt::If(arms, else_branch) => Json::object([
+                ("stmt", Json::from("If")),
+                (
+                    "arms",
+                    Json::Array(
+                        arms.iter()
+                            .map(|(keyword, condition, branch)| {
+                                Json::object([
+                                    ("keyword", token_to_json(keyword)),
+                                    ("condition", self.expr(condition)),
+                                    ("then", self.stmt(branch)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+                ("else", else_branch.as_deref().map(|stmt| self.stmt(stmt)).into()),
+            ]),
+            Stmt::While(keyword, condition, body) => Json::object([
+                ("stmt", Json::from("While")),
+                ("keyword", token_to_json(keyword)),
+                ("condition", self.expr(condition)),
+                ("body", self.stmt(body)),
+            ]),
+            Stmt::For(keyword, initializer, condition, increment, body) => Json::object([
+                ("stmt", Json::from("For")),
+                ("keyword", token_to_json(keyword)),
+                ("initializer", initializer.as_deref().map(|stmt| self.stmt(stmt)).into()),
+                ("condition", condition.as_ref().map(|expr| self.expr(expr)).into()),
+                ("increment", increment.as_ref().map(|expr| self.expr(expr)).into()),
+                ("body", self.stmt(body)),
+            ]),
+            Stmt::Var(name, annotation, initializer) => Json::object([
+                ("stmt", Json::from("Var")),
+                ("name", token_to_json(name)),
+                ("annotation", annotation.as_ref().map(token_to_json).into()),
+                ("initializer", initializer.as_ref().map(|expr| self.expr(expr)).into()),
+            ]),
+            Stmt::Labeled(label, body) => Json::object([
+                ("stmt", Json::from("Labeled")),
+                ("label", token_to_json(label)),
+                ("body", self.stmt(body)),
+            ]),
+            Stmt::Break(keyword, label) => Json::object([
+                ("stmt", Json::from("Break")),
+                ("keyword", token_to_json(keyword)),
+                ("label", label.as_ref().map(token_to_json).into()),
+            ]),
+            Stmt::Continue(keyword, label) => Json::object([
+                ("stmt", Json::from("Continue")),
+                ("keyword", token_to_json(keyword)),
+                ("label", label.as_ref().map(token_to_json).into()),
+            ]),
+            Stmt::ForIn(name, iterable, body) => Json::object([
+                ("stmt", Json::from("ForIn")),
+                ("name", token_to_json(name)),
+                ("iterable", self.expr(iterable)),
+                ("body", self.stmt(body)),
+            ]),
+            Stmt::With(keyword, name, initializer, body) => Json::object([
+                ("stmt", Json::from("With")),
+                ("keyword", token_to_json(keyword)),
+                ("name", token_to_json(name)),
+                ("initializer", self.expr(initializer)),
+                ("body", self.stmt(body)),
+            ]),
+            Stmt::Enum(name, cases) => Json::object([
+                ("stmt", Json::from("Enum")),
+                ("name", token_to_json(name)),
+                ("cases", tokens_to_json(cases)),
+            ]),
+            Stmt::Import(keyword, names, path) => Json::object([
+                ("stmt", Json::from("Import")),
+                ("keyword", token_to_json(keyword)),
+                ("names", tokens_to_json(names)),
+                ("path", Json::from(&**path)),
+            ]),
+            Stmt::Export(declaration) => Json::object([
+                ("stmt", Json::from("Export")),
+                ("declaration", self.stmt(declaration)),
+            ]),
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> Json {
+        if self.depth.exhausted(expr.token(), || expr.line()) {
+            return Json::Null;
+        }
+        match expr {
+            Expr::Literal(literal) => Json::object([
+                ("expr", Json::from("Literal")),
+                ("value", literal_to_json(literal)),
+            ]),
+            Expr::Logical(left, operator, right) | Expr::Binary(left, operator, right) => {
+                let kind = if matches!(expr, Expr::Logical(..)) { "Logical" } else { "Binary" };
+                Json::object([
+                    ("expr", Json::from(kind)),
+                    ("left", self.expr(left)),
+                    ("operator", token_to_json(operator)),
+                    ("right", self.expr(right)),
+                ])
+            }
+            Expr::Unary(operator, right) => Json::object([
+                ("expr", Json::from("Unary")),
+                ("operator", token_to_json(operator)),
+                ("right", self.expr(right)),
+            ]),
+            Expr::Assign(_, name, value) => Json::object([
+                ("expr", Json::from("Assign")),
+                ("name", token_to_json(name)),
+                ("value", self.expr(value)),
+            ]),
+            Expr::Lambda(_, params, body, signature) => Json::object([
+                ("expr", Json::from("Lambda")),
+                ("params", tokens_to_json(params)),
+                ("body", self.stmts(body)),
+                ("signature", signature.as_deref().map(signature_to_json).into()),
+            ]),
+            Expr::Call(callee, paren, arguments, named) => Json::object([
+                ("expr", Json::from("Call")),
+                ("callee", self.expr(callee)),
+                ("paren", token_to_json(paren)),
+                ("arguments", Json::Array(arguments.iter().map(|expr| self.expr(expr)).collect())),
+                ("named", self.named(named)),
+            ]),
+            Expr::Get(object, name) => Json::object([
+                ("expr", Json::from("Get")),
+                ("object", self.expr(object)),
+                ("name", token_to_json(name)),
+            ]),
+            Expr::Set(object, name, value) => Json::object([
+                ("expr", Json::from("Set")),
+                ("object", self.expr(object)),
+                ("name", token_to_json(name)),
+                ("value", self.expr(value)),
+            ]),
+            Expr::Index(object, bracket, index) => Json::object([
+                ("expr", Json::from("Index")),
+                ("object", self.expr(object)),
+                ("bracket", token_to_json(bracket)),
+                ("index", self.expr(index)),
+            ]),
+            Expr::Record(brace, fields) => Json::object([
+                ("expr", Json::from("Record")),
+                ("brace", token_to_json(brace)),
+                ("fields", self.named(fields)),
+            ]),
+            Expr::Grouping(expr) => Json::object([
+                ("expr", Json::from("Grouping")),
+                ("inner", self.expr(expr)),
+            ]),
+            Expr::Variable(_, name) => Json::object([
+                ("expr", Json::from("Variable")),
+                ("name", token_to_json(name)),
+            ]),
+            Expr::Empty => Json::object([("expr", Json::from("Empty"))]),
+        }
+    }
+
+    /// A record's fields or a call's named arguments.
+    fn named(&self, named: &[(Token, Expr)]) -> Json {
+        let named = named
+            .iter()
+            .map(|(name, value)| Json::object([("name", token_to_json(name)), ("value", self.expr(value))]));
+        Json::Array(named.collect())
+    }
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, String> {
+    json.get(key)
+        .ok_or_else(|| format!("Missing field '{}' in AST node.", key))
+}
+
+fn token_from_json(json: Option<&Json>) -> Result<Token, String> {
+    let json = json.ok_or("Expected a token.")?;
+    let name = field(json, "type")?.as_str().unwrap_or_default();
+    let token_type =
+        TokenType::from_name(name).ok_or_else(|| format!("Unknown token type '{}'.", name))?;
+    let lexeme = field(json, "lexeme")?.as_str().ok_or("Expected a lexeme string.")?;
+    let line = field(json, "line")?.as_i64().ok_or("Expected a line number.")?;
+    let mut token = Token::new(token_type, Symbol::intern(lexeme), None, line as u32);
+    token.column = json.get("column").and_then(Json::as_i64).unwrap_or(0) as u32;
+    Ok(token)
+}
+
+fn tokens_from_json(json: Option<&Json>) -> Result<Rc<[Token]>, String> {
+    let items = json.and_then(Json::as_array).ok_or("Expected a token list.")?;
+    items.iter().map(|item| token_from_json(Some(item))).collect()
+}
+
+fn signature_from_json(json: &Json) -> Result<Signature, String> {
+    let params = field(json, "params")?
+        .as_array()
+        .ok_or("Expected a list of parameter types.")?
+        .iter()
+        .map(|param| optional(Some(param), |param| token_from_json(Some(param))))
+        .collect::<Result<Rc<[Option<Token>]>, String>>()?;
+    Ok(Signature {
+        params,
+        returns: optional(json.get("returns"), |returns| token_from_json(Some(returns)))?,
+    })
+}
+
+pub fn literal_from_json(json: &Json) -> Result<Literal, String> {
+    match json {
+        Json::Null => Ok(Literal::Nil),
+        Json::Bool(b) => Ok(Literal::from(*b)),
+        Json::Int(i) => Ok(Literal::Int(*i)),
+        Json::Number(n) => Ok(Literal::Number(*n)),
+        Json::String(s) => Ok(Literal::from(s.as_str())),
+        Json::Object(_) => field(json, "float")?
+            .as_str()
+            .and_then(|n| n.parse().ok())
+            .map(Literal::Number)
+            .ok_or_else(|| "Invalid float literal.".to_string()),
+        Json::Array(_) => Err("Invalid literal.".to_string()),
+    }
+}
+
+fn optional<T>(
+    json: Option<&Json>,
+    load: impl Fn(&Json) -> Result<T, String>,
+) -> Result<Option<T>, String> {
+    match json {
+        None | Some(Json::Null) => Ok(None),
+        Some(json) => load(json).map(Some),
+    }
+}
+
+/// Loads statements and expressions, failing on ones nested too deeply
+/// for the stack.
+struct FromJson {
+    stack: StackGuard,
+}
+
+impl FromJson {
+    fn new() -> Self {
+        Self { stack: StackGuard::new() }
+    }
+
+    fn check_depth(&self) -> Result<(), String> {
+        match self.stack.exhausted() {
+            true => Err(stack::TOO_DEEP.to_string()),
+            false => Ok(()),
+        }
+    }
+
+    fn stmts(&self, json: Option<&Json>) -> Result<Vec<Stmt>, String> {
+        let items = json.and_then(Json::as_array).ok_or("Expected a statement list.")?;
+        items.iter().map(|json| self.stmt(json)).collect()
+    }
+
+    fn stmt(&self, json: &Json) -> Result<Stmt, String> {
+        self.check_depth()?;
+        let kind = field(json, "stmt")?.as_str().unwrap_or_default();
+        Ok(match kind {
+            "Block" => Stmt::Block(self.stmts(json.get("body"))?.into()),
+            "Expression" => Stmt::Expression(self.expr(field(json, "expr")?)?),
+            "Function" => Stmt::Function(
+                token_from_json(json.get("name"))?,
+                next_expr_id(),
+                tokens_from_json(json.get("params"))?,
+                self.stmts(json.get("body"))?.into(),
+                optional(json.get("signature"), signature_from_json)?.map(Rc::new),
+                json.get("doc").and_then(Json::as_str).map(Rc::from),
+            ),
+            "Print" => {
+                let values = field(json, "values")?
+                    .as_array()
+                    .ok_or("Expected a list of values.")?
+                    .iter()
+                    .map(|json| self.expr(json))
+                    .collect::<Result<Rc<[Expr]>, String>>()?;
+                Stmt::Print(values)
+            }
+            "Return" => Stmt::Return(
+                token_from_json(json.get("keyword"))?,
+                optional(json.get("value"), |json| self.expr(json))?,
+            ),
+            "If" => {
+                let arm = |json: &Json| -> Result<(Token, Expr, Stmt), String> {
+                    Ok((
+                        token_from_json(json.get("keyword"))?,
+                        self.expr(field(json, "condition")?)?,
+                        self.stmt(field(json, "then")?)?,
+                    ))
+                };
+                let arms = json.get("arms").and_then(Json::as_array).ok_or("Expected a list of arms.")?;
+                let arms = arms.iter().map(arm).collect::<Result<Vec<_>, _>>()?;
+                Stmt::If(arms.into(), optional(json.get("else"), |json| self.stmt(json))?.map(Rc::new))
+            }
+            "While" => Stmt::While(
+                token_from_json(json.get("keyword"))?,
+                self.expr(field(json, "condition")?)?,
+                Rc::new(self.stmt(field(json, "body")?)?),
+            ),
+            "For" => Stmt::For(
+                token_from_json(json.get("keyword"))?,
+                optional(json.get("initializer"), |json| self.stmt(json))?.map(Rc::new),
+                optional(json.get("condition"), |json| self.expr(json))?,
+                optional(json.get("increment"), |json| self.expr(json))?,
+                Rc::new(self.stmt(field(json, "body")?)?),
+            ),
+            "Var" => Stmt::Var(
+                token_from_json(json.get("name"))?,
+                optional(json.get("annotation"), |annotation| token_from_json(Some(annotation)))?,
+                optional(json.get("initializer"), |json| self.expr(json))?,
+            ),
+            "Labeled" => Stmt::Labeled(
+                token_from_json(json.get("label"))?,
+                Rc::new(self.stmt(field(json, "body")?)?),
+            ),
+            "Break" => Stmt::Break(
+                token_from_json(json.get("keyword"))?,
+                optional(json.get("label"), |label| token_from_json(Some(label)))?,
+            ),
+            "Continue" => Stmt::Continue(
+                token_from_json(json.get("keyword"))?,
+                optional(json.get("label"), |label| token_from_json(Some(label)))?,
+            ),
+            "ForIn" => Stmt::ForIn(
+                token_from_json(json.get("name"))?,
+                self.expr(field(json, "iterable")?)?,
+                Rc::new(self.stmt(field(json, "body")?)?),
+            ),
+            "With" => Stmt::With(
+                token_from_json(json.get("keyword"))?,
+                token_from_json(json.get("name"))?,
+                self.expr(field(json, "initializer")?)?,
+                Rc::new(self.stmt(field(json, "body")?)?),
+            ),
+            "Enum" => Stmt::Enum(token_from_json(json.get("name"))?, tokens_from_json(json.get("cases"))?),
+            "Import" => Stmt::Import(
+                token_from_json(json.get("keyword"))?,
+                tokens_from_json(json.get("names"))?,
+                Rc::from(field(json, "path")?.as_str().ok_or("Expected a module path string.")?),
+            ),
+            "Export" => Stmt::Export(Rc::new(self.stmt(field(json, "declaration")?)?)),
+            _ => return Err(format!("Unknown statement kind '{}'.", kind)),
+        })
+    }
+
+    fn expr(&self, json: &Json) -> Result<Expr, String> {
+        self.check_depth()?;
+        let kind = field(json, "expr")?.as_str().unwrap_or_default();
+        let operand = |key| self.expr(field(json, key)?).map(Rc::new);
+        Ok(match kind {
+            "Literal" => Expr::Literal(literal_from_json(field(json, "value")?)?),
+            "Logical" => Expr::Logical(
+                operand("left")?,
+                token_from_json(json.get("operator"))?,
+                operand("right")?,
+            ),
+            "Binary" => Expr::Binary(
+                operand("left")?,
+                token_from_json(json.get("operator"))?,
+                operand("right")?,
+            ),
+            "Unary" => Expr::Unary(token_from_json(json.get("operator"))?, operand("right")?),
+            "Assign" => Expr::Assign(
+                next_expr_id(),
+                token_from_json(json.get("name"))?,
+                operand("value")?,
+            ),
+            "Lambda" => Expr::Lambda(
+                next_expr_id(),
+                tokens_from_json(json.get("params"))?,
+                self.stmts(json.get("body"))?.into(),
+                optional(json.get("signature"), signature_from_json)?.map(Rc::new),
+            ),
+            "Call" => {
+                let arguments = field(json, "arguments")?
+                    .as_array()
+                    .ok_or("Expected an argument list.")?
+                    .iter()
+                    .map(|json| self.expr(json))
+                    .collect::<Result<Rc<[Expr]>, String>>()?;
+                // Left out by versions without named arguments.
+                let named = optional(json.get("named"), |json| self.named(json))?.unwrap_or_else(|| Rc::from([]));
+                Expr::Call(operand("callee")?, token_from_json(json.get("paren"))?, arguments, named)
+            }
+            "Get" => Expr::Get(operand("object")?, token_from_json(json.get("name"))?),
+            "Set" => Expr::Set(operand("object")?, token_from_json(json.get("name"))?, operand("value")?),
+            "Index" => Expr::Index(operand("object")?, token_from_json(json.get("bracket"))?, operand("index")?),
+            "Record" => {
+                Expr::Record(token_from_json(json.get("brace"))?, self.named(field(json, "fields")?)?)
+            }
+            "Grouping" => Expr::Grouping(operand("inner")?),
+            "Variable" => Expr::Variable(next_expr_id(), token_from_json(json.get("name"))?),
+            "Empty" => Expr::Empty,
+            _ => return Err(format!("Unknown expression kind '{}'.", kind)),
+        })
+    }
+
+    fn named(&self, json: &Json) -> Result<Rc<[(Token, Expr)]>, String> {
+        json.as_array()
+            .ok_or("Expected a list of names and values.")?
+            .iter()
+            .map(|item| Ok((token_from_json(item.get("name"))?, self.expr(field(item, "value")?)?)))
+            .collect()
+    }
+}
+
@@ -1,7 +1,13 @@
+use crate::error::ParserError;
 use crate::expr::Expr;
-use crate::token::Literal;
+use crate::stack::{DepthLimit, StackGuard};
+use crate::stmt::Stmt;
+use crate::token::{Literal, Token};
 
-pub struct AstPrinter;
+pub struct AstPrinter {
+    /// Subtrees nested too deeply to print come out as `...`.
+    depth: DepthLimit,
+}
 
 impl Default for AstPrinter {
     fn default() -> Self {
@@ -11,47 +17,179 @@ impl Default for AstPrinter {
 
 impl AstPrinter {
     pub fn new() -> Self {
-        Self
+        Self {
+            depth: DepthLimit::default(),
+        }
     }
 
-    pub fn print(&self, expr: Expr) -> String {
+    /// Measures stack use against `stack` rather than from where the
+    /// printer was made, as the tracer does to share the run's budget.
+    pub fn set_stack(&mut self, stack: StackGuard) {
+        self.depth = DepthLimit::new(stack);
+    }
+
+    /// Prints an expression, with `...` for any part nested too deeply to
+    /// print.
+    pub fn print(&self, expr: &Expr) -> String {
         self.output(expr)
     }
 
-    fn output(&self, expr: Expr) -> String {
+    /// Prints a whole program, one top-level statement per line, or fails
+    /// if it is nested too deeply to print.
+    pub fn print_program(&self, stmts: &[Stmt]) -> Result<String, ParserError> {
+        let text = stmts
+            .iter()
+            .map(|stmt| self.print_stmt(stmt) + "\n")
+            .collect();
+        self.depth.finish(text)
+    }
+
+    pub fn print_stmt(&self, stmt: &Stmt) -> String {
+        if self.depth.exhausted(None, || stmt.line()) {
+            return "...".to_string();
+        }
+        match stmt {
+            Stmt::Block(stmts) => {
+                let mut s = String::from("(block");
+                for stmt in stmts.iter() {
+                    s.push(' ');
+                    s.push_str(&self.print_stmt(stmt));
+                }
+                s.push(')');
+                s
+            }
+            Stmt::Expression(expr) => self.parenthesize(";", &[expr]),
+            Stmt::Function(name, _, params, body, ..) => {
+                format!("(fun {}{})", name.lexeme, self.function(params, body))
+            }
+            Stmt::Print(values) => self.parenthesize("print", &values.iter().collect::<Vec<_>>()),
+            Stmt::Return(_, None) => "(return)".to_string(),
+            Stmt::Return(_, Some(value)) => self.parenthesize("return", &[value]),
+            // Printed as the nested ifs the arms stand for.
+            Stmt::If(arms, else_branch) => {
+                let mut out = else_branch.as_ref().map(|else_branch| self.print_stmt(else_branch));
+                for (_, condition, branch) in arms.iter().rev() {
+                    let condition = self.output(condition);
+                    let branch = self.print_stmt(branch);
+                    out = Some(match out {
+                        Some(else_branch) => {
+                            format!("(if-else {} {} {})", condition, branch, else_branch)
+                        }
+                        None => format!("(if {} {})", condition, branch),
+                    });
+                }
+                out.unwrap_or_default()
+            }
+            Stmt::While(_, condition, body) => format!(
+                "(while {} {})",
+                self.output(condition),
+                self.print_stmt(body)
+            ),
+            Stmt::For(_, initializer, condition, increment, body) => format!(
+                "(for {} {} {} {})",
+                initializer
+                    .as_ref()
+                    .map_or("_".to_string(), |initializer| self.print_stmt(initializer)),
+                condition.as_ref().map_or("_".to_string(), |condition| self.output(condition)),
+                increment.as_ref().map_or("_".to_string(), |increment| self.output(increment)),
+                self.print_stmt(body)
+            ),
+            Stmt::Var(name, _, None) => format!("(var {})", name.lexeme),
+            Stmt::Var(name, _, Some(initializer)) => {
+                format!("(var {} = {})", name.lexeme, self.output(initializer))
+            }
+            Stmt::Labeled(label, body) => format!("(label {} {})", label.lexeme, self.print_stmt(body)),
+            Stmt::Break(_, None) => "(break)".to_string(),
+            Stmt::Break(_, Some(label)) => format!("(break {})", label.lexeme),
+            Stmt::Continue(_, None) => "(continue)".to_string(),
+            Stmt::Continue(_, Some(label)) => format!("(continue {})", label.lexeme),
+            Stmt::ForIn(name, iterable, body) => format!(
+                "(for-in {} {} {})",
+                name.lexeme,
+                self.output(iterable),
+                self.print_stmt(body)
+            ),
+            Stmt::With(_, name, initializer, body) => format!(
+                "(with {} {} {})",
+                name.lexeme,
+                self.output(initializer),
+                self.print_stmt(body)
+            ),
+            Stmt::Enum(name, cases) => {
+                let cases: Vec<&str> = cases.iter().map(|case| case.lexeme.as_str()).collect();
+                format!("(enum {} {})", name.lexeme, cases.join(" "))
+            }
+            Stmt::Import(_, names, path) => {
+                let names: Vec<&str> = names.iter().map(|name| name.lexeme.as_str()).collect();
+                format!("(import {} from \"{}\")", names.join(" "), path)
+            }
+            Stmt::Export(declaration) => format!("(export {})", self.print_stmt(declaration)),
+        }
+    }
+
+    fn output(&self, expr: &Expr) -> String {
+        if self.depth.exhausted(expr.token(), || expr.line()) {
+            return "...".to_string();
+        }
         match expr {
             Expr::Literal(literal) => self.parenthesize_literal(literal),
-            Expr::Unary(operator, right) => self.parenthesize(operator.lexeme, vec![*right]),
+            Expr::Unary(operator, right) => self.parenthesize(&operator.lexeme, &[right]),
             Expr::Binary(left, operator, right) => {
-                self.parenthesize(operator.lexeme, vec![*left, *right])
+                self.parenthesize(&operator.lexeme, &[left, right])
             }
-            Expr::Grouping(expr) => self.parenthesize("group".to_string(), vec![*expr]),
-            Expr::Variable(token) => format!("(var {})", token.lexeme),
-            Expr::Assign(token, value) => format!("(var {} {})", token.lexeme, self.output(*value)),
+            Expr::Grouping(expr) => self.parenthesize("group", &[expr]),
+            Expr::Variable(_, token) => token.lexeme.to_string(),
+            Expr::Assign(_, token, value) => format!("(= {} {})", token.lexeme, self.output(value)),
             Expr::Logical(left, operator, right) => format!(
                 "({} {} {})",
                 operator.lexeme,
-                self.output(*left),
-                self.output(*right)
+                self.output(left),
+                self.output(right)
             ),
-            Expr::Call(callee, _, arguments) => {
-                let mut s = self.output(*callee);
-                for arg in *arguments {
-                    s.push_str(&self.output(arg));
+            Expr::Call(callee, _, arguments, named) => {
+                let mut s = format!("(call {}", self.output(callee));
+                for arg in arguments.iter() {
                     s.push(' ');
+                    s.push_str(&self.output(arg));
+                }
+                for (name, value) in named.iter() {
+                    s.push_str(&format!(" {}: {}", name.lexeme, self.output(value)));
                 }
                 s.push(')');
                 s
             },
-            Expr::Lambda(_arguments, _body) => "(<lambda>)".to_string(),
-            Expr::Empty => "".to_string()
+            Expr::Get(object, name) => format!("(. {} {})", self.output(object), name.lexeme),
+            Expr::Set(object, name, value) => {
+                format!("(= (. {} {}) {})", self.output(object), name.lexeme, self.output(value))
+            }
+            Expr::Index(object, _, index) => format!("([] {} {})", self.output(object), self.output(index)),
+            Expr::Record(_, fields) => {
+                let mut s = String::from("(record");
+                for (name, value) in fields.iter() {
+                    s.push_str(&format!(" {}: {}", name.lexeme, self.output(value)));
+                }
+                s.push(')');
+                s
+            }
+            Expr::Lambda(_, params, body, _) => format!("(fun{})", self.function(params, body)),
+            Expr::Empty => "(empty)".to_string()
+
+        }
+    }
 
+    fn function(&self, params: &[Token], body: &[Stmt]) -> String {
+        let params: Vec<&str> = params.iter().map(|param| &*param.lexeme).collect();
+        let mut s = format!("({})", params.join(" "));
+        for stmt in body {
+            s.push(' ');
+            s.push_str(&self.print_stmt(stmt));
         }
+        s
     }
 
-    fn parenthesize(&self, name: String, exprs: Vec<Expr>) -> String {
+    fn parenthesize(&self, name: &str, exprs: &[&Expr]) -> String {
         let mut s = String::from("(");
-        s.push_str(&name);
+        s.push_str(name);
         for expr in exprs {
             s.push(' ');
             s.push_str(&self.output(expr));
@@ -60,7 +198,10 @@ impl AstPrinter {
         s
     }
 
-    fn parenthesize_literal(&self, literal: Literal) -> String {
-        literal.to_string()
+    fn parenthesize_literal(&self, literal: &Literal) -> String {
+        match literal {
+            Literal::String(s) => format!("{:?}", s),
+            _ => literal.to_string(),
+        }
     }
 }
@@ -0,0 +1,63 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Wall times from repeated runs of a script, reported by `rlox bench`.
+#[derive(Debug, Default, Clone)]
+pub struct Samples {
+    times: Vec<Duration>,
+}
+
+impl Samples {
+    pub fn push(&mut self, time: Duration) {
+        self.times.push(time);
+    }
+
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.times.is_empty() {
+            return Duration::ZERO;
+        }
+        self.times.iter().sum::<Duration>() / self.times.len() as u32
+    }
+
+    pub fn median(&self) -> Duration {
+        let mut times = self.times.clone();
+        times.sort();
+        match times.len() {
+            0 => Duration::ZERO,
+            n if n % 2 == 1 => times[n / 2],
+            n => (times[n / 2 - 1] + times[n / 2]) / 2,
+        }
+    }
+
+    /// The population standard deviation.
+    pub fn stddev(&self) -> Duration {
+        if self.times.is_empty() {
+            return Duration::ZERO;
+        }
+        let mean = self.mean().as_secs_f64();
+        let variance = self
+            .times
+            .iter()
+            .map(|time| (time.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / self.times.len() as f64;
+        Duration::from_secs_f64(variance.sqrt())
+    }
+}
+
+impl fmt::Display for Samples {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "iterations: {}", self.len())?;
+        writeln!(f, "mean:       {:?}", self.mean())?;
+        writeln!(f, "median:     {:?}", self.median())?;
+        write!(f, "stddev:     {:?}", self.stddev())
+    }
+}
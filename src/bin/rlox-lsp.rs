@@ -0,0 +1,18 @@
+use std::io;
+use std::process::exit;
+use std::thread;
+
+use treewalk::lsp::Server;
+use treewalk::stack;
+
+/// Matches the interpreter's stack so deeply nested documents still parse.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+fn main() -> io::Result<()> {
+    stack::set_budget(STACK_SIZE - 16 * 1024 * 1024);
+    let server = thread::Builder::new().stack_size(STACK_SIZE).spawn(|| {
+        Server::default().run(&mut io::stdin().lock(), &mut io::stdout().lock())
+    })?;
+    let shut_down = server.join().unwrap_or(Ok(false))?;
+    exit(if shut_down { 0 } else { 1 })
+}
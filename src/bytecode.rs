@@ -0,0 +1,213 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::symbol::Symbol;
+use crate::token::{format_number, Literal};
+use crate::vm::Vm;
+
+/// Instructions for the stack VM. Operands are stored inline; jump targets
+/// are absolute offsets into the owning chunk's code, and globals are
+/// named by their index in its `names`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCode {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Pops the value of an expression statement outside any function,
+    /// keeping it as the script's result.
+    PopResult,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetGlobal(u16),
+    DefineGlobal(u16),
+    SetGlobal(u16),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Not,
+    Negate,
+    Print,
+    Jump(u32),
+    JumpIfFalse(u32),
+    Loop(u32),
+    Call(u8),
+    Closure(u16),
+    CloseUpvalue,
+    /// Closes the upvalues of the locals from this slot up, leaving the
+    /// locals on the stack. A `for` loop runs this at the end of each pass,
+    /// so closures made on the next pass capture new variables.
+    CloseUpvalues(u8),
+    Return,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub lines: Vec<u32>,
+    pub constants: Vec<Value>,
+    /// The globals the chunk's code refers to, interned once when it's
+    /// compiled rather than each time it runs.
+    pub names: Vec<Symbol>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, op: OpCode, line: u32) -> usize {
+        self.code.push(op);
+        self.lines.push(line);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn add_name(&mut self, name: Symbol) -> usize {
+        match self.names.iter().position(|&known| known == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name);
+                self.names.len() - 1
+            }
+        }
+    }
+}
+
+/// Where a closure finds each captured variable when it is created: a slot
+/// in the enclosing frame, or one of the enclosing closure's own upvalues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpvalueDescriptor {
+    pub is_local: bool,
+    pub index: u8,
+}
+
+#[derive(Debug, Default)]
+pub struct FunctionProto {
+    pub name: Symbol,
+    pub arity: u8,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueDescriptor>,
+}
+
+/// A captured variable. It points into the VM stack while the declaring
+/// frame is live and owns the value once that frame's slot goes away.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+#[derive(Clone)]
+pub struct VmNative {
+    pub name: Symbol,
+    pub arity: u8,
+    pub function: fn(vm: &Vm, args: &[Value]) -> Value,
+}
+
+impl fmt::Debug for VmNative {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VmNative({})", self.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<FunctionProto>),
+    Closure(Rc<Closure>),
+    Native(VmNative),
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The same value as the tree-walker has it, if it's a plain one
+    /// (nil, a boolean, a number or a string).
+    pub fn to_plain_literal(&self) -> Option<Literal> {
+        match self {
+            Value::Nil => Some(Literal::Nil),
+            Value::Bool(b) => Some(Literal::from(*b)),
+            Value::Int(i) => Some(Literal::Int(*i)),
+            Value::Number(n) => Some(Literal::Number(*n)),
+            Value::String(s) => Some(Literal::String(s.clone())),
+            _ => None,
+        }
+    }
+
+    /// The inverse of [`Value::to_plain_literal`].
+    pub fn from_plain_literal(literal: &Literal) -> Option<Value> {
+        match literal {
+            Literal::Nil => Some(Value::Nil),
+            Literal::True => Some(Value::Bool(true)),
+            Literal::False => Some(Value::Bool(false)),
+            Literal::Int(i) => Some(Value::Int(*i)),
+            Literal::Number(n) => Some(Value::Number(*n)),
+            Literal::String(s) => Some(Value::String(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Int(a), Value::Number(b)) | (Value::Number(b), Value::Int(a)) => {
+                *a as f64 == *b
+            }
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => a.name == b.name && a.arity == b.arity,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Number(n) => write!(f, "{}", format_number(*n)),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Function(function) => write!(f, "<fn {}>", function.name),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.function.name),
+            Value::Native(_) => write!(f, "<native fn>"),
+        }
+    }
+}
@@ -1,11 +1,57 @@
-use crate::interpreter::{Interpreter, InterpreterResult};
-use crate::token::Literal;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::interpreter::{self, Interpreter, InterpreterResult};
+use crate::token::{Literal, Token};
 
 pub trait Callable {
     fn arity(&self) -> u8;
+    /// Whether a call with `given` arguments is allowed. Overloaded and
+    /// variadic natives allow counts besides their arity.
+    fn accepts(&self, given: usize) -> bool {
+        given == self.arity() as usize
+    }
     fn call(
-        &mut self,
-        interpreter: &Interpreter,
-        args: &Vec<Literal>,
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[Literal],
     ) -> InterpreterResult<Literal>;
 }
+
+/// Gives each function value its identity. Copies of a value share the ID,
+/// so `f == f` holds while two functions with the same name stay distinct.
+pub type FunctionId = usize;
+
+static NEXT_FUNCTION_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub fn next_function_id() -> FunctionId {
+    NEXT_FUNCTION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A function with its leading arguments fixed, made by `bind` and
+/// `curry`. Calling it calls `function` with `args` before the arguments
+/// given. A curried one may be given fewer than the rest, which binds
+/// those too and waits for the others.
+#[derive(Debug)]
+pub struct BoundFunction {
+    pub function: Literal,
+    pub args: Rc<[Literal]>,
+    pub curried: bool,
+}
+
+impl Callable for BoundFunction {
+    fn arity(&self) -> u8 {
+        let (arity, _) = interpreter::arity(&self.function).unwrap_or_default();
+        arity.saturating_sub(self.args.len()) as u8
+    }
+
+    fn accepts(&self, given: usize) -> bool {
+        let partial = self.curried && given > 0 && given < self.arity() as usize;
+        partial || interpreter::accepts(&self.function, self.args.len() + given)
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: &[Literal]) -> InterpreterResult<Literal> {
+        let args = self.args.iter().chain(args).cloned().collect();
+        interpreter.call_value(&self.function, args, &Token::default())
+    }
+}
@@ -0,0 +1,156 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::bench::Samples;
+use crate::docs::{self, DocFormat};
+use crate::formatter::Formatter;
+use crate::highlighter::{HighlightFormat, Highlighter};
+use crate::interpreter::{Interpreter, RunStatus};
+use crate::lint::{self, LintFormat};
+use crate::parser::Parser;
+use crate::resolver::{Resolve, Resolver};
+use crate::test_runner;
+
+/// Scans, parses and resolves a script, writing the result to `out` as
+/// a `.loxc` file that [`Interpreter::run_compiled_file`] runs without
+/// doing any of that again. Nothing is run.
+pub fn compile_file(interpreter: &mut Interpreter, path: &str, out: &str) -> Result<RunStatus, Box<dyn Error>> {
+    let contents: String = fs::read_to_string(path)?;
+    interpreter.set_script_path(path);
+    let Some(statements) = interpreter.parse(contents) else {
+        return Ok(interpreter.finish())
+    };
+    if interpreter.resolve_program(&statements) {
+        match interpreter.compiled(&statements) {
+            Ok(compiled) => fs::write(out, compiled)?,
+            Err(err) => interpreter.report(err.into()),
+        }
+    }
+    Ok(interpreter.finish())
+}
+
+/// Runs a script `iterations` times, each against fresh globals, and
+/// reports wall-time statistics on stderr. Scanning, parsing and
+/// resolving happen once, outside the timed runs.
+pub fn bench_file(interpreter: &mut Interpreter, path: &str, iterations: usize) -> Result<RunStatus, Box<dyn Error>> {
+    let contents: String = fs::read_to_string(path)?;
+    interpreter.set_script_path(path);
+    let Some(statements) = interpreter.parse(contents) else {
+        return Ok(interpreter.finish())
+    };
+    if !interpreter.resolve_program(&statements) {
+        return Ok(interpreter.finish())
+    }
+    let Some(prepared) = interpreter.prepare(statements) else {
+        return Ok(interpreter.finish())
+    };
+
+    let mut samples = Samples::default();
+    let snapshot = interpreter.snapshot();
+    for _ in 0..iterations {
+        interpreter.restore(snapshot.clone());
+        let start = Instant::now();
+        interpreter.run_prepared(&prepared);
+        samples.push(start.elapsed());
+        if interpreter.had_runtime_error() {
+            break;
+        }
+    }
+    eprintln!("{}", samples);
+    Ok(interpreter.finish())
+}
+
+/// Prints the canonical formatting of a script, or its syntax errors.
+pub fn format_file(interpreter: &mut Interpreter, path: &str) -> Result<RunStatus, Box<dyn Error>> {
+    let contents: String = fs::read_to_string(path)?;
+    let mut scanner = interpreter.scanner(contents);
+    if let Err(err) = scanner.scan_tokens() {
+        interpreter.report(err);
+    } else {
+        match Parser::new(scanner.tokens).parse() {
+            Ok(statements) => {
+                let mut formatter = Formatter::new(scanner.comments, scanner.blank_lines);
+                formatter.set_options(interpreter.format_options());
+                match formatter.format(&statements) {
+                    Ok(formatted) => print!("{}", formatted),
+                    Err(err) => interpreter.report(err.into()),
+                }
+            }
+            Err(err) => interpreter.report(err.into()),
+        }
+    }
+    Ok(interpreter.finish())
+}
+
+/// Checks a script without running it, printing every error and
+/// warning found, as text or as JSON diagnostics.
+pub fn lint_file(interpreter: &mut Interpreter, path: &str, format: LintFormat) -> Result<RunStatus, Box<dyn Error>> {
+    let contents: String = fs::read_to_string(path)?;
+    let mut scanner = interpreter.scanner(contents);
+    let mut errors = scanner.scan_tokens_collecting();
+    let (statements, parse_errors) = Parser::new(scanner.tokens).parse_collecting();
+    errors.extend(parse_errors);
+    let mut resolver = Resolver::collecting(interpreter);
+    resolver.resolve(statements.as_slice());
+    let resolution = resolver.into_resolution();
+    errors.extend(resolution.errors);
+    print!("{}", lint::render(&errors, &resolution.warnings, format, interpreter.sources()));
+    Ok(match (errors.is_empty(), resolution.warnings.is_empty()) {
+        (false, _) => RunStatus::StaticError,
+        (true, false) => RunStatus::Warnings,
+        (true, true) => RunStatus::Success,
+    })
+}
+
+/// Writes API docs for the scripts under `src`, a file or a directory,
+/// into the directory `out`: a page for each script, laid out as the
+/// scripts are, and an index linking to them.
+pub fn doc_files(
+    interpreter: &mut Interpreter,
+    src: &str,
+    out: &str,
+    format: DocFormat,
+) -> Result<RunStatus, Box<dyn Error>> {
+    let src = Path::new(src);
+    let mut scripts = vec![];
+    test_runner::collect_scripts(src, &mut scripts)?;
+    scripts.sort();
+    let root = if src.is_file() { src.parent().unwrap_or(Path::new("")) } else { src };
+    let out = Path::new(out);
+    fs::create_dir_all(out)?;
+    let mut modules = vec![];
+    for script in scripts {
+        let contents = fs::read_to_string(&script)?;
+        let Some(statements) = interpreter.parse_file(&script.to_string_lossy(), contents) else {
+            continue;
+        };
+        let relative = script.strip_prefix(root).unwrap_or(&script);
+        let title = relative.with_extension("").to_string_lossy().replace('\\', "/");
+        let page = relative.with_extension(format.extension());
+        if let Some(dir) = out.join(&page).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(out.join(&page), docs::page(&title, &statements, format))?;
+        modules.push((title, page.to_string_lossy().replace('\\', "/")));
+    }
+    let index = out.join(format!("index.{}", format.extension()));
+    fs::write(index, docs::index(&modules, format))?;
+    Ok(interpreter.finish())
+}
+
+/// Prints a script colorized from its token stream.
+pub fn highlight_file(
+    interpreter: &mut Interpreter,
+    path: &str,
+    format: HighlightFormat,
+) -> Result<RunStatus, Box<dyn Error>> {
+    let contents: String = fs::read_to_string(path)?;
+    let mut scanner = interpreter.scanner(contents.clone());
+    match scanner.scan_tokens() {
+        Ok(()) => print!("{}", Highlighter::new(&contents, format).highlight(&scanner.tokens)),
+        Err(err) => interpreter.report(err),
+    }
+    Ok(interpreter.finish())
+}
@@ -0,0 +1,628 @@
+use std::rc::Rc;
+
+use crate::bytecode::{Chunk, FunctionProto, OpCode, UpvalueDescriptor, Value};
+use crate::error::{LoxError, ParserError};
+use crate::expr::Expr;
+use crate::stack::{self, StackGuard};
+use crate::stmt::{PendingLabel, Stmt};
+use crate::symbol::Symbol;
+use crate::token::{Literal, Token, TokenType};
+
+const MAX_LOCALS: usize = 256;
+
+struct Local {
+    name: Symbol,
+    depth: Option<usize>,
+    captured: bool,
+}
+
+struct LoopState {
+    depth: usize,
+    label: Option<Symbol>,
+    breaks: Vec<usize>,
+    /// Jumps to patch to the increment, or back to the condition.
+    continues: Vec<usize>,
+}
+
+struct FunctionState {
+    proto: FunctionProto,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueDescriptor>,
+    scope_depth: usize,
+    loops: Vec<LoopState>,
+}
+
+impl FunctionState {
+    fn new(name: Symbol, arity: u8) -> Self {
+        // Slot zero holds the callee itself, as in clox.
+        let callee = Local {
+            name: Symbol::default(),
+            depth: Some(0),
+            captured: false,
+        };
+        Self {
+            proto: FunctionProto {
+                name,
+                arity,
+                ..FunctionProto::default()
+            },
+            locals: vec![callee],
+            upvalues: vec![],
+            scope_depth: 0,
+            loops: vec![],
+        }
+    }
+}
+
+/// Lowers a resolved program to bytecode for the `vm` backend.
+pub struct Compiler {
+    functions: Vec<FunctionState>,
+    errors: Vec<LoxError>,
+    line: u32,
+    label: PendingLabel,
+    stack: StackGuard,
+}
+
+type CompileResult = Result<(), ParserError>;
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            functions: vec![FunctionState::new(Symbol::intern("script"), 0)],
+            errors: vec![],
+            line: 1,
+            label: PendingLabel::default(),
+            stack: StackGuard::new(),
+        }
+    }
+
+    pub fn compile(mut self, stmts: &[Stmt]) -> Result<Rc<FunctionProto>, Vec<LoxError>> {
+        for stmt in stmts {
+            if let Err(err) = self.statement(stmt) {
+                self.errors.push(LoxError::Compile(err.code, err.token, err.message));
+            }
+        }
+        self.emit(OpCode::Nil);
+        self.emit(OpCode::Return);
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        let state = self.functions.pop().expect("Expected the script function.");
+        Ok(Rc::new(state.proto))
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("Expected a function being compiled.")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().proto.chunk
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        let line = self.line;
+        self.chunk().write(op, line)
+    }
+
+    /// Compiles a loop body with `break` and `continue` able to aim at it,
+    /// handing back the jumps they left to patch.
+    fn loop_body(&mut self, label: Option<Symbol>, body: &Stmt) -> Result<LoopState, ParserError> {
+        let depth = self.current().scope_depth;
+        self.current().loops.push(LoopState {
+            depth,
+            label,
+            breaks: vec![],
+            continues: vec![],
+        });
+        let result = self.statement(body);
+        let state = self.current().loops.pop().expect("Expected a loop.");
+        result.map(|()| state)
+    }
+
+    /// Stops before deeply nested code overflows the stack, reporting at
+    /// `token` or, without one, the line being compiled.
+    fn check_depth(&self, token: Option<&Token>) -> CompileResult {
+        match self.stack.exhausted() {
+            true => Err(stack::too_deep("C0003", token, Some(self.line))),
+            false => Ok(()),
+        }
+    }
+
+    /// Stands in for a literal, which keeps no token, to place an error on
+    /// the line being compiled.
+    fn here(&self) -> Token {
+        Token {
+            line: self.line,
+            ..Token::default()
+        }
+    }
+
+    fn error(&self, code: &'static str, token: &Token, message: &str) -> ParserError {
+        ParserError::new(code, token.clone(), message.to_string())
+    }
+
+    fn constant(&mut self, value: Value, token: &Token) -> Result<u16, ParserError> {
+        let index = self.chunk().add_constant(value);
+        u16::try_from(index).map_err(|_| self.error("C0002", token, "Too many constants in one chunk."))
+    }
+
+    fn global_name(&mut self, name: &Token) -> Result<u16, ParserError> {
+        let index = self.chunk().add_name(name.lexeme);
+        u16::try_from(index).map_err(|_| self.error("C0002", name, "Too many globals in one chunk."))
+    }
+
+    fn jump_target(&mut self) -> u32 {
+        self.chunk().code.len() as u32
+    }
+
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.jump_target();
+        let chunk = self.chunk();
+        chunk.code[at] = match chunk.code[at] {
+            OpCode::Jump(_) => OpCode::Jump(target),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(target),
+            op => op,
+        };
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current().scope_depth -= 1;
+        let depth = self.current().scope_depth;
+        self.pop_locals_above(depth, true);
+    }
+
+    /// Emits the pops for every local deeper than `depth`. A `break` leaves
+    /// the locals in place for the code it jumps over, so it passes
+    /// `forget: false`.
+    fn pop_locals_above(&mut self, depth: usize, forget: bool) {
+        let mut index = self.current().locals.len();
+        while index > 0 {
+            let local = &self.current().locals[index - 1];
+            if local.depth.is_some_and(|d| d <= depth) {
+                break;
+            }
+            let op = if local.captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.emit(op);
+            index -= 1;
+        }
+        if forget {
+            self.current().locals.truncate(index);
+        }
+    }
+
+    fn declare_local(&mut self, name: &Token) -> CompileResult {
+        let state = self.current();
+        if state.locals.len() >= MAX_LOCALS {
+            return Err(self.error("C0002", name, "Too many local variables in function."));
+        }
+        state.locals.push(Local {
+            name: name.lexeme,
+            depth: None,
+            captured: false,
+        });
+        Ok(())
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.current();
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    /// Declares `name` in the current scope. At the top level this returns
+    /// the name to pass to `DefineGlobal`.
+    fn declare_variable(&mut self, name: &Token) -> Result<Option<u16>, ParserError> {
+        if self.current().scope_depth == 0 {
+            return Ok(Some(self.global_name(name)?));
+        }
+        self.declare_local(name)?;
+        Ok(None)
+    }
+
+    fn define_variable(&mut self, global: Option<u16>) {
+        match global {
+            Some(index) => {
+                self.emit(OpCode::DefineGlobal(index));
+            }
+            None => self.mark_initialized(),
+        }
+    }
+
+    fn resolve_local(&self, function: usize, name: &Token) -> Result<Option<u8>, ParserError> {
+        let locals = &self.functions[function].locals;
+        for (slot, local) in locals.iter().enumerate().rev() {
+            if local.name == name.lexeme {
+                if local.depth.is_none() {
+                    return Err(self.error("C0004", name, "Can't read local variable in its own initializer."));
+                }
+                return Ok(Some(slot as u8));
+            }
+        }
+        Ok(None)
+    }
+
+    fn add_upvalue(&mut self, function: usize, upvalue: UpvalueDescriptor, name: &Token) -> Result<u8, ParserError> {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(index) = upvalues.iter().position(|u| *u == upvalue) {
+            return Ok(index as u8);
+        }
+        if upvalues.len() >= MAX_LOCALS {
+            return Err(self.error("C0002", name, "Too many closure variables in function."));
+        }
+        upvalues.push(upvalue);
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &Token) -> Result<Option<u8>, ParserError> {
+        if function == 0 {
+            return Ok(None);
+        }
+        if let Some(slot) = self.resolve_local(function - 1, name)? {
+            self.functions[function - 1].locals[slot as usize].captured = true;
+            let upvalue = UpvalueDescriptor { is_local: true, index: slot };
+            return Ok(Some(self.add_upvalue(function, upvalue, name)?));
+        }
+        if let Some(index) = self.resolve_upvalue(function - 1, name)? {
+            let upvalue = UpvalueDescriptor { is_local: false, index };
+            return Ok(Some(self.add_upvalue(function, upvalue, name)?));
+        }
+        Ok(None)
+    }
+
+    fn named_variable(&mut self, name: &Token, assign: bool) -> CompileResult {
+        self.line = name.line;
+        let function = self.functions.len() - 1;
+        let (get, set) = if let Some(slot) = self.resolve_local(function, name)? {
+            (OpCode::GetLocal(slot), OpCode::SetLocal(slot))
+        } else if let Some(index) = self.resolve_upvalue(function, name)? {
+            (OpCode::GetUpvalue(index), OpCode::SetUpvalue(index))
+        } else {
+            let index = self.global_name(name)?;
+            (OpCode::GetGlobal(index), OpCode::SetGlobal(index))
+        };
+        self.emit(if assign { set } else { get });
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> CompileResult {
+        self.check_depth(None)?;
+        // A block's line is found in its statements, which set their own.
+        if !matches!(stmt, Stmt::Block(_)) {
+            self.line = stmt.line().unwrap_or(self.line);
+        }
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.expression(expr)?;
+                match self.functions.len() {
+                    1 => self.emit(OpCode::PopResult),
+                    _ => self.emit(OpCode::Pop),
+                };
+            }
+            Stmt::Print(values) => {
+                let [value] = &**values else {
+                    let keyword = Token {
+                        token_type: TokenType::Print,
+                        lexeme: Symbol::intern("print"),
+                        line: values.iter().find_map(Expr::line).unwrap_or(self.line),
+                        ..Token::default()
+                    };
+                    return Err(self.error("C0001", &keyword, "Printing several values isn't supported by the VM."));
+                };
+                self.expression(value)?;
+                self.emit(OpCode::Print);
+            }
+            Stmt::Var(name, _, initializer) => {
+                self.line = name.line;
+                let global = self.declare_variable(name)?;
+                match initializer {
+                    Some(expr) => self.expression(expr)?,
+                    None => return Err(self.error("C0001", name, "Must assign value to new variable.")),
+                }
+                self.define_variable(global);
+            }
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                for stmt in stmts.iter() {
+                    self.statement(stmt)?;
+                }
+                self.end_scope();
+            }
+            Stmt::If(arms, else_branch) => {
+                let mut end_jumps = vec![];
+                for (_, condition, branch) in arms.iter() {
+                    self.expression(condition)?;
+                    let next_jump = self.emit(OpCode::JumpIfFalse(0));
+                    self.emit(OpCode::Pop);
+                    self.statement(branch)?;
+                    end_jumps.push(self.emit(OpCode::Jump(0)));
+                    self.patch_jump(next_jump);
+                    self.emit(OpCode::Pop);
+                }
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                for jump in end_jumps {
+                    self.patch_jump(jump);
+                }
+            }
+            Stmt::While(_, condition, body) => {
+                let label = self.label.take();
+                let line = self.line;
+                let loop_start = self.jump_target();
+                self.expression(condition)?;
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+
+                let state = self.loop_body(label, body)?;
+                for jump in state.continues {
+                    self.patch_jump(jump);
+                }
+                // Loop limits are reported where the loop starts.
+                self.line = line;
+                self.emit(OpCode::Loop(loop_start));
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+                for jump in state.breaks {
+                    self.patch_jump(jump);
+                }
+            }
+            Stmt::For(_, initializer, condition, increment, body) => {
+                let label = self.label.take();
+                let line = self.line;
+                self.begin_scope();
+                let first_slot = self.current().locals.len();
+                if let Some(initializer) = initializer {
+                    self.statement(initializer)?;
+                }
+                let loop_start = self.jump_target();
+                let exit_jump = match condition {
+                    Some(condition) => {
+                        self.expression(condition)?;
+                        let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+                        self.emit(OpCode::Pop);
+                        Some(exit_jump)
+                    }
+                    None => None,
+                };
+
+                let state = self.loop_body(label, body)?;
+                for jump in state.continues {
+                    self.patch_jump(jump);
+                }
+                // Closures from this pass keep the loop variables as they
+                // are now; the increment works on fresh ones.
+                if self.current().locals[first_slot..].iter().any(|local| local.captured) {
+                    self.emit(OpCode::CloseUpvalues(first_slot as u8));
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit(OpCode::Pop);
+                }
+                self.line = line;
+                self.emit(OpCode::Loop(loop_start));
+                if let Some(exit_jump) = exit_jump {
+                    self.patch_jump(exit_jump);
+                    self.emit(OpCode::Pop);
+                }
+                for jump in state.breaks {
+                    self.patch_jump(jump);
+                }
+                self.end_scope();
+            }
+            Stmt::Labeled(label, body) => {
+                self.label.set(label);
+                self.statement(body)?;
+            }
+            Stmt::Break(keyword, label) | Stmt::Continue(keyword, label) => {
+                let loops = &self.current().loops;
+                let target = match label {
+                    Some(label) => loops.iter().rposition(|state| state.label == Some(label.lexeme)),
+                    None => loops.len().checked_sub(1),
+                };
+                let Some(target) = target else {
+                    return Err(match label {
+                        Some(label) => self.error("C0005", label, &format!("No enclosing loop labeled '{}'.", label.lexeme)),
+                        None => self.error("C0005", keyword, "Expected to be within a loop."),
+                    });
+                };
+                let depth = self.current().loops[target].depth;
+                self.pop_locals_above(depth, false);
+                let jump = self.emit(OpCode::Jump(0));
+                let state = &mut self.current().loops[target];
+                match stmt {
+                    Stmt::Break(..) => state.breaks.push(jump),
+                    _ => state.continues.push(jump),
+                }
+            }
+            Stmt::Enum(name, _) => {
+                return Err(self.error("C0001", name, "Enums aren't supported by the VM."));
+            }
+            Stmt::Import(keyword, ..) => {
+                return Err(self.error("C0001", keyword, "'import' isn't supported by the VM."));
+            }
+            Stmt::Export(declaration) => return self.statement(declaration),
+            Stmt::ForIn(name, ..) => {
+                return Err(self.error("C0001", name, "'for..in' isn't supported by the VM."));
+            }
+            Stmt::With(keyword, ..) => {
+                return Err(self.error("C0001", keyword, "'with' isn't supported by the VM."));
+            }
+            Stmt::Function(name, _, params, body, ..) => {
+                self.line = name.line;
+                let global = self.declare_variable(name)?;
+                // Mark the name ready before compiling the body so local
+                // functions can call themselves.
+                if global.is_none() {
+                    self.mark_initialized();
+                }
+                self.function(name, params, body)?;
+                self.define_variable(global);
+            }
+            Stmt::Return(keyword, value) => {
+                self.line = keyword.line;
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => {
+                        self.emit(OpCode::Nil);
+                    }
+                }
+                self.emit(OpCode::Return);
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> CompileResult {
+        self.functions
+            .push(FunctionState::new(name.lexeme, params.len() as u8));
+        self.begin_scope();
+        let mut result = Ok(());
+        for param in params {
+            result = result.and_then(|_| self.declare_local(param));
+            self.mark_initialized();
+        }
+        for stmt in body {
+            if result.is_err() {
+                break;
+            }
+            result = self.statement(stmt);
+        }
+        self.emit(OpCode::Nil);
+        self.emit(OpCode::Return);
+
+        let state = self.functions.pop().expect("Expected a function being compiled.");
+        result?;
+        let mut proto = state.proto;
+        proto.upvalues = state.upvalues;
+        let index = self.constant(Value::Function(Rc::new(proto)), name)?;
+        self.emit(OpCode::Closure(index));
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> CompileResult {
+        self.check_depth(expr.token())?;
+        match expr {
+            Expr::Empty => {
+                self.emit(OpCode::Nil);
+            }
+            Expr::Literal(literal) => {
+                match literal {
+                    Literal::Nil => self.emit(OpCode::Nil),
+                    Literal::True => self.emit(OpCode::True),
+                    Literal::False => self.emit(OpCode::False),
+                    Literal::Int(i) => {
+                        let index = self.constant(Value::Int(*i), &self.here())?;
+                        self.emit(OpCode::Constant(index))
+                    }
+                    Literal::Number(n) => {
+                        let index = self.constant(Value::Number(*n), &self.here())?;
+                        self.emit(OpCode::Constant(index))
+                    }
+                    Literal::String(s) => {
+                        let index = self.constant(Value::String(s.clone()), &self.here())?;
+                        self.emit(OpCode::Constant(index))
+                    }
+                    _ => {
+                        let token = self.here();
+                        return Err(self.error("C0001", &token, "Function values can't appear as literals."));
+                    }
+                };
+            }
+            Expr::Grouping(expr) => self.expression(expr)?,
+            Expr::Unary(operator, right) => {
+                self.expression(right)?;
+                self.line = operator.line;
+                match operator.token_type {
+                    TokenType::Minus => self.emit(OpCode::Negate),
+                    _ => self.emit(OpCode::Not),
+                };
+            }
+            Expr::Binary(left, operator, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.line = operator.line;
+                let ops: &[OpCode] = match operator.token_type {
+                    TokenType::Plus => &[OpCode::Add],
+                    TokenType::Minus => &[OpCode::Subtract],
+                    TokenType::Star => &[OpCode::Multiply],
+                    TokenType::Slash => &[OpCode::Divide],
+                    TokenType::Percent => &[OpCode::Modulo],
+                    TokenType::Greater => &[OpCode::Greater],
+                    TokenType::GreaterEqual => &[OpCode::Less, OpCode::Not],
+                    TokenType::Less => &[OpCode::Less],
+                    TokenType::LessEqual => &[OpCode::Greater, OpCode::Not],
+                    TokenType::EqualEqual => &[OpCode::Equal],
+                    TokenType::BangEqual => &[OpCode::Equal, OpCode::Not],
+                    _ => return Err(self.error("C0001", operator, "Unknown binary operator.")),
+                };
+                for op in ops {
+                    self.emit(*op);
+                }
+            }
+            Expr::Logical(left, operator, right) => {
+                self.expression(left)?;
+                self.line = operator.line;
+                if operator.token_type == TokenType::Or {
+                    let else_jump = self.emit(OpCode::JumpIfFalse(0));
+                    let end_jump = self.emit(OpCode::Jump(0));
+                    self.patch_jump(else_jump);
+                    self.emit(OpCode::Pop);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump);
+                } else {
+                    let end_jump = self.emit(OpCode::JumpIfFalse(0));
+                    self.emit(OpCode::Pop);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump);
+                }
+            }
+            Expr::Variable(_, name) => self.named_variable(name, false)?,
+            Expr::Assign(_, name, value) => {
+                self.expression(value)?;
+                self.named_variable(name, true)?;
+            }
+            Expr::Call(_, _, _, named) if !named.is_empty() => {
+                return Err(self.error("C0001", &named[0].0, "Named arguments aren't supported by the VM."));
+            }
+            Expr::Call(callee, paren, arguments, _) => {
+                self.expression(callee)?;
+                for argument in arguments.iter() {
+                    self.expression(argument)?;
+                }
+                self.line = paren.line;
+                self.emit(OpCode::Call(arguments.len() as u8));
+            }
+            Expr::Get(_, name) | Expr::Set(_, name, _) => {
+                return Err(self.error("C0001", name, "Property access isn't supported by the VM."));
+            }
+            Expr::Index(_, bracket, _) => {
+                return Err(self.error("C0001", bracket, "Indexing isn't supported by the VM."));
+            }
+            Expr::Record(brace, _) => {
+                return Err(self.error("C0001", brace, "Records aren't supported by the VM."));
+            }
+            Expr::Lambda(_, params, body, _) => {
+                let name = self.here();
+                self.function(&name, params, body)?;
+            }
+        }
+        Ok(())
+    }
+}
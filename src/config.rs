@@ -0,0 +1,236 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formatter::FormatOptions;
+use crate::interpreter::Interpreter;
+use crate::stdlib::Capability;
+use crate::warning::{self, Warnings};
+
+/// The name of a project's configuration file.
+pub const FILE_NAME: &str = "lox.toml";
+
+/// A value in `lox.toml`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Array(Vec<Value>),
+}
+
+/// A project's `lox.toml`: the subset of TOML made of `[table]` headers
+/// and `key = value` lines, where a value is a boolean, an integer, a
+/// double-quoted string, or an array of those on one line. Keys before
+/// the first header belong to the table named "".
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    tables: Vec<(String, Vec<(String, Value)>)>,
+}
+
+impl Config {
+    /// The `lox.toml` in `dir` or the nearest directory above it.
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors().map(|dir| dir.join(FILE_NAME)).find(|path| path.is_file())
+    }
+
+    /// Reads and parses the file at `path`, giving errors as
+    /// `lox.toml:3: message`.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Config::parse(&text).map_err(|err| format!("{}:{}", path.display(), err))
+    }
+
+    /// Parses configuration text, giving errors as `3: message`.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config { tables: vec![(String::new(), vec![])] };
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: &str| format!("{}: {}", number + 1, message);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let name = header.strip_suffix(']').ok_or_else(|| error("Expected ']' after table name."))?;
+                config.tables.push((name.trim().to_string(), vec![]));
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| error("Expected '=' after key."))?;
+            let key = key.trim().trim_matches('"');
+            if key.is_empty() {
+                return Err(error("Expected a key."));
+            }
+            let value = parse_value(value.trim()).ok_or_else(|| error("Expected a boolean, integer, string or array."))?;
+            if let Some((_, entries)) = config.tables.last_mut() {
+                entries.push((key.to_string(), value));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The keys and values of the table `name`, in the order written.
+    pub fn table<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a (String, Value)> {
+        self.tables.iter().filter(move |(table, _)| table == name).flat_map(|(_, entries)| entries)
+    }
+
+    /// Sets `interpreter` up as the file says, resolving import paths
+    /// against `dir`, the directory the file is in. The top-level keys are
+    /// `warnings` ("off", "on" or "strict"), `check`, `string-coercion`,
+    /// `max-loop-iterations`, `import-paths` and `allow`, each like the
+    /// command-line option of the same name; `[lint]` turns rules on and
+    /// off, and `[format]` sets `indent` and `max-width` for `rlox fmt`.
+    pub fn apply(&self, interpreter: &mut Interpreter, dir: &Path) -> Result<(), String> {
+        if let Some((table, _)) = self.tables.iter().find(|(table, _)| !["", "lint", "format"].contains(&table.as_str())) {
+            return Err(format!("Unknown table '{}'.", table));
+        }
+        for (key, value) in self.table("") {
+            match (key.as_str(), value) {
+                ("warnings", Value::String(level)) => {
+                    let level = Warnings::from_name(level).ok_or_else(|| format!("Unknown warning level '{}'.", level))?;
+                    interpreter.set_warnings(level);
+                }
+                ("check", Value::Bool(check)) => interpreter.set_check(*check),
+                ("string-coercion", Value::Bool(coercion)) => interpreter.set_string_coercion(*coercion),
+                ("max-loop-iterations", Value::Int(max @ 0..)) => interpreter.set_max_loop_iterations(Some(*max as u64)),
+                ("import-paths", Value::Array(paths)) => {
+                    for path in strings(key, paths)? {
+                        interpreter.add_import_path(&dir.join(path).to_string_lossy());
+                    }
+                }
+                ("allow", Value::Array(names)) => {
+                    let allowed = strings(key, names)?
+                        .into_iter()
+                        .map(|name| Capability::from_name(name).ok_or_else(|| format!("Unknown capability '{}'.", name)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    interpreter.restrict(&allowed);
+                }
+                _ => return Err(format!("Unknown key '{}', or a value of the wrong type for it.", key)),
+            }
+        }
+        for (rule, enabled) in self.lint_rules()? {
+            interpreter.set_rule(rule, enabled);
+        }
+        let mut options = FormatOptions::default();
+        for (key, value) in self.table("format") {
+            match (key.as_str(), value) {
+                ("indent", Value::Int(indent @ 0..=16)) => options.indent = *indent as usize,
+                ("max-width", Value::Int(width @ 1..)) => options.max_width = *width as usize,
+                _ => return Err(format!("Unknown key '{}', or a value out of range for it.", key)),
+            }
+        }
+        interpreter.set_format_options(options);
+        Ok(())
+    }
+
+    /// The warning rules the `[lint]` table turns on or off, as in
+    /// `shadowing = false`.
+    pub fn lint_rules(&self) -> Result<Vec<(&'static str, bool)>, String> {
+        self.table("lint")
+            .map(|(name, value)| {
+                let rule = warning::rule(name).ok_or_else(|| format!("Unknown lint rule '{}'.", name))?;
+                match value {
+                    Value::Bool(enabled) => Ok((rule, *enabled)),
+                    _ => Err(format!("Expected true or false for lint rule '{}'.", name)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The strings in the array for `key`.
+fn strings<'a>(key: &str, values: &'a [Value]) -> Result<Vec<&'a str>, String> {
+    values
+        .iter()
+        .map(|value| match value {
+            Value::String(string) => Ok(string.as_str()),
+            _ => Err(format!("Expected strings in '{}'.", key)),
+        })
+        .collect()
+}
+
+/// Drops a `#` comment, leaving any `#` inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Option<Value> {
+    match text {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => (),
+    }
+    if let Some(items) = text.strip_prefix('[') {
+        let items = items.strip_suffix(']')?.trim();
+        return split_items(items)?.into_iter().map(parse_value).collect::<Option<_>>().map(Value::Array);
+    }
+    if let Some(string) = text.strip_prefix('"') {
+        return unescape(string.strip_suffix('"')?).map(Value::String);
+    }
+    text.replace('_', "").parse().ok().map(Value::Int)
+}
+
+/// The comma-separated items of an array, allowing a trailing comma.
+fn split_items(text: &str) -> Option<Vec<&str>> {
+    let mut items = vec![];
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    let last = text[start..].trim();
+    if !last.is_empty() {
+        items.push(last);
+    }
+    if in_string || items.iter().any(|item| item.is_empty()) {
+        return None;
+    }
+    Some(items)
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut string = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            return None;
+        }
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => string.push('\n'),
+            't' => string.push('\t'),
+            '"' => string.push('"'),
+            '\\' => string.push('\\'),
+            _ => return None,
+        }
+    }
+    Some(string)
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::expr::Expr;
+use crate::stmt::Stmt;
+
+/// Execution counts for each line that starts a statement, reported by
+/// `--coverage`. Lines are taken from [`Stmt::line`], so a statement whose
+/// tokens carry no line (like `print 1;`) isn't counted either way.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    lines: BTreeMap<u32, u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks every statement in `stmts` as executable, including ones in
+    /// function bodies and lambdas, so lines never run show up as misses.
+    pub fn add_program(&mut self, stmts: &[Stmt]) {
+        stmts.iter().for_each(|stmt| self.add_stmt(stmt));
+    }
+
+    pub fn hit(&mut self, line: u32) {
+        *self.lines.entry(line).or_insert(0) += 1;
+    }
+
+    pub fn uncovered(&self) -> impl Iterator<Item = u32> + '_ {
+        self.lines
+            .iter()
+            .filter(|(_, &count)| count == 0)
+            .map(|(&line, _)| line)
+    }
+
+    /// One line saying how many executable lines ran, and one listing the
+    /// ones that didn't.
+    pub fn summary(&self) -> String {
+        let total = self.lines.len();
+        let uncovered: Vec<String> = self.uncovered().map(|line| line.to_string()).collect();
+        let covered = total - uncovered.len();
+        let percent = if total == 0 { 100.0 } else { covered as f64 * 100.0 / total as f64 };
+        let mut summary = format!("coverage: {}/{} lines ({:.1}%)", covered, total, percent);
+        if !uncovered.is_empty() {
+            summary.push_str(&format!("\ncoverage: uncovered lines {}", uncovered.join(", ")));
+        }
+        summary
+    }
+
+    /// An lcov tracefile record for the script at `path`.
+    pub fn lcov(&self, path: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", path);
+        for (line, count) in &self.lines {
+            let _ = writeln!(out, "DA:{},{}", line, count);
+        }
+        let hit = self.lines.values().filter(|&&count| count > 0).count();
+        let _ = write!(out, "LF:{}\nLH:{}\nend_of_record\n", self.lines.len(), hit);
+        out
+    }
+
+    fn add_line(&mut self, line: Option<u32>) {
+        if let Some(line) = line {
+            self.lines.entry(line).or_insert(0);
+        }
+    }
+
+    fn add_stmt(&mut self, stmt: &Stmt) {
+        self.add_line(stmt.line());
+        match stmt {
+            Stmt::Block(stmts) | Stmt::Function(_, _, _, stmts, ..) => self.add_program(stmts),
+            Stmt::Expression(expr) => self.add_expr(expr),
+            Stmt::Print(values) => values.iter().for_each(|value| self.add_expr(value)),
+            Stmt::Return(_, value) => value.iter().for_each(|expr| self.add_expr(expr)),
+            Stmt::Var(_, _, initializer) => initializer.iter().for_each(|expr| self.add_expr(expr)),
+            Stmt::If(arms, else_branch) => {
+                for (_, condition, branch) in arms.iter() {
+                    self.add_expr(condition);
+                    self.add_stmt(branch);
+                }
+                if let Some(else_branch) = else_branch {
+                    self.add_stmt(else_branch);
+                }
+            }
+            Stmt::While(_, condition, body) => {
+                self.add_expr(condition);
+                self.add_stmt(body);
+            }
+            Stmt::For(keyword, initializer, condition, increment, body) => {
+                self.add_stmt(&Stmt::lower_for(keyword, initializer, condition, increment, body))
+            }
+            Stmt::Labeled(_, body) => self.add_stmt(body),
+            Stmt::ForIn(_, initializer, body) | Stmt::With(_, _, initializer, body) => {
+                self.add_expr(initializer);
+                self.add_stmt(body);
+            }
+            Stmt::Export(declaration) => self.add_stmt(declaration),
+            Stmt::Break(..) | Stmt::Continue(..) | Stmt::Enum(..) | Stmt::Import(..) => (),
+        }
+    }
+
+    /// Finds lambdas, whose bodies hold statements of their own.
+    fn add_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Lambda(_, _, body, _) => self.add_program(body),
+            Expr::Logical(left, _, right)
+            | Expr::Binary(left, _, right)
+            | Expr::Index(left, _, right)
+            | Expr::Set(left, _, right) => {
+                self.add_expr(left);
+                self.add_expr(right);
+            }
+            Expr::Unary(_, operand)
+            | Expr::Grouping(operand)
+            | Expr::Assign(_, _, operand)
+            | Expr::Get(operand, _) => {
+                self.add_expr(operand)
+            }
+            Expr::Call(callee, _, arguments, named) => {
+                self.add_expr(callee);
+                arguments.iter().for_each(|argument| self.add_expr(argument));
+                named.iter().for_each(|(_, value)| self.add_expr(value));
+            }
+            Expr::Record(_, fields) => fields.iter().for_each(|(_, value)| self.add_expr(value)),
+            Expr::Literal(_) | Expr::Variable(..) | Expr::Empty => (),
+        }
+    }
+}
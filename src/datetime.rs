@@ -0,0 +1,167 @@
+/// A moment broken into calendar fields, in UTC. There's no time zone
+/// database without a dependency, so everything here is UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millisecond: u32,
+}
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+impl DateTime {
+    /// The moment `millis` milliseconds after the Unix epoch.
+    pub fn from_millis(millis: f64) -> DateTime {
+        let millis = millis.floor() as i64;
+        let (year, month, day) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+        let time = millis.rem_euclid(MILLIS_PER_DAY);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (time / 3_600_000) as u32,
+            minute: (time / 60_000 % 60) as u32,
+            second: (time / 1000 % 60) as u32,
+            millisecond: (time % 1000) as u32,
+        }
+    }
+
+    /// Milliseconds since the Unix epoch.
+    pub fn to_millis(&self) -> f64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let time = ((self.hour as i64 * 60 + self.minute as i64) * 60 + self.second as i64) * 1000;
+        (days * MILLIS_PER_DAY + time + self.millisecond as i64) as f64
+    }
+
+    /// Writes the fields out by a `strftime`-like format: `%Y` for the
+    /// year, `%m`, `%d`, `%H`, `%M` and `%S` for two-digit fields, and
+    /// `%%` for a percent sign.
+    pub fn format(&self, format: &str) -> Result<String, String> {
+        let mut out = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => out.push_str(&format!("{:04}", self.year)),
+                Some('%') => out.push('%'),
+                Some(directive) => match self.field(directive) {
+                    Some(value) => out.push_str(&format!("{:02}", value)),
+                    None => return Err(format!("Unknown time format '%{}'.", directive)),
+                },
+                None => return Err("Time format ends with '%'.".to_string()),
+            }
+        }
+        Ok(out)
+    }
+
+    /// Reads `text` written in `format`, as [`DateTime::format`] writes
+    /// it. Fields the format leaves out are taken from 1970-01-01 00:00:00.
+    pub fn parse(text: &str, format: &str) -> Result<DateTime, String> {
+        let mut date = DateTime::from_millis(0.0);
+        let mut rest = text;
+        let mut chars = format.chars();
+        let mismatch = || format!("'{}' doesn't match the time format '{}'.", text, format);
+        while let Some(c) = chars.next() {
+            let expected = match c {
+                '%' => chars.next().ok_or_else(|| "Time format ends with '%'.".to_string())?,
+                c => {
+                    rest = rest.strip_prefix(c).ok_or_else(mismatch)?;
+                    continue;
+                }
+            };
+            if expected == '%' {
+                rest = rest.strip_prefix('%').ok_or_else(mismatch)?;
+                continue;
+            }
+            let width = match expected {
+                'Y' => 4,
+                directive if date.field(directive).is_some() => 2,
+                directive => return Err(format!("Unknown time format '%{}'.", directive)),
+            };
+            let digits = rest.chars().take(width).take_while(char::is_ascii_digit).count();
+            if digits == 0 {
+                return Err(mismatch());
+            }
+            let value: u32 = rest[..digits].parse().map_err(|_| mismatch())?;
+            rest = &rest[digits..];
+            match expected {
+                'Y' => date.year = value as i64,
+                'm' => date.month = value,
+                'd' => date.day = value,
+                'H' => date.hour = value,
+                'M' => date.minute = value,
+                _ => date.second = value,
+            }
+        }
+        if !rest.is_empty() {
+            return Err(mismatch());
+        }
+        let valid = (1..=12).contains(&date.month)
+            && (1..=days_in_month(date.year, date.month)).contains(&date.day)
+            && date.hour < 24
+            && date.minute < 60
+            && date.second < 60;
+        if !valid {
+            return Err(format!("'{}' isn't a valid time.", text));
+        }
+        Ok(date)
+    }
+
+    fn field(&self, directive: char) -> Option<u32> {
+        match directive {
+            'm' => Some(self.month),
+            'd' => Some(self.day),
+            'H' => Some(self.hour),
+            'M' => Some(self.minute),
+            'S' => Some(self.second),
+            _ => None,
+        }
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// The conversions between days since the epoch and the proleptic
+// Gregorian calendar follow Howard Hinnant's `chrono`-compatible
+// algorithms, counting in 400-year eras.
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
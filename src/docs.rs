@@ -0,0 +1,206 @@
+use std::rc::Rc;
+
+use crate::formatter::signature_source;
+use crate::highlighter::escape_html;
+use crate::native_function::NativeFunction;
+use crate::stmt::Stmt;
+use crate::token::Literal;
+
+/// How `rlox doc` writes its pages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DocFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+impl DocFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            DocFormat::Markdown => "md",
+            DocFormat::Html => "html",
+        }
+    }
+}
+
+/// A function a module documents: its name, how it's declared, and its
+/// doc comment.
+struct Entry {
+    name: String,
+    signature: String,
+    doc: Option<Rc<str>>,
+}
+
+/// The top-level functions of a module, or only the exported ones if it
+/// exports any, as those are its API.
+fn entries(stmts: &[Stmt]) -> Vec<Entry> {
+    let exports = stmts.iter().any(|stmt| matches!(stmt, Stmt::Export(_)));
+    stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Export(declaration) => Some(declaration.as_ref()),
+            stmt if !exports => Some(stmt),
+            _ => None,
+        })
+        .filter_map(|stmt| match stmt {
+            Stmt::Function(name, _, params, _, signature, doc) => Some(Entry {
+                name: name.lexeme.to_string(),
+                signature: format!("fun {}{}", name.lexeme, signature_source(params, signature.as_deref())),
+                doc: doc.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The API page for the module `title`, parsed into `stmts`.
+pub fn page(title: &str, stmts: &[Stmt], format: DocFormat) -> String {
+    let entries = entries(stmts);
+    match format {
+        DocFormat::Markdown => {
+            let mut out = format!("# {}\n", title);
+            for entry in entries {
+                out.push_str(&format!("\n## {}\n\n```lox\n{}\n```\n", entry.name, entry.signature));
+                if let Some(doc) = entry.doc {
+                    out.push_str(&format!("\n{}\n", doc));
+                }
+            }
+            out
+        }
+        DocFormat::Html => {
+            let mut body = format!("<h1>{}</h1>\n", escape_html(title));
+            for entry in entries {
+                body.push_str(&format!(
+                    "<h2 id=\"{0}\">{0}</h2>\n<pre><code>{1}</code></pre>\n",
+                    escape_html(&entry.name),
+                    escape_html(&entry.signature)
+                ));
+                for paragraph in entry.doc.iter().flat_map(|doc| doc.split("\n\n")) {
+                    body.push_str(&format!("<p>{}</p>\n", escape_html(paragraph.trim())));
+                }
+            }
+            html_document(title, &body)
+        }
+    }
+}
+
+/// The page linking to each module's page, given as its title and the
+/// path of its page relative to the index.
+pub fn index(modules: &[(String, String)], format: DocFormat) -> String {
+    match format {
+        DocFormat::Markdown => {
+            let links: String = modules.iter().map(|(title, link)| format!("- [{}]({})\n", title, link)).collect();
+            format!("# API\n\n{}", links)
+        }
+        DocFormat::Html => {
+            let links: String = modules
+                .iter()
+                .map(|(title, link)| format!("<li><a href=\"{}\">{}</a></li>\n", escape_html(link), escape_html(title)))
+                .collect();
+            html_document("API", &format!("<h1>API</h1>\n<ul>\n{}</ul>\n", links))
+        }
+    }
+}
+
+fn html_document(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(title),
+        body
+    )
+}
+
+/// How a function is called, as `help()` shows it: `fun add(a, b)` for a
+/// Lox function, and `name(a, b)` for a native, whose parameters have no
+/// names so are lettered, with `...` where it takes any number more.
+pub fn signature(value: &Literal) -> Option<String> {
+    match value {
+        Literal::LoxFunction(function) => {
+            let params: Vec<&str> = function.params().iter().map(|param| param.lexeme.as_str()).collect();
+            Some(format!("fun {}({})", function.name, params.join(", ")))
+        }
+        Literal::NativeFunction(native) => Some(native_signatures(&native.name, native)),
+        Literal::Bound(bound) => signature(&bound.function),
+        _ => None,
+    }
+}
+
+fn native_signature(name: &str, native: &NativeFunction) -> String {
+    let mut params: Vec<String> = (0..native.arity).map(|i| char::from(b'a' + i % 26).to_string()).collect();
+    if native.variadic {
+        params.push("...".to_string());
+    }
+    format!("{}({})", name, params.join(", "))
+}
+
+/// A native's signature for each number of arguments it takes, a line
+/// each.
+fn native_signatures(name: &str, native: &NativeFunction) -> String {
+    let mut arities: Vec<&NativeFunction> = native.arities().collect();
+    arities.sort_by_key(|native| native.arity);
+    arities.iter().map(|arity| native_signature(name, arity)).collect::<Vec<_>>().join("\n")
+}
+
+/// What `help(value)` prints: a function's signature and doc comment, or
+/// the signature of each function in a module like `math`. `None` for
+/// values that are neither.
+pub fn help(value: &Literal) -> Option<String> {
+    match value {
+        Literal::LoxFunction(function) => {
+            let doc = function.doc.as_deref().unwrap_or("No documentation.");
+            Some(format!("{}\n{}", signature(value)?, indent(doc)))
+        }
+        Literal::NativeFunction(native) => {
+            let takes = match (native.arity, native.variadic, native.arities().count() == 1) {
+                (1, false, true) => "1 argument".to_string(),
+                (1, true, true) => "at least 1 argument".to_string(),
+                _ => format!("{} arguments", native.describe_arity()),
+            };
+            Some(format!("{}\n  Native function taking {}.", signature(value)?, takes))
+        }
+        Literal::Bound(bound) => {
+            let bound_args = match bound.args.len() {
+                1 => "1 argument".to_string(),
+                count => format!("{} arguments", count),
+            };
+            let function = help(&bound.function)?;
+            Some(format!("{}\n  With {} bound.", function, bound_args))
+        }
+        Literal::Map(members) => {
+            let functions: Vec<String> = members
+                .iter()
+                .filter_map(|(name, member)| match member {
+                    Literal::NativeFunction(native) => Some(native_signatures(name, native)),
+                    _ => None,
+                })
+                .collect();
+            (!functions.is_empty()).then(|| functions.join("\n"))
+        }
+        _ => None,
+    }
+}
+
+/// What `help()` prints: the signature of every native function among
+/// `globals`, including those in modules, in order of name.
+pub fn native_index(globals: &[(&str, Literal)]) -> String {
+    let mut lines = vec![];
+    for (name, value) in globals {
+        match value {
+            Literal::NativeFunction(native) => lines.push(native_signatures(name, native)),
+            Literal::Map(members) => {
+                for (member, value) in members.iter() {
+                    if let Literal::NativeFunction(native) = value {
+                        lines.push(native_signatures(&format!("{}.{}", name, member), native));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    lines.sort();
+    lines.join("\n")
+}
+
+fn indent(text: &str) -> String {
+    text.lines().map(|line| format!("  {}", line).trim_end().to_string()).collect::<Vec<_>>().join("\n")
+}
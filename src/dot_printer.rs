@@ -0,0 +1,269 @@
+use std::fmt::Write;
+
+use crate::error::ParserError;
+use crate::expr::Expr;
+use crate::stack::DepthLimit;
+use crate::stmt::Stmt;
+use crate::token::{Literal, Token};
+
+/// Renders a program as a Graphviz digraph, one node per AST node with
+/// edges labelled by the child's role. Pipe the output through `dot -Tsvg`.
+#[derive(Default)]
+pub struct DotPrinter {
+    out: String,
+    next_id: usize,
+    depth: DepthLimit,
+}
+
+impl DotPrinter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The digraph, or an error if the program is nested too deeply to
+    /// print.
+    pub fn print_program(mut self, stmts: &[Stmt]) -> Result<String, ParserError> {
+        self.out.push_str("digraph ast {\n  node [shape=box, fontname=\"monospace\"];\n");
+        let root = self.node("program");
+        for stmt in stmts {
+            let child = self.stmt(stmt);
+            self.edge(root, child, "");
+        }
+        self.out.push_str("}\n");
+        self.depth.finish(self.out)
+    }
+
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        let _ = writeln!(self.out, "  n{} [label=\"{}\"];", id, escape(label));
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: &str) {
+        let _ = if label.is_empty() {
+            writeln!(self.out, "  n{} -> n{};", from, to)
+        } else {
+            writeln!(self.out, "  n{} -> n{} [label=\"{}\"];", from, to, escape(label))
+        };
+    }
+
+    fn child_stmt(&mut self, parent: usize, stmt: &Stmt, label: &str) {
+        let child = self.stmt(stmt);
+        self.edge(parent, child, label);
+    }
+
+    fn child_expr(&mut self, parent: usize, expr: &Expr, label: &str) {
+        let child = self.expr(expr);
+        self.edge(parent, child, label);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> usize {
+        if self.depth.exhausted(None, || stmt.line()) {
+            return self.node("...");
+        }
+        match stmt {
+            Stmt::Block(stmts) => {
+                let id = self.node("block");
+                for stmt in stmts.iter() {
+                    self.child_stmt(id, stmt, "");
+                }
+                id
+            }
+            Stmt::Expression(expr) => {
+                let id = self.node("expression");
+                self.child_expr(id, expr, "");
+                id
+            }
+            Stmt::Function(name, _, params, body, ..) => {
+                let params: Vec<&str> = params.iter().map(|param| &*param.lexeme).collect();
+                let id = self.node(&format!("fun {}({})", name.lexeme, params.join(", ")));
+                for stmt in body.iter() {
+                    self.child_stmt(id, stmt, "");
+                }
+                id
+            }
+            Stmt::Print(values) => {
+                let id = self.node("print");
+                for value in values.iter() {
+                    self.child_expr(id, value, "");
+                }
+                id
+            }
+            Stmt::Return(_, value) => {
+                let id = self.node("return");
+                if let Some(value) = value {
+                    self.child_expr(id, value, "");
+                }
+                id
+            }
+            Stmt::If(arms, else_branch) => {
+                let id = self.node("if");
+                for (i, (_, condition, branch)) in arms.iter().enumerate() {
+                    let suffix = if arms.len() > 1 { format!(" {}", i + 1) } else { String::new() };
+                    self.child_expr(id, condition, &format!("cond{}", suffix));
+                    self.child_stmt(id, branch, &format!("then{}", suffix));
+                }
+                if let Some(else_branch) = else_branch {
+                    self.child_stmt(id, else_branch, "else");
+                }
+                id
+            }
+            Stmt::While(_, condition, body) => {
+                let id = self.node("while");
+                self.child_expr(id, condition, "cond");
+                self.child_stmt(id, body, "body");
+                id
+            }
+            Stmt::For(_, initializer, condition, increment, body) => {
+                let id = self.node("for");
+                if let Some(initializer) = initializer {
+                    self.child_stmt(id, initializer, "init");
+                }
+                if let Some(condition) = condition {
+                    self.child_expr(id, condition, "cond");
+                }
+                if let Some(increment) = increment {
+                    self.child_expr(id, increment, "incr");
+                }
+                self.child_stmt(id, body, "body");
+                id
+            }
+            Stmt::Var(name, _, initializer) => {
+                let id = self.node(&format!("var {}", name.lexeme));
+                if let Some(initializer) = initializer {
+                    self.child_expr(id, initializer, "init");
+                }
+                id
+            }
+            Stmt::Labeled(label, body) => {
+                let id = self.node(&format!("{}:", label.lexeme));
+                self.child_stmt(id, body, "");
+                id
+            }
+            Stmt::Break(_, label) => self.node(&jump_label("break", label)),
+            Stmt::Continue(_, label) => self.node(&jump_label("continue", label)),
+            Stmt::ForIn(name, iterable, body) => {
+                let id = self.node(&format!("for {} in", name.lexeme));
+                self.child_expr(id, iterable, "iterable");
+                self.child_stmt(id, body, "body");
+                id
+            }
+            Stmt::With(_, name, initializer, body) => {
+                let id = self.node(&format!("with {}", name.lexeme));
+                self.child_expr(id, initializer, "init");
+                self.child_stmt(id, body, "body");
+                id
+            }
+            Stmt::Enum(name, cases) => {
+                let id = self.node(&format!("enum {}", name.lexeme));
+                for case in cases.iter() {
+                    let child = self.node(&case.lexeme);
+                    self.edge(id, child, "");
+                }
+                id
+            }
+            Stmt::Import(_, names, path) => {
+                let id = self.node(&format!("import from \"{}\"", path));
+                for name in names.iter() {
+                    let child = self.node(&name.lexeme);
+                    self.edge(id, child, "");
+                }
+                id
+            }
+            Stmt::Export(declaration) => {
+                let id = self.node("export");
+                self.child_stmt(id, declaration, "");
+                id
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> usize {
+        if self.depth.exhausted(expr.token(), || expr.line()) {
+            return self.node("...");
+        }
+        match expr {
+            Expr::Literal(Literal::String(s)) => self.node(&format!("{:?}", s)),
+            Expr::Literal(literal) => self.node(&literal.to_string()),
+            Expr::Logical(left, operator, right) | Expr::Binary(left, operator, right) => {
+                let id = self.node(&operator.lexeme);
+                self.child_expr(id, left, "left");
+                self.child_expr(id, right, "right");
+                id
+            }
+            Expr::Unary(operator, right) => {
+                let id = self.node(&operator.lexeme);
+                self.child_expr(id, right, "");
+                id
+            }
+            Expr::Assign(_, name, value) => {
+                let id = self.node(&format!("{} =", name.lexeme));
+                self.child_expr(id, value, "");
+                id
+            }
+            Expr::Lambda(_, params, body, _) => {
+                let params: Vec<&str> = params.iter().map(|param| &*param.lexeme).collect();
+                let id = self.node(&format!("fun({})", params.join(", ")));
+                for stmt in body.iter() {
+                    self.child_stmt(id, stmt, "");
+                }
+                id
+            }
+            Expr::Call(callee, _, arguments, named) => {
+                let id = self.node("call");
+                self.child_expr(id, callee, "callee");
+                for (i, argument) in arguments.iter().enumerate() {
+                    self.child_expr(id, argument, &format!("arg {}", i));
+                }
+                for (name, value) in named.iter() {
+                    self.child_expr(id, value, &name.lexeme);
+                }
+                id
+            }
+            Expr::Get(object, name) => {
+                let id = self.node(&format!(".{}", name.lexeme));
+                self.child_expr(id, object, "");
+                id
+            }
+            Expr::Set(object, name, value) => {
+                let id = self.node(&format!(".{} =", name.lexeme));
+                self.child_expr(id, object, "object");
+                self.child_expr(id, value, "value");
+                id
+            }
+            Expr::Index(object, _, index) => {
+                let id = self.node("[]");
+                self.child_expr(id, object, "");
+                self.child_expr(id, index, "index");
+                id
+            }
+            Expr::Record(_, fields) => {
+                let id = self.node("record");
+                for (name, value) in fields.iter() {
+                    self.child_expr(id, value, &name.lexeme);
+                }
+                id
+            }
+            Expr::Grouping(expr) => {
+                let id = self.node("group");
+                self.child_expr(id, expr, "");
+                id
+            }
+            Expr::Variable(_, name) => self.node(&name.lexeme),
+            Expr::Empty => self.node("empty"),
+        }
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// `break` or `continue`, with the label it names if there is one.
+fn jump_label(keyword: &str, label: &Option<Token>) -> String {
+    match label {
+        Some(label) => format!("{} {}", keyword, label.lexeme),
+        None => keyword.to_string(),
+    }
+}
@@ -1,12 +1,40 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use crate::error::{RuntimeError, RuntimeException};
+use crate::symbol::Symbol;
 use crate::token::{Literal, Token};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Where a variable's value lives. A variable a closure captures moves
+/// into a cell the closure shares, so assignments on either side are seen
+/// by both.
+#[derive(Clone, Debug)]
+pub enum Slot {
+    Value(Literal),
+    Captured(Rc<RefCell<Literal>>),
+}
+
+impl Slot {
+    fn get(&self) -> Literal {
+        match self {
+            Slot::Value(value) => value.clone(),
+            Slot::Captured(cell) => cell.borrow().clone(),
+        }
+    }
+
+    fn set(&mut self, value: Literal) {
+        match self {
+            Slot::Value(slot) => *slot = value,
+            Slot::Captured(cell) => *cell.borrow_mut() = value,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Environment {
-    pub enclosing: Option<Box<Environment>>,
-    values: HashMap<String, Literal>,
+    pub enclosing: Option<Rc<RefCell<Environment>>>,
+    values: HashMap<Symbol, Slot>,
 }
 
 impl Default for Environment {
@@ -23,111 +51,164 @@ impl Environment {
         }
     }
 
-    pub fn with_enclosing(enclosing: Environment) -> Self {
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
         Self {
-            enclosing: Some(Box::new(enclosing)),
+            enclosing: Some(enclosing),
             values: HashMap::new(),
         }
     }
 
-    pub fn wrap(env: Environment, enclosing: Environment, depth: u32) -> (Self, u32) {
-        match env.enclosing {
-            None => (
-                Self {
-                    enclosing: Some(Box::new(enclosing)),
-                    ..env.clone()
-                },
-                depth,
-            ),
-            Some(ref enc) => {
-                let (e, d) = Environment::wrap(*enc.clone(), enclosing, depth + 1);
-                return (
-                    Self {
-                        enclosing: Some(Box::new(e)),
-                        ..env.clone()
-                    },
-                    d,
-                );
-            }
+    /// The variables defined directly in this environment.
+    pub fn values(&self) -> impl Iterator<Item = (Symbol, Literal)> + '_ {
+        self.values.iter().map(|(name, slot)| (*name, slot.get()))
+    }
+
+    /// Where each variable defined directly in this environment lives.
+    pub fn slots(&self) -> impl Iterator<Item = (Symbol, &Slot)> {
+        self.values.iter().map(|(name, slot)| (*name, slot))
+    }
+
+    /// A new environment in the same place holding the current value of
+    /// each variable here, in slots of its own, so closures that captured
+    /// the variables here keep these ones.
+    pub fn copy_values(&self) -> Environment {
+        Self {
+            enclosing: self.enclosing.clone(),
+            values: self.values.iter().map(|(name, slot)| (*name, Slot::Value(slot.get()))).collect(),
         }
     }
 
-    pub fn unwrap(env: Environment, mut depth: u32) -> Self {
-        let mut env = env.clone();
-        let mut r = &mut env;
+    /// Whether a closure shares any of the variables here.
+    pub fn has_captures(&self) -> bool {
+        self.values.values().any(|slot| matches!(slot, Slot::Captured(_)))
+    }
 
-        while depth > 0 {
-            match r.enclosing {
-                None => panic!(),
-                Some(ref mut enc) => {
-                    r = enc;
-                }
+    pub fn remove(&mut self, name: Symbol) {
+        self.values.remove(&name);
+    }
+
+    /// Defines `name`, or sets it if it's already defined here, so that a
+    /// closure that captured it sees the value.
+    pub fn define(&mut self, name: Symbol, value: Literal) {
+        match self.values.get_mut(&name) {
+            Some(slot) => slot.set(value),
+            None => {
+                self.values.insert(name, Slot::Value(value));
             }
-            depth -= 1;
         }
+    }
 
-        r.enclosing = None;
-        env
+    /// Adds a variable a closure captured, sharing its cell.
+    pub fn define_captured(&mut self, name: Symbol, cell: Rc<RefCell<Literal>>) {
+        self.values.insert(name, Slot::Captured(cell));
     }
 
-    pub fn define(&mut self, name: String, value: Literal) {
-        self.values.insert(name, value);
+    /// The cell `name` lives in `distance` environments up, moving it into
+    /// one if it isn't in one yet. A variable that isn't defined yet, like
+    /// a local a function reads before it's declared, starts out `nil`.
+    pub fn capture_at(&mut self, distance: u32, name: Symbol) -> Result<Rc<RefCell<Literal>>, RuntimeException> {
+        if distance == 0 {
+            return Ok(self.capture(name));
+        }
+        match self.ancestor(distance) {
+            Some(environment) => Ok(environment.borrow_mut().capture(name)),
+            None => {
+                let message = format!("Could not find {} at expected depth.", name);
+                let token = Token {
+                    lexeme: name,
+                    ..Token::default()
+                };
+                Err(RuntimeException::base("E0030", token, message))
+            }
+        }
     }
 
-    pub fn assign(&mut self, name: Token, value: Literal) -> Result<(), RuntimeException> {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme, value);
+    fn capture(&mut self, name: Symbol) -> Rc<RefCell<Literal>> {
+        let slot = self.values.entry(name).or_insert(Slot::Value(Literal::Nil));
+        if let Slot::Value(value) = slot {
+            *slot = Slot::Captured(Rc::new(RefCell::new(std::mem::replace(value, Literal::Nil))));
+        }
+        match slot {
+            Slot::Captured(cell) => cell.clone(),
+            Slot::Value(_) => unreachable!(),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Literal) -> Result<(), RuntimeException> {
+        if let Some(slot) = self.values.get_mut(&name.lexeme) {
+            slot.set(value);
             return Ok(());
         }
 
-        match &mut self.enclosing {
-            Some(enclosing) => enclosing.assign(name, value),
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
             None => {
                 let message = format!("Undefined variable {}.", name.lexeme);
-                Err(RuntimeException::Base(RuntimeError::new(name, message)))
+                Err(RuntimeException::Base(RuntimeError::new("E0001", name.clone(), message)))
             }
         }
     }
 
-    pub fn assign_at(&mut self, distance: u32, name: Token, value: Literal) -> Result<(), RuntimeException> {
-        self.ancestor(distance).values.insert(name.lexeme, value);
-        Ok(())
+    pub fn assign_at(&mut self, distance: u32, name: &Token, value: Literal) -> Result<(), RuntimeException> {
+        if distance == 0 {
+            self.define(name.lexeme, value);
+            return Ok(());
+        }
+        match self.ancestor(distance) {
+            Some(environment) => {
+                environment.borrow_mut().define(name.lexeme, value);
+                Ok(())
+            }
+            None => {
+                let message = format!("Could not find {} at expected depth.", name.lexeme);
+                Err(RuntimeException::base("E0030", name.clone(), message))
+            }
+        }
     }
 
-    pub fn get(&self, name: Token) -> Result<Literal, RuntimeException> {
+    pub fn get(&self, name: &Token) -> Result<Literal, RuntimeException> {
         match self.values.get(&name.lexeme) {
-            Some(v) => Ok(v.clone()),
+            Some(slot) => Ok(slot.get()),
             None => match &self.enclosing {
-                Some(env) => (*env).get(name),
+                Some(env) => env.borrow().get(name),
                 _ => {
                     let message = format!("Undefined variable {}.", name.lexeme);
-                    Err(RuntimeException::base(name, message))
+                    Err(RuntimeException::base("E0001", name.clone(), message))
                 }
             },
         }
     }
 
-    pub fn get_at(&self, distance: u32, name: String) -> Result<Literal, RuntimeException> {
-        match self.ancestor(distance).values.get(&name) {
-            Some(v) => Ok(v.clone()),
+    pub fn get_at(&self, distance: u32, name: Symbol) -> Result<Literal, RuntimeException> {
+        let value = if distance == 0 {
+            self.values.get(&name).map(Slot::get)
+        } else {
+            self.ancestor(distance)
+                .and_then(|environment| environment.borrow().values.get(&name).map(Slot::get))
+        };
+
+        match value {
+            Some(v) => Ok(v),
             None => {
                 let message = format!("Could not find {} at expected depth.", name);
-                Err(RuntimeException::base(Token::from_string(name), message))
+                let token = Token {
+                    lexeme: name,
+                    ..Token::default()
+                };
+                Err(RuntimeException::base("E0030", token, message))
             }
         }
     }
 
-    fn ancestor(&self, mut distance: u32) -> Environment {
-        let mut environment = self;
-        loop {
-            if distance == 0 {
-                return environment.clone();
-            }
-            environment = &*environment
-                .enclosing
-                .as_ref()
-                .expect("Expected an enclosing environment.");
-            distance -= 1;
+    /// Returns the environment `distance` hops up the chain, if the chain
+    /// is that long. Callers handle `distance == 0` themselves since `self`
+    /// isn't reachable as an `Rc`.
+    fn ancestor(&self, distance: u32) -> Option<Rc<RefCell<Environment>>> {
+        let mut environment = self.enclosing.clone()?;
+        for _ in 1..distance {
+            let enclosing = environment.borrow().enclosing.clone()?;
+            environment = enclosing;
         }
+        Some(environment)
     }
 }
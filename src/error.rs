@@ -1,18 +1,202 @@
 use std::error::Error;
 use std::fmt;
 
+use crate::error_codes::{self, ErrorCode};
+use crate::source_map::{SourceId, SourceMap};
+use crate::symbol::Symbol;
 use crate::token::Token;
+use crate::token::TokenType;
 use crate::token::Literal;
 
+/// The stage of running a program that an error comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Scan,
+    Parse,
+    Resolve,
+    /// The `--check` pass.
+    Type,
+    Compile,
+    Runtime,
+}
+
+impl Phase {
+    /// The phase in lowercase, as diagnostics in JSON give it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Scan => "scan",
+            Phase::Parse => "parse",
+            Phase::Resolve => "resolve",
+            Phase::Type => "type",
+            Phase::Compile => "compile",
+            Phase::Runtime => "runtime",
+        }
+    }
+}
+
+/// Where an error is in the source. Lines count from 1; columns are byte
+/// offsets from 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub source: SourceId,
+    pub line: u32,
+    pub column: u32,
+    pub length: u32,
+}
+
+impl From<&Token> for Span {
+    fn from(token: &Token) -> Self {
+        Self {
+            source: token.source,
+            line: token.line,
+            column: token.column,
+            length: token.lexeme.len().max(1) as u32,
+        }
+    }
+}
+
+/// Any error a program can fail with, whichever phase found it, with the
+/// code from [`ERROR_CODES`](crate::error_codes::ERROR_CODES) it was made
+/// with. Scan errors also cover a malformed `--from-ast` file, which
+/// stands in for the source text. Messages are boxed to keep results
+/// that carry an error small.
+#[derive(Debug, Clone)]
+pub enum LoxError {
+    Scan(&'static ErrorCode, Span, Box<str>),
+    Parse(&'static ErrorCode, Token, Box<str>),
+    Resolve(&'static ErrorCode, Token, Box<str>),
+    Type(&'static ErrorCode, Token, Box<str>),
+    Compile(&'static ErrorCode, Token, Box<str>),
+    Runtime(&'static ErrorCode, Token, Box<str>),
+}
+
+impl LoxError {
+    pub fn phase(&self) -> Phase {
+        match self {
+            LoxError::Scan(..) => Phase::Scan,
+            LoxError::Parse(..) => Phase::Parse,
+            LoxError::Resolve(..) => Phase::Resolve,
+            LoxError::Type(..) => Phase::Type,
+            LoxError::Compile(..) => Phase::Compile,
+            LoxError::Runtime(..) => Phase::Runtime,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            LoxError::Scan(_, _, message)
+            | LoxError::Parse(_, _, message)
+            | LoxError::Resolve(_, _, message)
+            | LoxError::Type(_, _, message)
+            | LoxError::Compile(_, _, message)
+            | LoxError::Runtime(_, _, message) => message,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            LoxError::Scan(_, span, _) => *span,
+            LoxError::Parse(_, token, _)
+            | LoxError::Resolve(_, token, _)
+            | LoxError::Type(_, token, _)
+            | LoxError::Compile(_, token, _)
+            | LoxError::Runtime(_, token, _) => Span::from(token),
+        }
+    }
+
+    /// The stable code `rlox explain` describes the error under.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LoxError::Scan(code, ..)
+            | LoxError::Parse(code, ..)
+            | LoxError::Resolve(code, ..)
+            | LoxError::Type(code, ..)
+            | LoxError::Compile(code, ..)
+            | LoxError::Runtime(code, ..) => code.code,
+        }
+    }
+
+    /// Whether the program was rejected before it started running.
+    pub fn is_static(&self) -> bool {
+        self.phase() != Phase::Runtime
+    }
+
+    /// The error as reported on stderr, with lines in files other than the
+    /// main script written `[utils.lox:12]` rather than `[line 12]`. With
+    /// `code`, "Error" is followed by the error's code, as in
+    /// `Error[R0001]`.
+    pub fn render(&self, sources: &SourceMap, code: bool) -> String {
+        let file = sources.name(self.span().source);
+        let mut text = String::new();
+        let _ = self.write(&mut text, file, code);
+        text
+    }
+
+    fn write(&self, f: &mut impl fmt::Write, file: Option<&str>, code: bool) -> fmt::Result {
+        let at = |line: u32| match file {
+            Some(file) => format!("[{}:{}]", file, line),
+            None => format!("[line {}]", line),
+        };
+        let error = match code {
+            true => format!("Error[{}]", self.code()),
+            false => "Error".to_string(),
+        };
+        match self {
+            LoxError::Scan(_, span, message) => write!(f, "{} {}: {}", at(span.line), error, message),
+            LoxError::Parse(_, token, message)
+            | LoxError::Resolve(_, token, message)
+            | LoxError::Type(_, token, message)
+            | LoxError::Compile(_, token, message) => {
+                if token.token_type == TokenType::Eof {
+                    write!(f, "{} {} at end: {}", at(token.line), error, message)
+                } else if token.lexeme.is_empty() {
+                    // A stand-in for a node that keeps no token of its own.
+                    write!(f, "{} {}: {}", at(token.line), error, message)
+                } else {
+                    write!(f, "{} {} at '{}': {}", at(token.line), error, token.lexeme, message)
+                }
+            }
+            LoxError::Runtime(_, token, message) if code => {
+                write!(f, "{}: {}\n{}", error, message, at(token.line))
+            }
+            LoxError::Runtime(_, token, message) => write!(f, "{}\n{}", message, at(token.line)),
+        }
+    }
+}
+
+impl Error for LoxError {}
+
+/// Renders the error the way the interpreter reports it on stderr for the
+/// main script; see [`LoxError::render`] for other files.
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, None, false)
+    }
+}
+
+impl From<ParserError> for LoxError {
+    fn from(error: ParserError) -> Self {
+        LoxError::Parse(error.code, error.token, error.message)
+    }
+}
+
+impl From<RuntimeError> for LoxError {
+    fn from(error: RuntimeError) -> Self {
+        LoxError::Runtime(error.code, error.token, error.message)
+    }
+}
+
 #[derive(Debug)]
 pub struct ParserError {
+    pub code: &'static ErrorCode,
     pub token: Token,
-    pub message: String,
+    pub message: Box<str>,
 }
 
 impl ParserError {
-    pub fn new(token: Token, message: std::string::String) -> Self {
-        Self { token, message }
+    /// An error under the code spelled `code`, like "P0001".
+    pub fn new(code: &str, token: Token, message: std::string::String) -> Self {
+        Self { code: error_codes::get(code), token, message: message.into() }
     }
 }
 
@@ -30,13 +214,15 @@ impl fmt::Display for ParserError {
 
 #[derive(Debug,Clone)]
 pub struct RuntimeError {
+    pub code: &'static ErrorCode,
     pub token: Token,
-    pub message: String
+    pub message: Box<str>
 }
 
 impl RuntimeError {
-    pub fn new(token: Token, message: String) -> Self {
-        Self { token, message }
+    /// An error under the code spelled `code`, like "E0001".
+    pub fn new(code: &str, token: Token, message: String) -> Self {
+        Self { code: error_codes::get(code), token, message: message.into() }
     }
 }
 
@@ -67,12 +253,14 @@ impl Return {
 pub enum RuntimeException {
     Base(RuntimeError),
     Return(Return),
-    Break
+    /// Leaves the innermost loop, or the one with the given label.
+    Break(Option<Symbol>),
+    Continue(Option<Symbol>),
 }
 
 impl RuntimeException {
-    pub fn base(token: Token, message: String) -> Self {
-        let runtime_error = RuntimeError::new(token, message);
+    pub fn base(code: &str, token: Token, message: String) -> Self {
+        let runtime_error = RuntimeError::new(code, token, message);
         RuntimeException::Base(runtime_error)
     }
 
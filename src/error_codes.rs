@@ -0,0 +1,738 @@
+use crate::error::Phase;
+
+/// A kind of error with a code that stays the same from release to
+/// release, for `rlox explain` to describe at more length than the one
+/// line the error itself gets.
+#[derive(Debug)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub phase: Phase,
+    pub title: &'static str,
+    pub explanation: &'static str,
+}
+
+/// Every code, grouped by phase: `L` for scanning, `P` parsing, `R`
+/// resolving, `T` the `--check` pass, `C` compiling for the VM and `E`
+/// running. Every error is made with a code of its own; each phase's
+/// `0000` is left from before they were, for `rlox explain` to still
+/// describe. Codes are only ever added, never renumbered.
+pub const ERROR_CODES: &[ErrorCode] = &[
+    ErrorCode {
+        code: "L0000",
+        phase: Phase::Scan,
+        title: "Invalid input",
+        explanation: "The source couldn't be split into tokens here.",
+    },
+    ErrorCode {
+        code: "L0001",
+        phase: Phase::Scan,
+        title: "Unterminated string",
+        explanation: "A string was opened with '\"' but the file ended before it was closed. \
+Strings may span lines, so the missing quote can be far above where the file ends.
+
+    print \"hello;   // the string runs to the end of the file
+    print \"hello\";  // closed",
+    },
+    ErrorCode {
+        code: "L0002",
+        phase: Phase::Scan,
+        title: "Unexpected character",
+        explanation: "The character isn't part of any token Lox has, like '#' or '@'. \
+Outside strings and comments, Lox only uses ASCII letters, digits, '_' and its operators.
+
+    var total = 1 # 2;   // '#' isn't an operator
+    var total = 1 + 2;",
+    },
+    ErrorCode {
+        code: "L0003",
+        phase: Phase::Scan,
+        title: "Invalid number",
+        explanation: "The digits don't make a number the interpreter can represent.",
+    },
+    ErrorCode {
+        code: "L0004",
+        phase: Phase::Scan,
+        title: "Unreadable AST file",
+        explanation: "The file given with --from-ast, or a program compiled with 'rlox compile', isn't one the \
+interpreter can read back: it was changed by hand, cut short or written by another version. \
+Write it again from the source.",
+    },
+    ErrorCode {
+        code: "P0000",
+        phase: Phase::Parse,
+        title: "Syntax error",
+        explanation: "The tokens don't fit the grammar here. The message says what the parser \
+expected instead.",
+    },
+    ErrorCode {
+        code: "P0001",
+        phase: Phase::Parse,
+        title: "Expected an expression",
+        explanation: "The parser needed a value, like a number, a variable or a call, and found \
+something else. This is often a stray operator or a missing operand.
+
+    var x = 1 + ;   // nothing after '+'
+    var x = 1 + 2;",
+    },
+    ErrorCode {
+        code: "P0002",
+        phase: Phase::Parse,
+        title: "Missing semicolon",
+        explanation: "Statements end with ';'. The error points at the token after where the \
+semicolon belongs, which may be on the next line.
+
+    print \"hi\"    // missing ';'
+    print \"hi\";",
+    },
+    ErrorCode {
+        code: "P0003",
+        phase: Phase::Parse,
+        title: "Unclosed bracket",
+        explanation: "A '(', '[' or '{' wasn't closed where the parser expected it to be. \
+Count the brackets before the error; an extra argument or a missing comma can look the same.
+
+    print max(1, 2;    // missing ')'
+    print max(1, 2);",
+    },
+    ErrorCode {
+        code: "P0004",
+        phase: Phase::Parse,
+        title: "Invalid assignment target",
+        explanation: "Only a variable, or a field of a variable's map or record, can be \
+assigned to.
+
+    1 + x = 3;   // can't assign to a sum
+    x = 3;",
+    },
+    ErrorCode {
+        code: "P0005",
+        phase: Phase::Parse,
+        title: "Too many arguments or parameters",
+        explanation: "A function can take at most 255 parameters, and a call pass at most 255 \
+arguments. Pass a map or record instead of many separate values.",
+    },
+    ErrorCode {
+        code: "P0006",
+        phase: Phase::Parse,
+        title: "Too much nesting",
+        explanation: "Expressions or blocks are nested too deeply for the parser. Split the \
+code into functions or variables.",
+    },
+    ErrorCode {
+        code: "P0007",
+        phase: Phase::Parse,
+        title: "Extension used in reference Lox",
+        explanation: "With --compat=jlox, only the syntax of the book's jlox is accepted. \
+Drop the option to use the extensions, or rewrite the code without them.",
+    },
+    ErrorCode {
+        code: "P0008",
+        phase: Phase::Parse,
+        title: "Duplicate name",
+        explanation: "A record's fields, an enum's cases, the names an import lists and a \
+call's named arguments must each be different.
+
+    var point = { x: 1, x: 2 };   // 'x' twice
+    var point = { x: 1, y: 2 };",
+    },
+    ErrorCode {
+        code: "P0009",
+        phase: Phase::Parse,
+        title: "Positional argument after a named one",
+        explanation: "Arguments passed by name come after all those passed by position, \
+which fill the parameters from the left.
+
+    drawRect(width: 10, 20);    // which parameter is 20 for?
+    drawRect(10, height: 20);",
+    },
+    ErrorCode {
+        code: "P0010",
+        phase: Phase::Parse,
+        title: "Expected a name",
+        explanation: "A name was needed here, like a variable's after 'var' or a field's after '.'.
+
+    var = 1;   // no name
+    var count = 1;",
+    },
+    ErrorCode {
+        code: "P0011",
+        phase: Phase::Parse,
+        title: "Expected a keyword or punctuation",
+        explanation: "The parser needed a particular token, like the '(' after 'if' or the '{' that starts a \
+function's body, and found something else.
+
+    if x > 1 print x;     // the condition needs parentheses
+    if (x > 1) print x;",
+    },
+    ErrorCode {
+        code: "P0012",
+        phase: Phase::Parse,
+        title: "Expected a type",
+        explanation: "A ':' in a declaration starts a type annotation, which must name a type.
+
+    var count: = 1;   // no type after ':'
+    var count: number = 1;",
+    },
+    ErrorCode {
+        code: "P0013",
+        phase: Phase::Parse,
+        title: "Comma after the last argument",
+        explanation: "A call's arguments are separated by commas, with none after the last.
+
+    add(1, 2, );   // nothing after the last ','
+    add(1, 2);",
+    },
+    ErrorCode {
+        code: "P0014",
+        phase: Phase::Parse,
+        title: "Missing index",
+        explanation: "Brackets after a value index into it, so they need an index between them.
+
+    var first = data[];   // no index
+    var first = data[0];",
+    },
+    ErrorCode {
+        code: "P0015",
+        phase: Phase::Parse,
+        title: "More after an expression",
+        explanation: "Only a single expression was expected, with nothing after it. Expressions evaluated on \
+their own, as by a host embedding the interpreter, have no trailing semicolon.",
+    },
+    ErrorCode {
+        code: "P0016",
+        phase: Phase::Parse,
+        title: "Expected a module path",
+        explanation: "An import names the module it's from with a string.
+
+    import { sqrt } from math;     // the path must be quoted
+    import { sqrt } from \"math\";",
+    },
+    ErrorCode {
+        code: "P0017",
+        phase: Phase::Parse,
+        title: "Label without a loop",
+        explanation: "A label names the loop after it, for 'break' and 'continue' to aim at, so it must be \
+followed by 'while' or 'for'.
+
+    outer: print 1;   // nothing to label
+    outer: while (true) break outer;",
+    },
+    ErrorCode {
+        code: "R0000",
+        phase: Phase::Resolve,
+        title: "Invalid use of a name",
+        explanation: "A name is used somewhere the rules for declaring and using names don't \
+allow.",
+    },
+    ErrorCode {
+        code: "R0001",
+        phase: Phase::Resolve,
+        title: "Undefined variable",
+        explanation: "The name isn't declared anywhere the code can see it: not in any \
+enclosing block or function, and not as a global. Check the spelling, and that the \
+declaration comes first.
+
+    fun count() {
+      var total = 0;
+      return totl;   // misspelled
+    }",
+    },
+    ErrorCode {
+        code: "R0002",
+        phase: Phase::Resolve,
+        title: "Variable read in its own initializer",
+        explanation: "A local variable's initializer can't use the variable being declared, \
+which doesn't have a value yet. To use an outer variable of the same name, give the new one \
+another name.
+
+    var a = 1;
+    {
+      var a = a + 1;   // which 'a'?
+      var b = a + 1;
+    }",
+    },
+    ErrorCode {
+        code: "R0003",
+        phase: Phase::Resolve,
+        title: "Variable already declared",
+        explanation: "A block or function can declare each local name once. Assign to the \
+existing variable instead, or give the new one another name.
+
+    {
+      var x = 1;
+      var x = 2;   // already declared
+      x = 2;
+    }",
+    },
+    ErrorCode {
+        code: "R0004",
+        phase: Phase::Resolve,
+        title: "Return outside a function",
+        explanation: "'return' leaves a function, so it can't be used outside one. Hosts can \
+allow it to end the script with --top-level-return.",
+    },
+    ErrorCode {
+        code: "R0005",
+        phase: Phase::Resolve,
+        title: "Break or continue outside a loop",
+        explanation: "'break' and 'continue' only make sense inside a loop, and a label they \
+name must belong to a loop around them.
+
+    outer: while (true) {
+      while (true) break outer;
+    }",
+    },
+    ErrorCode {
+        code: "R0006",
+        phase: Phase::Resolve,
+        title: "Label already in use",
+        explanation: "A loop inside another can't reuse the outer loop's label, or 'break' \
+couldn't tell which one it meant.",
+    },
+    ErrorCode {
+        code: "R0007",
+        phase: Phase::Resolve,
+        title: "Export outside the top level",
+        explanation: "Only a module's top-level declarations can be exported.",
+    },
+    ErrorCode {
+        code: "R0008",
+        phase: Phase::Resolve,
+        title: "Unreachable code",
+        explanation: "Code after a 'return' in the same block never runs. Delete it, or move \
+the 'return' below it.",
+    },
+    ErrorCode {
+        code: "R0009",
+        phase: Phase::Resolve,
+        title: "Unread local variable",
+        explanation: "A warning, an error only under --strict: a local variable or function \
+is declared but nothing reads it. Delete it, start its name with '_', or silence the warning \
+with a comment.
+
+    fun greet() {
+      var unused = 1;   // lox-ignore: unused-variable
+      print \"hi\";
+    }",
+    },
+    ErrorCode {
+        code: "R0010",
+        phase: Phase::Resolve,
+        title: "Shadowed variable",
+        explanation: "A warning: a local variable or parameter has the name of one in an \
+enclosing block or function, which it hides until its scope ends. Renaming one of them \
+makes clear which is meant.
+
+    var total = 0;
+    {
+      var count = 1;
+      fun add(count) { total = total + count; }   // shadows 'count'
+    }",
+    },
+    ErrorCode {
+        code: "R0011",
+        phase: Phase::Resolve,
+        title: "Constant condition",
+        explanation: "A warning: an 'if', 'while' or 'for' condition is a literal, so the same \
+branch is always taken. 'while (true)' is allowed, for loops left with 'break'.
+
+    if (false) print \"never\";",
+    },
+    ErrorCode {
+        code: "R0012",
+        phase: Phase::Resolve,
+        title: "Long parameter list",
+        explanation: "A warning: a function takes more than five parameters, which makes its \
+calls hard to read. Passing a record of the related values keeps them together.",
+    },
+    ErrorCode {
+        code: "R0013",
+        phase: Phase::Resolve,
+        title: "Deeply nested blocks",
+        explanation: "A warning: blocks within a function are nested more than four deep. \
+Moving the inner loops or branches into functions of their own keeps each one readable.",
+    },
+    ErrorCode {
+        code: "T0000",
+        phase: Phase::Type,
+        title: "Type error",
+        explanation: "The --check pass found a value of the wrong type.",
+    },
+    ErrorCode {
+        code: "T0001",
+        phase: Phase::Type,
+        title: "Mismatched types",
+        explanation: "A value's type doesn't match the type written for where it goes.
+
+    fun double(n: number): number { return n * 2; }
+    double(\"two\");   // a string where a number is expected",
+    },
+    ErrorCode {
+        code: "T0002",
+        phase: Phase::Type,
+        title: "Unknown type",
+        explanation: "Type annotations can name 'any', 'bool', 'nil', 'number', 'string', \
+'function' and 'record'.",
+    },
+    ErrorCode {
+        code: "T0003",
+        phase: Phase::Type,
+        title: "Wrong operand types",
+        explanation: "With --check, an operator is given values whose declared types it doesn't work on. \
+Arithmetic and comparison take numbers; '+' also joins strings.
+
+    var name: string = \"lox\";
+    print name - 1;   // can't subtract from a string",
+    },
+    ErrorCode {
+        code: "T0004",
+        phase: Phase::Type,
+        title: "Calling a value that isn't a function",
+        explanation: "With --check, a value declared with a type other than a function's is called.",
+    },
+    ErrorCode {
+        code: "T0005",
+        phase: Phase::Type,
+        title: "Wrong number of arguments",
+        explanation: "With --check, a function is called with more or fewer arguments than it's declared \
+with parameters.
+
+    fun add(a: number, b: number): number { return a + b; }
+    add(1);   // one short",
+    },
+    ErrorCode {
+        code: "C0000",
+        phase: Phase::Compile,
+        title: "Compile error",
+        explanation: "The program couldn't be compiled to bytecode for the VM.",
+    },
+    ErrorCode {
+        code: "C0001",
+        phase: Phase::Compile,
+        title: "Not supported by the VM",
+        explanation: "The VM backend doesn't have every feature the tree-walker has yet. Run \
+the program with --backend=tree.",
+    },
+    ErrorCode {
+        code: "C0002",
+        phase: Phase::Compile,
+        title: "Function too large for the VM",
+        explanation: "A function has more constants, locals or captured variables than the \
+VM's instructions can address. Split it into smaller functions.",
+    },
+    ErrorCode {
+        code: "C0003",
+        phase: Phase::Compile,
+        title: "Too much nesting",
+        explanation: "Expressions or blocks are nested too deeply to compile. Split the code \
+into functions or variables.",
+    },
+    ErrorCode {
+        code: "C0004",
+        phase: Phase::Compile,
+        title: "Local variable read in its own initializer",
+        explanation: "A local variable's initializer reads the variable being declared, which has no value yet.
+
+    { var a = a; }   // a isn't defined yet",
+    },
+    ErrorCode {
+        code: "C0005",
+        phase: Phase::Compile,
+        title: "'break' or 'continue' outside a loop",
+        explanation: "'break' and 'continue' must be inside a loop, and in the same function as it. With a \
+label, the loop must be one that encloses them.",
+    },
+    ErrorCode {
+        code: "E0000",
+        phase: Phase::Runtime,
+        title: "Runtime error",
+        explanation: "The program failed while running.",
+    },
+    ErrorCode {
+        code: "E0001",
+        phase: Phase::Runtime,
+        title: "Undefined variable",
+        explanation: "A global was read or assigned before it was defined. Globals are looked \
+up as the code runs, so a function can use one defined after it, but only once the \
+definition has run.
+
+    fun show() { print greeting; }
+    show();                  // not defined yet
+    var greeting = \"hi\";
+    show();",
+    },
+    ErrorCode {
+        code: "E0002",
+        phase: Phase::Runtime,
+        title: "Wrong operand types",
+        explanation: "An operator was given values it doesn't work on. Arithmetic and \
+comparison take numbers; '+' also joins strings.
+
+    print \"3\" - 1;   // can't subtract from a string
+    print 3 - 1;",
+    },
+    ErrorCode {
+        code: "E0003",
+        phase: Phase::Runtime,
+        title: "Division by zero",
+        explanation: "A number was divided by zero. Check the divisor first, or use \
+--compat=jlox to get infinity or NaN as the book's jlox does.",
+    },
+    ErrorCode {
+        code: "E0004",
+        phase: Phase::Runtime,
+        title: "Calling a value that isn't a function",
+        explanation: "Only functions, natives and bound functions can be called.
+
+    var name = \"lox\";
+    name();   // a string isn't callable",
+    },
+    ErrorCode {
+        code: "E0005",
+        phase: Phase::Runtime,
+        title: "Wrong number of arguments",
+        explanation: "A function was called with more or fewer arguments than it has \
+parameters.
+
+    fun add(a, b) { return a + b; }
+    add(1);      // one short
+    add(1, 2);",
+    },
+    ErrorCode {
+        code: "E0006",
+        phase: Phase::Runtime,
+        title: "Missing property",
+        explanation: "The value has no property of that name, or no properties at all. Maps \
+and records have the fields they were made with; modules, strings, numbers and bytes have \
+their members and methods.",
+    },
+    ErrorCode {
+        code: "E0007",
+        phase: Phase::Runtime,
+        title: "Invalid index",
+        explanation: "An index must be a whole number from 0 to one less than the length.",
+    },
+    ErrorCode {
+        code: "E0008",
+        phase: Phase::Runtime,
+        title: "Stack overflow",
+        explanation: "Calls nested too deeply, usually a recursive function missing the case \
+that stops it.
+
+    fun count(n) { return count(n + 1); }   // never stops",
+    },
+    ErrorCode {
+        code: "E0009",
+        phase: Phase::Runtime,
+        title: "Out of memory",
+        explanation: "The program held more memory than --max-memory allows.",
+    },
+    ErrorCode {
+        code: "E0010",
+        phase: Phase::Runtime,
+        title: "Integer overflow",
+        explanation: "Integer arithmetic went past the range of a 64-bit integer. Use a \
+number with a fraction, like 1.0, to compute with floats instead.",
+    },
+    ErrorCode {
+        code: "E0011",
+        phase: Phase::Runtime,
+        title: "Import failed",
+        explanation: "A module couldn't be imported. It's searched for next to the importing \
+file, then in each -I directory, then in LOX_PATH. Modules can't import each other in a \
+cycle, and selective imports can only name what the module exports. Importing reads files, \
+so it's only allowed with the fs capability.",
+    },
+    ErrorCode {
+        code: "E0012",
+        phase: Phase::Runtime,
+        title: "Comparing different types",
+        explanation: "A warning, given with --warn: '==' or '!=' compared values of different \
+types, which are never equal: the string \"1\" never equals the number 1.
+
+    if (input == 1) print \"one\";   // always false if input is \"1\"",
+    },
+    ErrorCode {
+        code: "E0013",
+        phase: Phase::Runtime,
+        title: "Implicit string conversion",
+        explanation: "A warning, given with --warn: '+' joined a number or other value to a \
+string, converting it first. That's often meant, but can also be an addition gone wrong.
+
+    var total = \"1\" + 2;    // \"12\", not 3",
+    },
+    ErrorCode {
+        code: "E0014",
+        phase: Phase::Runtime,
+        title: "nil joined to a string",
+        explanation: "A warning, given with --warn: '+' joined nil to a string, which usually \
+means a variable was never set or a function returned nothing.",
+    },
+    ErrorCode {
+        code: "E0015",
+        phase: Phase::Runtime,
+        title: "Loop iteration limit",
+        explanation: "A loop went round more times than --max-loop-iterations allows, which in \
+the REPL is a million unless set otherwise, so a loop that never ends doesn't hang it. \
+Check that the condition eventually turns false, or raise the limit, with 0 for none.
+
+    var i = 0;
+    while (i < 10) print i;   // never increments i",
+    },
+    ErrorCode {
+        code: "E0016",
+        phase: Phase::Runtime,
+        title: "Named argument mismatch",
+        explanation: "A named argument must name one of the function's parameters that no \
+positional argument has filled, and together they must give every parameter a value. \
+Natives' parameters have no names, so they only take arguments by position.
+
+    fun drawRect(width, height) { }
+    drawRect(10, width: 20);   // width is already 10
+    drawRect(height: 20, width: 10);",
+    },
+    ErrorCode {
+        code: "E0017",
+        phase: Phase::Runtime,
+        title: "Changing a frozen value",
+        explanation: "freeze() made a map or record, and every map and record inside it, \
+read-only. Build a new value with the changed field instead.
+
+    var origin = freeze({ x: 0, y: 0 });
+    origin.x = 1;   // error
+    var moved = { x: 1, y: origin.y };",
+    },
+    ErrorCode {
+        code: "E0018",
+        phase: Phase::Runtime,
+        title: "Invalid argument",
+        explanation: "A native function was given a value it can't use: one of the wrong type, like a string \
+where it takes a number, or one out of the range it takes.
+
+    print math.sqrt(\"4\");   // takes a number
+    print math.sqrt(4);",
+    },
+    ErrorCode {
+        code: "E0019",
+        phase: Phase::Runtime,
+        title: "Input or output failed",
+        explanation: "Reading or writing a file, a network request, running a command or reading input failed. \
+The message gives the reason the system reported, like a file that doesn't exist.",
+    },
+    ErrorCode {
+        code: "E0020",
+        phase: Phase::Runtime,
+        title: "Text encoding failed",
+        explanation: "Text couldn't be converted to or from bytes. The encodings are \"utf-8\", \"latin-1\" \
+and \"ascii\", and not every character can be written, or every byte read, in the last two.
+
+    bytes.encode(\"é\", \"ascii\");   // not an ASCII character",
+    },
+    ErrorCode {
+        code: "E0021",
+        phase: Phase::Runtime,
+        title: "Invalid time format",
+        explanation: "A time format has a '%' directive formatTime and parseTime don't know, or the text given \
+to parseTime doesn't match its format.",
+    },
+    ErrorCode {
+        code: "E0022",
+        phase: Phase::Runtime,
+        title: "Thread failed",
+        explanation: "A thread couldn't be spawned, failed or was joined twice, or a value that can't be sent \
+between threads was. Only numbers, strings, booleans, nil, bytes, maps and records can be \
+sent; a function can't.",
+    },
+    ErrorCode {
+        code: "E0023",
+        phase: Phase::Runtime,
+        title: "Invalid iterator",
+        explanation: "An iterator's 'next' function must return a record with a 'done' field, true once there \
+are no more values, and a 'value' field with the next one.",
+    },
+    ErrorCode {
+        code: "E0024",
+        phase: Phase::Runtime,
+        title: "Not iterable",
+        explanation: "'for..in' loops over strings, maps, records and iterators.
+
+    for (var x in 10) print x;             // a number isn't iterable
+    for (var x in range(0, 10)) print x;",
+    },
+    ErrorCode {
+        code: "E0025",
+        phase: Phase::Runtime,
+        title: "Nothing to close",
+        explanation: "'with' calls its value's 'close' function once the body finishes, so the value must be \
+a map or record with a 'close' function taking no arguments.
+
+    with (var file = 1) print file;   // 1 has nothing to close",
+    },
+    ErrorCode {
+        code: "E0026",
+        phase: Phase::Runtime,
+        title: "'break' or 'continue' outside a loop",
+        explanation: "'break' and 'continue' must be inside a loop, and in the same function as it.
+
+    if (done) break;   // not in a loop",
+    },
+    ErrorCode {
+        code: "E0027",
+        phase: Phase::Runtime,
+        title: "Variable without a value",
+        explanation: "A variable must be given a value when it's declared.
+
+    var total;       // no value
+    var total = 0;",
+    },
+    ErrorCode {
+        code: "E0028",
+        phase: Phase::Runtime,
+        title: "Setting a field of a temporary value",
+        explanation: "Maps and records are values, so a field is set on a copy that's stored back where the \
+map or record came from. That has to be a variable, or a field of one.
+
+    makePoint().x = 1;   // nowhere to store the copy
+    var point = makePoint();
+    point.x = 1;",
+    },
+    ErrorCode {
+        code: "E0029",
+        phase: Phase::Runtime,
+        title: "Failed expectation",
+        explanation: "expect(actual, expected) was given two values that aren't equal. In a test() block this \
+fails the test.",
+    },
+    ErrorCode {
+        code: "E0030",
+        phase: Phase::Runtime,
+        title: "Internal error",
+        explanation: "The interpreter reached a state it never should, such as a variable missing from where \
+the resolver placed it. This is a bug in the interpreter, not the program.",
+    },
+    ErrorCode {
+        code: "E0031",
+        phase: Phase::Runtime,
+        title: "Invalid exit status",
+        explanation: "With `--top-level-return`, a script's top-level `return` gives the status it exits with, \
+so it must be `nil`, for 0, or an integer from 0 to 255.
+
+    return \"done\";   // not a status
+    return 3;",
+    },
+];
+
+/// The code spelled `code`, in any case.
+pub fn find(code: &str) -> Option<&'static ErrorCode> {
+    ERROR_CODES.iter().find(|known| known.code.eq_ignore_ascii_case(code))
+}
+
+/// The code spelled exactly `code`, for an error to be made with. Giving
+/// one that doesn't exist, or a phase's `0000`, is a bug in the
+/// interpreter.
+pub fn get(code: &str) -> &'static ErrorCode {
+    ERROR_CODES
+        .iter()
+        .find(|known| known.code == code && !code.ends_with("0000"))
+        .unwrap_or_else(|| panic!("'{}' isn't an error code of its own.", code))
+}
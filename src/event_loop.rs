@@ -0,0 +1,134 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+use crate::error::RuntimeException;
+use crate::interpreter::{self, Interpreter};
+use crate::native_function::NativeFunction;
+use crate::token::{Literal, Token};
+
+/// A callback waiting in an [`EventLoop`].
+struct Timer {
+    id: i64,
+    /// When it's next due, in the loop's milliseconds.
+    due: f64,
+    /// How long until it's due again, for `setInterval`.
+    interval: Option<f64>,
+    callback: Literal,
+}
+
+/// The callbacks `setTimeout` and `setInterval` queued, which
+/// `runEventLoop()` calls as they come due. The loop keeps its own time,
+/// which only moves while it runs, so a callback is due a fixed delay
+/// after the one that scheduled it however long the callbacks took.
+#[derive(Default)]
+pub struct EventLoop {
+    timers: Vec<Timer>,
+    now: f64,
+    next_id: i64,
+}
+
+impl EventLoop {
+    /// Queues `callback` to run `delay` milliseconds from now, and every
+    /// `delay` milliseconds after that if it repeats. Returns an ID for
+    /// [`EventLoop::cancel`].
+    pub fn schedule(&mut self, callback: Literal, delay: f64, repeat: bool) -> i64 {
+        self.next_id += 1;
+        let delay = delay.max(0.0);
+        self.timers.push(Timer {
+            id: self.next_id,
+            due: self.now + delay,
+            interval: repeat.then_some(delay),
+            callback,
+        });
+        self.next_id
+    }
+
+    /// Drops a queued callback. Returns whether there was one with `id`.
+    pub fn cancel(&mut self, id: i64) -> bool {
+        let before = self.timers.len();
+        self.timers.retain(|timer| timer.id != id);
+        self.timers.len() < before
+    }
+
+    /// Moves time on to the next callback due, first by when it's due and
+    /// then by when it was queued, and gives it with how long the wait
+    /// was. A repeating callback is queued again before it runs, so it can
+    /// cancel itself.
+    pub fn advance(&mut self) -> Option<(f64, Literal)> {
+        let index = (0..self.timers.len()).min_by(|&a, &b| self.timers[a].due.total_cmp(&self.timers[b].due))?;
+        let mut timer = self.timers.remove(index);
+        let waited = timer.due - self.now;
+        self.now = timer.due;
+        let callback = timer.callback.clone();
+        if let Some(interval) = timer.interval {
+            // An interval of 0 would starve every other callback.
+            timer.due += interval.max(1.0);
+            self.timers.push(timer);
+        }
+        Some((waited, callback))
+    }
+}
+
+/// The event loop natives, defined as globals. There's no sleeping on
+/// `wasm32-unknown-unknown`, so no `runEventLoop` there; a host can drive
+/// [`EventLoop::advance`] itself.
+pub fn natives() -> Vec<(&'static str, Literal)> {
+    vec![
+        ("setTimeout", Literal::NativeFunction(NativeFunction::new("setTimeout", 2, set_timeout))),
+        ("setInterval", Literal::NativeFunction(NativeFunction::new("setInterval", 2, set_interval))),
+        ("clearTimer", Literal::NativeFunction(NativeFunction::new("clearTimer", 1, clear_timer))),
+        #[cfg(not(target_arch = "wasm32"))]
+        ("runEventLoop", Literal::NativeFunction(NativeFunction::new("runEventLoop", 0, run_event_loop))),
+    ]
+}
+
+fn error(code: &'static str, message: String) -> RuntimeException {
+    RuntimeException::base(code, Token::default(), message)
+}
+
+fn schedule(interpreter: &mut Interpreter, args: &[Literal], native: &str, repeat: bool) -> Result<Literal, RuntimeException> {
+    let callback = args[0].clone();
+    if !matches!(callback, Literal::LoxFunction(_) | Literal::NativeFunction(_)) {
+        return Err(error("E0018", format!("{} expects a function.", native)));
+    }
+    if !interpreter::accepts(&callback, 0) {
+        return Err(error("E0018", format!("{} expects a function taking no arguments.", native)));
+    }
+    let Some(delay) = args[1].as_f64() else {
+        return Err(error("E0018", format!("{} expects a number of milliseconds.", native)));
+    };
+    Ok(Literal::Int(interpreter.event_loop().schedule(callback, delay, repeat)))
+}
+
+/// `setTimeout(fn, ms)` runs `fn` once, `ms` milliseconds into the loop.
+fn set_timeout(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    schedule(interpreter, args, "setTimeout", false)
+}
+
+/// `setInterval(fn, ms)` runs `fn` every `ms` milliseconds until
+/// `clearTimer` cancels it.
+fn set_interval(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    schedule(interpreter, args, "setInterval", true)
+}
+
+fn clear_timer(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let Literal::Int(id) = args[0] else {
+        return Err(error("E0018", "clearTimer expects a timer ID.".to_string()));
+    };
+    Ok(Literal::from(interpreter.event_loop().cancel(id)))
+}
+
+/// Calls queued callbacks as they come due, sleeping in between, until
+/// none are left. A callback's error stops the loop.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_event_loop(interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let start = Instant::now();
+    let mut elapsed = 0.0;
+    while let Some((waited, callback)) = interpreter.event_loop().advance() {
+        elapsed += waited;
+        let due = start + Duration::from_secs_f64(elapsed / 1000.0);
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+        interpreter.call_value(&callback, vec![], &Token::default())?;
+    }
+    Ok(Literal::Nil)
+}
@@ -1,16 +1,133 @@
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::token::{Literal,Token};
-use crate::stmt::Stmt;
+use crate::stmt::{Signature, Stmt};
+
+/// Identifies an expression node that the resolver records scope
+/// distances for, or a function whose captures it records. IDs are unique for the lifetime of the process so that
+/// REPL lines parsed separately never collide.
+pub type ExprId = usize;
+
+static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub fn next_expr_id() -> ExprId {
+    NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 #[derive(Debug,Clone,Eq,PartialEq,Hash)]
 pub enum Expr {
     Literal(Literal),
-    Logical(Box<Expr>, Token, Box<Expr>),
-    Unary(Token, Box<Expr>),
-    Assign(Token, Box<Expr>),
-    Binary(Box<Expr>, Token, Box<Expr>),
-    Lambda(Vec<Token>, Box<Vec<Stmt>>),
-    Call(Box<Expr>, Token, Box<Vec<Expr>>),
-    Grouping(Box<Expr>),
-    Variable(Token),
+    Logical(Rc<Expr>, Token, Rc<Expr>),
+    Unary(Token, Rc<Expr>),
+    Assign(ExprId, Token, Rc<Expr>),
+    Binary(Rc<Expr>, Token, Rc<Expr>),
+    Lambda(ExprId, Rc<[Token]>, Rc<[Stmt]>, Option<Rc<Signature>>),
+    /// A call, its closing paren, its positional arguments, and the
+    /// `name: value` ones after them.
+    Call(Rc<Expr>, Token, Rc<[Expr]>, Rc<[(Token, Expr)]>),
+    /// `object.name`.
+    Get(Rc<Expr>, Token),
+    /// `object.name = value`.
+    Set(Rc<Expr>, Token, Rc<Expr>),
+    /// `object[index]` and its opening bracket.
+    Index(Rc<Expr>, Token, Rc<Expr>),
+    /// A `{ name: value, ... }` record literal and its opening brace.
+    Record(Token, Rc<[(Token, Expr)]>),
+    Grouping(Rc<Expr>),
+    Variable(ExprId, Token),
+    /// Never produced by the parser; stands in for a subexpression that
+    /// has been taken apart, as when dropping deep trees.
     Empty
 }
+
+thread_local! {
+    static PLACEHOLDER: Rc<Expr> = Rc::new(Expr::Empty);
+}
+
+impl Expr {
+    /// The token an error about the whole expression is reported at: its
+    /// operator, name, paren, bracket or brace. Literals and groupings
+    /// keep none.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Expr::Logical(_, token, _)
+            | Expr::Binary(_, token, _)
+            | Expr::Unary(token, _)
+            | Expr::Assign(_, token, _)
+            | Expr::Call(_, token, ..)
+            | Expr::Get(_, token)
+            | Expr::Set(_, token, _)
+            | Expr::Index(_, token, _)
+            | Expr::Record(token, _)
+            | Expr::Variable(_, token) => Some(token),
+            Expr::Lambda(_, params, ..) => params.first(),
+            Expr::Literal(_) | Expr::Grouping(_) | Expr::Empty => None,
+        }
+    }
+
+    /// The first source line the expression's tokens mention. Literals
+    /// don't keep a token, so an expression made only of them has none.
+    pub fn line(&self) -> Option<u32> {
+        // Walks down the left operand, falling back to the innermost
+        // operator seen when the leftmost operand has no line.
+        let mut expr = self;
+        let mut operator_line = None;
+        let line = loop {
+            match expr {
+                Expr::Literal(_) | Expr::Empty => break None,
+                Expr::Logical(left, operator, _) | Expr::Binary(left, operator, _) => {
+                    operator_line = Some(operator.line);
+                    expr = left;
+                }
+                Expr::Grouping(inner) => expr = inner,
+                Expr::Unary(operator, _) => break Some(operator.line),
+                Expr::Assign(_, name, _) | Expr::Variable(_, name) => break Some(name.line),
+                Expr::Lambda(_, params, body, _) => {
+                    break params
+                        .first()
+                        .map(|param| param.line)
+                        .or_else(|| body.iter().find_map(Stmt::line))
+                }
+                Expr::Call(callee, paren, ..) => break callee.line().or(Some(paren.line)),
+                Expr::Get(object, name) | Expr::Set(object, name, _) => break object.line().or(Some(name.line)),
+                Expr::Index(object, bracket, _) => break object.line().or(Some(bracket.line)),
+                Expr::Record(brace, _) => break Some(brace.line),
+            }
+        };
+        line.or(operator_line)
+    }
+
+    /// Moves this node's operand pointers into `children`, leaving shared
+    /// placeholders behind.
+    fn take_operands(&mut self, children: &mut Vec<Rc<Expr>>) {
+        let placeholder = || PLACEHOLDER.with(Rc::clone);
+        match self {
+            Expr::Binary(left, _, right)
+            | Expr::Logical(left, _, right)
+            | Expr::Index(left, _, right)
+            | Expr::Set(left, _, right) => {
+                children.push(std::mem::replace(left, placeholder()));
+                children.push(std::mem::replace(right, placeholder()));
+            }
+            Expr::Unary(_, operand) | Expr::Grouping(operand) | Expr::Get(operand, _) => {
+                children.push(std::mem::replace(operand, placeholder()));
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Drop for Expr {
+    /// Tears down operator chains iteratively so that dropping a very deep
+    /// expression can't overflow the stack.
+    fn drop(&mut self) {
+        let mut children = vec![];
+        self.take_operands(&mut children);
+        while let Some(child) = children.pop() {
+            if let Ok(mut expr) = Rc::try_unwrap(child) {
+                expr.take_operands(&mut children);
+            }
+        }
+    }
+}
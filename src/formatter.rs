@@ -0,0 +1,503 @@
+use crate::error::ParserError;
+use crate::expr::Expr;
+use crate::scanner::Comment;
+use crate::stack::DepthLimit;
+use crate::stmt::{Signature, Stmt};
+use crate::token::{Literal, Token};
+
+/// The layout choices a project can make, in the `[format]` table of its
+/// `lox.toml`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Spaces per level of indentation.
+    pub indent: usize,
+    /// Calls whose arguments would run past this column get one argument
+    /// per line.
+    pub max_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self { indent: 2, max_width: 80 }
+    }
+}
+
+/// Pretty-prints a parsed program back to Lox source in one canonical
+/// layout. Comments collected by the scanner are put back before the
+/// statement that follows them, or at the end of the line they trailed, and
+/// runs of blank lines collapse into one.
+pub struct Formatter {
+    out: String,
+    indent: usize,
+    comments: Vec<Comment>,
+    next_comment: usize,
+    blank_lines: Vec<u32>,
+    next_blank_line: usize,
+    /// Counts blocks in source order, matching the scanner's numbering.
+    block_count: usize,
+    /// One level of indentation.
+    unit: String,
+    max_width: usize,
+    depth: DepthLimit,
+}
+
+impl Formatter {
+    pub fn new(comments: Vec<Comment>, blank_lines: Vec<u32>) -> Self {
+        Self {
+            out: String::new(),
+            indent: 0,
+            comments,
+            next_comment: 0,
+            blank_lines,
+            next_blank_line: 0,
+            block_count: 0,
+            unit: " ".repeat(FormatOptions::default().indent),
+            max_width: FormatOptions::default().max_width,
+            depth: DepthLimit::default(),
+        }
+    }
+
+    pub fn set_options(&mut self, options: FormatOptions) {
+        self.unit = " ".repeat(options.indent);
+        self.max_width = options.max_width;
+    }
+
+    /// The formatted program, or an error if it is nested too deeply to
+    /// format.
+    pub fn format(mut self, stmts: &[Stmt]) -> Result<String, ParserError> {
+        self.items(stmts);
+        self.flush_comments(|_| true);
+        self.depth.finish(self.out)
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str(&self.unit);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Writes one blank line if the source had any before `line`, unless
+    /// it would open the file or a block.
+    fn gap(&mut self, line: u32) {
+        let mut blank = false;
+        while self.blank_lines.get(self.next_blank_line).is_some_and(|&blank_line| blank_line < line) {
+            blank = true;
+            self.next_blank_line += 1;
+        }
+        let after_brace = self.out.is_empty() || self.out.ends_with("{\n");
+        if blank && !after_brace && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn flush_comments(&mut self, include: impl Fn(&Comment) -> bool) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if !include(comment) {
+                break;
+            }
+            let (line, text) = (comment.line, comment.text.clone());
+            self.gap(line);
+            self.line(&text);
+            self.next_comment += 1;
+        }
+    }
+
+    fn items(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            let start = stmt.line();
+            if let Some(start) = start {
+                self.flush_comments(|comment| comment.line < start);
+                self.gap(start);
+            }
+            self.stmt(stmt);
+
+            let end = stmt_end_line(stmt);
+            if let (Some(start), Some(end)) = (start, end) {
+                if let Some(comment) = self.comments.get(self.next_comment) {
+                    if comment.trailing && (start..=end).contains(&comment.line) {
+                        self.out.pop();
+                        self.out.push(' ');
+                        self.out.push_str(&comment.text);
+                        self.out.push('\n');
+                        self.next_comment += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes `header {`, the statements, and the closing brace along with
+    /// any comments that were inside it.
+    fn block(&mut self, header: &str, stmts: &[Stmt]) {
+        self.line(&format!("{}{{", header));
+        self.block_count += 1;
+        let block = self.block_count;
+        self.indent += 1;
+        self.items(stmts);
+        self.flush_comments(|comment| comment.block == block);
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// Writes a loop or branch body after its header: blocks open on the
+    /// header's line and anything else goes indented on the next one.
+    fn body(&mut self, header: &str, body: &Stmt) {
+        match body {
+            Stmt::Block(stmts) => self.block(&format!("{} ", header), stmts),
+            _ => {
+                self.line(header);
+                self.indent += 1;
+                self.stmt(body);
+                self.indent -= 1;
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        if self.depth.exhausted(None, || stmt.line()) {
+            return;
+        }
+        match stmt {
+            Stmt::Block(stmts) => self.block("", stmts),
+            Stmt::Expression(expr) => {
+                let expr = self.expr(expr);
+                self.line(&format!("{};", expr));
+            }
+            Stmt::Function(name, _, params, body, signature, _) => {
+                let header = format!("fun {}{} ", name.lexeme, signature_source(params, signature.as_deref()));
+                self.block(&header, body)
+            }
+            Stmt::Print(values) => {
+                let values: Vec<String> = values.iter().map(|value| self.expr(value)).collect();
+                self.line(&format!("print {};", values.join(", ")));
+            }
+            Stmt::Return(_, None) => self.line("return;"),
+            Stmt::Return(_, Some(value)) => {
+                let value = self.expr(value);
+                self.line(&format!("return {};", value));
+            }
+            Stmt::If(arms, else_branch) => self.if_chain("", arms, else_branch.as_deref()),
+            Stmt::While(_, condition, body) => {
+                let condition = self.expr(condition);
+                self.body(&format!("while ({})", condition), body);
+            }
+            Stmt::For(_, initializer, condition, increment, body) => {
+                let mut header = match initializer.as_deref() {
+                    Some(Stmt::Var(name, annotation, Some(value))) => {
+                        format!("var {}{} = {};", name.lexeme, annotation_source(annotation), self.expr(value))
+                    }
+                    Some(Stmt::Var(name, annotation, None)) => {
+                        format!("var {}{};", name.lexeme, annotation_source(annotation))
+                    }
+                    Some(Stmt::Expression(expr)) => format!("{};", self.expr(expr)),
+                    _ => ";".to_string(),
+                };
+                if let Some(condition) = condition {
+                    header = format!("{} {}", header, self.expr(condition));
+                }
+                header.push(';');
+                if let Some(increment) = increment {
+                    header = format!("{} {}", header, self.expr(increment));
+                }
+                self.body(&format!("for ({})", header), body);
+            }
+            Stmt::Var(name, annotation, None) => {
+                self.line(&format!("var {}{};", name.lexeme, annotation_source(annotation)))
+            }
+            Stmt::Var(name, annotation, Some(initializer)) => {
+                let initializer = self.expr(initializer);
+                self.line(&format!("var {}{} = {};", name.lexeme, annotation_source(annotation), initializer));
+            }
+            Stmt::Labeled(label, body) => {
+                // The label goes in front of the loop's header line.
+                let start = self.out.len() + self.unit.len() * self.indent;
+                self.stmt(body);
+                self.out.insert_str(start, &format!("{}: ", label.lexeme));
+            }
+            Stmt::Break(_, None) => self.line("break;"),
+            Stmt::Break(_, Some(label)) => self.line(&format!("break {};", label.lexeme)),
+            Stmt::Continue(_, None) => self.line("continue;"),
+            Stmt::Continue(_, Some(label)) => self.line(&format!("continue {};", label.lexeme)),
+            Stmt::ForIn(name, iterable, body) => {
+                let iterable = self.expr(iterable);
+                self.body(&format!("for (var {} in {})", name.lexeme, iterable), body);
+            }
+            Stmt::With(_, name, initializer, body) => {
+                let initializer = self.expr(initializer);
+                self.body(&format!("with (var {} = {})", name.lexeme, initializer), body);
+            }
+            Stmt::Enum(name, cases) => {
+                let cases: Vec<&str> = cases.iter().map(|case| case.lexeme.as_str()).collect();
+                self.line(&format!("enum {} {{ {} }}", name.lexeme, cases.join(", ")));
+            }
+            Stmt::Import(_, names, path) => {
+                let names: Vec<&str> = names.iter().map(|name| name.lexeme.as_str()).collect();
+                self.line(&format!("import {{ {} }} from \"{}\";", names.join(", "), path));
+            }
+            Stmt::Export(declaration) => {
+                // The keyword goes in front of the declaration's first line.
+                let start = self.out.len() + self.indent * self.unit.len();
+                self.stmt(declaration);
+                self.out.insert_str(start, "export ");
+            }
+        }
+    }
+
+    /// Lays out `if`/`else` so an `else if` chain stays flat and `else`
+    /// shares the closing brace's line.
+    fn if_chain(&mut self, prefix: &str, arms: &[(Token, Expr, Stmt)], else_branch: Option<&Stmt>) {
+        if self.depth.exhausted(arms.first().map(|(keyword, ..)| keyword), || None) {
+            return;
+        }
+        let mut previous = None;
+        for (_, condition, branch) in arms {
+            let prefix = match previous {
+                Some(previous) => self.else_prefix(previous) + " ",
+                None => prefix.to_string(),
+            };
+            let condition = self.expr(condition);
+            self.body(&format!("{}if ({})", prefix, condition), branch);
+            previous = Some(branch);
+        }
+        let (Some(else_branch), Some(previous)) = (else_branch, previous) else {
+            return;
+        };
+        let prefix = self.else_prefix(previous);
+        match else_branch {
+            Stmt::If(arms, else_branch) => self.if_chain(&(prefix + " "), arms, else_branch.as_deref()),
+            _ => self.body(&prefix, else_branch),
+        }
+    }
+
+    /// `else`, pulled up onto the closing brace of the branch before it
+    /// when there is one.
+    fn else_prefix(&mut self, branch: &Stmt) -> String {
+        if matches!(branch, Stmt::Block(_)) && self.out.ends_with("}\n") {
+            self.out.truncate(self.out.len() - 1);
+            let line_start = self.out.rfind('\n').map_or(0, |i| i + 1);
+            let closing = self.out.split_off(line_start);
+            closing.trim_start().to_string() + " else"
+        } else {
+            "else".to_string()
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        if self.depth.exhausted(expr.token(), || expr.line()) {
+            return String::new();
+        }
+        match expr {
+            Expr::Literal(literal) => literal_source(literal),
+            Expr::Logical(left, operator, right) | Expr::Binary(left, operator, right) => {
+                format!("{} {} {}", self.expr(left), operator.lexeme, self.expr(right))
+            }
+            Expr::Unary(operator, right) => format!("{}{}", operator.lexeme, self.expr(right)),
+            Expr::Assign(_, name, value) => format!("{} = {}", name.lexeme, self.expr(value)),
+            Expr::Lambda(_, params, body, signature) => {
+                let out = std::mem::take(&mut self.out);
+                self.block(&format!("fun {} ", signature_source(params, signature.as_deref())), body);
+                let lambda = std::mem::replace(&mut self.out, out);
+                lambda.trim().to_string()
+            }
+            Expr::Call(callee, _, arguments, named) => {
+                let callee = self.expr(callee);
+                let mut arguments: Vec<String> =
+                    arguments.iter().map(|argument| self.expr(argument)).collect();
+                arguments.extend(named.iter().map(|(name, value)| format!("{}: {}", name.lexeme, self.expr(value))));
+                let call = format!("{}({})", callee, arguments.join(", "));
+                let width = self.indent * self.unit.len() + call.len();
+                if width <= self.max_width || arguments.is_empty() || call.contains('\n') {
+                    return call;
+                }
+                let inner = self.unit.repeat(self.indent + 1);
+                let mut wrapped = format!("{}(\n", callee);
+                for argument in &arguments {
+                    wrapped.push_str(&format!("{}{},\n", inner, argument));
+                }
+                wrapped.truncate(wrapped.len() - 2);
+                wrapped.push('\n');
+                wrapped.push_str(&self.unit.repeat(self.indent));
+                wrapped.push(')');
+                wrapped
+            }
+            Expr::Get(object, name) => format!("{}.{}", self.expr(object), name.lexeme),
+            Expr::Set(object, name, value) => format!("{}.{} = {}", self.expr(object), name.lexeme, self.expr(value)),
+            Expr::Index(object, _, index) => format!("{}[{}]", self.expr(object), self.expr(index)),
+            Expr::Record(_, fields) if fields.is_empty() => "{}".to_string(),
+            Expr::Record(_, fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name.lexeme, self.expr(value)))
+                    .collect();
+                format!("{{ {} }}", fields.join(", "))
+            }
+            Expr::Grouping(expr) => format!("({})", self.expr(expr)),
+            Expr::Variable(_, name) => name.lexeme.to_string(),
+            Expr::Empty => String::new(),
+        }
+    }
+}
+
+/// `(a, b)`, with any types written on the parameters and result.
+pub fn signature_source(params: &[Token], signature: Option<&Signature>) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .enumerate()
+        .map(|(i, param)| {
+            let annotation = signature.and_then(|signature| signature.params[i].clone());
+            format!("{}{}", param.lexeme, annotation_source(&annotation))
+        })
+        .collect();
+    let returns = signature.and_then(|signature| signature.returns.clone());
+    format!("({}){}", params.join(", "), annotation_source(&returns))
+}
+
+fn annotation_source(annotation: &Option<Token>) -> String {
+    match annotation {
+        Some(type_name) => format!(": {}", type_name.lexeme),
+        None => String::new(),
+    }
+}
+
+/// Writes a literal the way the scanner reads it back. Floats always keep
+/// a fraction so they don't turn into integers, and never an exponent,
+/// which the scanner doesn't read.
+fn literal_source(literal: &Literal) -> String {
+    match literal {
+        Literal::String(s) => format!("\"{}\"", s),
+        Literal::Number(n) if n.fract() == 0.0 => format!("{:.1}", n),
+        Literal::Number(n) => n.to_string(),
+        _ => literal.to_string(),
+    }
+}
+
+/// The last source line a statement's tokens mention, if any.
+fn stmt_end_line(stmt: &Stmt) -> Option<u32> {
+    let mut lines = vec![];
+    collect_stmt_lines(stmt, &mut lines);
+    lines.into_iter().max()
+}
+
+fn collect_stmt_lines(stmt: &Stmt, lines: &mut Vec<u32>) {
+    match stmt {
+        Stmt::Block(stmts) => stmts.iter().for_each(|stmt| collect_stmt_lines(stmt, lines)),
+        Stmt::Expression(expr) => collect_expr_lines(expr, lines),
+        Stmt::Print(values) => values.iter().for_each(|value| collect_expr_lines(value, lines)),
+        Stmt::Function(name, _, params, body, ..) => {
+            lines.push(name.line);
+            lines.extend(params.iter().map(|param| param.line));
+            body.iter().for_each(|stmt| collect_stmt_lines(stmt, lines));
+        }
+        Stmt::Return(keyword, value) => {
+            lines.push(keyword.line);
+            value.iter().for_each(|value| collect_expr_lines(value, lines));
+        }
+        Stmt::If(arms, else_branch) => {
+            for (_, condition, branch) in arms.iter() {
+                collect_expr_lines(condition, lines);
+                collect_stmt_lines(branch, lines);
+            }
+            else_branch.iter().for_each(|stmt| collect_stmt_lines(stmt, lines));
+        }
+        Stmt::While(_, condition, body) => {
+            collect_expr_lines(condition, lines);
+            collect_stmt_lines(body, lines);
+        }
+        Stmt::For(_, initializer, condition, increment, body) => {
+            initializer.iter().for_each(|stmt| collect_stmt_lines(stmt, lines));
+            condition.iter().for_each(|expr| collect_expr_lines(expr, lines));
+            increment.iter().for_each(|expr| collect_expr_lines(expr, lines));
+            collect_stmt_lines(body, lines);
+        }
+        Stmt::Var(name, annotation, initializer) => {
+            lines.push(name.line);
+            lines.extend(annotation.iter().map(|annotation| annotation.line));
+            initializer.iter().for_each(|expr| collect_expr_lines(expr, lines));
+        }
+        Stmt::Labeled(label, body) => {
+            lines.push(label.line);
+            collect_stmt_lines(body, lines);
+        }
+        Stmt::Break(keyword, label) | Stmt::Continue(keyword, label) => {
+            lines.push(keyword.line);
+            lines.extend(label.iter().map(|label| label.line));
+        }
+        Stmt::ForIn(name, iterable, body) => {
+            lines.push(name.line);
+            collect_expr_lines(iterable, lines);
+            collect_stmt_lines(body, lines);
+        }
+        Stmt::With(keyword, name, initializer, body) => {
+            lines.push(keyword.line);
+            lines.push(name.line);
+            collect_expr_lines(initializer, lines);
+            collect_stmt_lines(body, lines);
+        }
+        Stmt::Enum(name, cases) => {
+            lines.push(name.line);
+            lines.extend(cases.iter().map(|case| case.line));
+        }
+        Stmt::Import(keyword, names, _) => {
+            lines.push(keyword.line);
+            lines.extend(names.iter().map(|name| name.line));
+        }
+        Stmt::Export(declaration) => collect_stmt_lines(declaration, lines),
+    }
+}
+
+fn collect_expr_lines(expr: &Expr, lines: &mut Vec<u32>) {
+    match expr {
+        Expr::Literal(_) | Expr::Empty => (),
+        Expr::Logical(left, operator, right) | Expr::Binary(left, operator, right) => {
+            collect_expr_lines(left, lines);
+            lines.push(operator.line);
+            collect_expr_lines(right, lines);
+        }
+        Expr::Unary(operator, right) => {
+            lines.push(operator.line);
+            collect_expr_lines(right, lines);
+        }
+        Expr::Assign(_, name, value) => {
+            lines.push(name.line);
+            collect_expr_lines(value, lines);
+        }
+        Expr::Lambda(_, params, body, _) => {
+            lines.extend(params.iter().map(|param| param.line));
+            body.iter().for_each(|stmt| collect_stmt_lines(stmt, lines));
+        }
+        Expr::Call(callee, paren, arguments, named) => {
+            collect_expr_lines(callee, lines);
+            lines.push(paren.line);
+            arguments.iter().for_each(|argument| collect_expr_lines(argument, lines));
+            for (name, value) in named.iter() {
+                lines.push(name.line);
+                collect_expr_lines(value, lines);
+            }
+        }
+        Expr::Get(object, name) => {
+            collect_expr_lines(object, lines);
+            lines.push(name.line);
+        }
+        Expr::Set(object, name, value) => {
+            collect_expr_lines(object, lines);
+            lines.push(name.line);
+            collect_expr_lines(value, lines);
+        }
+        Expr::Index(object, bracket, index) => {
+            collect_expr_lines(object, lines);
+            lines.push(bracket.line);
+            collect_expr_lines(index, lines);
+        }
+        Expr::Record(brace, fields) => {
+            lines.push(brace.line);
+            for (name, value) in fields.iter() {
+                lines.push(name.line);
+                collect_expr_lines(value, lines);
+            }
+        }
+        Expr::Grouping(expr) => collect_expr_lines(expr, lines),
+        Expr::Variable(_, name) => lines.push(name.line),
+    }
+}
@@ -0,0 +1,233 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use crate::environment::{Environment, Slot};
+use crate::token::{Literal, Members};
+
+/// Counters the collector keeps, reported by `--gc-stats`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GcStats {
+    pub collections: u64,
+    pub environments_freed: u64,
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "gc collections:          {}", self.collections)?;
+        write!(f, "environments freed:      {}", self.environments_freed)
+    }
+}
+
+/// Frees environments that only keep each other alive. A function holds
+/// its closure's environment, so a function stored in that environment or
+/// one it encloses makes an `Rc` cycle that would otherwise never be
+/// dropped.
+///
+/// Every cycle goes through an environment some function closed over, so
+/// those are the ones tracked. A collection walks everything they reach
+/// and subtracts the references found inside the walk from each `Rc`'s
+/// count. What has references left over is held from outside, by the
+/// interpreter or a native, and it and everything it reaches stay alive.
+/// The rest is garbage, and emptying its environments breaks the cycles.
+/// References a native's closure holds can't be seen, so they only ever
+/// keep things alive.
+#[derive(Default)]
+pub struct Collector {
+    tracked: Vec<Weak<RefCell<Environment>>>,
+    /// How many tracked environments trigger the next collection.
+    threshold: usize,
+    stats: GcStats,
+}
+
+/// An environment, captured variable, function, map or record found in a
+/// collection.
+struct Node {
+    strong: usize,
+    internal: usize,
+    references: Vec<usize>,
+    environment: Option<Weak<RefCell<Environment>>>,
+    /// Set when the node was borrowed, so its references couldn't be read.
+    opaque: bool,
+}
+
+const FIRST_THRESHOLD: usize = 1024;
+
+impl Collector {
+    /// Tracks an environment a function closed over, collecting once
+    /// enough have been tracked since the last collection.
+    pub fn track(&mut self, environment: &Rc<RefCell<Environment>>) {
+        self.tracked.push(Rc::downgrade(environment));
+        if self.tracked.len() >= self.threshold.max(FIRST_THRESHOLD) {
+            self.collect();
+            self.threshold = self.tracked.len() * 2;
+        }
+    }
+
+    pub fn stats(&self) -> &GcStats {
+        &self.stats
+    }
+
+    /// Frees what's unreachable now, returning how many environments that
+    /// emptied.
+    pub fn collect(&mut self) -> usize {
+        self.tracked.retain(|environment| environment.strong_count() > 0);
+        let mut nodes = HashMap::new();
+        let mut pending = self.tracked.clone();
+        while let Some(weak) = pending.pop() {
+            let address = weak.as_ptr() as usize;
+            if nodes.contains_key(&address) {
+                continue;
+            }
+            let strong = weak.strong_count();
+            let Some(environment) = weak.upgrade() else {
+                continue;
+            };
+            let mut node = Node {
+                strong,
+                internal: 0,
+                references: vec![],
+                environment: Some(weak),
+                opaque: false,
+            };
+            match environment.try_borrow() {
+                Ok(environment) => {
+                    if let Some(enclosing) = &environment.enclosing {
+                        node.references.push(Rc::as_ptr(enclosing) as usize);
+                        pending.push(Rc::downgrade(enclosing));
+                    }
+                    for (_, slot) in environment.slots() {
+                        match slot {
+                            Slot::Value(value) => visit(value, &mut node.references, &mut nodes, &mut pending),
+                            Slot::Captured(cell) => visit_cell(cell, &mut node.references, &mut nodes, &mut pending),
+                        }
+                    }
+                }
+                Err(_) => node.opaque = true,
+            }
+            nodes.insert(address, node);
+        }
+
+        let references: Vec<usize> = nodes.values().flat_map(|node| node.references.clone()).collect();
+        for reference in references {
+            if let Some(node) = nodes.get_mut(&reference) {
+                node.internal += 1;
+            }
+        }
+        let mut live: Vec<usize> = nodes
+            .iter()
+            .filter(|(_, node)| node.opaque || node.strong > node.internal)
+            .map(|(&address, _)| address)
+            .collect();
+        let mut reached = HashSet::new();
+        while let Some(address) = live.pop() {
+            if reached.insert(address) {
+                live.extend(nodes.get(&address).into_iter().flat_map(|node| node.references.iter().copied()));
+            }
+        }
+
+        // Dropped only once every borrow is let go, since dropping an
+        // environment can drop others.
+        let mut garbage = vec![];
+        for (address, node) in &nodes {
+            let Some(environment) = node.environment.as_ref().and_then(Weak::upgrade) else {
+                continue;
+            };
+            if reached.contains(address) {
+                continue;
+            }
+            let contents = environment.try_borrow_mut().map(|mut environment| std::mem::take(&mut *environment));
+            garbage.extend(contents.ok());
+        }
+        let freed = garbage.len();
+        drop(garbage);
+        self.tracked.retain(|environment| environment.strong_count() > 0);
+        self.stats.collections += 1;
+        self.stats.environments_freed += freed as u64;
+        freed
+    }
+}
+
+/// Records the references `value` holds, adding the functions, maps and
+/// records it reaches as nodes.
+fn visit(
+    value: &Literal,
+    references: &mut Vec<usize>,
+    nodes: &mut HashMap<usize, Node>,
+    pending: &mut Vec<Weak<RefCell<Environment>>>,
+) {
+    let (address, node) = match value {
+        Literal::LoxFunction(function) => {
+            let address = Rc::as_ptr(function) as *const () as usize;
+            references.push(address);
+            if nodes.contains_key(&address) {
+                return;
+            }
+            pending.push(Rc::downgrade(&function.closure));
+            pending.push(Rc::downgrade(&function.globals));
+            let node = Node {
+                strong: Rc::strong_count(function),
+                internal: 0,
+                references: vec![Rc::as_ptr(&function.closure) as usize, Rc::as_ptr(&function.globals) as usize],
+                environment: None,
+                opaque: false,
+            };
+            (address, node)
+        }
+        Literal::Map(members) | Literal::Record(members) => {
+            let address = Rc::as_ptr(members) as *const () as usize;
+            references.push(address);
+            if nodes.contains_key(&address) {
+                return;
+            }
+            let node = members_node(members, nodes, pending);
+            (address, node)
+        }
+        _ => return,
+    };
+    nodes.insert(address, node);
+}
+
+fn visit_cell(
+    cell: &Rc<RefCell<Literal>>,
+    references: &mut Vec<usize>,
+    nodes: &mut HashMap<usize, Node>,
+    pending: &mut Vec<Weak<RefCell<Environment>>>,
+) {
+    let address = Rc::as_ptr(cell) as *const () as usize;
+    references.push(address);
+    if nodes.contains_key(&address) {
+        return;
+    }
+    let mut node = Node {
+        strong: Rc::strong_count(cell),
+        internal: 0,
+        references: vec![],
+        environment: None,
+        opaque: false,
+    };
+    match cell.try_borrow() {
+        Ok(value) => visit(&value, &mut node.references, nodes, pending),
+        Err(_) => node.opaque = true,
+    }
+    nodes.insert(address, node);
+}
+
+fn members_node(
+    members: &Rc<Members>,
+    nodes: &mut HashMap<usize, Node>,
+    pending: &mut Vec<Weak<RefCell<Environment>>>,
+) -> Node {
+    let mut references = vec![];
+    for value in members.values() {
+        visit(value, &mut references, nodes, pending);
+    }
+    Node {
+        strong: Rc::strong_count(members),
+        internal: 0,
+        references,
+        environment: None,
+        opaque: false,
+    }
+}
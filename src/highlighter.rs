@@ -0,0 +1,147 @@
+use crate::scanner;
+use crate::token::{Token, TokenType};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HighlightFormat {
+    #[default]
+    Ansi,
+    Html,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Keyword,
+    String,
+    Number,
+    Identifier,
+    Operator,
+    Comment,
+}
+
+impl Style {
+    fn of(token_type: TokenType) -> Style {
+        use TokenType::*;
+        match token_type {
+            _ if scanner::is_keyword(token_type) => Style::Keyword,
+            TokenType::String => Style::String,
+            Number => Style::Number,
+            Identifier => Style::Identifier,
+            _ => Style::Operator,
+        }
+    }
+
+    fn ansi(self) -> &'static str {
+        match self {
+            Style::Keyword => "\x1b[1;35m",
+            Style::String => "\x1b[32m",
+            Style::Number => "\x1b[33m",
+            Style::Identifier => "",
+            Style::Operator => "\x1b[36m",
+            Style::Comment => "\x1b[2;37m",
+        }
+    }
+
+    fn css(self) -> &'static str {
+        match self {
+            Style::Keyword => "lox-keyword",
+            Style::String => "lox-string",
+            Style::Number => "lox-number",
+            Style::Identifier => "lox-identifier",
+            Style::Operator => "lox-operator",
+            Style::Comment => "lox-comment",
+        }
+    }
+}
+
+/// Colorizes `source` from the tokens the scanner produced for it. Text
+/// between tokens is copied through, with `//` comments styled as well.
+pub struct Highlighter<'a> {
+    source: &'a str,
+    position: usize,
+    format: HighlightFormat,
+    out: String,
+}
+
+impl<'a> Highlighter<'a> {
+    pub fn new(source: &'a str, format: HighlightFormat) -> Self {
+        Self {
+            source,
+            position: 0,
+            format,
+            out: String::new(),
+        }
+    }
+
+    pub fn highlight(mut self, tokens: &[Token]) -> String {
+        if self.format == HighlightFormat::Html {
+            self.out.push_str("<pre class=\"lox\"><code>");
+        }
+        for token in tokens {
+            if token.token_type == TokenType::Eof {
+                continue;
+            }
+            let lexeme: &str = &token.lexeme;
+            while self.position < self.source.len()
+                && (self.rest().starts_with("//") || !self.rest().starts_with(lexeme))
+            {
+                self.trivia();
+            }
+            self.styled(Style::of(token.token_type), lexeme);
+            self.position += lexeme.len();
+        }
+        while self.position < self.source.len() {
+            self.trivia();
+        }
+        if self.format == HighlightFormat::Html {
+            self.out.push_str("</code></pre>\n");
+        }
+        self.out
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    /// Copies a comment, or a single character of anything else.
+    fn trivia(&mut self) {
+        let rest = self.rest();
+        if rest.starts_with("//") {
+            let comment = &rest[..rest.find('\n').unwrap_or(rest.len())];
+            self.styled(Style::Comment, comment);
+            self.position += comment.len();
+        } else if let Some(c) = rest.chars().next() {
+            self.plain(&rest[..c.len_utf8()]);
+            self.position += c.len_utf8();
+        }
+    }
+
+    fn plain(&mut self, text: &str) {
+        match self.format {
+            HighlightFormat::Ansi => self.out.push_str(text),
+            HighlightFormat::Html => self.out.push_str(&escape_html(text)),
+        }
+    }
+
+    fn styled(&mut self, style: Style, text: &str) {
+        match self.format {
+            HighlightFormat::Ansi if style.ansi().is_empty() => self.out.push_str(text),
+            HighlightFormat::Ansi => {
+                self.out.push_str(style.ansi());
+                self.out.push_str(text);
+                self.out.push_str("\x1b[0m");
+            }
+            HighlightFormat::Html => {
+                let text = escape_html(text);
+                self.out
+                    .push_str(&format!("<span class=\"{}\">{}</span>", style.css(), text));
+            }
+        }
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
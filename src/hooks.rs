@@ -0,0 +1,34 @@
+use crate::error::{LoxError, Span};
+use crate::expr::Expr;
+use crate::interpreter::Interpreter;
+use crate::stmt::Stmt;
+use crate::token::Literal;
+
+/// Called before each statement runs, with the interpreter about to run
+/// it, whose [`Interpreter::environment`] is the one it runs in. The span
+/// covers the statement's line; its column isn't known.
+pub type StatementHook = Box<dyn FnMut(&mut Interpreter, &Stmt, Span)>;
+/// Called with each expression and the value it evaluated to.
+pub type ExpressionHook = Box<dyn FnMut(&mut Interpreter, &Expr, &Literal)>;
+/// Called with the callee and arguments before a call, at its `(`. Calls
+/// natives make back into the script, as `map` does, are at line 0.
+pub type CallHook = Box<dyn FnMut(&mut Interpreter, &Literal, &[Literal], Span)>;
+/// Called with the callee and the value it returned after a call
+/// finishes without an error.
+pub type ReturnHook = Box<dyn FnMut(&mut Interpreter, &Literal, &Literal, Span)>;
+/// Called with every error reported, whichever phase found it.
+pub type ErrorHook = Box<dyn FnMut(&LoxError)>;
+
+/// The instrumentation registered on an interpreter. Tracing, `--stats`,
+/// the debugger and coverage watch a run through these instead of each
+/// patching the interpreter's loop; hooks run in the order registered.
+/// While hooks of a kind run they're set aside, so code a hook runs, like
+/// the debugger's, doesn't fire them again.
+#[derive(Default)]
+pub struct Hooks {
+    pub statement: Vec<StatementHook>,
+    pub expression: Vec<ExpressionHook>,
+    pub call: Vec<CallHook>,
+    pub ret: Vec<ReturnHook>,
+    pub error: Vec<ErrorHook>,
+}
@@ -0,0 +1,91 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long a request may wait to connect, or on the server once
+/// connected, before giving up.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A response's status code and body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Sends a request and reads the whole response. Only plain `http://`
+/// URLs work, since there's no TLS without a dependency. Requests are
+/// HTTP/1.0 so servers close the connection when they're done rather
+/// than chunking the body. URLs with spaces or control characters are
+/// refused, since those would let a URL add headers of its own. A
+/// response longer than `limit` bytes fails rather than being read whole.
+pub fn request(method: &str, url: &str, body: Option<&str>, limit: Option<usize>) -> io::Result<Response> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_string());
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid("only http:// URLs are supported"))?;
+    if rest.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return Err(invalid("URLs can't contain spaces or control characters"));
+    }
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    // An IPv6 host is bracketed, and has colons of its own before the port.
+    let port_start = authority.rfind(']').unwrap_or(0);
+    let address = if authority[port_start..].contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let mut stream = connect(&address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut head = format!("{} {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: rlox\r\n", method, path, authority);
+    if let Some(body) = body {
+        head.push_str(&format!("Content-Type: text/plain\r\nContent-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.unwrap_or_default().as_bytes())?;
+
+    let mut response = vec![];
+    match limit {
+        Some(limit) => {
+            stream.take(limit as u64 + 1).read_to_end(&mut response)?;
+            if response.len() > limit {
+                let message = format!("the response is over the {} bytes of memory left", limit);
+                return Err(io::Error::new(io::ErrorKind::OutOfMemory, message));
+            }
+        }
+        None => {
+            stream.read_to_end(&mut response)?;
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed response");
+    let (head, body) = response.split_once("\r\n\r\n").ok_or_else(malformed)?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(malformed)?;
+    Ok(Response {
+        status,
+        body: body.to_string(),
+    })
+}
+
+/// Connects to the first of the addresses `address` resolves to that
+/// answers within [`TIMEOUT`].
+fn connect(address: &str) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address found for the host");
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
@@ -1,202 +1,1798 @@
 use std::error::Error;
 use std::fs;
-use std::io::{stderr, Write};
-use std::process::exit;
-use std::collections::HashMap;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::callable::Callable;
+use crate::ast_cache;
+use crate::ast_json::{literal_from_json, literal_to_json, program_from_json, program_to_json};
+use crate::ast_printer::AstPrinter;
+use crate::bytecode::{FunctionProto, Value};
+use crate::callable::{BoundFunction, Callable};
+use crate::compiler::Compiler;
+use crate::docs;
+use crate::dot_printer::DotPrinter;
 use crate::environment::Environment;
+use crate::hooks::{CallHook, ErrorHook, ExpressionHook, Hooks, ReturnHook, StatementHook};
 use crate::error::*;
-use crate::expr::Expr;
+use crate::error_codes;
+use crate::event_loop::{self, EventLoop};
+use crate::formatter::FormatOptions;
+use crate::gc::Collector;
+use crate::iterator;
+use crate::expr::{Expr, ExprId};
+use crate::json::Json;
+use crate::logging::{self, LogLevel};
 use crate::lox_function::LoxFunction;
+use crate::memory;
+use crate::modules::ModulePath;
 use crate::native_function::*;
+use crate::optimizer::Optimizer;
 use crate::parser::Parser;
 use crate::resolver::{Resolver, Resolve};
 use crate::scanner::Scanner;
-use crate::stmt::Stmt;
+use crate::coverage::Coverage;
+use crate::source_map::{SourceId, SourceMap};
+use crate::stack::StackGuard;
+use crate::stats::Stats;
+use crate::stdlib::{self, Capability};
+use crate::stmt::{PendingLabel, Stmt};
+use crate::symbol::Symbol;
+use crate::token::EnumCase;
 use crate::token::Literal;
+use crate::token::Members;
 use crate::token::Token;
 use crate::token::TokenType;
+use crate::typecheck::{Type, TypeChecker};
+use crate::vm::Vm;
+use crate::warning::{self, Suppressions, Warning, Warnings};
 
 pub type InterpreterResult<T> = Result<T, RuntimeException>;
 
+/// Which engine executes a program once the front end has resolved it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    TreeWalk,
+    Vm,
+}
+
+/// A program readied by [`Interpreter::prepare`] for the backend that
+/// runs it.
+pub enum Prepared {
+    Tree(Vec<Stmt>),
+    Vm(Rc<FunctionProto>),
+}
+
+/// Which values count as false in conditions and under `!`, `and` and
+/// `or`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Truthiness {
+    /// Only `nil` and `false`, as in Lox.
+    #[default]
+    Lox,
+    /// `0` and `""` as well, as in most scripting languages.
+    Script,
+}
+
+/// Which dialect of Lox to run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compat {
+    /// This interpreter's Lox, extensions and all.
+    #[default]
+    Extended,
+    /// The book's jlox: `var x;` is nil, `+` doesn't stringify, division
+    /// by zero gives infinity or NaN, and extensions like `break` and
+    /// lambdas are syntax errors.
+    Jlox,
+}
+
+impl Compat {
+    /// How jlox prints a number Rust would print as `inf` or `NaN`.
+    pub fn non_finite_name(self, n: f64) -> Option<&'static str> {
+        match self {
+            Compat::Jlox if n.is_nan() => Some("NaN"),
+            Compat::Jlox if n == f64::INFINITY => Some("Infinity"),
+            Compat::Jlox if n == f64::NEG_INFINITY => Some("-Infinity"),
+            _ => None,
+        }
+    }
+}
+
+/// How `--ast` style flags dump a program instead of running it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AstFormat {
+    SExpr,
+    Dot,
+    /// Writes the program as JSON to the given path.
+    Json(String),
+}
+
+/// How a run ended. Only the `rlox` binary turns this into a process exit
+/// status, so an embedding program keeps control.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStatus {
+    Success,
+    /// A scan, parse, resolve or compile error stopped the program
+    /// before it ran.
+    StaticError,
+    RuntimeError,
+    /// The program ran, but a `test()` block failed.
+    TestFailure,
+    /// `rlox lint` found warnings, but no errors.
+    Warnings,
+}
+
+impl RunStatus {
+    /// The exit status `rlox` uses, following the sysexits codes jlox
+    /// uses.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            RunStatus::Success => 0,
+            RunStatus::StaticError => 65,
+            RunStatus::RuntimeError => 70,
+            RunStatus::TestFailure | RunStatus::Warnings => 1,
+        }
+    }
+}
+
+/// The global state of an interpreter at one point, to go back to with
+/// [`Interpreter::restore`]. Values are copied shallowly: a closure's
+/// captured locals stay shared with the live interpreter.
 #[derive(Clone)]
+pub struct Snapshot {
+    globals: Environment,
+    vm_globals: HashMap<Symbol, Value>,
+}
+
+impl Snapshot {
+    /// The globals holding plain values (nil, booleans, numbers and
+    /// strings) as a JSON object, from both backends. Functions and
+    /// natives are left out.
+    pub fn to_json(&self) -> Json {
+        let vm_values = self
+            .vm_globals
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_plain_literal()?)));
+        let mut values: Vec<(&str, Literal)> = self
+            .globals
+            .values()
+            .filter(|(_, value)| is_plain(value))
+            .map(|(name, value)| (name.as_str(), value))
+            .chain(vm_values)
+            .collect();
+        values.sort_by_key(|&(name, _)| name);
+        Json::Object(
+            values
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), literal_to_json(&value)))
+                .collect(),
+        )
+    }
+
+    /// A snapshot of a fresh interpreter with the globals in `json`, as
+    /// written by [`Snapshot::to_json`], defined on top of the natives.
+    pub fn from_json(json: &Json) -> Result<Snapshot, String> {
+        let Json::Object(fields) = json else {
+            return Err("Expected an object of globals.".to_string());
+        };
+        let mut globals = native_globals().borrow().clone();
+        let mut vm_globals = Vm::default().globals().clone();
+        for (name, value) in fields {
+            let value = literal_from_json(value)?;
+            if let Some(vm_value) = Value::from_plain_literal(&value) {
+                vm_globals.insert(Symbol::intern(name), vm_value);
+            }
+            globals.define(Symbol::intern(name), value);
+        }
+        Ok(Snapshot { globals, vm_globals })
+    }
+}
+
+/// `text` repeated `count` times, for `*` between a string and a number.
+/// Shared with the VM so both backends agree on the errors.
+pub fn repeat_string(text: &str, count: f64) -> Result<String, String> {
+    if count < 0.0 || count.fract() != 0.0 {
+        return Err("A string can only be repeated a whole, non-negative number of times.".to_string());
+    }
+    match text.len().checked_mul(count as usize) {
+        Some(length) if length <= isize::MAX as usize => Ok(text.repeat(count as usize)),
+        _ => Err("String repetition is too long.".to_string()),
+    }
+}
+
+fn is_plain(value: &Literal) -> bool {
+    matches!(
+        value,
+        Literal::Nil | Literal::True | Literal::False | Literal::Int(_) | Literal::Number(_) | Literal::String(_)
+    )
+}
+
 pub struct Interpreter {
     had_error: bool,
     had_runtime_error: bool,
-    pub environment: Environment,
+    errors: Vec<LoxError>,
+    pub globals: Rc<RefCell<Environment>>,
+    pub environment: Rc<RefCell<Environment>>,
     repl: bool,
     loop_count: u32,
-    locals: HashMap<Expr, u32>
+    label: PendingLabel,
+    locals: HashMap<ExprId, u32>,
+    /// The variables each function declaration captures from the
+    /// functions and blocks around it, with how many environments up from
+    /// where it's declared each lives.
+    captures: HashMap<ExprId, Rc<[(Symbol, u32)]>>,
+    backend: Backend,
+    optimize: bool,
+    check: bool,
+    /// Leaves globals to be looked up when they're used, so the resolver
+    /// doesn't report ones it can't see declared.
+    late_binding: bool,
+    compat: Compat,
+    truthiness: Truthiness,
+    /// Whether `+` with one string operand stringifies the other.
+    string_coercion: bool,
+    /// What `print a, b;` puts between the values.
+    print_separator: String,
+    format_options: FormatOptions,
+    /// Whether `print` is a native function rather than a statement.
+    print_function: bool,
+    log_level: LogLevel,
+    /// Whether reported errors show their codes, see
+    /// [`Interpreter::set_error_codes`].
+    error_codes: bool,
+    warnings: Warnings,
+    /// Rules turned off with [`Interpreter::set_rule`].
+    disabled_rules: Vec<&'static str>,
+    /// The lines `// lox-ignore:` comments silence warnings on, shared
+    /// with every scanner the interpreter makes.
+    suppressions: Suppressions,
+    /// The operators a runtime warning has been given for, by where they
+    /// are, so each warns only once.
+    warned_sites: HashSet<(SourceId, u32, u32)>,
+    dump_ast: Option<AstFormat>,
+    /// Line of the statement being traced, for expressions without one.
+    trace_line: u32,
+    call_depth: usize,
+    /// What the last top-level expression statement evaluated to, or what
+    /// a top-level `return` gave, for [`Interpreter::eval_str`].
+    last_value: Literal,
+    /// Lets programs `return` from the top level, see
+    /// [`Interpreter::set_top_level_return`].
+    top_level_return: bool,
+    /// Whether a top-level `return` gives an exit status, see
+    /// [`Interpreter::set_return_exit_status`].
+    return_exit_status: bool,
+    /// What a top-level `return` ended the last program with.
+    returned: Option<Literal>,
+    /// Set for the length of a run, to catch runaway recursion.
+    stack: Option<StackGuard>,
+    stats: Stats,
+    /// Whether hooks are counting [`Stats`].
+    counting: bool,
+    print_stats: bool,
+    /// Frees the environments closures keep alive in cycles.
+    gc: Collector,
+    print_gc_stats: bool,
+    /// The most memory scripts may hold, see [`Interpreter::set_max_memory`].
+    max_memory: Option<usize>,
+    /// See [`Interpreter::set_max_loop_iterations`].
+    max_loop_iterations: Option<u64>,
+    /// Capabilities [`Interpreter::restrict`] took away.
+    denied: Vec<Capability>,
+    /// What the last measurement found, and roughly how much has been
+    /// allocated since.
+    measured_memory: usize,
+    unmeasured_memory: usize,
+    /// Whether the debugger is attached, to pause at `breakpoint()`.
+    debug: bool,
+    test_mode: bool,
+    coverage: Option<Rc<RefCell<Coverage>>>,
+    hooks: Hooks,
+    /// Where `--coverage=` writes an lcov tracefile, if it was given one.
+    lcov_path: Option<String>,
+    /// The script being run, for reports that name it.
+    script_path: Option<String>,
+    /// The files loaded besides the main script, for errors to name.
+    sources: SourceMap,
+    /// The file source being run is scanned as coming from.
+    current_source: SourceId,
+    /// Where `import` looks for modules, and what it's loaded so far.
+    module_path: ModulePath,
+    modules: HashMap<PathBuf, Literal>,
+    /// The modules being run, innermost last, so one that ends up
+    /// importing itself is caught.
+    loading: Vec<PathBuf>,
+    /// Each `test()` block run in test mode, with its failure if it had one.
+    test_results: Vec<(String, Option<String>)>,
+    /// Set while a `breakpoint()` prompt runs: names the resolver left as
+    /// globals are looked up through the paused scope instead.
+    dynamic_scope: bool,
+    /// Where `print` writes when output is captured instead of going to
+    /// stdout.
+    output: Option<String>,
+    /// Where `lines()` and `readAll()` read from instead of stdin, see
+    /// [`Interpreter::set_input`].
+    input: Option<Box<dyn BufRead>>,
+    /// Callbacks queued by `setTimeout` and `setInterval`.
+    event_loop: EventLoop,
+    /// Stands in for the system clock when set, see
+    /// [`Interpreter::set_clock`].
+    clock: Option<Rc<dyn Fn() -> f64>>,
+    /// Extension keywords enabled on top of the standard ones.
+    keywords: Vec<(String, TokenType)>,
+    vm: Vm,
+}
+
+/// The `close` member of a map or record, if it is a function that takes
+/// no arguments.
+fn close_function(resource: &Literal) -> Option<Rc<dyn Callable>> {
+    let (Literal::Map(members) | Literal::Record(members)) = resource else {
+        return None;
+    };
+    let close: Rc<dyn Callable> = match members.get("close")? {
+        Literal::LoxFunction(function) => function.clone(),
+        Literal::NativeFunction(function) => Rc::new(function.clone()),
+        _ => return None,
+    };
+    close.accepts(0).then_some(close)
+}
+
+/// How many times a loop run at the REPL may go round, unless
+/// [`Interpreter::set_max_loop_iterations`] says otherwise.
+pub const REPL_MAX_LOOP_ITERATIONS: u64 = 1_000_000;
+
+/// Asks the terminal to mark pasted text with [`PASTE_START`] and
+/// [`PASTE_END`], and stops it asking.
+const BRACKETED_PASTE_ON: &str = "\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &str = "\x1b[?2004l";
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+/// Reads a paste for the REPL: `first`, then lines up to where `end`
+/// finds its end in one, or to the end of input.
+fn read_paste(first: String, end: impl Fn(&str) -> Option<usize>) -> String {
+    let mut chunk = String::new();
+    let mut line = first;
+    loop {
+        if let Some(cut) = end(&line) {
+            chunk.push_str(&line[..cut]);
+            return chunk;
+        }
+        chunk.push_str(&line);
+        line.clear();
+        if !matches!(io::stdin().read_line(&mut line), Ok(read) if read > 0) {
+            return chunk;
+        }
+    }
+}
+
+/// `help(fn)` prints a function's signature and doc comment, and
+/// `help()` the signature of every native function.
+fn help(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let error = |code, message: &str| RuntimeException::base(code, Token::default(), message.to_string());
+    let text = match args {
+        [] => docs::native_index(&interpreter.globals()),
+        [value] => docs::help(value).ok_or_else(|| error("E0018", "help expects a function or a module."))?,
+        _ => return Err(error("E0005", "help takes at most one argument.")),
+    };
+    interpreter.print_line(text);
+    Ok(Literal::Nil)
+}
+
+/// A global scope holding only the native functions and modules.
+fn native_globals() -> Rc<RefCell<Environment>> {
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    let breakpoint = Literal::NativeFunction(NativeFunction::new("breakpoint", 0, breakpoint));
+    globals.borrow_mut().define(Symbol::intern("breakpoint"), breakpoint);
+    let test = Literal::NativeFunction(NativeFunction::new("test", 2, test));
+    globals.borrow_mut().define(Symbol::intern("test"), test);
+    let expect = Literal::NativeFunction(NativeFunction::new("expect", 2, expect));
+    globals.borrow_mut().define(Symbol::intern("expect"), expect);
+    let deep_equal = Literal::NativeFunction(NativeFunction::new("deepEqual", 2, deep_equal));
+    globals.borrow_mut().define(Symbol::intern("deepEqual"), deep_equal);
+    let freeze = Literal::NativeFunction(NativeFunction::new("freeze", 1, freeze));
+    globals.borrow_mut().define(Symbol::intern("freeze"), freeze);
+    let help = Literal::NativeFunction(NativeFunction::variadic("help", 0, help));
+    globals.borrow_mut().define(Symbol::intern("help"), help);
+    let repr = Literal::NativeFunction(NativeFunction::new("repr", 1, repr));
+    globals.borrow_mut().define(Symbol::intern("repr"), repr);
+    let globals_native = Literal::NativeFunction(NativeFunction::new("globals", 0, globals_map));
+    globals.borrow_mut().define(Symbol::intern("globals"), globals_native);
+    let gc_collect = Literal::NativeFunction(NativeFunction::new("gcCollect", 0, gc_collect));
+    globals.borrow_mut().define(Symbol::intern("gcCollect"), gc_collect);
+    let memory_used = Literal::NativeFunction(NativeFunction::new("memoryUsed", 0, memory_used));
+    globals.borrow_mut().define(Symbol::intern("memoryUsed"), memory_used);
+    for (name, value) in event_loop::natives() {
+        globals.borrow_mut().define(Symbol::intern(name), value);
+    }
+    for (name, value) in iterator::natives() {
+        globals.borrow_mut().define(Symbol::intern(name), value);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    for (name, value) in crate::threads::natives() {
+        globals.borrow_mut().define(Symbol::intern(name), value);
+    }
+    let natives = stdlib::modules().into_iter().chain(stdlib::globals()).chain(stdlib::capability_globals());
+    for (name, value) in natives.chain(logging::globals()) {
+        globals.borrow_mut().define(Symbol::intern(name), value);
+    }
+    globals
 }
 
 impl Default for Interpreter {
     fn default() -> Self {
-        let mut environment = Environment::new();
-        let clock = Literal::NativeFunction(NativeFunction {
-            name: "clock".to_string(),
-            arity: 0,
-            callable: clock,
-        });
-        environment.define("clock".to_string(), clock);
-        Self {
+        let globals = native_globals();
+        let mut interpreter = Self {
             had_error: false,
             had_runtime_error: false,
-            environment,
+            errors: vec![],
+            environment: globals.clone(),
+            globals,
             repl: false,
             loop_count: 0,
-            locals: HashMap::new()
-        }
+            label: PendingLabel::default(),
+            locals: HashMap::new(),
+            captures: HashMap::new(),
+            backend: Backend::default(),
+            optimize: false,
+            check: false,
+            late_binding: false,
+            compat: Compat::default(),
+            truthiness: Truthiness::default(),
+            string_coercion: true,
+            print_separator: " ".to_string(),
+            format_options: FormatOptions::default(),
+            print_function: false,
+            log_level: LogLevel::default(),
+            error_codes: false,
+            warnings: Warnings::default(),
+            disabled_rules: vec![],
+            suppressions: Suppressions::default(),
+            warned_sites: HashSet::new(),
+            dump_ast: None,
+            trace_line: 0,
+            call_depth: 0,
+            last_value: Literal::Nil,
+            top_level_return: false,
+            return_exit_status: false,
+            returned: None,
+            stack: None,
+            stats: Stats::default(),
+            counting: false,
+            print_stats: false,
+            gc: Collector::default(),
+            print_gc_stats: false,
+            max_memory: None,
+            max_loop_iterations: None,
+            denied: vec![],
+            measured_memory: 0,
+            unmeasured_memory: 0,
+            debug: false,
+            test_mode: false,
+            coverage: None,
+            hooks: Hooks::default(),
+            lcov_path: None,
+            script_path: None,
+            sources: SourceMap::new(),
+            current_source: SourceId::MAIN,
+            module_path: ModulePath::default(),
+            modules: HashMap::new(),
+            loading: vec![],
+            test_results: vec![],
+            dynamic_scope: false,
+            output: None,
+            input: None,
+            event_loop: EventLoop::default(),
+            clock: None,
+            keywords: vec![],
+            vm: Vm::default(),
+        };
+        interpreter.share_natives_with_vm();
+        interpreter
     }
 }
 
 impl Interpreter {
-    pub fn new(environment: &Environment) -> Self {
-        Self {
-            had_error: false,
-            had_runtime_error: false,
-            environment: Environment::with_enclosing(environment.clone()),
-            loop_count: 0,
-            repl: false,
-            locals: HashMap::new()
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Runs the [`Optimizer`] over every program after resolution.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Runs the [`TypeChecker`] over every program after resolution, which
+    /// then only runs if the check passes.
+    pub fn set_check(&mut self, check: bool) {
+        self.check = check;
+    }
+
+    /// Has `import` look for modules in `dir` too, after the importing
+    /// file's directory and any added before it.
+    pub fn add_import_path(&mut self, dir: &str) {
+        self.module_path.add(dir);
+    }
+
+    /// Runs the module `name` names, found on the module path, in a scope
+    /// of its own in front of the globals, and gives back the top-level
+    /// names it exports as a map, or all of them if it exports none.
+    /// Importing a module that's still being run, directly or through
+    /// others, is an error naming the chain of imports, rather than giving
+    /// back a module only partly defined. A module is run once; importing it again gives the
+    /// same map.
+    pub fn import_module(&mut self, name: &str) -> InterpreterResult<Literal> {
+        if !self.allows(Capability::Fs) {
+            let message = "Importing modules needs the 'fs' capability.".to_string();
+            return Err(RuntimeException::base("E0011", Token::default(), message));
+        }
+        let importer = match self.current_source {
+            SourceId::MAIN => self.script_path.as_deref(),
+            source => self.sources.name(source),
+        };
+        let from = importer.and_then(|path| Path::new(path).parent()).unwrap_or(Path::new("")).to_path_buf();
+        let path = self
+            .module_path
+            .find(name, &from)
+            .map_err(|message| RuntimeException::base("E0011", Token::default(), message))?;
+        if let Some(module) = self.modules.get(&path) {
+            return Ok(module.clone());
+        }
+        let script = self.script_path.as_ref().and_then(|script| fs::canonicalize(script).ok());
+        let chain: Vec<&PathBuf> = script.iter().chain(&self.loading).collect();
+        if let Some(start) = chain.iter().position(|loading| **loading == path) {
+            let names: Vec<_> = chain[start..]
+                .iter()
+                .copied()
+                .chain([&path])
+                .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+                .collect();
+            let message = format!("Circular import: {}.", names.join(" -> "));
+            return Err(RuntimeException::base("E0011", Token::default(), message));
+        }
+        let contents = fs::read_to_string(&path).map_err(|err| {
+            RuntimeException::base("E0011", Token::default(), format!("Could not read module '{}': {}.", name, err))
+        })?;
+
+        // Named like the script is, relative to where the interpreter runs.
+        let shown = std::env::current_dir().ok().and_then(|dir| path.strip_prefix(dir).ok()).unwrap_or(&path);
+        let source = std::mem::replace(&mut self.current_source, self.sources.add(&shown.to_string_lossy()));
+        self.loading.push(path.clone());
+        let statements = self.parse(contents);
+        if let Some(statements) = &statements {
+            Resolver::new(self).resolve(statements.as_slice());
+        }
+        let result = match statements {
+            Some(statements) if !self.had_error => self.run_module(&statements),
+            _ => Err(RuntimeException::base(
+                "E0011",
+                Token::default(),
+                format!("Module '{}' has errors.", name),
+            )),
+        };
+        self.current_source = source;
+        self.loading.pop();
+
+        let module = result?;
+        self.modules.insert(path, module.clone());
+        Ok(module)
+    }
+
+    fn run_module(&mut self, statements: &[Stmt]) -> InterpreterResult<Literal> {
+        let scope = Rc::new(RefCell::new(Environment::with_enclosing(self.globals.clone())));
+        let globals = std::mem::replace(&mut self.globals, scope.clone());
+        let environment = std::mem::replace(&mut self.environment, scope.clone());
+        let result = statements.iter().try_for_each(|stmt| self.execute(stmt));
+        self.globals = globals;
+        self.environment = environment;
+        match result {
+            Ok(()) | Err(RuntimeException::Return(_)) => (),
+            Err(err) => return Err(err),
         }
+        let exports: Vec<Symbol> = statements
+            .iter()
+            .filter(|stmt| matches!(stmt, Stmt::Export(_)))
+            .flat_map(|stmt| stmt.declared().into_iter().map(|name| name.lexeme))
+            .collect();
+        let members = scope
+            .borrow()
+            .values()
+            .filter(|(name, _)| exports.is_empty() || exports.contains(name))
+            .map(|(name, value)| (Rc::from(name.as_str()), value))
+            .collect();
+        Ok(Literal::Map(Rc::new(members)))
     }
 
-    pub fn run_file(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+    /// Lets scripts use globals the resolver can't see declared, like ones
+    /// a later REPL line defines, failing only if they're still undefined
+    /// when they're used.
+    pub fn set_late_binding(&mut self, late_binding: bool) {
+        self.late_binding = late_binding;
+    }
+
+    /// Whether the resolver should report uses of globals that aren't
+    /// declared. Not at a `breakpoint()` prompt, where names it leaves as
+    /// globals may be the paused scope's locals.
+    pub fn checks_globals(&self) -> bool {
+        !self.late_binding && !self.dynamic_scope
+    }
+
+    /// Switches dialect. [`Compat::Jlox`] also puts truthiness, string
+    /// coercion and late binding back to the book's, though any can be
+    /// changed after.
+    pub fn set_compat(&mut self, compat: Compat) {
+        self.compat = compat;
+        self.vm.set_compat(compat);
+        if compat == Compat::Jlox {
+            self.set_truthiness(Truthiness::Lox);
+            self.set_string_coercion(false);
+            self.set_late_binding(true);
+        }
+    }
+
+    pub fn set_truthiness(&mut self, truthiness: Truthiness) {
+        self.truthiness = truthiness;
+        self.vm.set_truthiness(truthiness);
+    }
+
+    /// Whether `"a" + 1` makes `"a1"`, as it does by default, or is an
+    /// error as in reference Lox.
+    pub fn set_string_coercion(&mut self, string_coercion: bool) {
+        self.string_coercion = string_coercion;
+        self.vm.set_string_coercion(string_coercion);
+    }
+
+    /// What `print a, b;` puts between the values, a space by default.
+    pub fn set_print_separator(&mut self, separator: &str) {
+        self.print_separator = separator.to_string();
+    }
+
+    /// Logs every statement the tree-walker executes and every expression
+    /// result to stderr, indented by call depth.
+    pub fn set_trace(&mut self, trace: bool) {
+        if trace {
+            self.on_statement(Box::new(|interpreter, stmt, _| interpreter.trace_stmt(stmt)));
+            self.on_expression(Box::new(|interpreter, expr, value| interpreter.trace_expr(expr, value)));
+        }
+    }
+
+    /// Reports the tree-walker's [`Stats`] to stderr once a script ends.
+    pub fn set_print_stats(&mut self, print_stats: bool) {
+        self.print_stats = print_stats;
+        if print_stats {
+            self.count_stats();
+        }
+    }
+
+    /// Counts statements, expressions and calls into [`Stats`] from here
+    /// on, if that isn't being done already.
+    fn count_stats(&mut self) {
+        if std::mem::replace(&mut self.counting, true) {
+            return;
+        }
+        self.on_statement(Box::new(|interpreter, _, _| interpreter.stats.statements += 1));
+        self.on_expression(Box::new(|interpreter, _, _| interpreter.stats.expressions += 1));
+        self.on_call(Box::new(|interpreter, _, _, _| interpreter.stats.calls += 1));
+    }
+
+    /// Prints how much the cycle collector freed once the script ends.
+    pub fn set_print_gc_stats(&mut self, print_gc_stats: bool) {
+        self.print_gc_stats = print_gc_stats;
+    }
+
+    /// Frees environments that only closures in cycles keep alive, as
+    /// `gcCollect()` does. Returns how many were freed.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.gc.collect()
+    }
+
+    /// Makes the script fail with a runtime error once it holds more than
+    /// `max_memory` bytes, rather than running the host out of memory.
+    /// What it holds is only measured after enough has been allocated, so
+    /// it may go over by an eighth before it's stopped.
+    pub fn set_max_memory(&mut self, max_memory: Option<usize>) {
+        self.max_memory = max_memory;
+    }
+
+    /// Makes a loop that goes round more than `max` times fail with a
+    /// runtime error, so one that never ends can't hang the interpreter.
+    /// `Some(0)` means no limit. Left unset, there's no limit for scripts,
+    /// and [`REPL_MAX_LOOP_ITERATIONS`] in the REPL.
+    pub fn set_max_loop_iterations(&mut self, max: Option<u64>) {
+        self.max_loop_iterations = max;
+    }
+
+    /// The limit on the iterations of a loop starting now, if any.
+    fn loop_limit(&self) -> Option<u64> {
+        match self.max_loop_iterations {
+            Some(0) => None,
+            None if self.repl => Some(REPL_MAX_LOOP_ITERATIONS),
+            max => max,
+        }
+    }
+
+    /// Counts another iteration of a loop, failing with an error placed at
+    /// `token` once there are more than `limit`.
+    fn count_iteration(&self, iterations: &mut u64, limit: Option<u64>, token: &Token) -> InterpreterResult<()> {
+        *iterations += 1;
+        match limit {
+            Some(limit) if *iterations > limit => Err(RuntimeException::base(
+                "E0015",
+                token.clone(),
+                format!("Loop exceeded {} iterations.", limit),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Roughly how many bytes the values the running code can reach hold,
+    /// as `memoryUsed()` reports.
+    pub fn memory_used(&self) -> usize {
+        memory::measure(&[&self.globals, &self.environment], &Literal::Nil)
+    }
+
+    /// How many more bytes the script may hold under
+    /// [`Interpreter::set_max_memory`], if there's a limit.
+    pub fn memory_left(&self) -> Option<usize> {
+        self.max_memory.map(|limit| limit.saturating_sub(self.memory_used()))
+    }
+
+    /// Counts `bytes` just allocated for `value`, measuring what's held
+    /// once the estimate could be over the limit.
+    fn charge(&mut self, token: &Token, bytes: usize, value: &Literal) -> InterpreterResult<()> {
+        let Some(limit) = self.max_memory else {
+            return Ok(());
+        };
+        self.unmeasured_memory += bytes;
+        if self.unmeasured_memory <= limit.saturating_sub(self.measured_memory).max(limit / 8) {
+            return Ok(());
+        }
+        self.measured_memory = memory::measure(&[&self.globals, &self.environment], value);
+        self.unmeasured_memory = 0;
+        if self.measured_memory > limit {
+            let message = format!(
+                "Out of memory: holding about {} bytes, over the limit of {}.",
+                self.measured_memory, limit
+            );
+            return Err(RuntimeException::base("E0009", token.clone(), message));
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Makes `breakpoint()` pause a script. It always pauses in the REPL.
+    pub fn set_debug(&mut self, debug: bool) {
+        if debug {
+            self.attach_debugger();
+        }
+    }
+
+    /// Pauses at every call to `breakpoint()`, if that isn't being done
+    /// already.
+    fn attach_debugger(&mut self) {
+        if std::mem::replace(&mut self.debug, true) {
+            return;
+        }
+        self.on_call(Box::new(|interpreter, callee, _, _| {
+            if matches!(callee, Literal::NativeFunction(native) if &*native.name == "breakpoint") {
+                if let Err(err) = interpreter.pause() {
+                    eprintln!("{}", err);
+                }
+            }
+        }));
+    }
+
+    /// Runs `test()` blocks, which are skipped otherwise, and reports them
+    /// on stderr when the script ends.
+    pub fn set_test_mode(&mut self, test_mode: bool) {
+        self.test_mode = test_mode;
+    }
+
+    pub fn test_mode(&self) -> bool {
+        self.test_mode
+    }
+
+    /// Counts the lines the tree-walker executes and reports them when the
+    /// script ends: a summary on stderr, plus an lcov file at `lcov_path`.
+    pub fn set_coverage(&mut self, lcov_path: Option<String>) {
+        let coverage = Rc::new(RefCell::new(Coverage::new()));
+        self.coverage = Some(coverage.clone());
+        self.lcov_path = lcov_path;
+        // A block's line is its first statement's, which counts itself.
+        self.on_statement(Box::new(move |_, stmt, span| {
+            if span.source == SourceId::MAIN && !matches!(stmt, Stmt::Block(_)) {
+                coverage.borrow_mut().hit(span.line);
+            }
+        }));
+    }
+
+    /// Registers `hook` to run before every statement.
+    pub fn on_statement(&mut self, hook: StatementHook) {
+        self.hooks.statement.push(hook);
+    }
+
+    /// Registers `hook` to run after every expression is evaluated.
+    pub fn on_expression(&mut self, hook: ExpressionHook) {
+        self.hooks.expression.push(hook);
+    }
+
+    /// Registers `hook` to run before every call.
+    pub fn on_call(&mut self, hook: CallHook) {
+        self.hooks.call.push(hook);
+    }
+
+    /// Registers `hook` to run after every call that returns.
+    pub fn on_return(&mut self, hook: ReturnHook) {
+        self.hooks.ret.push(hook);
+    }
+
+    /// Registers `hook` to run on every error reported.
+    pub fn on_error(&mut self, hook: ErrorHook) {
+        self.hooks.error.push(hook);
+    }
+
+    /// The scope the running code looks names up in.
+    pub fn environment(&self) -> Rc<RefCell<Environment>> {
+        self.environment.clone()
+    }
+
+    /// Every global variable and its value, sorted by name. Of those the
+    /// VM defined, only the plain values are listed, since its functions
+    /// have no value here.
+    pub fn globals(&self) -> Vec<(&'static str, Literal)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .borrow()
+            .values()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect();
+        let defined: HashSet<&str> = globals.iter().map(|&(name, _)| name).collect();
+        let vm_globals: Vec<_> = self
+            .vm
+            .globals()
+            .iter()
+            .filter(|(name, _)| !defined.contains(name.as_str()))
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_plain_literal()?)))
+            .collect();
+        globals.extend(vm_globals);
+        globals.sort_by_key(|&(name, _)| name);
+        globals
+    }
+
+    /// The name of every global either backend has defined, so programs
+    /// run later may use them.
+    pub fn global_names(&self) -> HashSet<Symbol> {
+        let mut names: HashSet<Symbol> = self.globals.borrow().values().map(|(name, _)| name).collect();
+        names.extend(self.vm.globals().keys().copied());
+        names
+    }
+
+    /// Prints each global other than the natives, for `:env`.
+    fn print_env(&mut self) {
+        for (name, value) in self.globals() {
+            if !matches!(value, Literal::NativeFunction(_)) {
+                self.print_line(format!("{} = {}", name, value));
+            }
+        }
+    }
+
+    /// Saves the global state, for [`Interpreter::restore`].
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            globals: self.globals.borrow().clone(),
+            vm_globals: self.vm.globals().clone(),
+        }
+    }
+
+    /// Puts the globals back as they were when `snapshot` was taken.
+    /// Functions defined since keep working, since the global environment
+    /// itself is reused.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        *self.globals.borrow_mut() = snapshot.globals;
+        self.vm.set_globals(snapshot.vm_globals);
+    }
+
+    /// Records the outcome of a `test()` block.
+    pub fn record_test(&mut self, name: String, failure: Option<String>) {
+        self.test_results.push((name, failure));
+    }
+
+    /// Prints each program in `format` instead of executing it.
+    pub fn set_dump_ast(&mut self, format: AstFormat) {
+        self.dump_ast = Some(format);
+    }
+
+    /// Also defines each standard library module's members as globals of
+    /// their own, so `sqrt` works as well as `math.sqrt`. Scripts can still
+    /// declare their own globals with these names.
+    /// Modules removed by [`Interpreter::restrict`] are skipped.
+    pub fn import_unprefixed(&mut self) {
+        for (module, _) in stdlib::modules() {
+            let members = self
+                .globals
+                .borrow()
+                .values()
+                .find(|(name, _)| name.as_str() == module)
+                .map(|(_, value)| value.clone());
+            let Some(Literal::Map(members)) = members else { continue };
+            for (name, value) in members.iter() {
+                self.globals.borrow_mut().define(Symbol::intern(name), value.clone());
+            }
+        }
+        self.share_natives_with_vm();
+    }
+
+    /// Tells the VM which of the globals only the tree-walker defines.
+    fn share_natives_with_vm(&mut self) {
+        let vm_globals = self.vm.globals();
+        let unsupported = self
+            .globals
+            .borrow()
+            .values()
+            .map(|(name, _)| name)
+            .filter(|name| !vm_globals.contains_key(name))
+            .collect();
+        self.vm.set_unsupported(unsupported);
+    }
+
+    /// Removes the natives of every capability not in `allowed`, so that
+    /// untrusted scripts can't reach them.
+    pub fn restrict(&mut self, allowed: &[Capability]) {
+        for capability in Capability::ALL {
+            if !allowed.contains(&capability) {
+                if !self.denied.contains(&capability) {
+                    self.denied.push(capability);
+                }
+                for name in capability.globals() {
+                    self.globals.borrow_mut().remove(Symbol::intern(name));
+                    self.vm.remove_global(Symbol::intern(name));
+                }
+            }
+        }
+        self.share_natives_with_vm();
+    }
+
+    /// Whether scripts may use `capability`: it was compiled in, and
+    /// [`Interpreter::restrict`] hasn't taken it away.
+    pub fn allows(&self, capability: Capability) -> bool {
+        capability.is_compiled_in() && !self.denied.contains(&capability)
+    }
+
+    /// Reports errors as `Error[P0001]` rather than `Error`, with the code
+    /// `rlox explain` takes. Off by default, keeping jlox's format.
+    pub fn set_error_codes(&mut self, error_codes: bool) {
+        self.error_codes = error_codes;
+    }
+
+    /// Whether warnings, such as the resolver's for unused local variables
+    /// and those for suspicious operations at runtime, are dropped,
+    /// printed, or made errors. Off by default.
+    pub fn set_warnings(&mut self, warnings: Warnings) {
+        self.warnings = warnings;
+    }
+
+    /// Reports `warning` as [`Interpreter::set_warnings`] says to, unless a
+    /// `// lox-ignore:` comment silences it.
+    pub fn warn(&mut self, warning: Warning) {
+        if self.is_suppressed(&warning) {
+            return;
+        }
+        match self.warnings {
+            Warnings::Off => (),
+            Warnings::On if self.output.is_none() => eprintln!("{}", warning.render(&self.sources)),
+            Warnings::On => (),
+            Warnings::Strict => self.report(LoxError::Resolve(error_codes::get(warning.code()), warning.token, warning.message.into())),
+        }
+    }
+
+    pub fn is_suppressed(&self, warning: &Warning) -> bool {
+        self.suppressions.is_suppressed(warning)
+    }
+
+    /// Turns one of the [`RULES`](crate::warning::RULES) on or off. All are on to start
+    /// with.
+    pub fn set_rule(&mut self, rule: &'static str, enabled: bool) {
+        self.disabled_rules.retain(|disabled| *disabled != rule);
+        if !enabled {
+            self.disabled_rules.push(rule);
+        }
+    }
+
+    pub fn warns_about(&self, rule: &str) -> bool {
+        !self.disabled_rules.contains(&rule)
+    }
+
+    /// The file being scanned or resolved.
+    pub fn current_source(&self) -> SourceId {
+        self.current_source
+    }
+
+    /// Drops messages `logDebug()` and the rest log below `level`, which
+    /// is `Info` by default.
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.log_level = level;
+    }
+
+    pub fn log_level(&self) -> LogLevel {
+        self.log_level
+    }
+
+    /// Makes `print` a native function taking any number of values, so it
+    /// can be passed around like any other, rather than a statement. Only
+    /// the tree-walker has it.
+    pub fn set_print_function(&mut self, print_function: bool) {
+        self.print_function = print_function;
+        let name = Symbol::intern("print");
+        if !print_function {
+            self.globals.borrow_mut().remove(name);
+            return;
+        }
+        let print = NativeFunction::variadic("print", 0, |interpreter, args| {
+            let texts: Vec<String> = args.iter().map(|value| interpreter.stringify(value.clone())).collect();
+            let text = texts.join(&interpreter.print_separator);
+            interpreter.print_line(text);
+            Ok(Literal::Nil)
+        });
+        self.globals.borrow_mut().define(name, Literal::NativeFunction(print));
+    }
+
+    /// Makes `word` a keyword in every program scanned from now on.
+    pub fn add_keyword(&mut self, word: &str, token_type: TokenType) {
+        self.keywords.push((word.to_string(), token_type));
+    }
+
+    /// A scanner for `source` that knows the keywords this interpreter
+    /// was given, with its errors placed in the file being run.
+    pub fn scanner(&self, source: String) -> Scanner {
+        let mut scanner = Scanner::new(source);
+        scanner.set_source(self.current_source);
+        scanner.set_suppressions(self.suppressions.clone());
+        for (word, token_type) in &self.keywords {
+            scanner.add_keyword(word, *token_type);
+        }
+        if self.print_function {
+            scanner.remove_keyword("print");
+        }
+        scanner
+    }
+
+    pub fn run_file(&mut self, path: &str) -> Result<RunStatus, Box<dyn Error>> {
         let contents: String = fs::read_to_string(path)?;
+        self.script_path = Some(path.to_string());
         self.run(contents)?;
+        Ok(self.finish())
+    }
+
+    /// Runs the user's prelude of helpers against the globals, before the
+    /// script or REPL. Its errors name the file, and it's left out of AST
+    /// dumps and coverage, which are about the script.
+    pub fn run_prelude(&mut self, path: &str) -> Result<RunStatus, Box<dyn Error>> {
+        let contents: String = fs::read_to_string(path)?;
+        let dump_ast = self.dump_ast.take();
+        let coverage = self.coverage.take();
+        self.current_source = self.sources.add(path);
+        let status = self.run(contents);
+        self.current_source = SourceId::MAIN;
+        self.dump_ast = dump_ast;
+        self.coverage = coverage;
+        status
+    }
+
+    /// Runs a program previously written with `--emit-ast`.
+    pub fn run_ast_file(&mut self, path: &str) -> Result<RunStatus, Box<dyn Error>> {
+        let contents: String = fs::read_to_string(path)?;
+        self.script_path = Some(path.to_string());
+        match Json::parse(&contents).and_then(|json| program_from_json(&json)) {
+            Ok(statements) => self.run_program(statements)?,
+            Err(message) => self.report(LoxError::Scan(error_codes::get("L0004"), Span::default(), message.into())),
+        }
+        Ok(self.finish())
+    }
+
+    /// Runs a `.loxc` file written by [`crate::cli::compile_file`].
+    pub fn run_compiled_file(&mut self, path: &str) -> Result<RunStatus, Box<dyn Error>> {
+        let contents: String = fs::read_to_string(path)?;
+        self.script_path = Some(path.to_string());
+        match ast_cache::read(&contents) {
+            Ok((statements, resolution)) => {
+                self.last_value = Literal::Nil;
+                self.returned = None;
+                for (id, depth) in resolution.locals {
+                    self.resolve(id, depth);
+                }
+                for (id, captures) in resolution.captures {
+                    self.resolve_captures(id, captures);
+                }
+                self.run_resolved(statements)?;
+            }
+            Err(message) => self.report(LoxError::Scan(error_codes::get("L0004"), Span::default(), message.into())),
+        }
+        Ok(self.finish())
+    }
+
+    /// How `rlox fmt` lays scripts out.
+    pub fn set_format_options(&mut self, options: FormatOptions) {
+        self.format_options = options;
+    }
+
+    pub fn format_options(&self) -> FormatOptions {
+        self.format_options
+    }
+
+    /// Prints the end-of-run reports asked for, then says how the run
+    /// ended.
+    pub fn finish(&self) -> RunStatus {
+        if self.print_stats {
+            eprintln!("{}", self.stats);
+        }
+        if self.print_gc_stats {
+            eprintln!("{}", self.gc.stats());
+        }
+
+        if let Some(coverage) = &self.coverage {
+            let coverage = coverage.borrow();
+            eprintln!("{}", coverage.summary());
+            if let Some(lcov_path) = &self.lcov_path {
+                let script_path = self.script_path.as_deref().unwrap_or_default();
+                if let Err(err) = fs::write(lcov_path, coverage.lcov(script_path)) {
+                    eprintln!("Could not write {}: {}", lcov_path, err);
+                }
+            }
+        }
+
+        for (name, failure) in &self.test_results {
+            match failure {
+                None => eprintln!("test: ok {}", name),
+                Some(failure) => eprintln!("test: FAIL {}: {}", name, failure),
+            }
+        }
+
+        self.status()
+    }
+
+    /// How the code run so far has gone.
+    pub fn status(&self) -> RunStatus {
+        if self.had_error {
+            RunStatus::StaticError
+        } else if self.had_runtime_error {
+            RunStatus::RuntimeError
+        } else if self.test_results.iter().any(|(_, failure)| failure.is_some()) {
+            RunStatus::TestFailure
+        } else {
+            RunStatus::Success
+        }
+    }
+
+    /// Runs a chunk of source against the interpreter's current globals.
+    /// Errors are reported on stderr and reflected in the returned status.
+    pub fn run(&mut self, source: String) -> Result<RunStatus, Box<dyn Error>> {
+        if let Some(statements) = self.parse(source) {
+            self.run_program(statements)?;
+        }
+        Ok(self.status())
+    }
+
+    /// Runs `source` like [`Interpreter::run`], giving back the value of
+    /// the last expression statement it ran outside any function, or what
+    /// a top-level `return` returned, or `nil` if there was neither. Errors
+    /// are reported as usual, and the first is given back. On the VM, a
+    /// function comes back as `nil`.
+    pub fn eval_str(&mut self, source: &str) -> Result<Literal, Box<dyn Error>> {
+        self.had_error = false;
+        self.had_runtime_error = false;
+        let before = self.errors.len();
+        match self.run(source.to_string())? {
+            RunStatus::StaticError | RunStatus::RuntimeError => match self.errors.get(before) {
+                Some(error) => Err(Box::new(error.clone())),
+                None => Err("The program failed.".into()),
+            },
+            _ => Ok(std::mem::replace(&mut self.last_value, Literal::Nil)),
+        }
+    }
+
+    /// Evaluates a single expression, with no statements or trailing
+    /// semicolon, where `bindings` are defined as variables in front of
+    /// the globals. Nothing is reported or printed: errors, including
+    /// scan, parse and resolve errors, come back as the result. The
+    /// expression is resolved with the bindings in scope each time, so
+    /// they can differ from one call to the next.
+    pub fn eval_expr(&mut self, source: &str, bindings: &[(&str, Literal)]) -> InterpreterResult<Literal> {
+        let static_error = |error: &LoxError| {
+            let token = Token {
+                line: error.span().line,
+                ..Token::default()
+            };
+            RuntimeException::base(error.code(), token, error.message().to_string())
+        };
+        let mut parser = Parser::from_scanner(self.scanner(source.to_string()));
+        parser.set_compat(self.compat);
+        let expr = parser.parse_expression();
+        if let Some(error) = parser.take_scan_errors().first() {
+            return Err(static_error(error));
+        }
+        let expr = expr.map_err(|err| RuntimeException::base(err.code.code, err.token, err.message.into()))?;
+        let names: Vec<Token> = bindings
+            .iter()
+            .map(|(name, _)| Token::new(TokenType::Identifier, Symbol::intern(name), None, 0))
+            .collect();
+        let mut resolver = Resolver::collecting(self);
+        resolver.resolve_expression(&names, &expr);
+        if let Some(error) = resolver.into_resolution().errors.first() {
+            return Err(static_error(error));
+        }
+
+        let mut environment = Environment::with_enclosing(self.globals.clone());
+        for (name, value) in bindings {
+            environment.define(Symbol::intern(name), value.clone());
+        }
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        let outermost = self.stack.is_none();
+        if outermost {
+            self.stack = Some(StackGuard::new());
+        }
+        let result = self.evaluate(&expr);
+        if outermost {
+            self.stack = None;
+        }
+        self.environment = previous;
+        result
+    }
+
+    /// Lets programs `return` a value from the top level, ending the
+    /// program, for hosts that take it from [`Interpreter::eval_str`].
+    pub fn set_top_level_return(&mut self, top_level_return: bool) {
+        self.top_level_return = top_level_return;
+    }
+
+    /// Makes a top-level `return` give the exit status a script ends with,
+    /// as `rlox --top-level-return` does, so anything but `nil` or an
+    /// integer from 0 to 255 is a runtime error.
+    pub fn set_return_exit_status(&mut self, return_exit_status: bool) {
+        self.return_exit_status = return_exit_status;
+    }
+
+    pub fn allows_top_level_return(&self) -> bool {
+        self.top_level_return
+    }
+
+    /// The value the last program gave a top-level `return`, if it ended
+    /// with one. A bare `return;` gives `nil`.
+    pub fn returned(&self) -> Option<&Literal> {
+        self.returned.as_ref()
+    }
+
+    /// Runs an already parsed program against the current globals, like
+    /// [`Interpreter::run`].
+    pub fn run_statements(&mut self, statements: Vec<Stmt>) -> Result<RunStatus, Box<dyn Error>> {
+        self.run_program(statements)?;
+        Ok(self.status())
+    }
+
+    /// Calls a function from outside any running program, the way a
+    /// spawned thread starts.
+    pub fn call(&mut self, callee: &dyn Callable, args: &[Literal]) -> InterpreterResult<Literal> {
+        let outermost = self.stack.is_none();
+        if outermost {
+            self.stack = Some(StackGuard::new());
+        }
+        let result = callee.call(self, args);
+        if outermost {
+            self.stack = None;
+        }
+        result
+    }
+
+    /// Makes `clock()` and `now()` report `clock`'s milliseconds since the
+    /// Unix epoch instead of the system time, so runs that print times can
+    /// be reproduced.
+    pub fn set_clock(&mut self, clock: Box<dyn Fn() -> f64>) {
+        let clock: Rc<dyn Fn() -> f64> = Rc::from(clock);
+        self.vm.set_clock(clock.clone());
+        self.clock = Some(clock);
+    }
+
+    /// Milliseconds since the Unix epoch, by the clock [`Interpreter::set_clock`]
+    /// gave or else the system's.
+    #[cfg(feature = "stdlib-time")]
+    pub fn unix_millis(&self) -> f64 {
+        self.clock.as_ref().map_or_else(unix_millis, |clock| clock())
+    }
+
+    pub fn event_loop(&mut self) -> &mut EventLoop {
+        &mut self.event_loop
+    }
+
+    /// Scans and parses `source` in one pass, reporting the scan errors
+    /// met on the way, or else the first syntax error.
+    pub fn parse(&mut self, source: String) -> Option<Vec<Stmt>> {
+        let mut parser = Parser::from_scanner(self.scanner(source));
+        parser.set_compat(self.compat);
+        let statements = parser.parse();
+        let scan_errors = parser.take_scan_errors();
+        if !scan_errors.is_empty() {
+            scan_errors.into_iter().for_each(|error| self.report(error));
+            return None
+        }
+        match statements {
+            Ok(statements) => Some(statements),
+            Err(err) => {
+                self.report(err.into());
+                None
+            }
+        }
+    }
+
+    /// Parses `source` read from the file at `path`, which its errors
+    /// name.
+    pub fn parse_file(&mut self, path: &str, source: String) -> Option<Vec<Stmt>> {
+        self.current_source = self.sources.add(path);
+        let statements = self.parse(source);
+        self.current_source = SourceId::MAIN;
+        statements
+    }
+
+    /// Resolves `statements`, reporting any errors. Returns whether there
+    /// were none, so they can be run.
+    pub fn resolve_program(&mut self, statements: &[Stmt]) -> bool {
+        Resolver::new(self).resolve(statements);
+        !self.had_error
+    }
+
+    /// A resolved program as a `.loxc` file, keeping what the resolver
+    /// decided, for [`Interpreter::run_compiled_file`] to run without
+    /// resolving it again.
+    pub fn compiled(&self, statements: &[Stmt]) -> Result<String, ParserError> {
+        ast_cache::write(
+            statements,
+            |id| self.locals.get(&id).copied(),
+            |id| self.captures.get(&id).cloned(),
+        )
+    }
+
+    /// Readies a resolved program to be run any number of times with
+    /// [`Interpreter::run_prepared`]: optimized if asked to, and compiled
+    /// for the VM backend. Compile errors are reported, giving `None`.
+    pub fn prepare(&mut self, statements: Vec<Stmt>) -> Option<Prepared> {
+        let statements = if self.optimize {
+            Optimizer::new(self).optimize(&statements)
+        } else {
+            statements
+        };
+        match self.backend {
+            Backend::TreeWalk => Some(Prepared::Tree(statements)),
+            Backend::Vm => match Compiler::new().compile(&statements) {
+                Ok(script) => Some(Prepared::Vm(script)),
+                Err(errors) => {
+                    errors.into_iter().for_each(|error| self.report(error));
+                    None
+                }
+            },
+        }
+    }
+
+    /// Runs a program [`Interpreter::prepare`] readied, reporting the
+    /// runtime error it stops with, if it does.
+    pub fn run_prepared(&mut self, prepared: &Prepared) {
+        self.environment = self.globals.clone();
+        let result = match prepared {
+            Prepared::Tree(statements) => match self.interpret(statements) {
+                Err(RuntimeException::Base(err)) => Err(LoxError::from(err)),
+                _ => Ok(()),
+            },
+            Prepared::Vm(script) => {
+                self.vm.set_max_loop_iterations(self.loop_limit());
+                self.vm.run(script.clone()).map_err(LoxError::from)
+            }
+        };
+        if let Err(err) = result {
+            self.report(err);
+        }
+    }
+
+    /// Whether a runtime error has been reported.
+    pub fn had_runtime_error(&self) -> bool {
+        self.had_runtime_error
+    }
+
+    /// Names the script being run in reports like coverage's.
+    pub fn set_script_path(&mut self, path: &str) {
+        self.script_path = Some(path.to_string());
+    }
+
+    /// Resolves and executes a parsed program, or dumps it when an
+    /// [`AstFormat`] was requested.
+    fn run_program(&mut self, statements: Vec<Stmt>) -> Result<(), Box<dyn Error>> {
+        self.last_value = Literal::Nil;
+        self.returned = None;
+        let mut resolver = Resolver::new(self);
+        resolver.resolve(statements.as_slice());
 
         if self.had_error {
-            exit(65)
+            return Ok(())
+        }
+        self.run_resolved(statements)
+    }
+
+    /// Runs a program the resolver has already been through.
+    fn run_resolved(&mut self, statements: Vec<Stmt>) -> Result<(), Box<dyn Error>> {
+        if self.check {
+            let errors = TypeChecker::new(self.string_coercion).check(&statements);
+            if !errors.is_empty() {
+                errors.into_iter().for_each(|error| self.report(error));
+                return Ok(())
+            }
+        }
+
+        if let Some(coverage) = &self.coverage {
+            coverage.borrow_mut().add_program(&statements);
+        }
+
+        let statements = if self.optimize {
+            Optimizer::new(self).optimize(&statements)
+        } else {
+            statements
+        };
+
+        if let Some(format) = &self.dump_ast {
+            let printed = match format {
+                AstFormat::SExpr => AstPrinter::new().print_program(&statements),
+                AstFormat::Dot => DotPrinter::new().print_program(&statements),
+                AstFormat::Json(_) => program_to_json(&statements).map(|json| json.to_string()),
+            };
+            match (printed, format) {
+                (Err(err), _) => self.report(err.into()),
+                (Ok(json), AstFormat::Json(path)) => fs::write(path, json)?,
+                (Ok(text), _) => print!("{}", text),
+            }
+            return Ok(())
+        }
+
+        match self.backend {
+            Backend::TreeWalk => {
+                if let Err(RuntimeException::Base(err)) = self.interpret(&statements) {
+                    self.report(err.into());
+                }
+            }
+            Backend::Vm => self.run_vm(&statements)?,
+        }
+        Ok(())
+    }
+
+    fn run_vm(&mut self, statements: &[Stmt]) -> Result<(), std::io::Error> {
+        match Compiler::new().compile(statements) {
+            Err(errors) => {
+                for error in errors {
+                    self.report(error);
+                }
+            }
+            Ok(script) => {
+                self.vm.set_max_loop_iterations(self.loop_limit());
+                match self.vm.run(script) {
+                    // The VM's functions have no value here, so they come
+                    // back as nil.
+                    Ok(()) => self.last_value = self.vm.take_result().to_plain_literal().unwrap_or(Literal::Nil),
+                    Err(err) => self.report(err.into()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and runs lines until end of input. `:env` lists the globals
+    /// and `:undo` takes back the last line's effect on them. `:time line`
+    /// runs the line and reports how long it took, and `:timing on` does
+    /// that for every line until `:timing off`. `:paste` reads lines until
+    /// `:end` and runs them as one input, as is done for text a terminal
+    /// marks as pasted. A loop going round more than
+    /// [`REPL_MAX_LOOP_ITERATIONS`] times is stopped with an error, unless
+    /// [`Interpreter::set_max_loop_iterations`] sets another limit.
+    pub fn run_prompt(&mut self) -> Result<(), Box<dyn Error>> {
+        self.repl = true;
+        self.attach_debugger();
+        let mut history = vec![];
+        // Whether every input is timed, as with `:time`.
+        let mut timing = false;
+        let terminal = io::stdin().is_terminal() && io::stdout().is_terminal();
+        if terminal {
+            print!("{}", BRACKETED_PASTE_ON);
+        }
+        loop {
+            let mut input = String::new();
+            print!("> ");
+            let _ = std::io::stdout().flush();
+            match std::io::stdin().read_line(&mut input) {
+                Ok(0) | Err(_) => break,
+                Ok(_) if input.trim() == ":env" => self.print_env(),
+                Ok(_) if input.trim() == ":undo" => match history.pop() {
+                    Some(snapshot) => self.restore(snapshot),
+                    None => eprintln!("Nothing to undo."),
+                },
+                Ok(_) if input.trim() == ":timing on" => timing = true,
+                Ok(_) if input.trim() == ":timing off" => timing = false,
+                Ok(_) => {
+                    // Pastes run whole, so a function pasted from the book
+                    // isn't run a line at a time.
+                    let input = if input.trim() == ":paste" {
+                        eprintln!("Pasting. End with ':end' on a line of its own, or Ctrl-D.");
+                        read_paste(String::new(), |line| (line.trim() == ":end").then_some(0))
+                    } else if let Some(start) = input.find(PASTE_START) {
+                        let first = input[start + PASTE_START.len()..].to_string();
+                        read_paste(first, |line| line.find(PASTE_END))
+                    } else {
+                        input
+                    };
+                    let (input, timed) = match input.trim().strip_prefix(":time ") {
+                        // `:time expr` can leave off the semicolon.
+                        Some(input) if !input.ends_with([';', '}']) => (format!("{};", input), true),
+                        Some(input) => (input.to_string(), true),
+                        None => (input, timing),
+                    };
+                    let snapshot = self.snapshot();
+                    // A line rejected before running changed nothing.
+                    if self.run_timed(input, timed)? != RunStatus::StaticError {
+                        history.push(snapshot);
+                    }
+                    self.had_error = false;
+                }
+            }
         }
-
-        if self.had_runtime_error {
-            exit(70)
+        if terminal {
+            print!("{}", BRACKETED_PASTE_OFF);
         }
-
         Ok(())
     }
 
-    fn run(&mut self, source: String) -> Result<(), Box<dyn Error>> {
-        let mut scanner = Scanner::new(source);
-        if let Err(err) = scanner.scan_tokens() {
-            self.error(scanner.line as u32, err.to_string())?;
-        }
-
-        let mut parser = Parser::new(scanner.tokens);
-        let statements = parser.parse();
-
-        if self.had_error {
-            return Ok(())
+    /// Runs a line of REPL input, then with `timed` prints how long it
+    /// took and, on the tree-walker, how many statements it executed.
+    fn run_timed(&mut self, input: String, timed: bool) -> Result<RunStatus, Box<dyn Error>> {
+        if timed {
+            self.count_stats();
         }
-
-        match statements {
-            Err(err) => {
-                parser.synchronize();
-                self.parser_error(err)?
-            }
-            Ok(statements) => {
-                let mut resolver = Resolver::new(self.clone());
-                resolver.resolve(statements.clone());
-                self.had_error = resolver.interpreter.had_error;
-
-                if self.had_error {
-                    return Ok(())
+        let statements = self.stats.statements;
+        let start = Instant::now();
+        let status = self.run(input)?;
+        let elapsed = start.elapsed();
+        if timed && status != RunStatus::StaticError {
+            match self.backend {
+                Backend::TreeWalk => {
+                    let executed = self.stats.statements - statements;
+                    self.print_line(format!("time: {:?}, {} statements", elapsed, executed))
                 }
-
-                if let Err(err) = self.interpret(statements) {
-                    if let RuntimeException::Base(err) = err {
-                        self.runtime_error(err)?;
-                    }
-                };
+                Backend::Vm => self.print_line(format!("time: {:?}", elapsed)),
             }
         }
-        Ok(())
+        Ok(status)
     }
 
-    pub fn run_prompt(&mut self) -> Result<(), Box<dyn Error>> {
+    /// Pauses at a `breakpoint()` call and reads statements to run in the
+    /// caller's scope until `continue` or end of input.
+    fn pause(&mut self) -> Result<(), Box<dyn Error>> {
+        eprintln!("Paused at breakpoint. Enter statements to run here, ':env' to list globals, or 'continue' to resume.");
+        let repl = std::mem::replace(&mut self.repl, true);
+        let dynamic_scope = std::mem::replace(&mut self.dynamic_scope, true);
         loop {
             let mut input = String::new();
-            print!("> ");
+            print!("debug> ");
             let _ = std::io::stdout().flush();
             match std::io::stdin().read_line(&mut input) {
+                Ok(0) | Err(_) => break,
+                Ok(_) if matches!(input.trim(), "continue" | "c") => break,
+                Ok(_) if input.trim() == ":env" => self.print_env(),
                 Ok(_) => {
                     self.run(input)?;
                     self.had_error = false;
+                    self.had_runtime_error = false;
                 }
-                Err(_) => break,
             }
         }
+        self.repl = repl;
+        self.dynamic_scope = dynamic_scope;
         Ok(())
     }
 
-    pub fn error(&mut self, line: u32, message: String) -> Result<(), std::io::Error> {
-        self.report(line, "".to_string(), message)?;
-        Ok(())
+    /// Collects what programs print for [`Interpreter::take_output`]
+    /// rather than writing it to stdout.
+    pub fn capture_output(&mut self) {
+        self.output = Some(String::new());
+        self.vm.capture_output();
     }
 
-    fn parser_error(&mut self, parser_error: ParserError) -> Result<(), std::io::Error> {
-        writeln!(
-            stderr(),
-            "{}\n[line {}]",
-            parser_error.message,
-            parser_error.token.line
-        )
+    /// Everything printed since output was captured or last taken.
+    pub fn take_output(&mut self) -> String {
+        let output = self.output.as_mut().map(std::mem::take).unwrap_or_default();
+        output + &self.vm.take_output()
     }
 
-    fn runtime_error(&mut self, runtime_error: RuntimeError) -> Result<(), std::io::Error> {
-        writeln!(
-            stderr(),
-            "{}\n[line {}]",
-            runtime_error.message,
-            runtime_error.token.line
-        )?;
-        self.had_runtime_error = true;
-        Ok(())
+    /// Makes `lines()` and `readAll()` read `input` rather than stdin, so
+    /// embedders and tests can feed a script its input.
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = Some(input);
     }
 
-    fn report(
-        &mut self,
-        line: u32,
-        location: String,
-        message: String,
-    ) -> Result<(), std::io::Error> {
-        writeln!(stderr(), "[line {}] Error{}: {}", line, location, message)?;
-        self.had_error = true;
-        Ok(())
+    /// The next line of input without its line ending, or `None` at the
+    /// end.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        let read = match &mut self.input {
+            Some(input) => input.read_line(&mut line)?,
+            None => io::stdin().lock().read_line(&mut line)?,
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    /// Whatever input is left.
+    pub fn read_all(&mut self) -> io::Result<String> {
+        let mut text = String::new();
+        match &mut self.input {
+            Some(input) => input.read_to_string(&mut text)?,
+            None => io::stdin().lock().read_to_string(&mut text)?,
+        };
+        Ok(text)
+    }
+
+    fn print_line(&mut self, text: String) {
+        match &mut self.output {
+            Some(output) => {
+                output.push_str(&text);
+                output.push('\n');
+            }
+            None => println!("{}", text),
+        }
+    }
+
+    /// Writes a log message apart from what the program prints, to
+    /// stderr, or with the rest of the output when that's captured.
+    pub fn log_line(&mut self, text: String) {
+        match &mut self.output {
+            Some(output) => {
+                output.push_str(&text);
+                output.push('\n');
+            }
+            None => eprintln!("{}", text),
+        }
     }
 
-    pub fn log_error(&mut self, token: Token, message: String) -> Result<(), std::io::Error> {
-        if token.token_type == TokenType::Eof {
-            self.report(token.line, "at end".to_string(), message)?;
+    /// Prints an error, unless output is captured, and remembers it,
+    /// flagging the run as failed with the exit status its phase calls for.
+    pub fn report(&mut self, error: LoxError) {
+        if self.output.is_none() {
+            eprintln!("{}", error.render(&self.sources, self.error_codes));
+        }
+        for hook in &mut self.hooks.error {
+            hook(&error);
+        }
+        if error.is_static() {
+            self.had_error = true;
         } else {
-            self.report(token.line, format!(" at '{}'", token.lexeme), message)?;
+            self.had_runtime_error = true;
         }
-        Ok(())
+        self.errors.push(error);
+    }
+
+    /// The files loaded besides the main script, to look up the
+    /// [`SourceId`] of an error's span in.
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    /// Every error reported so far.
+    pub fn errors(&self) -> &[LoxError] {
+        &self.errors
+    }
+
+    fn trace_line(&self, line: Option<u32>, text: String) {
+        let indent = "  ".repeat(self.call_depth);
+        let line = line.unwrap_or(self.trace_line);
+        eprintln!("{}[line {}] {}", indent, line, text);
+    }
+
+    fn trace_stmt(&mut self, stmt: &Stmt) {
+        if let Some(line) = stmt.line() {
+            self.trace_line = line;
+        }
+        let text = match stmt {
+            Stmt::Block(_) => "block".to_string(),
+            Stmt::Expression(_) => "expression".to_string(),
+            Stmt::Function(name, ..) => format!("fun {}", name.lexeme),
+            Stmt::Print(_) => "print".to_string(),
+            Stmt::Return(..) => "return".to_string(),
+            Stmt::If(..) => "if".to_string(),
+            Stmt::While(..) => "while".to_string(),
+            Stmt::For(..) => "for".to_string(),
+            Stmt::Var(name, _, _) => format!("var {}", name.lexeme),
+            Stmt::Break(..) => "break".to_string(),
+            Stmt::Continue(..) => "continue".to_string(),
+            Stmt::Labeled(label, _) => format!("{}:", label.lexeme),
+            Stmt::Enum(name, _) => format!("enum {}", name.lexeme),
+            Stmt::Import(_, _, path) => format!("import from \"{}\"", path),
+            Stmt::Export(_) => "export".to_string(),
+            Stmt::With(_, name, ..) => format!("with {}", name.lexeme),
+            Stmt::ForIn(name, ..) => format!("for {} in", name.lexeme),
+        };
+        self.trace_line(stmt.line(), text);
+    }
+
+    fn trace_expr(&self, expr: &Expr, value: &Literal) {
+        let value = match value {
+            Literal::String(s) => format!("{:?}", s),
+            value => value.to_string(),
+        };
+        // The printer shares the run's budget, having only what's left.
+        let mut printer = AstPrinter::new();
+        if let Some(stack) = self.stack {
+            printer.set_stack(stack);
+        }
+        let text = format!("{} => {}", printer.print(expr), value);
+        self.trace_line(expr.line(), text);
+    }
+
+    /// Runs the hooks `select` picks, calling each with `fire`. They're set
+    /// aside meanwhile, so code they run doesn't fire them again.
+    fn run_hooks<H>(&mut self, select: fn(&mut Hooks) -> &mut Vec<H>, mut fire: impl FnMut(&mut Self, &mut H)) {
+        let mut hooks = std::mem::take(select(&mut self.hooks));
+        for hook in &mut hooks {
+            fire(self, hook);
+        }
+        // Hooks registered while these ran go after them.
+        let added = std::mem::replace(select(&mut self.hooks), hooks);
+        select(&mut self.hooks).extend(added);
+    }
+
+    fn statement_hooks(&mut self, stmt: &Stmt) {
+        let (false, Some(line)) = (self.hooks.statement.is_empty(), stmt.line()) else {
+            return;
+        };
+        let span = Span {
+            source: self.current_source,
+            line,
+            ..Span::default()
+        };
+        self.run_hooks(|hooks| &mut hooks.statement, |interpreter, hook| hook(interpreter, stmt, span));
     }
 
-    fn execute(&mut self, stmt: Stmt) -> InterpreterResult<()> {
+    fn execute(&mut self, stmt: &Stmt) -> InterpreterResult<()> {
+        self.check_stack(|| stmt.line())?;
+        self.statement_hooks(stmt);
         match stmt {
             Stmt::Expression(expr) => {
-                match expr {
-                    Expr::Assign(_, _) => {
-                        self.evaluate(expr)?;
-                    }
-                    _ => {
-                        let value = self.evaluate(expr)?;
-                        if self.repl {
-                            println!("{}", self.stringify(value))
-                        }
-                    }
-                };
+                let value = self.evaluate(expr)?;
+                if self.call_depth == 0 {
+                    self.last_value = value;
+                }
                 Ok(())
             }
-            Stmt::Print(expr) => {
-                let value = self.evaluate(expr)?;
-                println!("{}", self.stringify(value));
+            Stmt::Print(values) => {
+                let mut texts = vec![];
+                for value in values.iter() {
+                    let value = self.evaluate(value)?;
+                    texts.push(self.stringify(value));
+                }
+                let text = texts.join(&self.print_separator);
+                self.print_line(text);
                 Ok(())
             }
-            Stmt::Var(token, initializer) => {
+            Stmt::Var(token, _, initializer) => {
                 let mut value = None;
                 if let Some(expr) = initializer {
                     value = Some(self.evaluate(expr)?)
@@ -205,343 +1801,951 @@ impl Interpreter {
                 match value {
                     None => {
                         return Err(RuntimeException::base(
-                            token,
+                            "E0027",
+                            token.clone(),
                             "Must assign value to new variable.".to_string(),
                         ))
                     }
-                    Some(v) => self.environment.define(token.lexeme, v),
+                    Some(v) => self.environment.borrow_mut().define(token.lexeme, v),
                 }
 
                 Ok(())
             }
-            Stmt::While(condition, body) => {
-                let mut value = self.evaluate(condition.clone())?;
-                self.loop_count += 1;
-                while self.is_truthy(&value) {
-                    match self.execute((*body).clone()) {
-                        Ok(()) => (),
-                        Err(err) => match err {
-                            RuntimeException::Break => break,
-                            _ => return Err(err),
-                        },
+            Stmt::With(keyword, name, initializer, body) => {
+                let resource = self.evaluate(initializer)?;
+                let Some(close) = close_function(&resource) else {
+                    return Err(RuntimeException::base(
+                        "E0025",
+                        keyword.clone(),
+                        "Only values with a 'close' function can be used in 'with'.".to_string(),
+                    ));
+                };
+                let mut environment = Environment::with_enclosing(self.environment.clone());
+                environment.define(name.lexeme, resource);
+                let result = self.execute_block(std::slice::from_ref(body), environment);
+                // An error from the body outranks one from closing.
+                let closed = close.call(self, &[]).map(|_| ());
+                result.and(closed)
+            }
+            Stmt::Enum(name, cases) => {
+                let cases = cases
+                    .iter()
+                    .map(|case| {
+                        let value = EnumCase {
+                            enum_name: name.lexeme,
+                            name: case.lexeme,
+                        };
+                        (Rc::from(case.lexeme.as_str()), Literal::EnumCase(Rc::new(value)))
+                    })
+                    .collect();
+                self.environment.borrow_mut().define(name.lexeme, Literal::Map(Rc::new(cases)));
+                Ok(())
+            }
+            Stmt::Import(keyword, names, path) => {
+                let module = self.import_module(path).map_err(|err| match err {
+                    RuntimeException::Base(mut err) if err.token.line == 0 => {
+                        err.token = keyword.clone();
+                        RuntimeException::Base(err)
                     }
-                    value = self.evaluate(condition.clone())?;
+                    err => err,
+                })?;
+                let Literal::Map(members) = module else {
+                    unreachable!("modules are maps")
+                };
+                for name in names.iter() {
+                    let Some(value) = members.get(name.lexeme.as_str()) else {
+                        let message = format!("Module '{}' has no export '{}'.", path, name.lexeme);
+                        return Err(RuntimeException::base("E0011", name.clone(), message));
+                    };
+                    self.environment.borrow_mut().define(name.lexeme, value.clone());
                 }
-                self.loop_count -= 1;
                 Ok(())
             }
+            Stmt::Export(declaration) => self.execute(declaration),
+            Stmt::While(keyword, condition, body) => {
+                let label = self.label.take();
+                self.execute_loop(label, keyword, Some(condition), body, None, false)
+            }
+            Stmt::For(keyword, initializer, condition, increment, body) => {
+                let label = self.label.take();
+                let environment = Environment::with_enclosing(self.environment.clone());
+                self.stats.environments += 1;
+                let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+                let result = match initializer {
+                    Some(initializer) => self.execute(initializer),
+                    None => Ok(()),
+                };
+                let result = result.and_then(|()| {
+                    self.execute_loop(label, keyword, condition.as_ref(), body, increment.as_ref(), true)
+                });
+                self.environment = previous;
+                result
+            }
+            Stmt::ForIn(name, iterable, body) => {
+                let label = self.label.take();
+                let value = self.evaluate(iterable)?;
+                let Some(next) = iterator::iterator(&value) else {
+                    return Err(RuntimeException::base(
+                        "E0024",
+                        name.clone(),
+                        "Can only iterate over strings, maps, records and iterators.".to_string(),
+                    ));
+                };
+                self.execute_for_in(label, name, next.as_ref(), body)
+            }
+            Stmt::Labeled(label, body) => {
+                self.label.set(label);
+                self.execute(body)
+            }
             Stmt::Block(stmts) => self.evaluate_block(stmts),
-            Stmt::If(condition, then_branch, else_branch) => {
-                let value = self.evaluate(condition)?;
-                if self.is_truthy(&value) {
-                    self.execute(*then_branch)?;
-                } else if let Some(else_branch) = *else_branch {
-                    self.execute(else_branch)?;
+            Stmt::If(arms, else_branch) => {
+                for (_, condition, branch) in arms.iter() {
+                    let value = self.evaluate(condition)?;
+                    if self.is_truthy(&value) {
+                        return self.execute(branch);
+                    }
+                }
+                match else_branch {
+                    Some(else_branch) => self.execute(else_branch),
+                    None => Ok(()),
                 }
-                Ok(())
             }
-            Stmt::Break(token) => {
+            Stmt::Break(token, label) | Stmt::Continue(token, label) => {
+                let label = label.as_ref().map(|label| label.lexeme);
                 if self.loop_count > 0 {
-                    Err(RuntimeException::Break)
+                    Err(match stmt {
+                        Stmt::Break(..) => RuntimeException::Break(label),
+                        _ => RuntimeException::Continue(label),
+                    })
                 } else {
                     Err(RuntimeException::base(
-                        token,
+                        "E0026",
+                        token.clone(),
                         "Expected to be within a loop.".to_string(),
                     ))
                 }
             }
-            Stmt::Function(name, params, body) => {
-                let stmt = Stmt::Function(name.clone(), params, body);
-                let function = Literal::LoxFunction(LoxFunction::new(
-                    name.lexeme.clone(),
-                    stmt,
-                    self.environment.clone(),
-                ));
-                self.environment.define(name.lexeme, function);
+            Stmt::Function(name, id, params, body, _, doc) => {
+                // Defined first so a function can capture itself.
+                self.environment.borrow_mut().define(name.lexeme, Literal::Nil);
+                let closure = self.closure(*id)?;
+                let mut function = LoxFunction::new(name.lexeme, params.clone(), body.clone(), closure, self.globals.clone());
+                function.doc = doc.clone();
+                self.environment.borrow_mut().define(name.lexeme, Literal::LoxFunction(Rc::new(function)));
                 Ok(())
             }
-            Stmt::Return(_keyword, value) => {
-                let v = match *value {
+            Stmt::Return(keyword, value) => {
+                let v = match value.as_ref() {
                     Some(value) => Some(self.evaluate(value)?),
                     None => None,
                 };
+                let exit_status = matches!(v, None | Some(Literal::Nil | Literal::Int(0..=255)));
+                if self.return_exit_status && self.call_depth == 0 && !exit_status {
+                    let message = "A top-level return must give an exit status from 0 to 255.".to_string();
+                    return Err(RuntimeException::base("E0031", keyword.clone(), message));
+                }
 
                 Err(RuntimeException::Return(Return::new(v)))
             }
         }
     }
 
-    pub fn resolve(&mut self, expr: Expr, depth: u32) {
-        self.locals.insert(expr, depth);
+    /// Runs `body` while `condition` holds, or forever without one, then
+    /// `increment` after each pass, including one cut short by `continue`.
+    /// For a `for` loop, the variables its initializer declared are
+    /// copied to a fresh scope before each `increment`, as JavaScript's
+    /// `let` does, so closures made in one pass keep that pass's values.
+    /// Going round too often is an error at the loop's `keyword`.
+    fn execute_loop(
+        &mut self,
+        label: Option<Symbol>,
+        keyword: &Token,
+        condition: Option<&Expr>,
+        body: &Stmt,
+        increment: Option<&Expr>,
+        per_iteration: bool,
+    ) -> InterpreterResult<()> {
+        let targets = |target: &Option<Symbol>| target.is_none() || *target == label;
+        let limit = self.loop_limit();
+        let mut iterations = 0;
+        self.loop_count += 1;
+        let result = loop {
+            let value = match condition {
+                Some(condition) => self.evaluate(condition),
+                None => Ok(Literal::True),
+            };
+            match value {
+                Ok(value) if self.is_truthy(&value) => (),
+                Ok(_) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+            if let Err(err) = self.count_iteration(&mut iterations, limit, keyword) {
+                break Err(err);
+            }
+            match self.execute(body) {
+                Ok(()) => (),
+                Err(RuntimeException::Break(target)) if targets(&target) => break Ok(()),
+                Err(RuntimeException::Continue(target)) if targets(&target) => (),
+                Err(err) => break Err(err),
+            }
+            if per_iteration {
+                self.renew_loop_scope();
+            }
+            if let Some(increment) = increment {
+                if let Err(err) = self.evaluate(increment) {
+                    break Err(err);
+                }
+            }
+        };
+        self.loop_count -= 1;
+        result
     }
 
-    pub fn evaluate_block(&mut self, stmts: Vec<Stmt>) -> InterpreterResult<()> {
-        self.environment = Environment::with_enclosing(self.environment.clone());
-        for stmt in stmts {
-            self.execute(stmt)?;
+    /// Moves a `for` loop on to a copy of its scope if anything could still
+    /// see this one: a closure that captured its variables, or one that
+    /// holds the scope itself. Otherwise a copy couldn't be told apart.
+    fn renew_loop_scope(&mut self) {
+        let shared = Rc::strong_count(&self.environment) > 1 || self.environment.borrow().has_captures();
+        if shared {
+            let next = self.environment.borrow().copy_values();
+            self.environment = Rc::new(RefCell::new(next));
+            self.stats.environments += 1;
         }
+    }
+
+    /// Runs `body` once per value from `next`, each time in a fresh scope
+    /// holding the value as `name`, so closures capture their own.
+    fn execute_for_in(
+        &mut self,
+        label: Option<Symbol>,
+        name: &Token,
+        next: &dyn Callable,
+        body: &Stmt,
+    ) -> InterpreterResult<()> {
+        let targets = |target: &Option<Symbol>| target.is_none() || *target == label;
+        let limit = self.loop_limit();
+        let mut iterations = 0;
+        self.loop_count += 1;
+        let result = loop {
+            let value = match iterator::step(self, next) {
+                Ok(Some(value)) => value,
+                Ok(None) => break Ok(()),
+                Err(err) => break Err(err),
+            };
+            if let Err(err) = self.count_iteration(&mut iterations, limit, name) {
+                break Err(err);
+            }
+            let mut environment = Environment::with_enclosing(self.environment.clone());
+            environment.define(name.lexeme, value);
+            match self.execute_block(std::slice::from_ref(body), environment) {
+                Ok(()) => (),
+                Err(RuntimeException::Break(target)) if targets(&target) => break Ok(()),
+                Err(RuntimeException::Continue(target)) if targets(&target) => (),
+                Err(err) => break Err(err),
+            }
+        };
+        self.loop_count -= 1;
+        result
+    }
+
+    pub fn resolve(&mut self, id: ExprId, depth: u32) {
+        self.locals.insert(id, depth);
+    }
 
-        if let Some(enclosing) = self.environment.enclosing.clone() {
-            self.environment = *enclosing;
+    pub fn resolve_captures(&mut self, id: ExprId, captures: Vec<(Symbol, u32)>) {
+        self.captures.insert(id, Rc::from(captures));
+    }
+
+    /// The environment a function declared here closes over: just the
+    /// variables it captures, in cells shared with where they're declared,
+    /// in front of the globals. A function the resolver hasn't seen keeps
+    /// the whole chain, as does one declared at a `breakpoint()` prompt,
+    /// whose globals may be the paused scope's locals.
+    fn closure(&mut self, id: ExprId) -> InterpreterResult<Rc<RefCell<Environment>>> {
+        let Some(captures) = self.captures.get(&id).cloned() else {
+            self.gc.track(&self.environment);
+            return Ok(self.environment.clone());
+        };
+        let enclosing = if self.dynamic_scope { &self.environment } else { &self.globals };
+        if captures.is_empty() {
+            return Ok(enclosing.clone());
         }
+        let mut closure = Environment::with_enclosing(enclosing.clone());
+        for &(name, distance) in captures.iter() {
+            let cell = self.environment.borrow_mut().capture_at(distance, name)?;
+            closure.define_captured(name, cell);
+        }
+        let closure = Rc::new(RefCell::new(closure));
+        self.gc.track(&closure);
+        Ok(closure)
+    }
 
-        Ok(())
+    pub fn evaluate_block(&mut self, stmts: &[Stmt]) -> InterpreterResult<()> {
+        let environment = Environment::with_enclosing(self.environment.clone());
+        self.execute_block(stmts, environment)
+    }
+
+    pub fn execute_block(
+        &mut self,
+        stmts: &[Stmt],
+        environment: Environment,
+    ) -> InterpreterResult<()> {
+        self.stats.environments += 1;
+        let previous = std::mem::replace(
+            &mut self.environment,
+            Rc::new(RefCell::new(environment)),
+        );
+        let result = stmts.iter().try_for_each(|stmt| self.execute(stmt));
+        self.environment = previous;
+        result
     }
 
-    fn evaluate(&mut self, expr: Expr) -> InterpreterResult<Literal> {
+    /// Runs a function body in `environment`, looking up globals in
+    /// `globals`. Loops in the caller don't extend into the callee, so
+    /// `break` there is still an error.
+    pub fn execute_function_body(
+        &mut self,
+        body: &[Stmt],
+        environment: Environment,
+        globals: &Rc<RefCell<Environment>>,
+    ) -> InterpreterResult<()> {
+        let loop_count = std::mem::replace(&mut self.loop_count, 0);
+        let globals = std::mem::replace(&mut self.globals, globals.clone());
+        self.call_depth += 1;
+        self.stats.peak_call_depth = self.stats.peak_call_depth.max(self.call_depth);
+        let result = self.execute_block(body, environment);
+        self.call_depth -= 1;
+        self.globals = globals;
+        self.loop_count = loop_count;
+        result
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> InterpreterResult<Literal> {
+        self.check_stack(|| expr.line())?;
+        if self.hooks.expression.is_empty() {
+            return self.evaluate_expr(expr);
+        }
+        let value = self.evaluate_expr(expr)?;
+        self.run_hooks(|hooks| &mut hooks.expression, |interpreter, hook| hook(interpreter, expr, &value));
+        Ok(value)
+    }
+
+    fn evaluate_expr(&mut self, expr: &Expr) -> InterpreterResult<Literal> {
         match expr {
             Expr::Empty => Ok(Literal::Nil),
-            Expr::Literal(literal) => Ok(literal),
-            Expr::Grouping(expr) => self.evaluate(*expr),
+            Expr::Literal(literal) => Ok(literal.clone()),
+            Expr::Grouping(expr) => self.evaluate(expr),
             Expr::Unary(operator, right) => {
-                let right = self.evaluate(*right);
-                match (operator.token_type, right.clone()) {
-                    (TokenType::Minus, Ok(Literal::Number(n))) => Ok(Literal::Number(-n)),
-                    (TokenType::Minus, _) => Err(RuntimeException::base(
-                        operator,
-                        "Operand must be a number.".to_string(),
-                    )),
-                    (TokenType::Bang, Ok(_)) => {
-                        let b = !self.is_truthy(&right.unwrap());
-                        if b {
-                            Ok(Literal::True)
-                        } else {
-                            Ok(Literal::False)
-                        }
-                    }
-                    (_, Err(err)) => Err(err),
-                    _ => panic!(),
-                }
+                let right = self.evaluate(right)?;
+                self.unary(operator, right)
             }
-            Expr::Assign(name, value) => {
-                let expr = Expr::Assign(name.clone(), value.clone());
-                let value = self.evaluate(*value)?;
-                let distance = self.locals.get(&expr);
-                if let Some(distance) = distance {
-                    self.environment.assign_at(*distance, name, value.clone())?;
-                } else {
-                    self.environment.assign(name, value.clone())?;
+            Expr::Assign(id, name, value) => {
+                let value = self.evaluate(value)?;
+                self.assign_variable(*id, name, value.clone())?;
+                Ok(value)
+            }
+            Expr::Set(object, name, value) => {
+                if !is_place(object) {
+                    let message = "Can only set fields of a variable's map or record.".to_string();
+                    return Err(RuntimeException::base("E0028", name.clone(), message));
                 }
+                let value = self.evaluate(value)?;
+                self.set_property(object, name, value.clone())?;
                 Ok(value)
             }
-            Expr::Variable(ref name) => self.look_up_variable(name.clone(), expr),
+            Expr::Variable(id, name) => self.look_up_variable(name, *id),
             Expr::Logical(left, operator, right) => {
-                let left = self.evaluate(*left)?;
+                let left = self.evaluate(left)?;
 
-                if operator.token_type == TokenType::Or && self.is_truthy(&left) {
-                    return Ok(left);
-                }
-
-                if !self.is_truthy(&left) {
+                if operator.token_type == TokenType::Or {
+                    if self.is_truthy(&left) {
+                        return Ok(left);
+                    }
+                } else if !self.is_truthy(&left) {
                     return Ok(left);
                 }
 
-                self.evaluate(*right)
+                self.evaluate(right)
             }
-            Expr::Lambda(arguments, body) => {
-                let stmt = Stmt::Function(Token::from_str(""), arguments, body);
-                let function = LoxFunction::new("".to_string(), stmt, self.environment.clone());
-                Ok(Literal::LoxFunction(function))
+            Expr::Lambda(id, params, body, _) => {
+                let closure = self.closure(*id)?;
+                let function = LoxFunction::new(Symbol::default(), params.clone(), body.clone(), closure, self.globals.clone());
+                Ok(Literal::LoxFunction(Rc::new(function)))
             }
-            Expr::Call(callee, paren, arguments) => {
-                let callee2 = self.evaluate(*callee.clone())?;
+            Expr::Call(callee, paren, arguments, named) => {
+                let callee2 = self.evaluate(callee)?;
                 let mut args = vec![];
-                for argument in *arguments {
+                for argument in arguments.iter() {
                     args.push(self.evaluate(argument)?);
                 }
-
-                match callee2 {
-                    Literal::LoxFunction(mut lf) => {
-                        if args.len() != lf.arity() as usize {
-                            let message = format!(
-                                "Expected {} arguments but got {}.",
-                                lf.arity(),
-                                args.len()
-                            );
-                            return Err(RuntimeException::base(paren, message));
-                        }
-                        let result = lf.call(self, &args);
-                        match *callee {
-                            Expr::Variable(token) => {
-                                self.environment.assign(token, Literal::LoxFunction(lf))?;
-                            }
-                            _ => (),
-                        }
-                        result
-                    }
-                    Literal::NativeFunction(mut nf) => {
-                        if args.len() != nf.arity() as usize {
-                            let message = format!(
-                                "Expected {} arguments but got {}.",
-                                nf.arity(),
-                                args.len()
-                            );
-                            return Err(RuntimeException::base(paren, message));
-                        }
-                        nf.call(self, &args)
-                    }
-                    _ => {
-                        return Err(RuntimeException::base(
-                            paren,
-                            "Can only call functions and classes.".to_string(),
-                        ));
+                if !named.is_empty() {
+                    let mut values = vec![];
+                    for (name, value) in named.iter() {
+                        values.push((name, self.evaluate(value)?));
                     }
+                    args = bind_named(&callee2, args, values, paren)?;
                 }
+                self.call_value(&callee2, args, paren)
             }
-            Expr::Binary(left, operator, right) => {
-                let left = self.evaluate(*left);
-                let right = self.evaluate(*right);
-                match (operator.token_type, left, right) {
-                    (TokenType::Minus, Ok(Literal::Number(a)), Ok(Literal::Number(b))) => {
-                        Ok(Literal::Number(a - b))
-                    }
-                    (TokenType::Minus, _, _) => Err(RuntimeException::base(
-                        operator,
-                        "Operands must be numbers.".to_string(),
-                    )),
-                    (TokenType::Slash, Ok(Literal::Number(a)), Ok(Literal::Number(b))) => {
-                        if b == 0.0 {
-                            Err(RuntimeException::base(
-                                operator,
-                                "Cannot divide by zero".to_string(),
-                            ))
-                        } else {
-                            Ok(Literal::Number(a / b))
-                        }
-                    }
-                    (TokenType::Slash, _, _) => Err(RuntimeException::base(
-                        operator,
-                        "Operands must be numbers.".to_string(),
-                    )),
-                    (TokenType::Star, Ok(Literal::Number(a)), Ok(Literal::Number(b))) => {
-                        Ok(Literal::Number(a * b))
-                    }
-                    (TokenType::Star, _, _) => Err(RuntimeException::base(
-                        operator,
-                        "Operands must be numbers.".to_string(),
-                    )),
-                    (TokenType::Plus, Ok(Literal::Number(a)), Ok(Literal::Number(b))) => {
-                        Ok(Literal::Number(a + b))
-                    }
-                    (TokenType::Plus, Ok(Literal::String(mut s)), Ok(Literal::String(s2))) => {
-                        s.push_str(&s2);
-                        Ok(Literal::String(s))
-                    }
-                    (TokenType::Plus, Ok(Literal::String(mut s)), Ok(literal)) => {
-                        s.push_str(&literal.to_string());
-                        Ok(Literal::String(s))
-                    }
-                    (TokenType::Plus, Ok(literal), Ok(Literal::String(s2))) => {
-                        let mut s = literal.to_string();
-                        s.push_str(&s2);
-                        Ok(Literal::String(s))
-                    }
-                    (TokenType::Plus, Ok(l1), Ok(l2)) => {
-                        println!("l1: {:?}, l2: {:?}", l1, l2);
-                        Err(RuntimeException::base(
-                            operator,
-                            "Operands must be two numbers or two strings.".to_string(),
-                        ))
-                    }
-                    (TokenType::Percent, Ok(Literal::Number(a)), Ok(Literal::Number(b))) => {
-                        Ok(Literal::Number(a % b))
-                    }
-                    (TokenType::Percent, _, _) => Err(RuntimeException::base(
-                        operator,
-                        "Operands must be numbers".to_string(),
-                    )),
-                    (TokenType::Greater, Ok(Literal::Number(a)), Ok(Literal::Number(b))) => {
-                        Ok(Literal::from(a > b))
-                    }
-                    (TokenType::Greater, _, _) => Err(RuntimeException::base(
-                        operator,
-                        "Operands must be numbers.".to_string(),
-                    )),
-                    (TokenType::GreaterEqual, Ok(Literal::Number(a)), Ok(Literal::Number(b))) => {
-                        Ok(Literal::from(a >= b))
-                    }
-                    (TokenType::GreaterEqual, _, _) => Err(RuntimeException::base(
-                        operator,
-                        "Operands must be numbers.".to_string(),
+            Expr::Record(brace, fields) => {
+                let mut record = BTreeMap::new();
+                for (name, value) in fields.iter() {
+                    record.insert(Rc::from(name.lexeme.as_str()), self.evaluate(value)?);
+                }
+                let record = Literal::Record(Rc::new(record.into()));
+                self.charge(brace, memory::measure(&[], &record), &record)?;
+                Ok(record)
+            }
+            Expr::Get(object, name) => match self.evaluate(object)? {
+                Literal::Map(map) | Literal::Record(map) => match map.get(name.lexeme.as_str()) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(RuntimeException::base(
+                        "E0006",
+                        name.clone(),
+                        format!("Undefined property '{}'.", name.lexeme),
                     )),
-                    (TokenType::Less, Ok(Literal::Number(a)), Ok(Literal::Number(b))) => {
-                        Ok(Literal::from(a < b))
-                    }
-                    (TokenType::Less, _, _) => Err(RuntimeException::base(
-                        operator,
-                        "Operands must be numbers.".to_string(),
+                },
+                receiver => match stdlib::method(&receiver, name.lexeme.as_str()) {
+                    Some(method) => Ok(Literal::NativeFunction(method)),
+                    None if stdlib::has_methods(&receiver) => Err(RuntimeException::base(
+                        "E0006",
+                        name.clone(),
+                        format!("Undefined property '{}'.", name.lexeme),
                     )),
-                    (TokenType::LessEqual, Ok(Literal::Number(a)), Ok(Literal::Number(b))) => {
-                        Ok(Literal::from(a <= b))
-                    }
-                    (TokenType::LessEqual, _, _) => Err(RuntimeException::base(
-                        operator,
-                        "Operands must be numbers.".to_string(),
+                    None => Err(RuntimeException::base(
+                        "E0006",
+                        name.clone(),
+                        "Only modules, maps, records, strings, numbers and bytes have properties.".to_string(),
                     )),
-                    (TokenType::BangEqual, Ok(l1), Ok(l2)) => {
-                        Ok(Literal::from(!self.is_equal(&l1, &l2)))
-                    }
-                    (TokenType::EqualEqual, Ok(l1), Ok(l2)) => {
-                        Ok(Literal::from(self.is_equal(&l1, &l2)))
-                    }
-                    (_, Err(err), _) => Err(err),
-                    (_, _, Err(err)) => Err(err),
+                },
+            },
+            Expr::Index(object, bracket, index) => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let Literal::Bytes(bytes) = object else {
+                    return Err(RuntimeException::base("E0007", bracket.clone(), "Only bytes can be indexed.".to_string()));
+                };
+                let Literal::Int(i) = index else {
+                    return Err(RuntimeException::base("E0007", bracket.clone(), "Index must be an integer.".to_string()));
+                };
+                match usize::try_from(i).ok().and_then(|i| bytes.get(i)) {
+                    Some(&byte) => Ok(Literal::Int(byte as i64)),
+                    None => Err(RuntimeException::base("E0007", bracket.clone(), "Index out of range.".to_string())),
+                }
+            }
+            Expr::Binary(_, _, _) => self.evaluate_binary(expr),
+        }
+    }
+
+    /// Evaluates a chain of binary operators with an explicit work stack.
+    /// The parser builds `a + b + c + ...` as a left-leaning tree, so
+    /// walking its spine in a loop keeps long chains from overflowing the
+    /// Rust stack; only the right operands are evaluated recursively.
+    fn evaluate_binary(&mut self, expr: &Expr) -> InterpreterResult<Literal> {
+        let mut pending = vec![];
+        let mut leftmost = expr;
+        while let Expr::Binary(left, operator, right) = leftmost {
+            pending.push((operator, right));
+            leftmost = left;
+        }
+
+        let mut value = self.evaluate(leftmost)?;
+        while let Some((operator, right)) = pending.pop() {
+            let right = self.evaluate(right)?;
+            if self.warnings != Warnings::Off {
+                self.check_operands(operator, &value, &right)?;
+            }
+            value = self.binary(operator.clone(), value, right)?;
+            if let Literal::String(s) = &value {
+                self.charge(operator, s.len(), &value)?;
+            }
+        }
+        Ok(value)
+    }
 
-                    _ => unimplemented!(),
+    /// Warns, the first time `operator` does so, about an operation that
+    /// works but is likely a mistake: comparing values of different types,
+    /// which are never equal, or joining a number, nil or any other
+    /// non-string to a string. Under `--strict` it's an error instead.
+    fn check_operands(&mut self, operator: &Token, left: &Literal, right: &Literal) -> InterpreterResult<()> {
+        let warning = match (operator.token_type, left, right) {
+            (TokenType::EqualEqual | TokenType::BangEqual, left, right) => {
+                let (left, right) = (Type::of(left), Type::of(right));
+                if left == right || [left, right].iter().any(|t| matches!(t, Type::Nil | Type::Any)) {
+                    return Ok(());
                 }
+                let always = operator.token_type == TokenType::BangEqual;
+                let message = format!("Comparing a {} with a {} is always {}.", left, right, always);
+                Warning::runtime(operator, warning::MIXED_EQUALITY, message)
+            }
+            (TokenType::Plus, Literal::String(_), Literal::String(_)) => return Ok(()),
+            (TokenType::Plus, Literal::String(_), Literal::Nil) | (TokenType::Plus, Literal::Nil, Literal::String(_))
+                if self.string_coercion =>
+            {
+                let message = "nil is joined to a string as \"nil\".".to_string();
+                Warning::runtime(operator, warning::NIL_OPERAND, message)
+            }
+            (TokenType::Plus, Literal::String(_), other) | (TokenType::Plus, other, Literal::String(_))
+                if self.string_coercion =>
+            {
+                let message = format!("A {} is converted to a string to join it.", Type::of(other));
+                Warning::runtime(operator, warning::IMPLICIT_CONVERSION, message)
+            }
+            _ => return Ok(()),
+        };
+        if !self.warns_about(warning.rule)
+            || self.is_suppressed(&warning)
+            || !self.warned_sites.insert((operator.source, operator.line, operator.column))
+        {
+            return Ok(());
+        }
+        if self.warnings == Warnings::Strict {
+            return Err(RuntimeException::base(warning.code(), warning.token, warning.message));
+        }
+        self.warn(warning);
+        Ok(())
+    }
+
+    pub fn binary(
+        &self,
+        operator: Token,
+        left: Literal,
+        right: Literal,
+    ) -> InterpreterResult<Literal> {
+        use TokenType::*;
+
+        match (operator.token_type, left, right) {
+            (Star, Literal::String(s), count) | (Star, count, Literal::String(s)) => match count.as_f64() {
+                Some(count) => repeat_string(&s, count)
+                    .map(Literal::from)
+                    .map_err(|message| RuntimeException::base("E0002", operator, message)),
+                None => Err(RuntimeException::base(
+                    "E0002",
+                    operator,
+                    "A string can only be repeated by a number.".to_string(),
+                )),
+            },
+            (Minus | Slash | Star | Plus | Percent, a, b) if a.is_number() && b.is_number() => {
+                self.arithmetic(operator, &a, &b)
+            }
+            (Plus, Literal::String(s1), Literal::String(s2)) => {
+                Ok(Literal::from(format!("{}{}", s1, s2)))
+            }
+            (Plus, Literal::String(s), literal) if self.string_coercion => {
+                Ok(Literal::from(format!("{}{}", s, literal)))
+            }
+            (Plus, literal, Literal::String(s2)) if self.string_coercion => {
+                Ok(Literal::from(format!("{}{}", literal, s2)))
             }
+            (Plus, _, _) => Err(RuntimeException::base(
+                "E0002",
+                operator,
+                "Operands must be two numbers or two strings.".to_string(),
+            )),
+            (Greater | GreaterEqual | Less | LessEqual, a, b) if a.is_number() && b.is_number() => {
+                let ordering = a.compare_numbers(&b);
+                let result = match operator.token_type {
+                    Greater => ordering.is_some_and(|o| o.is_gt()),
+                    GreaterEqual => ordering.is_some_and(|o| o.is_ge()),
+                    Less => ordering.is_some_and(|o| o.is_lt()),
+                    _ => ordering.is_some_and(|o| o.is_le()),
+                };
+                Ok(Literal::from(result))
+            }
+            (BangEqual, l1, l2) => Ok(Literal::from(!self.is_equal(&l1, &l2))),
+            (EqualEqual, l1, l2) => Ok(Literal::from(self.is_equal(&l1, &l2))),
+            _ => Err(RuntimeException::base(
+                "E0002",
+                operator,
+                "Operands must be numbers.".to_string(),
+            )),
+        }
+    }
+
+    /// Integer operands stay integers, with overflow reported as an error;
+    /// a division that doesn't come out even, or any float operand,
+    /// promotes the result to a float.
+    fn arithmetic(
+        &self,
+        operator: Token,
+        left: &Literal,
+        right: &Literal,
+    ) -> InterpreterResult<Literal> {
+        let divisor_is_zero = right.as_f64() == Some(0.0)
+            && matches!(operator.token_type, TokenType::Slash | TokenType::Percent);
+        if divisor_is_zero && self.compat != Compat::Jlox {
+            return Err(RuntimeException::base(
+                "E0003",
+                operator,
+                "Cannot divide by zero".to_string(),
+            ));
+        }
+
+        // jlox divides by zero in floating point.
+        if let (Literal::Int(a), Literal::Int(b), false) = (left, right, divisor_is_zero) {
+            let (a, b) = (*a, *b);
+            let result = match operator.token_type {
+                TokenType::Plus => a.checked_add(b),
+                TokenType::Minus => a.checked_sub(b),
+                TokenType::Star => a.checked_mul(b),
+                // The one quotient that doesn't fit is an overflow, not a float.
+                TokenType::Slash if b == -1 => a.checked_neg(),
+                TokenType::Slash if a % b == 0 => Some(a / b),
+                TokenType::Slash => return Ok(Literal::Number(a as f64 / b as f64)),
+                // `MIN % -1` is 0 even though `MIN / -1` overflows.
+                _ => Some(a.wrapping_rem(b)),
+            };
+            return result.map(Literal::Int).ok_or_else(|| {
+                RuntimeException::base("E0010", operator, "Integer overflow.".to_string())
+            });
+        }
+
+        let (a, b) = (left.as_f64().unwrap_or_default(), right.as_f64().unwrap_or_default());
+        let result = match operator.token_type {
+            TokenType::Plus => a + b,
+            TokenType::Minus => a - b,
+            TokenType::Star => a * b,
+            TokenType::Slash => a / b,
+            _ => a % b,
+        };
+        Ok(Literal::Number(result))
+    }
+
+    pub fn unary(&self, operator: &Token, right: Literal) -> InterpreterResult<Literal> {
+        match (operator.token_type, right) {
+            (TokenType::Minus, Literal::Int(i)) => i.checked_neg().map(Literal::Int).ok_or_else(|| {
+                RuntimeException::base("E0010", operator.clone(), "Integer overflow.".to_string())
+            }),
+            (TokenType::Minus, Literal::Number(n)) => Ok(Literal::Number(-n)),
+            (TokenType::Bang, right) => Ok(Literal::from(!self.is_truthy(&right))),
+            _ => Err(RuntimeException::base(
+                "E0002",
+                operator.clone(),
+                "Operand must be a number.".to_string(),
+            )),
+        }
+    }
+
+    pub fn is_truthy(&self, v: &Literal) -> bool {
+        match v {
+            Literal::Nil | Literal::False => false,
+            Literal::Int(0) if self.truthiness == Truthiness::Script => false,
+            Literal::Number(n) if self.truthiness == Truthiness::Script => *n != 0.0,
+            Literal::String(s) if self.truthiness == Truthiness::Script => !s.is_empty(),
+            _ => true,
+        }
+    }
+
+    /// Calls `callee` with `args`, reporting a wrong number of arguments
+    /// at `paren`. Every call goes through here, natives' calls back into
+    /// the script included, so the call and return hooks see them all.
+    pub fn call_value(&mut self, callee: &Literal, args: Vec<Literal>, paren: &Token) -> InterpreterResult<Literal> {
+        if self.hooks.call.is_empty() && self.hooks.ret.is_empty() {
+            return self.invoke(callee, args, paren);
+        }
+        let span = Span::from(paren);
+        self.run_hooks(|hooks| &mut hooks.call, |interpreter, hook| hook(interpreter, callee, &args, span));
+        let result = self.invoke(callee, args, paren);
+        if let Ok(value) = &result {
+            self.run_hooks(|hooks| &mut hooks.ret, |interpreter, hook| hook(interpreter, callee, value, span));
         }
+        result
     }
 
-    fn is_truthy(&self, v: &Literal) -> bool {
-        !matches!(v, Literal::Nil | Literal::False)
+    fn invoke(&mut self, callee: &Literal, args: Vec<Literal>, paren: &Token) -> InterpreterResult<Literal> {
+        match callee {
+            Literal::LoxFunction(lf) => {
+                check_arity(paren, lf.arity() as usize, false, args.len())?;
+                let frame = std::mem::size_of::<RefCell<Environment>>()
+                    + args.len() * std::mem::size_of::<(Symbol, Literal)>();
+                self.charge(paren, frame, &Literal::Nil)?;
+                lf.call(self, &args)
+            }
+            Literal::NativeFunction(nf) => {
+                if nf.for_arity(args.len()).is_none() {
+                    let message = format!("Expected {} arguments but got {}.", nf.describe_arity(), args.len());
+                    return Err(RuntimeException::base("E0005", paren.clone(), message));
+                }
+                // Natives don't know where they were called from, so
+                // their errors are placed at the call.
+                let result = nf.call(self, &args).map_err(|err| match err {
+                    RuntimeException::Base(mut err) if err.token.line == 0 => {
+                        err.token = paren.clone();
+                        RuntimeException::Base(err)
+                    }
+                    err => err,
+                })?;
+                self.charge(paren, memory::measure(&[], &result), &result)?;
+                Ok(result)
+            }
+            Literal::Bound(bound) => {
+                let (remaining, variadic) = arity(callee).unwrap_or_default();
+                let partial = bound.curried && !args.is_empty() && args.len() < remaining;
+                if !bound.accepts(args.len()) {
+                    check_arity(paren, remaining, variadic, args.len())?;
+                }
+                let all: Vec<Literal> = bound.args.iter().cloned().chain(args).collect();
+                if partial {
+                    let bound = BoundFunction {
+                        function: bound.function.clone(),
+                        args: Rc::from(all),
+                        curried: true,
+                    };
+                    return Ok(Literal::Bound(Rc::new(bound)));
+                }
+                self.invoke(&bound.function, all, paren)
+            }
+            _ => Err(RuntimeException::base(
+                "E0004",
+                paren.clone(),
+                "Can only call functions and classes.".to_string(),
+            )),
+        }
     }
 
-    fn is_equal(&self, a: &Literal, b: &Literal) -> bool {
+    pub fn is_equal(&self, a: &Literal, b: &Literal) -> bool {
         match (a, b) {
             (Literal::Nil, Literal::Nil) => true,
             (Literal::Nil, _) => false,
             (Literal::True, Literal::True) => true,
             (Literal::False, Literal::False) => true,
-            (Literal::Number(i), Literal::Number(j)) => i == j,
+            (a, b) if a.is_number() && b.is_number() => {
+                a.compare_numbers(b) == Some(std::cmp::Ordering::Equal)
+            }
             (Literal::String(s1), Literal::String(s2)) => s1 == s2,
-            (Literal::NativeFunction(f1), Literal::NativeFunction(f2)) => {
-                f1.name == f2.name && f1.arity == f2.arity
+            (Literal::NativeFunction(f1), Literal::NativeFunction(f2)) => f1 == f2,
+            (Literal::LoxFunction(f1), Literal::LoxFunction(f2)) => f1 == f2,
+            (Literal::Map(m1), Literal::Map(m2)) => Rc::ptr_eq(m1, m2),
+            (Literal::Record(r1), Literal::Record(r2)) => {
+                r1.len() == r2.len()
+                    && r1.iter().zip(r2.iter()).all(|((k1, v1), (k2, v2))| k1 == k2 && self.is_equal(v1, v2))
             }
+            (Literal::EnumCase(c1), Literal::EnumCase(c2)) => Rc::ptr_eq(c1, c2),
+            (Literal::Bytes(b1), Literal::Bytes(b2)) => b1 == b2,
+            (Literal::Bound(b1), Literal::Bound(b2)) => Rc::ptr_eq(b1, b2),
+            (Literal::Channel(c1), Literal::Channel(c2)) => Arc::ptr_eq(c1, c2),
+            (Literal::Thread(t1), Literal::Thread(t2)) => Rc::ptr_eq(t1, t2),
             _ => false,
         }
     }
 
-    fn interpret(&mut self, stmts: Vec<Stmt>) -> InterpreterResult<()> {
-        for stmt in stmts {
-            self.execute(stmt)?;
+    /// Binds a value the REPL echoed to `_`, moving the two before it to
+    /// `__` and `___`, so later lines can build on them.
+    fn remember_result(&mut self, value: Literal) {
+        let mut globals = self.globals.borrow_mut();
+        let names = ["___", "__", "_"].map(Symbol::intern);
+        for i in 0..2 {
+            if let Ok(older) = globals.get_at(0, names[i + 1]) {
+                globals.define(names[i], older);
+            }
+        }
+        globals.define(names[2], value);
+    }
+
+    /// Runs a program. The REPL also echoes each top-level expression
+    /// statement's value, other than assignments, and keeps the last three
+    /// in `_`, `__` and `___`.
+    fn interpret(&mut self, stmts: &[Stmt]) -> InterpreterResult<()> {
+        // Programs run from a breakpoint share the outer run's guard.
+        let outermost = self.stack.is_none();
+        if outermost {
+            self.stack = Some(StackGuard::new());
+        }
+        let result = stmts.iter().try_for_each(|stmt| match stmt {
+            Stmt::Expression(expr) if self.repl && !matches!(expr, Expr::Assign(..)) => {
+                self.statement_hooks(stmt);
+                let value = self.evaluate(expr)?;
+                let text = self.stringify(value.clone());
+                self.print_line(text);
+                self.remember_result(value.clone());
+                self.last_value = value;
+                Ok(())
+            }
+            _ => self.execute(stmt),
+        });
+        let result = match result {
+            Err(RuntimeException::Return(Return { value })) => {
+                self.last_value = value.unwrap_or(Literal::Nil);
+                self.returned = Some(self.last_value.clone());
+                Ok(())
+            }
+            result => result,
+        };
+        if outermost {
+            self.stack = None;
+        }
+        result
+    }
+
+    /// Fails once deep recursion in the program has used up the stack
+    /// budget, rather than letting it overflow the native stack.
+    fn check_stack(&self, line: impl FnOnce() -> Option<u32>) -> InterpreterResult<()> {
+        if self.stack.is_some_and(|stack| stack.exhausted()) {
+            let token = Token {
+                line: line().unwrap_or(self.trace_line),
+                ..Token::default()
+            };
+            return Err(RuntimeException::base("E0008", token, "Stack overflow.".to_string()));
         }
         Ok(())
     }
 
     fn stringify(&self, literal: Literal) -> String {
         match literal {
-            Literal::Nil => "nil".to_string(),
-            Literal::Number(n) => {
-                let mut text = n.to_string();
-                if text.ends_with(".0") {
-                    text = text[0..text.len() - 2].to_string();
-                }
-                text
+            Literal::Number(n) => match self.compat.non_finite_name(n) {
+                Some(name) => name.to_string(),
+                None => literal.to_string(),
+            },
+            literal => literal.to_string(),
+        }
+    }
+
+    fn assign_variable(&mut self, id: ExprId, name: &Token, value: Literal) -> InterpreterResult<()> {
+        if let Some(distance) = self.locals.get(&id) {
+            self.environment.borrow_mut().assign_at(*distance, name, value)
+        } else if self.dynamic_scope {
+            self.environment.borrow_mut().assign(name, value)
+        } else {
+            self.globals.borrow_mut().assign(name, value)
+        }
+    }
+
+    /// Sets `name` on the map or record `object` evaluates to. These are
+    /// values, like numbers, so the field is set on a copy, which is
+    /// stored back where `object` came from.
+    fn set_property(&mut self, object: &Expr, name: &Token, value: Literal) -> InterpreterResult<()> {
+        let with_field = |kind: &str, members: &Members| {
+            if members.is_frozen() {
+                let message = format!("Can't change a field of a frozen {}.", kind);
+                return Err(RuntimeException::base("E0017", name.clone(), message));
+            }
+            let mut members = members.to_map();
+            members.insert(Rc::from(name.lexeme.as_str()), value);
+            Ok(Rc::new(members.into()))
+        };
+        let updated = match self.evaluate(object)? {
+            Literal::Map(members) => Literal::Map(with_field("map", &members)?),
+            Literal::Record(members) => Literal::Record(with_field("record", &members)?),
+            _ => {
+                let message = "Only maps and records have fields.".to_string();
+                return Err(RuntimeException::base("E0006", name.clone(), message));
             }
-            Literal::String(s) => s,
-            Literal::True => "true".to_string(),
-            Literal::False => "false".to_string(),
-            Literal::NativeFunction(_) => "<native fn>".to_string(),
-            Literal::LoxFunction(f) => format!("<fn {}>", f.name),
+        };
+        self.store(object, updated)
+    }
+
+    /// Stores `value` in the variable or field `place` names.
+    fn store(&mut self, place: &Expr, value: Literal) -> InterpreterResult<()> {
+        match place {
+            Expr::Variable(id, name) => self.assign_variable(*id, name, value),
+            Expr::Get(object, name) => self.set_property(object, name, value),
+            Expr::Grouping(inner) => self.store(inner, value),
+            _ => unreachable!("only places are stored to"),
         }
     }
 
-    fn look_up_variable(&self, name: Token, expr: Expr) -> InterpreterResult<Literal> {
-        let distance = self.locals.get(&expr);
+    fn look_up_variable(&self, name: &Token, id: ExprId) -> InterpreterResult<Literal> {
+        let distance = self.locals.get(&id);
         if let Some(distance) = distance {
-            return self.environment.get_at(*distance, name.lexeme);
+            return self.environment.borrow().get_at(*distance, name.lexeme);
+        }
+        if self.dynamic_scope {
+            return self.environment.borrow().get(name);
+        }
+        self.globals.borrow().get(name)
+    }
+}
+
+/// How many arguments `callee` takes, and whether it takes more, or
+/// `None` if it can't be called.
+pub fn arity(callee: &Literal) -> Option<(usize, bool)> {
+    match callee {
+        Literal::LoxFunction(function) => Some((function.arity() as usize, false)),
+        Literal::NativeFunction(function) => Some((function.arity as usize, function.variadic)),
+        Literal::Bound(bound) => {
+            let (arity, variadic) = arity(&bound.function)?;
+            Some((arity.saturating_sub(bound.args.len()), variadic))
+        }
+        _ => None,
+    }
+}
+
+/// Whether `callee` can be called with `given` arguments.
+pub fn accepts(callee: &Literal, given: usize) -> bool {
+    match callee {
+        Literal::LoxFunction(function) => function.accepts(given),
+        Literal::NativeFunction(function) => function.accepts(given),
+        Literal::Bound(bound) => bound.accepts(given),
+        _ => false,
+    }
+}
+
+/// The names of the parameters `callee` takes arguments for, or `None`
+/// if they have no names, as for natives.
+fn param_names(callee: &Literal) -> Option<Vec<Symbol>> {
+    match callee {
+        Literal::LoxFunction(function) => Some(function.params().iter().map(|param| param.lexeme).collect()),
+        Literal::Bound(bound) => {
+            let mut names = param_names(&bound.function)?;
+            names.drain(..bound.args.len().min(names.len()));
+            Some(names)
+        }
+        _ => None,
+    }
+}
+
+/// Puts `named` arguments in the places of the parameters they name,
+/// after the `positional` ones, for a call at `paren`.
+fn bind_named(
+    callee: &Literal,
+    positional: Vec<Literal>,
+    named: Vec<(&Token, Literal)>,
+    paren: &Token,
+) -> InterpreterResult<Vec<Literal>> {
+    let Some(params) = param_names(callee) else {
+        let message = "Only Lox functions take named arguments.".to_string();
+        return Err(RuntimeException::base("E0016", named[0].0.clone(), message));
+    };
+    let mut slots: Vec<Option<Literal>> = positional.into_iter().map(Some).collect();
+    if slots.len() < params.len() {
+        slots.resize(params.len(), None);
+    }
+    for (name, value) in named {
+        let Some(index) = params.iter().position(|param| *param == name.lexeme) else {
+            let message = format!("No parameter named '{}'.", name.lexeme);
+            return Err(RuntimeException::base("E0016", name.clone(), message));
+        };
+        if slots[index].is_some() {
+            let message = format!("Argument '{}' is already given by position.", name.lexeme);
+            return Err(RuntimeException::base("E0016", name.clone(), message));
         }
-        self.environment.get(name)
+        slots[index] = Some(value);
+    }
+    if let Some(index) = slots.iter().position(Option::is_none) {
+        let message = format!("Missing argument '{}'.", params[index]);
+        return Err(RuntimeException::base("E0016", paren.clone(), message));
+    }
+    Ok(slots.into_iter().flatten().collect())
+}
+
+fn check_arity(paren: &Token, arity: usize, variadic: bool, given: usize) -> InterpreterResult<()> {
+    if given == arity || (variadic && given > arity) {
+        return Ok(());
+    }
+    let message = if variadic {
+        format!("Expected at least {} arguments but got {}.", arity, given)
+    } else {
+        format!("Expected {} arguments but got {}.", arity, given)
+    };
+    Err(RuntimeException::base("E0005", paren.clone(), message))
+}
+
+/// Whether `expr` names somewhere a value can be stored back to: a
+/// variable, or a field of one, however deep.
+fn is_place(expr: &Expr) -> bool {
+    match expr {
+        Expr::Variable(..) => true,
+        Expr::Get(object, _) | Expr::Grouping(object) => is_place(object),
+        _ => false,
     }
 }
@@ -0,0 +1,264 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::callable::Callable;
+use crate::error::RuntimeException;
+use crate::interpreter::{self, Interpreter};
+use crate::native_function::NativeFunction;
+use crate::token::{Literal, Token};
+
+/// An iterator is a map or record with a `next` function taking no
+/// arguments. Each call gives `{ done: false, value: v }` for the next
+/// value, and `{ done: true }` once there are none left.
+///
+/// Strings iterate their characters, and other maps and records their
+/// keys. Anything else can't be iterated.
+pub fn iterator(value: &Literal) -> Option<Rc<dyn Callable>> {
+    match value {
+        Literal::Map(members) | Literal::Record(members) => match members.get("next") {
+            Some(Literal::LoxFunction(next)) if next.accepts(0) => Some(next.clone()),
+            Some(Literal::NativeFunction(next)) if next.accepts(0) => Some(Rc::new(next.clone())),
+            _ => {
+                let keys = RefCell::new(members.keys().cloned().collect::<Vec<_>>().into_iter());
+                Some(Rc::new(next_native(move |_| Ok(keys.borrow_mut().next().map(Literal::String)))))
+            }
+        },
+        Literal::String(s) => {
+            let s = s.clone();
+            let offset = Cell::new(0);
+            Some(Rc::new(next_native(move |_| {
+                let Some(c) = s[offset.get()..].chars().next() else {
+                    return Ok(None);
+                };
+                offset.set(offset.get() + c.len_utf8());
+                Ok(Some(Literal::String(Rc::from(c.to_string()))))
+            })))
+        }
+        _ => None,
+    }
+}
+
+/// Calls `next` once, giving the value it produced or `None` when the
+/// iterator is done.
+pub fn step(interpreter: &mut Interpreter, next: &dyn Callable) -> Result<Option<Literal>, RuntimeException> {
+    let result = next.call(interpreter, &[])?;
+    let (Literal::Map(fields) | Literal::Record(fields)) = &result else {
+        return Err(error("E0023", "An iterator's 'next' must return a record with 'done' and 'value'."));
+    };
+    if fields.get("done").is_some_and(|done| interpreter.is_truthy(done)) {
+        return Ok(None);
+    }
+    Ok(Some(fields.get("value").cloned().unwrap_or(Literal::Nil)))
+}
+
+/// An iterator record over `values`, for natives that produce a sequence.
+pub fn from_values(values: Vec<Literal>) -> Literal {
+    let values = RefCell::new(values.into_iter());
+    lazy(move |_| Ok(values.borrow_mut().next()))
+}
+
+/// The natives that make iterators, defined as globals.
+pub fn natives() -> Vec<(&'static str, Literal)> {
+    vec![
+        ("range", Literal::NativeFunction(NativeFunction::new("range", 2, range))),
+        ("keys", Literal::NativeFunction(NativeFunction::new("keys", 1, keys))),
+        ("values", Literal::NativeFunction(NativeFunction::new("values", 1, values))),
+        ("enumerate", Literal::NativeFunction(NativeFunction::new("enumerate", 1, enumerate))),
+        ("zip", Literal::NativeFunction(NativeFunction::new("zip", 2, zip))),
+        ("map", Literal::NativeFunction(NativeFunction::new("map", 2, map))),
+        ("filter", Literal::NativeFunction(NativeFunction::new("filter", 2, filter))),
+        ("take", Literal::NativeFunction(NativeFunction::new("take", 2, take))),
+        ("takeWhile", Literal::NativeFunction(NativeFunction::new("takeWhile", 2, take_while))),
+    ]
+}
+
+/// A `next` native that runs `step` for each value, so the values are
+/// only worked out as they're asked for.
+fn next_native(step: impl Fn(&mut Interpreter) -> Result<Option<Literal>, RuntimeException> + 'static) -> NativeFunction {
+    NativeFunction::new("next", 0, move |interpreter, _| {
+        let value = step(interpreter)?;
+        Ok(record(&[
+            ("done", Literal::from(value.is_none())),
+            ("value", value.unwrap_or(Literal::Nil)),
+        ]))
+    })
+}
+
+/// An iterator record around [`next_native`].
+pub fn lazy(step: impl Fn(&mut Interpreter) -> Result<Option<Literal>, RuntimeException> + 'static) -> Literal {
+    record(&[("next", Literal::NativeFunction(next_native(step)))])
+}
+
+fn record(fields: &[(&str, Literal)]) -> Literal {
+    let fields: BTreeMap<Rc<str>, Literal> = fields
+        .iter()
+        .map(|(name, value)| (Rc::from(*name), value.clone()))
+        .collect();
+    Literal::Record(Rc::new(fields.into()))
+}
+
+fn error(code: &'static str, message: &str) -> RuntimeException {
+    RuntimeException::base(code, Token::default(), message.to_string())
+}
+
+fn iterable(value: &Literal, native: &str) -> Result<Rc<dyn Callable>, RuntimeException> {
+    iterator(value).ok_or_else(|| error("E0018", &format!("{} expects something to iterate.", native)))
+}
+
+/// A function argument that takes one value, like `map`'s.
+fn function(value: &Literal, native: &str) -> Result<Literal, RuntimeException> {
+    if interpreter::arity(value).is_none() {
+        return Err(error("E0018", &format!("{} expects a function.", native)));
+    }
+    if !interpreter::accepts(value, 1) {
+        return Err(error("E0018", &format!("{} expects a function taking one argument.", native)));
+    }
+    Ok(value.clone())
+}
+
+/// Calls `function` on `value` the way a call in the script would, so
+/// hooks see it.
+fn call(interpreter: &mut Interpreter, function: &Literal, value: Literal) -> Result<Literal, RuntimeException> {
+    interpreter.call_value(function, vec![value], &Token::default())
+}
+
+fn members<'a>(value: &'a Literal, native: &str) -> Result<&'a BTreeMap<Rc<str>, Literal>, RuntimeException> {
+    match value {
+        Literal::Map(members) | Literal::Record(members) => Ok(members),
+        _ => Err(error("E0018", &format!("{} expects a map or record.", native))),
+    }
+}
+
+/// `range(start, end)`, counting up by one from `start` to just before
+/// `end`. Integers stay integers.
+fn range(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    match (&args[0], &args[1]) {
+        (&Literal::Int(start), &Literal::Int(end)) => {
+            let current = Cell::new(start);
+            Ok(lazy(move |_| {
+                let value = current.get();
+                if value >= end {
+                    return Ok(None);
+                }
+                current.set(value + 1);
+                Ok(Some(Literal::Int(value)))
+            }))
+        }
+        (start, end) => {
+            let (Some(start), Some(end)) = (start.as_f64(), end.as_f64()) else {
+                return Err(error("E0018", "range expects numbers."));
+            };
+            let current = Cell::new(start);
+            Ok(lazy(move |_| {
+                let value = current.get();
+                if value >= end {
+                    return Ok(None);
+                }
+                current.set(value + 1.0);
+                Ok(Some(Literal::Number(value)))
+            }))
+        }
+    }
+}
+
+fn keys(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let keys = RefCell::new(members(&args[0], "keys")?.keys().cloned().collect::<Vec<_>>().into_iter());
+    Ok(lazy(move |_| Ok(keys.borrow_mut().next().map(Literal::String))))
+}
+
+fn values(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(from_values(members(&args[0], "values")?.values().cloned().collect()))
+}
+
+/// Pairs each value with its position, as `{ index, value }`.
+fn enumerate(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let next = iterable(&args[0], "enumerate")?;
+    let index = Cell::new(0);
+    Ok(lazy(move |interpreter| {
+        let Some(value) = step(interpreter, next.as_ref())? else {
+            return Ok(None);
+        };
+        index.set(index.get() + 1);
+        Ok(Some(record(&[("index", Literal::Int(index.get() - 1)), ("value", value)])))
+    }))
+}
+
+/// Walks two iterables together as `{ first, second }`, stopping when
+/// either runs out.
+fn zip(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let first = iterable(&args[0], "zip")?;
+    let second = iterable(&args[1], "zip")?;
+    Ok(lazy(move |interpreter| {
+        let Some(a) = step(interpreter, first.as_ref())? else {
+            return Ok(None);
+        };
+        let Some(b) = step(interpreter, second.as_ref())? else {
+            return Ok(None);
+        };
+        Ok(Some(record(&[("first", a), ("second", b)])))
+    }))
+}
+
+/// Calls `function` on each value as it's asked for.
+fn map(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let next = iterable(&args[0], "map")?;
+    let function = function(&args[1], "map")?;
+    Ok(lazy(move |interpreter| match step(interpreter, next.as_ref())? {
+        Some(value) => call(interpreter, &function, value).map(Some),
+        None => Ok(None),
+    }))
+}
+
+/// The values `predicate` is truthy for.
+fn filter(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let next = iterable(&args[0], "filter")?;
+    let predicate = function(&args[1], "filter")?;
+    Ok(lazy(move |interpreter| {
+        while let Some(value) = step(interpreter, next.as_ref())? {
+            let keep = call(interpreter, &predicate, value.clone())?;
+            if interpreter.is_truthy(&keep) {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }))
+}
+
+/// At most the first `count` values. Once they're given, the underlying
+/// iterator isn't asked for more.
+fn take(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let next = iterable(&args[0], "take")?;
+    let Literal::Int(count) = args[1] else {
+        return Err(error("E0018", "take expects an integer count."));
+    };
+    let left = Cell::new(count);
+    Ok(lazy(move |interpreter| {
+        if left.get() <= 0 {
+            return Ok(None);
+        }
+        left.set(left.get() - 1);
+        step(interpreter, next.as_ref())
+    }))
+}
+
+/// Values up to the first one `predicate` isn't truthy for.
+fn take_while(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let next = iterable(&args[0], "takeWhile")?;
+    let predicate = function(&args[1], "takeWhile")?;
+    let done = Cell::new(false);
+    Ok(lazy(move |interpreter| {
+        if done.get() {
+            return Ok(None);
+        }
+        let Some(value) = step(interpreter, next.as_ref())? else {
+            return Ok(None);
+        };
+        let keep = call(interpreter, &predicate, value.clone())?;
+        if interpreter.is_truthy(&keep) {
+            return Ok(Some(value));
+        }
+        done.set(true);
+        Ok(None)
+    }))
+}
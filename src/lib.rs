@@ -1 +1,52 @@
-
+pub mod ast_cache;
+pub mod ast_json;
+pub mod ast_printer;
+pub mod bench;
+pub mod bytecode;
+pub mod callable;
+pub mod cli;
+pub mod compiler;
+pub mod config;
+pub mod coverage;
+pub mod datetime;
+pub mod declaration;
+pub mod docs;
+pub mod dot_printer;
+pub mod environment;
+pub mod error;
+pub mod error_codes;
+pub mod event_loop;
+pub mod expr;
+pub mod formatter;
+pub mod gc;
+pub mod highlighter;
+pub mod hooks;
+#[cfg(feature = "stdlib-net")]
+pub mod http;
+pub mod interpreter;
+pub mod iterator;
+pub mod json;
+pub mod lint;
+pub mod logging;
+pub mod lox_function;
+pub mod lsp;
+pub mod memory;
+pub mod modules;
+pub mod native_function;
+pub mod optimizer;
+pub mod parser;
+pub mod playground;
+pub mod resolver;
+pub mod scanner;
+pub mod source_map;
+pub mod stack;
+pub mod stats;
+pub mod stdlib;
+pub mod stmt;
+pub mod symbol;
+pub mod test_runner;
+pub mod threads;
+pub mod token;
+pub mod typecheck;
+pub mod vm;
+pub mod warning;
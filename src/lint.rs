@@ -0,0 +1,52 @@
+use crate::error::{LoxError, Phase, Span};
+use crate::json::Json;
+use crate::source_map::SourceMap;
+use crate::warning::Warning;
+
+/// How `rlox lint` writes what it finds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LintFormat {
+    /// Errors and warnings as the interpreter reports them on stderr.
+    #[default]
+    Text,
+    /// A JSON array of diagnostics, each with the `phase`, `message`,
+    /// `line`, `column` and `length` the playground gives, plus its
+    /// `severity`, `code`, and for warnings the `rule` that found it.
+    Json,
+}
+
+/// Everything linting found, in source order with errors first.
+pub fn render(errors: &[LoxError], warnings: &[Warning], format: LintFormat, sources: &SourceMap) -> String {
+    let mut warnings: Vec<&Warning> = warnings.iter().collect();
+    warnings.sort_by_key(|warning| (warning.token.line, warning.token.column));
+    match format {
+        LintFormat::Text => {
+            let errors = errors.iter().map(|error| error.render(sources, true));
+            let warnings = warnings.iter().map(|warning| format!("{} [{}]", warning.render(sources), warning.rule));
+            errors.chain(warnings).map(|line| line + "\n").collect()
+        }
+        LintFormat::Json => {
+            let errors = errors.iter().map(|error| {
+                diagnostic("error", error.phase(), error.code(), None, error.message(), error.span())
+            });
+            let warnings = warnings.iter().map(|warning| {
+                let span = Span::from(&warning.token);
+                diagnostic("warning", warning.phase, warning.code(), Some(warning.rule), &warning.message, span)
+            });
+            format!("{}\n", Json::Array(errors.chain(warnings).collect()))
+        }
+    }
+}
+
+fn diagnostic(severity: &str, phase: Phase, code: &str, rule: Option<&str>, message: &str, span: Span) -> Json {
+    Json::object([
+        ("severity", Json::from(severity)),
+        ("phase", Json::from(phase.name())),
+        ("code", Json::from(code)),
+        ("rule", rule.map_or(Json::Null, Json::from)),
+        ("message", Json::from(message)),
+        ("line", Json::Int(span.line as i64)),
+        ("column", Json::Int(span.column as i64)),
+        ("length", Json::Int(span.length as i64)),
+    ])
+}
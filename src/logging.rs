@@ -0,0 +1,92 @@
+#[cfg(feature = "stdlib-time")]
+use crate::datetime::DateTime;
+use crate::error::RuntimeException;
+use crate::interpreter::Interpreter;
+use crate::native_function::NativeFunction;
+use crate::token::Literal;
+
+/// How serious a logged message is. Messages below the interpreter's
+/// level, see [`Interpreter::set_log_level`], are dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+    /// Above every level, to drop them all.
+    Off,
+}
+
+impl LogLevel {
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        match name {
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "warn" => Some(LogLevel::Warn),
+            "error" => Some(LogLevel::Error),
+            "off" => Some(LogLevel::Off),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+            LogLevel::Off => "OFF",
+        }
+    }
+}
+
+/// `logDebug(message)` and the rest, one for each level.
+pub fn globals() -> Vec<(&'static str, Literal)> {
+    vec![
+        ("logDebug", Literal::NativeFunction(NativeFunction::new("logDebug", 1, log_debug))),
+        ("logInfo", Literal::NativeFunction(NativeFunction::new("logInfo", 1, log_info))),
+        ("logWarn", Literal::NativeFunction(NativeFunction::new("logWarn", 1, log_warn))),
+        ("logError", Literal::NativeFunction(NativeFunction::new("logError", 1, log_error))),
+    ]
+}
+
+fn log_debug(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    log(interpreter, LogLevel::Debug, &args[0])
+}
+
+fn log_info(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    log(interpreter, LogLevel::Info, &args[0])
+}
+
+fn log_warn(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    log(interpreter, LogLevel::Warn, &args[0])
+}
+
+fn log_error(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    log(interpreter, LogLevel::Error, &args[0])
+}
+
+/// Writes `message` after the UTC time and its level, so it stands apart
+/// from the program's output and from `--trace` lines. It goes to stderr
+/// unless the interpreter captures output, see [`Interpreter::log_line`].
+fn log(interpreter: &mut Interpreter, level: LogLevel, message: &Literal) -> Result<Literal, RuntimeException> {
+    if level >= interpreter.log_level() {
+        let text = format!("{}{:5} {}", timestamp(interpreter), level.label(), message);
+        interpreter.log_line(text);
+    }
+    Ok(Literal::Nil)
+}
+
+#[cfg(feature = "stdlib-time")]
+fn timestamp(interpreter: &Interpreter) -> String {
+    let now = DateTime::from_millis(interpreter.unix_millis());
+    let time = now.format("%Y-%m-%dT%H:%M:%S").unwrap_or_default();
+    format!("{}.{:03}Z ", time, now.millisecond)
+}
+
+/// Without a clock, messages go without a time.
+#[cfg(not(feature = "stdlib-time"))]
+fn timestamp(_interpreter: &Interpreter) -> String {
+    String::new()
+}
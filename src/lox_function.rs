@@ -1,66 +1,102 @@
-use crate::callable::Callable;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::callable::{next_function_id, Callable, FunctionId};
 use crate::environment::Environment;
 use crate::error::RuntimeException;
+use crate::expr::next_expr_id;
 use crate::interpreter::Interpreter;
 use crate::stmt::Stmt;
+use crate::symbol::Symbol;
 use crate::token::Literal;
 use crate::token::Token;
+use crate::token::TokenType;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// A function value. The parameter list and body are shared with the AST
+/// node that declared them, so creating a closure never copies the tree.
+#[derive(Clone)]
 pub struct LoxFunction {
-    pub name: String,
-    declaration: Box<Stmt>,
-    pub closure: Environment,
+    pub id: FunctionId,
+    pub name: Symbol,
+    params: Rc<[Token]>,
+    body: Rc<[Stmt]>,
+    pub closure: Rc<RefCell<Environment>>,
+    /// The globals it was declared among, which differ from the script's
+    /// for a function an imported module declared.
+    pub globals: Rc<RefCell<Environment>>,
+    /// What the `///` comments before its declaration say, for `help()`.
+    pub doc: Option<Rc<str>>,
 }
 
 impl LoxFunction {
-    pub fn new(name: String, declaration: Stmt, closure: Environment) -> Self {
+    pub fn new(
+        name: Symbol,
+        params: Rc<[Token]>,
+        body: Rc<[Stmt]>,
+        closure: Rc<RefCell<Environment>>,
+        globals: Rc<RefCell<Environment>>,
+    ) -> Self {
         Self {
+            id: next_function_id(),
             name,
-            declaration: Box::new(declaration),
+            params,
+            body,
             closure,
+            globals,
+            doc: None,
         }
     }
+
+    pub fn params(&self) -> &[Token] {
+        &self.params
+    }
+
+    /// The declaration the function came from, for running it somewhere
+    /// its closure can't go.
+    pub fn declaration(&self) -> Stmt {
+        let name = Token::new(TokenType::Identifier, self.name, None, 0);
+        Stmt::Function(name, next_expr_id(), self.params.clone(), self.body.clone(), None, self.doc.clone())
+    }
+}
+
+impl PartialEq for LoxFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for LoxFunction {}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LoxFunction({})", self.name)
+    }
 }
 
 impl Callable for LoxFunction {
     fn arity(&self) -> u8 {
-        match &*self.declaration {
-            Stmt::Function(_name, params, _body) => params.len() as u8,
-            _ => 0,
-        }
+        self.params.len() as u8
     }
 
     fn call(
-        &mut self,
-        interpreter: &Interpreter,
-        args: &Vec<Literal>,
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[Literal],
     ) -> Result<Literal, RuntimeException> {
-        let (env, depth) =
-            Environment::wrap(self.closure.clone(), interpreter.environment.clone(), 0);
-        let mut interpreter2 = Interpreter::new(&env);
-        match &*self.declaration {
-            Stmt::Function(_name, params, body) => {
-                for (i, param) in params.iter().enumerate() {
-                    let value: Literal = args.get(i).unwrap().clone();
-                    interpreter2.environment.define(param.lexeme.clone(), value);
-                }
+        let mut environment = Environment::with_enclosing(self.closure.clone());
+        for (param, value) in self.params.iter().zip(args) {
+            environment.define(param.lexeme, value.clone());
+        }
 
-                let result = interpreter2.evaluate_block(*(*body).clone());
-                self.closure = Environment::unwrap(interpreter2.environment, depth);
-                match result {
-                    Err(RuntimeException::Return(r)) => match r.value {
-                        Some(v) => return Ok(v),
-                        None => return Ok(Literal::Nil),
-                    },
-                    Err(err) => return Err(err),
-                    _ => return Ok(Literal::Nil),
-                }
-            }
-            _ => Err(RuntimeException::base(
-                Token::default(),
-                "Invalid function declaration.".to_string(),
-            )),
+        let result = interpreter.execute_function_body(&self.body, environment, &self.globals);
+        match result {
+            Err(RuntimeException::Return(r)) => match r.value {
+                Some(v) => Ok(v),
+                None => Ok(Literal::Nil),
+            },
+            Err(err) => Err(err),
+            _ => Ok(Literal::Nil),
         }
     }
 }
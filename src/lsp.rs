@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::error::LoxError;
+use crate::interpreter::Interpreter;
+use crate::json::Json;
+use crate::parser::Parser;
+use crate::resolver::{Resolve, Resolver};
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use crate::token::Token;
+
+/// A problem in a document. Lines count from 1 like the rest of the
+/// interpreter; columns are byte offsets from 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: u32,
+    pub column: u32,
+    pub length: u32,
+    pub message: String,
+    pub code: &'static str,
+}
+
+impl From<LoxError> for Diagnostic {
+    fn from(error: LoxError) -> Self {
+        let span = error.span();
+        Self {
+            line: span.line,
+            column: span.column,
+            length: span.length,
+            message: error.message().to_string(),
+            code: error.code(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Enum,
+    Function,
+    Variable,
+}
+
+/// Everything the server knows about one version of a document.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    /// Each variable use paired with the token that declared it.
+    pub references: Vec<(Token, Token)>,
+    /// Named functions anywhere in the document, and top-level variables
+    /// and enums.
+    pub symbols: Vec<(Token, SymbolKind)>,
+    /// The document's lines, to turn byte columns into the UTF-16 ones
+    /// clients count in.
+    lines: Vec<String>,
+}
+
+impl Analysis {
+    /// Scans, parses and resolves `source`, collecting every error along
+    /// the way rather than stopping at the first.
+    pub fn new(source: &str) -> Self {
+        let mut analysis = Analysis {
+            lines: source.split('\n').map(str::to_string).collect(),
+            ..Analysis::default()
+        };
+        let mut scanner = Scanner::new(source.to_string());
+        let errors = scanner.scan_tokens_collecting();
+        analysis.diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+
+        let (statements, errors) = Parser::new(scanner.tokens).parse_collecting();
+        analysis.diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+
+        let mut interpreter = Interpreter::default();
+        let mut resolver = Resolver::collecting(&mut interpreter);
+        resolver.resolve(statements.as_slice());
+        let resolution = resolver.into_resolution();
+        analysis.diagnostics.extend(resolution.errors.into_iter().map(Diagnostic::from));
+        analysis.references = resolution.references;
+
+        for stmt in &statements {
+            let stmt = match stmt {
+                Stmt::Export(declaration) => declaration,
+                stmt => stmt,
+            };
+            match stmt {
+                Stmt::Var(name, _, _) => analysis.symbols.push((name.clone(), SymbolKind::Variable)),
+                Stmt::Enum(name, _) => analysis.symbols.push((name.clone(), SymbolKind::Enum)),
+                _ => (),
+            }
+            collect_functions(stmt, &mut analysis.symbols);
+        }
+        analysis
+    }
+
+    /// The declaration of the variable used at `line` and `column`, if any.
+    pub fn definition(&self, line: u32, column: u32) -> Option<&Token> {
+        self.references
+            .iter()
+            .find(|(name, _)| {
+                name.line == line
+                    && (name.column..name.column + name.lexeme.len() as u32).contains(&column)
+            })
+            .map(|(_, declaration)| declaration)
+    }
+
+    /// The UTF-16 column of byte `column` on `line`. Columns past the end
+    /// of the line, as multi-line tokens end at, count a unit per byte.
+    pub fn utf16_column(&self, line: u32, column: u32) -> u32 {
+        let text = self.line(line);
+        let column = column as usize;
+        let units: usize = text
+            .char_indices()
+            .take_while(|&(index, _)| index < column)
+            .map(|(_, c)| c.len_utf16())
+            .sum();
+        (units + column.saturating_sub(text.len())) as u32
+    }
+
+    /// The byte column of UTF-16 column `character` on `line`.
+    pub fn byte_column(&self, line: u32, character: u32) -> u32 {
+        let text = self.line(line);
+        let mut units = 0;
+        for (index, c) in text.char_indices() {
+            if units >= character {
+                return index as u32;
+            }
+            units += c.len_utf16() as u32;
+        }
+        text.len() as u32 + character.saturating_sub(units)
+    }
+
+    fn line(&self, line: u32) -> &str {
+        let index = (line as usize).wrapping_sub(1);
+        self.lines.get(index).map_or("", String::as_str)
+    }
+}
+
+fn collect_functions(stmt: &Stmt, symbols: &mut Vec<(Token, SymbolKind)>) {
+    match stmt {
+        Stmt::Function(name, _, _, body, ..) => {
+            symbols.push((name.clone(), SymbolKind::Function));
+            body.iter().for_each(|stmt| collect_functions(stmt, symbols));
+        }
+        Stmt::Block(stmts) => stmts.iter().for_each(|stmt| collect_functions(stmt, symbols)),
+        Stmt::If(arms, else_branch) => {
+            arms.iter().for_each(|(_, _, branch)| collect_functions(branch, symbols));
+            if let Some(else_branch) = else_branch {
+                collect_functions(else_branch, symbols);
+            }
+        }
+        Stmt::While(_, _, body)
+        | Stmt::For(_, _, _, _, body)
+        | Stmt::ForIn(_, _, body)
+        | Stmt::Labeled(_, body)
+        | Stmt::With(_, _, _, body) => collect_functions(body, symbols),
+        _ => (),
+    }
+}
+
+/// A Language Server Protocol server over JSON-RPC. Documents are synced
+/// in full on every change; positions count UTF-16 code units, as the
+/// protocol has them by default.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Analysis>,
+    shutdown: bool,
+}
+
+impl Server {
+    /// Serves requests until the client sends `exit`. Returns whether it
+    /// asked to shut down first, which decides the exit status.
+    pub fn run(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<bool> {
+        while let Some(body) = read_message(input)? {
+            let message = match Json::parse(&body) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            if message.get("method").and_then(Json::as_str) == Some("exit") {
+                return Ok(self.shutdown);
+            }
+            for reply in self.handle(&message) {
+                write_message(output, &reply)?;
+            }
+        }
+        Ok(self.shutdown)
+    }
+
+    /// Answers one message with the responses and notifications to send.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or_default();
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let result = match method {
+            "initialize" => Json::object([(
+                "capabilities",
+                Json::object([
+                    ("textDocumentSync", Json::Int(1)),
+                    ("definitionProvider", Json::Bool(true)),
+                    ("documentSymbolProvider", Json::Bool(true)),
+                ]),
+            )]),
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didOpen" => params.get("textDocument").and_then(|d| d.get("text")),
+                    _ => params
+                        .get("contentChanges")
+                        .and_then(Json::as_array)
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change.get("text")),
+                };
+                let analysis = Analysis::new(text.and_then(Json::as_str).unwrap_or_default());
+                let diagnostics = publish_diagnostics(&uri, &analysis);
+                self.documents.insert(uri, analysis);
+                return vec![diagnostics];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, &Analysis::default())];
+            }
+            "textDocument/definition" => {
+                let position = params.get("position").unwrap_or(&Json::Null);
+                let line = position.get("line").and_then(Json::as_i64).unwrap_or(-1);
+                let column = position.get("character").and_then(Json::as_i64).unwrap_or(-1);
+                self.documents
+                    .get(&uri)
+                    .and_then(|analysis| {
+                        let line = line as u32 + 1;
+                        let declaration = analysis.definition(line, analysis.byte_column(line, column as u32))?;
+                        Some(location(&uri, analysis, declaration))
+                    })
+                    .unwrap_or(Json::Null)
+            }
+            "textDocument/documentSymbol" => {
+                let symbols = self.documents.get(&uri).map_or(vec![], |analysis| {
+                    analysis
+                        .symbols
+                        .iter()
+                        .map(|(name, kind)| {
+                            let kind = match kind {
+                                SymbolKind::Enum => 10,
+                                SymbolKind::Function => 12,
+                                SymbolKind::Variable => 13,
+                            };
+                            Json::object([
+                                ("name", Json::from(name.lexeme.to_string())),
+                                ("kind", Json::Int(kind)),
+                                ("location", location(&uri, analysis, name)),
+                            ])
+                        })
+                        .collect()
+                });
+                Json::Array(symbols)
+            }
+            _ => match message.get("id") {
+                Some(id) => {
+                    return vec![Json::object([
+                        ("jsonrpc", Json::from("2.0")),
+                        ("id", id.clone()),
+                        (
+                            "error",
+                            Json::object([
+                                ("code", Json::Int(-32601)),
+                                ("message", Json::from(format!("Unhandled method '{}'.", method))),
+                            ]),
+                        ),
+                    ])]
+                }
+                None => return vec![],
+            },
+        };
+        match message.get("id") {
+            Some(id) => vec![Json::object([
+                ("jsonrpc", Json::from("2.0")),
+                ("id", id.clone()),
+                ("result", result),
+            ])],
+            None => vec![],
+        }
+    }
+}
+
+/// The range of `length` bytes from byte `column` on `line`, in the
+/// protocol's lines from 0 and UTF-16 columns.
+fn range(analysis: &Analysis, line: u32, column: u32, length: u32) -> Json {
+    let position = |column: u32| {
+        Json::object([
+            ("line", Json::Int(line.saturating_sub(1) as i64)),
+            ("character", Json::Int(analysis.utf16_column(line, column) as i64)),
+        ])
+    };
+    Json::object([("start", position(column)), ("end", position(column + length))])
+}
+
+fn location(uri: &str, analysis: &Analysis, token: &Token) -> Json {
+    Json::object([
+        ("uri", Json::from(uri)),
+        ("range", range(analysis, token.line, token.column, token.lexeme.len() as u32)),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, analysis: &Analysis) -> Json {
+    let diagnostics = analysis
+        .diagnostics
+        .iter()
+        .map(|diagnostic| {
+            Json::object([
+                ("range", range(analysis, diagnostic.line, diagnostic.column, diagnostic.length)),
+                ("severity", Json::Int(1)),
+                ("code", Json::from(diagnostic.code)),
+                ("source", Json::from("rlox")),
+                ("message", Json::from(diagnostic.message.as_str())),
+            ])
+        })
+        .collect();
+    Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Json::object([("uri", Json::from(uri)), ("diagnostics", Json::Array(diagnostics))]),
+        ),
+    ])
+}
+
+/// Reads one `Content-Length` framed message body, or `None` at the end
+/// of input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
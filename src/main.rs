@@ -1,35 +1,347 @@
 use std::env;
+use std::path::Path;
+use std::panic;
+use std::thread;
 use std::process::exit;
 use std::error::Error;
-use std::cmp::Ordering::*;
-
-pub mod ast_printer;
-pub mod callable;
-pub mod declaration;
-pub mod environment;
-pub mod error;
-pub mod expr;
-pub mod interpreter;
-pub mod lox_function;
-pub mod native_function;
-pub mod parser;
-pub mod resolver;
-pub mod scanner;
-pub mod stmt;
-pub mod token;
-
-use crate::interpreter::Interpreter;
+
+use treewalk::cli;
+use treewalk::config::Config;
+use treewalk::docs::DocFormat;
+use treewalk::error_codes;
+use treewalk::highlighter::HighlightFormat;
+use treewalk::interpreter::{AstFormat, Backend, Compat, Interpreter, RunStatus, Truthiness};
+use treewalk::lint::LintFormat;
+use treewalk::logging::LogLevel;
+use treewalk::scanner;
+use treewalk::stack;
+use treewalk::stdlib::Capability;
+use treewalk::test_runner;
+use treewalk::token::Literal;
+use treewalk::warning::{self, Warnings};
+
+/// Deeply nested programs recurse through the parser and interpreter, so
+/// the work runs on a thread with far more stack than `main` is given.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // Leave headroom for the frames between two checks of the budget.
+    stack::set_budget(STACK_SIZE - 16 * 1024 * 1024);
+    let runner = thread::Builder::new().stack_size(STACK_SIZE).spawn(run)?;
+    match runner.join() {
+        Ok(Ok(0)) => Ok(()),
+        Ok(Ok(code)) => exit(code),
+        Ok(Err(err)) => Err(err.into()),
+        Err(panic) => panic::resume_unwind(panic),
+    }
+}
+
+fn usage() -> ! {
+    println!("Usage: rlox [--backend=tree|vm] [--opt] [--check] [--late-binding] [--top-level-return] [--compat=jlox] [--truthiness=lox|script] [--no-string-coercion] [--error-codes] [--warn|--strict] [--enable=rule,rule] [--disable=rule,rule] [--print-separator=str] [--print-function] [--ast|--ast-dot|--emit-ast=out.json] [--from-ast] [--trace] [--log-level=debug|info|warn|error|off] [--stats] [--gc-stats] [--max-memory=64M] [--max-loop-iterations=n] [--stack-budget=1M] [--debug] [--test] [--coverage[=out.lcov]] [--keywords=const,continue,match] [--flat-natives] [--allow=exec,fs,net,time] [--prelude file.lox|--no-prelude] [--no-config] [-I dir] [script]");
+    println!("       rlox compile [script] [-o out.loxc]");
+    println!("       rlox run [script.lox|script.loxc]");
+    println!("       rlox fmt [script]");
+    println!("       rlox highlight [--format=ansi|html] [script]");
+    println!("       rlox test [dir]");
+    println!("       rlox lint [--enable=rule,rule] [--disable=rule,rule] [--format=text|json] [script]");
+    println!("       rlox doc [--format=markdown|html] [dir|script] [-o out]");
+    println!("       rlox explain [code]");
+    println!("       rlox bench [--iterations N] [script]");
+    exit(64);
+}
+
+/// `~/.loxrc`, if there is one.
+fn default_prelude() -> Option<String> {
+    let path = Path::new(&env::var_os("HOME")?).join(".loxrc");
+    path.is_file().then(|| path.to_string_lossy().into_owned())
+}
+
+/// Options that take a value may also be written `--option value`; this
+/// rewrites those to `--option=value`, and `-I dir` to `--import-path=dir`.
+fn join_option_values(mut args: impl Iterator<Item = String>) -> impl Iterator<Item = String> {
+    std::iter::from_fn(move || {
+        let arg = args.next()?;
+        if arg == "--iterations" || arg == "--prelude" {
+            if let Some(value) = args.next() {
+                return Some(format!("{}={}", arg, value));
+            }
+        }
+        if arg == "-I" {
+            return Some(format!("--import-path={}", args.next().unwrap_or_else(|| usage())));
+        }
+        if let Some(dir) = arg.strip_prefix("-I") {
+            return Some(format!("--import-path={}", dir));
+        }
+        Some(arg)
+    })
+}
+
+/// A number of bytes, optionally with a `K`, `M` or `G` suffix.
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, unit) = match size.char_indices().last()? {
+        (end, 'K' | 'k') => (&size[..end], 1 << 10),
+        (end, 'M' | 'm') => (&size[..end], 1 << 20),
+        (end, 'G' | 'g') => (&size[..end], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+/// `rlox explain`: describes the error with `code`, or lists every code.
+fn explain(code: Option<&str>) -> i32 {
+    let Some(code) = code else {
+        for known in error_codes::ERROR_CODES {
+            println!("{}  {}", known.code, known.title);
+        }
+        return 0;
+    };
+    match error_codes::find(code) {
+        Some(known) => {
+            println!("{}: {}\n\n{}", known.code, known.title, known.explanation);
+            0
+        }
+        None => {
+            eprintln!("No error has the code '{}'. Run 'rlox explain' to list them.", code);
+            1
+        }
+    }
+}
+
+/// The rules a comma-separated `--enable=` or `--disable=` list names,
+/// where `all` names every rule.
+fn parse_rules(names: &str) -> Vec<&'static str> {
+    names
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .flat_map(|name| match name {
+            "all" => warning::RULES.to_vec(),
+            name => vec![warning::rule(name).unwrap_or_else(|| usage())],
+        })
+        .collect()
+}
+
+/// Applies the `lox.toml` found from the directory of `path`, the script
+/// or directory the command works on, or else from the current directory.
+fn load_config(interpreter: &mut Interpreter, path: Option<&str>) -> Result<(), String> {
+    let start = match path.map(Path::new) {
+        Some(dir) if dir.is_dir() => Some(dir),
+        path => path.and_then(Path::parent).filter(|dir| !dir.as_os_str().is_empty()),
+    };
+    let start = start.unwrap_or(Path::new(".")).canonicalize().unwrap_or_default();
+    let Some(path) = Config::find(&start) else {
+        return Ok(());
+    };
+    let dir = path.parent().unwrap_or(Path::new("."));
+    Config::load(&path)?
+        .apply(interpreter, dir)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Runs the command line, returning the status to exit with.
+fn run() -> Result<i32, String> {
     let mut interpreter = Interpreter::default();
-    let args: Vec<String> = env::args().skip(1).collect();
-    match args.len().cmp(&1) {
-        Greater => {
-            println!("Usage: rlox [script]");
-            exit(64);
+    let (options, mut args): (Vec<String>, Vec<String>) =
+        join_option_values(env::args().skip(1)).partition(|arg| arg.starts_with("--"));
+    let mut from_ast = false;
+    let mut iterations = 10;
+    let mut highlight_format = HighlightFormat::default();
+    let mut lint_format = LintFormat::default();
+    let mut doc_format = DocFormat::default();
+    let mut flat_natives = false;
+    let mut prelude = default_prelude();
+    // Before the options, so they can override it.
+    if !options.iter().any(|option| option == "--no-config") {
+        load_config(&mut interpreter, args.last().map(String::as_str))?;
+    }
+    for option in &options {
+        if let Some(path) = option.strip_prefix("--emit-ast=") {
+            interpreter.set_dump_ast(AstFormat::Json(path.to_string()));
+            continue;
+        }
+        if let Some(count) = option.strip_prefix("--iterations=") {
+            iterations = count.parse().unwrap_or_else(|_| usage());
+            continue;
+        }
+        if let Some(path) = option.strip_prefix("--coverage=") {
+            interpreter.set_coverage(Some(path.to_string()));
+            continue;
+        }
+        if let Some(dir) = option.strip_prefix("--import-path=") {
+            interpreter.add_import_path(dir);
+            continue;
+        }
+        if let Some(path) = option.strip_prefix("--prelude=") {
+            prelude = Some(path.to_string());
+            continue;
+        }
+        if let Some(names) = option.strip_prefix("--allow=") {
+            let allowed: Vec<Capability> = names
+                .split(',')
+                .filter(|name| !name.is_empty())
+                .map(|name| Capability::from_name(name).unwrap_or_else(|| usage()))
+                .collect();
+            interpreter.restrict(&allowed);
+            continue;
+        }
+        if let Some(size) = option.strip_prefix("--max-memory=") {
+            interpreter.set_max_memory(Some(parse_size(size).unwrap_or_else(|| usage())));
+            continue;
+        }
+        // Lower only: the runner thread has no more stack than the default.
+        if let Some(size) = option.strip_prefix("--stack-budget=") {
+            let budget = parse_size(size).unwrap_or_else(|| usage());
+            stack::set_budget(budget.min(stack::budget()));
+            continue;
+        }
+        if let Some(max) = option.strip_prefix("--max-loop-iterations=") {
+            interpreter.set_max_loop_iterations(Some(max.parse().unwrap_or_else(|_| usage())));
+            continue;
+        }
+        if let Some(level) = option.strip_prefix("--log-level=") {
+            interpreter.set_log_level(LogLevel::from_name(level).unwrap_or_else(|| usage()));
+            continue;
+        }
+        if let Some(names) = option.strip_prefix("--enable=") {
+            parse_rules(names).into_iter().for_each(|rule| interpreter.set_rule(rule, true));
+            continue;
+        }
+        if let Some(names) = option.strip_prefix("--disable=") {
+            parse_rules(names).into_iter().for_each(|rule| interpreter.set_rule(rule, false));
+            continue;
+        }
+        if let Some(separator) = option.strip_prefix("--print-separator=") {
+            interpreter.set_print_separator(separator);
+            continue;
+        }
+        if let Some(words) = option.strip_prefix("--keywords=") {
+            for word in words.split(',') {
+                let token_type = scanner::extension_keyword(word).unwrap_or_else(|| usage());
+                interpreter.add_keyword(word, token_type);
+            }
+            continue;
+        }
+        match option.as_str() {
+            "--backend=tree" => interpreter.set_backend(Backend::TreeWalk),
+            "--backend=vm" => interpreter.set_backend(Backend::Vm),
+            "--opt" => interpreter.set_optimize(true),
+            "--check" => interpreter.set_check(true),
+            "--late-binding" => interpreter.set_late_binding(true),
+            "--top-level-return" => {
+                interpreter.set_top_level_return(true);
+                interpreter.set_return_exit_status(true);
+            }
+            "--compat=jlox" => interpreter.set_compat(Compat::Jlox),
+            "--truthiness=lox" => interpreter.set_truthiness(Truthiness::Lox),
+            "--truthiness=script" => interpreter.set_truthiness(Truthiness::Script),
+            "--no-string-coercion" => interpreter.set_string_coercion(false),
+            "--error-codes" => interpreter.set_error_codes(true),
+            "--warn" => interpreter.set_warnings(Warnings::On),
+            "--strict" => interpreter.set_warnings(Warnings::Strict),
+            "--print-function" => interpreter.set_print_function(true),
+            "--ast" => interpreter.set_dump_ast(AstFormat::SExpr),
+            "--ast-dot" => interpreter.set_dump_ast(AstFormat::Dot),
+            "--from-ast" => from_ast = true,
+            "--trace" => interpreter.set_trace(true),
+            "--stats" => interpreter.set_print_stats(true),
+            "--gc-stats" => interpreter.set_print_gc_stats(true),
+            "--debug" => interpreter.set_debug(true),
+            "--test" => interpreter.set_test_mode(true),
+            "--coverage" => interpreter.set_coverage(None),
+            "--flat-natives" => flat_natives = true,
+            "--no-prelude" => prelude = None,
+            "--no-config" => (),
+            "--format=ansi" => highlight_format = HighlightFormat::Ansi,
+            "--format=html" => {
+                highlight_format = HighlightFormat::Html;
+                doc_format = DocFormat::Html;
+            }
+            "--format=markdown" => doc_format = DocFormat::Markdown,
+            "--format=text" => lint_format = LintFormat::Text,
+            "--format=json" => lint_format = LintFormat::Json,
+            _ => usage(),
+        }
+    }
+    // These run on the tree-walker's hooks, which the VM doesn't have.
+    if interpreter.backend() == Backend::Vm {
+        let hooked = ["--trace", "--stats", "--coverage", "--debug"];
+        let option = options.iter().find(|option| hooked.contains(&option.split('=').next().unwrap_or_default()));
+        if let Some(option) = option {
+            return Err(format!("{} isn't supported by the VM backend.", option));
+        }
+    }
+    // After any --allow, so that restricted modules stay out.
+    if flat_natives {
+        interpreter.import_unprefixed();
+    }
+    match args.first().map(String::as_str) {
+        Some("fmt") if args.len() == 2 => {
+            let status = cli::format_file(&mut interpreter, &args[1]).map_err(|err| err.to_string())?;
+            return Ok(status.exit_code());
+        }
+        Some("highlight") if args.len() == 2 => {
+            let status = cli::highlight_file(&mut interpreter, &args[1], highlight_format)
+                .map_err(|err| err.to_string())?;
+            return Ok(status.exit_code());
+        }
+        Some("test") if args.len() == 2 => {
+            let program = env::current_exe().map_err(|err| err.to_string())?;
+            match test_runner::run_tests(&program, &options, Path::new(&args[1])) {
+                Ok(true) => return Ok(0),
+                Ok(false) => return Ok(1),
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+        Some("lint") if args.len() == 2 => {
+            let status = cli::lint_file(&mut interpreter, &args[1], lint_format).map_err(|err| err.to_string())?;
+            return Ok(status.exit_code());
+        }
+        Some("bench") if args.len() == 2 => {
+            let status = cli::bench_file(&mut interpreter, &args[1], iterations).map_err(|err| err.to_string())?;
+            return Ok(status.exit_code());
+        }
+        Some("compile") if args.len() == 2 || (args.len() == 4 && args[2] == "-o") => {
+            let out = match args.get(3) {
+                Some(out) => out.clone(),
+                None => Path::new(&args[1]).with_extension("loxc").to_string_lossy().into_owned(),
+            };
+            let status = cli::compile_file(&mut interpreter, &args[1], &out).map_err(|err| err.to_string())?;
+            return Ok(status.exit_code());
+        }
+        Some("doc") if args.len() == 2 || (args.len() == 4 && args[2] == "-o") => {
+            let out = args.get(3).map_or("docs", String::as_str);
+            let status = cli::doc_files(&mut interpreter, &args[1], out, doc_format).map_err(|err| err.to_string())?;
+            return Ok(status.exit_code());
+        }
+        Some("explain") if args.len() <= 2 => return Ok(explain(args.get(1).map(String::as_str))),
+        Some("run") if args.len() == 2 => {
+            args.remove(0);
+        }
+        Some("fmt" | "highlight" | "test" | "lint" | "doc" | "bench" | "compile" | "explain" | "run") => usage(),
+        _ => (),
+    }
+    if args.len() > 1 || (args.is_empty() && from_ast) {
+        usage();
+    }
+    // The prelude runs last of all set-up, so it sees the natives the
+    // options left, and before anything else so the script can use it.
+    if let Some(path) = &prelude {
+        let status = interpreter.run_prelude(path).map_err(|err| format!("{}: {}", path, err))?;
+        if status != RunStatus::Success {
+            return Ok(status.exit_code());
+        }
+    }
+    let status = match args.first() {
+        Some(path) if from_ast => interpreter.run_ast_file(path).map_err(|err| err.to_string())?,
+        Some(path) if path.ends_with(".loxc") => interpreter.run_compiled_file(path).map_err(|err| err.to_string())?,
+        Some(path) => interpreter.run_file(path).map_err(|err| err.to_string())?,
+        None => {
+            interpreter.run_prompt().map_err(|err| err.to_string())?;
+            return Ok(0)
         }
-        Equal => interpreter.run_file(&args[0])?,
-        _ => interpreter.run_prompt()?
+    };
+    // A script ending with `return n;` exits with status `n`.
+    match interpreter.returned() {
+        Some(Literal::Int(code)) if status == RunStatus::Success => Ok(*code as i32),
+        _ => Ok(status.exit_code()),
     }
-    Ok(())
 }
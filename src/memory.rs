@@ -0,0 +1,95 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::rc::Rc;
+
+use crate::callable::BoundFunction;
+use crate::environment::{Environment, Slot};
+use crate::lox_function::LoxFunction;
+use crate::symbol::Symbol;
+use crate::token::{EnumCase, Literal};
+
+/// The reference counts in front of every `Rc` allocation.
+const RC_HEADER: usize = 2 * size_of::<usize>();
+
+/// Roughly how many bytes the environments in `roots` and everything
+/// they reach hold, plus `extra`. Shared values are counted once, and
+/// what natives hold inside their closures isn't counted at all.
+pub fn measure(roots: &[&Rc<RefCell<Environment>>], extra: &Literal) -> usize {
+    let mut measure = Measure::default();
+    for root in roots {
+        measure.environment(root);
+    }
+    measure.literal(extra);
+    measure.bytes
+}
+
+#[derive(Default)]
+struct Measure {
+    seen: HashSet<usize>,
+    bytes: usize,
+}
+
+impl Measure {
+    /// Whether the allocation at `address` is new to the walk.
+    fn first(&mut self, address: *const ()) -> bool {
+        self.seen.insert(address as usize)
+    }
+
+    fn environment(&mut self, environment: &Rc<RefCell<Environment>>) {
+        if !self.first(Rc::as_ptr(environment) as *const ()) {
+            return;
+        }
+        // A borrowed environment is being changed, and is counted as empty.
+        let Ok(environment) = environment.try_borrow() else {
+            return;
+        };
+        self.bytes += RC_HEADER + size_of::<RefCell<Environment>>();
+        for (_, slot) in environment.slots() {
+            self.bytes += size_of::<(Symbol, Slot)>();
+            match slot {
+                Slot::Value(value) => self.literal(value),
+                Slot::Captured(cell) if self.first(Rc::as_ptr(cell) as *const ()) => {
+                    self.bytes += RC_HEADER + size_of::<RefCell<Literal>>();
+                    if let Ok(value) = cell.try_borrow() {
+                        self.literal(&value);
+                    }
+                }
+                Slot::Captured(_) => (),
+            }
+        }
+        if let Some(enclosing) = &environment.enclosing {
+            self.environment(enclosing);
+        }
+    }
+
+    fn literal(&mut self, value: &Literal) {
+        match value {
+            Literal::String(s) if self.first(Rc::as_ptr(s) as *const ()) => self.bytes += RC_HEADER + s.len(),
+            Literal::Bytes(bytes) if self.first(Rc::as_ptr(bytes) as *const ()) => {
+                self.bytes += RC_HEADER + bytes.len()
+            }
+            Literal::LoxFunction(function) if self.first(Rc::as_ptr(function) as *const ()) => {
+                self.bytes += RC_HEADER + size_of::<LoxFunction>();
+                self.environment(&function.closure);
+                self.environment(&function.globals);
+            }
+            Literal::Map(members) | Literal::Record(members) if self.first(Rc::as_ptr(members) as *const ()) => {
+                self.bytes += RC_HEADER;
+                for (name, value) in members.iter() {
+                    self.bytes += size_of::<(Rc<str>, Literal)>() + name.len();
+                    self.literal(value);
+                }
+            }
+            Literal::EnumCase(case) if self.first(Rc::as_ptr(case) as *const ()) => {
+                self.bytes += RC_HEADER + size_of::<EnumCase>()
+            }
+            Literal::Bound(bound) if self.first(Rc::as_ptr(bound) as *const ()) => {
+                self.bytes += 2 * RC_HEADER + size_of::<BoundFunction>() + bound.args.len() * size_of::<Literal>();
+                self.literal(&bound.function);
+                bound.args.iter().for_each(|arg| self.literal(arg));
+            }
+            _ => (),
+        }
+    }
+}
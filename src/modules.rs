@@ -0,0 +1,44 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Where `import` looks for the modules it's given: the importing file's
+/// directory, then each directory added with `-I`, then those listed in
+/// the `LOX_PATH` environment variable. A name without an extension gets
+/// `.lox`, and an absolute one is only looked for where it says.
+#[derive(Debug, Default)]
+pub struct ModulePath {
+    dirs: Vec<PathBuf>,
+}
+
+impl ModulePath {
+    pub fn add(&mut self, dir: impl Into<PathBuf>) {
+        self.dirs.push(dir.into());
+    }
+
+    /// The first file `name` names, searched for from a file in `from`,
+    /// made canonical so that every way of naming a module finds the same
+    /// one.
+    pub fn find(&self, name: &str, from: &Path) -> Result<PathBuf, String> {
+        let mut file = PathBuf::from(name);
+        if file.extension().is_none() {
+            file.set_extension("lox");
+        }
+        let candidates: Vec<PathBuf> = if file.is_absolute() {
+            vec![file]
+        } else {
+            let lox_path = env::var_os("LOX_PATH").map(|paths| env::split_paths(&paths).collect::<Vec<_>>());
+            std::iter::once(from)
+                .chain(self.dirs.iter().map(PathBuf::as_path))
+                .chain(lox_path.iter().flatten().map(PathBuf::as_path))
+                .map(|dir| dir.join(&file))
+                .collect()
+        };
+        match candidates.iter().find(|candidate| candidate.is_file()) {
+            Some(found) => Ok(found.canonicalize().unwrap_or_else(|_| found.clone())),
+            None => {
+                let searched: Vec<_> = candidates.iter().map(|candidate| candidate.display().to_string()).collect();
+                Err(format!("Module '{}' not found, searched: {}.", name, searched.join(", ")))
+            }
+        }
+    }
+}
@@ -1,22 +1,110 @@
 use std::fmt;
+use std::rc::Rc;
+#[cfg(all(feature = "stdlib-time", not(target_arch = "wasm32")))]
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::callable::Callable;
 use crate::error::RuntimeException;
 use crate::token::Literal;
 use crate::token::Token;
-use crate::interpreter::Interpreter;
+use crate::interpreter::{self, Interpreter};
 
+/// The Rust side of a native. Natives are usually plain functions, but a
+/// closure can keep state between calls, as iterators do.
+pub type NativeFn = dyn Fn(&mut Interpreter, &[Literal]) -> Result<Literal, RuntimeException>;
+
+/// Copies of a native share its callable, whose address gives the native
+/// its identity the way [`crate::callable::FunctionId`] does for Lox
+/// functions.
 #[derive(Clone)]
 pub struct NativeFunction {
-    pub name: String,
+    pub name: Rc<str>,
     pub arity: u8,
-    pub callable: fn(interpreter: &Interpreter, args: &Vec<Literal>) -> Result<Literal, RuntimeException>,
+    /// Whether the native takes any number of arguments past its arity.
+    pub variadic: bool,
+    pub callable: Rc<NativeFn>,
+    /// Natives of the same name taking other numbers of arguments, which
+    /// calls with those numbers go to instead. Boxed apart so values stay
+    /// small.
+    pub overloads: Option<Rc<Vec<NativeFunction>>>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: u8,
+        callable: impl Fn(&mut Interpreter, &[Literal]) -> Result<Literal, RuntimeException> + 'static,
+    ) -> Self {
+        Self {
+            name: Rc::from(name),
+            arity,
+            variadic: false,
+            callable: Rc::new(callable),
+            overloads: None,
+        }
+    }
+
+    /// A native taking at least `arity` arguments.
+    pub fn variadic(
+        name: &str,
+        arity: u8,
+        callable: impl Fn(&mut Interpreter, &[Literal]) -> Result<Literal, RuntimeException> + 'static,
+    ) -> Self {
+        Self {
+            variadic: true,
+            ..Self::new(name, arity, callable)
+        }
+    }
+
+    /// The native, also taking `other`'s number of arguments by calling
+    /// `other`, as `random()` and `random(max)` are one native.
+    pub fn overload(mut self, other: NativeFunction) -> Self {
+        let mut overloads: Vec<NativeFunction> = self.overloads.take().map(Rc::unwrap_or_clone).unwrap_or_default();
+        overloads.extend(other.overloads.iter().flat_map(|others| others.iter()).cloned());
+        overloads.push(NativeFunction { overloads: None, ..other });
+        self.overloads = Some(Rc::new(overloads));
+        self
+    }
+
+    /// The native itself and each of its overloads.
+    pub fn arities(&self) -> impl Iterator<Item = &NativeFunction> {
+        std::iter::once(self).chain(self.overloads.iter().flat_map(|overloads| overloads.iter()))
+    }
+
+    /// The one of [`NativeFunction::arities`] a call with `given` arguments
+    /// goes to, preferring one taking exactly that many.
+    pub fn for_arity(&self, given: usize) -> Option<&NativeFunction> {
+        self.arities()
+            .find(|native| native.arity as usize == given)
+            .or_else(|| self.arities().find(|native| native.variadic && given > native.arity as usize))
+    }
+
+    /// How many arguments the native takes, as the error for a wrong number
+    /// gives it: `2`, `at least 1`, or `0 or 1` for one with overloads.
+    pub fn describe_arity(&self) -> String {
+        let mut arities: Vec<&NativeFunction> = self.arities().collect();
+        arities.sort_by_key(|native| native.arity);
+        let described: Vec<String> = arities
+            .iter()
+            .map(|native| match native.variadic {
+                true => format!("at least {}", native.arity),
+                false => native.arity.to_string(),
+            })
+            .collect();
+        match described.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+            _ => described.concat(),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        Rc::as_ptr(&self.callable) as *const () as usize
+    }
 }
 
 impl PartialEq for NativeFunction {
     fn eq(&self, other: &Self) -> bool {
-        self.name == other.name && self.arity == other.arity
+        self.id() == other.id()
     }
 }
 
@@ -31,20 +119,142 @@ impl Callable for NativeFunction {
         self.arity
     }
 
-    fn call(&mut self, interpreter: &Interpreter, args: &Vec<Literal>) -> Result<Literal, RuntimeException> {
-        (self.callable)(interpreter, args)
+    fn accepts(&self, given: usize) -> bool {
+        self.for_arity(given).is_some()
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+        let native = self.for_arity(args.len()).unwrap_or(self);
+        (native.callable)(interpreter, args)
     }
 }
 
-pub fn clock(_interpreter: &Interpreter, args: &Vec<Literal>) -> Result<Literal, RuntimeException> {
-    if args.len() != 0 {
+/// `breakpoint()` does nothing itself. The debugger the REPL and `--debug`
+/// attach pauses when it's called.
+pub fn breakpoint(_interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Nil)
+}
+
+/// `test(name, fn)` runs `fn` as a named test in test mode, recording the
+/// first error it raises as the failure. Otherwise it does nothing. Like
+/// the other natives that call back into Lox, it is tree-walker only.
+pub fn test(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    if !interpreter.test_mode() {
+        return Ok(Literal::Nil)
+    }
+    let result = match &args[1] {
+        function @ (Literal::LoxFunction(_) | Literal::NativeFunction(_)) if interpreter::accepts(function, 0) => {
+            interpreter.call_value(function, vec![], &Token::default())
+        }
+        _ => {
+            let message = "test() expects a function with no parameters.".to_string();
+            return Err(RuntimeException::base("E0018", Token::default(), message))
+        }
+    };
+    let failure = match result {
+        Err(RuntimeException::Base(err)) => Some(format!("[line {}] {}", err.token.line, err.message)),
+        _ => None,
+    };
+    interpreter.record_test(args[0].to_string(), failure);
+    Ok(Literal::Nil)
+}
+
+/// `globals()` returns a map of every global variable to its value.
+pub fn globals_map(interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let map = interpreter
+        .globals()
+        .into_iter()
+        .map(|(name, value)| (Rc::from(name), value))
+        .collect();
+    Ok(Literal::Map(Rc::new(map)))
+}
+
+/// `expect(actual, expected)` raises an error unless the two are equal.
+pub fn expect(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    if interpreter.is_equal(&args[0], &args[1]) {
+        return Ok(Literal::Nil)
+    }
+    let message = format!("Expected {} but got {}.", repr_string(&args[1]), repr_string(&args[0]));
+    Err(RuntimeException::base("E0029", Token::default(), message))
+}
+
+/// `deepEqual(a, b)` compares maps by their contents rather than by
+/// identity, including maps inside records, and everything else as `==`
+/// does.
+pub fn deep_equal(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::from(values_deep_equal(interpreter, &args[0], &args[1])))
+}
+
+fn values_deep_equal(interpreter: &Interpreter, a: &Literal, b: &Literal) -> bool {
+    match (a, b) {
+        (Literal::Map(m1), Literal::Map(m2)) | (Literal::Record(m1), Literal::Record(m2)) => {
+            m1.len() == m2.len()
+                && m1.iter().zip(m2.iter()).all(|((k1, v1), (k2, v2))| {
+                    k1 == k2 && values_deep_equal(interpreter, v1, v2)
+                })
+        }
+        (a, b) => interpreter.is_equal(a, b),
+    }
+}
+
+/// `gcCollect()` frees closures caught in cycles now rather than when the
+/// collector next runs, and says how many environments it freed.
+pub fn gc_collect(interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Int(interpreter.collect_garbage() as i64))
+}
+
+/// `memoryUsed()`, roughly how many bytes the script's values hold.
+pub fn memory_used(interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Int(interpreter.memory_used() as i64))
+}
+
+/// `freeze(value)` returns a read-only copy of a map or record, with every
+/// map and record inside it read-only too. Other values come back as they
+/// are.
+pub fn freeze(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(args[0].frozen())
+}
+
+/// `repr(x)` shows a value as it would be written in source, quoting and
+/// escaping strings, including those inside maps and records.
+pub fn repr(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::from(repr_string(&args[0])))
+}
+
+fn repr_string(value: &Literal) -> String {
+    match value {
+        Literal::String(s) => format!("{:?}", s),
+        Literal::Map(map) | Literal::Record(map) => {
+            let members: Vec<String> = map
+                .iter()
+                .map(|(key, value)| format!("{}: {}", key, repr_string(value)))
+                .collect();
+            format!("{{{}}}", members.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+#[cfg(feature = "stdlib-time")]
+pub fn clock(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    if !args.is_empty() {
         let message = format!("Expected 0 args, received {}.", args.len());
-        return Err(RuntimeException::base(Token::default(), message))
+        return Err(RuntimeException::base("E0005", Token::default(), message))
     }
 
-    let start = SystemTime::now();
-    let since_epoch = start.duration_since(UNIX_EPOCH).unwrap();
+    Ok(Literal::Number(interpreter.unix_millis()))
+}
+
+/// Milliseconds since the Unix epoch, for `clock()`. There's no system
+/// clock on `wasm32-unknown-unknown`, where this is always 0.
+#[cfg(all(feature = "stdlib-time", not(target_arch = "wasm32")))]
+pub fn unix_millis() -> f64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_millis() as f64
+}
 
-    Ok(Literal::Number(since_epoch.as_millis() as f64))
+#[cfg(all(feature = "stdlib-time", target_arch = "wasm32"))]
+pub fn unix_millis() -> f64 {
+    0.0
 }
 
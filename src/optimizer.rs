@@ -0,0 +1,198 @@
+use std::rc::Rc;
+
+use crate::expr::Expr;
+use crate::interpreter::Interpreter;
+use crate::stmt::Stmt;
+use crate::token::TokenType;
+
+/// Rewrites resolved programs by folding constant expressions and dropping
+/// branches whose conditions are known up front. Folding goes through the
+/// interpreter's own operators, so a folded program behaves exactly like
+/// the original; anything that would fail at runtime is left in place.
+/// Variable and assignment nodes keep their IDs, so the resolver's scope
+/// distances stay valid.
+pub struct Optimizer<'a> {
+    interpreter: &'a Interpreter,
+}
+
+impl<'a> Optimizer<'a> {
+    pub fn new(interpreter: &'a Interpreter) -> Self {
+        Self { interpreter }
+    }
+
+    pub fn optimize(&self, stmts: &[Stmt]) -> Vec<Stmt> {
+        stmts.iter().map(|stmt| self.optimize_stmt(stmt)).collect()
+    }
+
+    fn optimize_block(&self, stmts: &[Stmt]) -> Rc<[Stmt]> {
+        self.optimize(stmts).into()
+    }
+
+    fn optimize_stmt(&self, stmt: &Stmt) -> Stmt {
+        match stmt {
+            Stmt::Block(stmts) => Stmt::Block(self.optimize_block(stmts)),
+            Stmt::Expression(expr) => Stmt::Expression(self.fold(expr)),
+            Stmt::Function(name, id, params, body, signature, doc) => Stmt::Function(
+                name.clone(),
+                *id,
+                params.clone(),
+                self.optimize_block(body),
+                signature.clone(),
+                doc.clone(),
+            ),
+            Stmt::Print(values) => Stmt::Print(values.iter().map(|value| self.fold(value)).collect()),
+            Stmt::Return(keyword, value) => {
+                Stmt::Return(keyword.clone(), value.as_ref().map(|value| self.fold(value)))
+            }
+            Stmt::If(arms, else_branch) => {
+                // Arms with a constant false condition drop out, and one
+                // that is constant true becomes the else branch.
+                let mut kept = vec![];
+                let mut otherwise = None;
+                for (keyword, condition, branch) in arms.iter() {
+                    let condition = self.fold(condition);
+                    match &condition {
+                        Expr::Literal(literal) if self.interpreter.is_truthy(literal) => {
+                            otherwise = Some(branch);
+                            break;
+                        }
+                        Expr::Literal(_) => (),
+                        _ => kept.push((keyword.clone(), condition, self.optimize_stmt(branch))),
+                    }
+                }
+                let otherwise = otherwise
+                    .or(else_branch.as_deref())
+                    .map(|branch| self.optimize_stmt(branch));
+                if kept.is_empty() {
+                    otherwise.unwrap_or_else(|| Stmt::Block(Rc::from([])))
+                } else {
+                    Stmt::If(kept.into(), otherwise.map(Rc::new))
+                }
+            }
+            Stmt::While(keyword, condition, body) => {
+                let condition = self.fold(condition);
+                match &condition {
+                    Expr::Literal(literal) if !self.interpreter.is_truthy(literal) => {
+                        Stmt::Block(Rc::from([]))
+                    }
+                    _ => Stmt::While(keyword.clone(), condition, Rc::new(self.optimize_stmt(body))),
+                }
+            }
+            Stmt::For(keyword, initializer, condition, increment, body) => Stmt::For(
+                keyword.clone(),
+                initializer
+                    .as_ref()
+                    .map(|initializer| Rc::new(self.optimize_stmt(initializer))),
+                condition.as_ref().map(|condition| self.fold(condition)),
+                increment.as_ref().map(|increment| self.fold(increment)),
+                Rc::new(self.optimize_stmt(body)),
+            ),
+            Stmt::Var(name, annotation, initializer) => Stmt::Var(
+                name.clone(),
+                annotation.clone(),
+                initializer.as_ref().map(|initializer| self.fold(initializer)),
+            ),
+            Stmt::Labeled(label, body) => Stmt::Labeled(label.clone(), Rc::new(self.optimize_stmt(body))),
+            Stmt::ForIn(name, iterable, body) => {
+                Stmt::ForIn(name.clone(), self.fold(iterable), Rc::new(self.optimize_stmt(body)))
+            }
+            Stmt::With(keyword, name, initializer, body) => Stmt::With(
+                keyword.clone(),
+                name.clone(),
+                self.fold(initializer),
+                Rc::new(self.optimize_stmt(body)),
+            ),
+            Stmt::Export(declaration) => Stmt::Export(Rc::new(self.optimize_stmt(declaration))),
+            Stmt::Break(..) | Stmt::Continue(..) | Stmt::Enum(..) | Stmt::Import(..) => stmt.clone(),
+        }
+    }
+
+    fn fold(&self, expr: &Expr) -> Expr {
+        match expr {
+            Expr::Grouping(inner) => {
+                let inner = self.fold(inner);
+                match inner {
+                    Expr::Literal(_) => inner,
+                    _ => Expr::Grouping(Rc::new(inner)),
+                }
+            }
+            Expr::Unary(operator, right) => {
+                let right = self.fold(right);
+                let literal = match &right {
+                    Expr::Literal(literal) => self.interpreter.unary(operator, literal.clone()).ok(),
+                    _ => None,
+                };
+                match literal {
+                    Some(literal) => Expr::Literal(literal),
+                    None => Expr::Unary(operator.clone(), Rc::new(right)),
+                }
+            }
+            Expr::Binary(..) => self.fold_binary(expr),
+            Expr::Logical(left, operator, right) => {
+                let left = self.fold(left);
+                match &left {
+                    Expr::Literal(literal) => {
+                        let truthy = self.interpreter.is_truthy(literal);
+                        if truthy == (operator.token_type == TokenType::Or) {
+                            left
+                        } else {
+                            self.fold(right)
+                        }
+                    }
+                    _ => Expr::Logical(Rc::new(left), operator.clone(), Rc::new(self.fold(right))),
+                }
+            }
+            Expr::Assign(id, name, value) => {
+                Expr::Assign(*id, name.clone(), Rc::new(self.fold(value)))
+            }
+            Expr::Lambda(id, params, body, signature) => {
+                Expr::Lambda(*id, params.clone(), self.optimize_block(body), signature.clone())
+            }
+            Expr::Call(callee, paren, arguments, named) => Expr::Call(
+                Rc::new(self.fold(callee)),
+                paren.clone(),
+                arguments.iter().map(|argument| self.fold(argument)).collect(),
+                named.iter().map(|(name, value)| (name.clone(), self.fold(value))).collect(),
+            ),
+            Expr::Get(object, name) => Expr::Get(Rc::new(self.fold(object)), name.clone()),
+            Expr::Set(object, name, value) => {
+                Expr::Set(Rc::new(self.fold(object)), name.clone(), Rc::new(self.fold(value)))
+            }
+            Expr::Index(object, bracket, index) => {
+                Expr::Index(Rc::new(self.fold(object)), bracket.clone(), Rc::new(self.fold(index)))
+            }
+            Expr::Record(brace, fields) => Expr::Record(
+                brace.clone(),
+                fields.iter().map(|(name, value)| (name.clone(), self.fold(value))).collect(),
+            ),
+            Expr::Literal(_) | Expr::Variable(..) | Expr::Empty => expr.clone(),
+        }
+    }
+
+    /// Folds an operator chain left to right without recursing down its
+    /// spine, mirroring `Interpreter::evaluate_binary`.
+    fn fold_binary(&self, expr: &Expr) -> Expr {
+        let mut pending = vec![];
+        let mut leftmost = expr;
+        while let Expr::Binary(left, operator, right) = leftmost {
+            pending.push((operator, right));
+            leftmost = left;
+        }
+
+        let mut folded = self.fold(leftmost);
+        while let Some((operator, right)) = pending.pop() {
+            let right = self.fold(right);
+            let literal = match (&folded, &right) {
+                (Expr::Literal(a), Expr::Literal(b)) => {
+                    self.interpreter.binary(operator.clone(), a.clone(), b.clone()).ok()
+                }
+                _ => None,
+            };
+            folded = match literal {
+                Some(literal) => Expr::Literal(literal),
+                None => Expr::Binary(Rc::new(folded), operator.clone(), Rc::new(right)),
+            };
+        }
+        folded
+    }
+}
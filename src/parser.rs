@@ -1,30 +1,131 @@
-use crate::error::ParserError;
-use crate::expr::Expr;
-use crate::stmt::Stmt;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::error::{LoxError, ParserError};
+use crate::expr::{next_expr_id, Expr};
+use crate::interpreter::Compat;
+use crate::scanner::Scanner;
+use crate::stack::{self, StackGuard};
+use crate::stmt::{Signature, Stmt};
+use crate::symbol::Symbol;
 use crate::token::TokenType::{self, *};
 use crate::token::{Literal, Token};
 
 type ParseResult<T> = Result<T, ParserError>;
 
+/// A point to go back to with [`Parser::rollback`].
+pub struct Checkpoint {
+    consumed: usize,
+    previous: Token,
+}
+
+/// A recursive descent parser pulling tokens one at a time, so it only
+/// holds the current token and the one before it, plus those it has
+/// looked ahead at or may go back over.
 pub struct Parser {
-    pub tokens: Vec<Token>,
-    pub current: usize,
+    tokens: Box<dyn Iterator<Item = Result<Token, LoxError>>>,
+    current: Token,
+    previous: Token,
+    /// Tokens pulled by [`Parser::peek_n`] or put back by
+    /// [`Parser::rollback`], to be consumed before any new ones.
+    ahead: VecDeque<Token>,
+    /// The tokens consumed since the oldest open checkpoint.
+    consumed: Vec<Token>,
+    checkpoints: usize,
+    compat: Compat,
+    scan_errors: Vec<LoxError>,
+    stack: StackGuard,
 }
 
 impl Parser {
-    pub fn default() -> Self {
-        Self {
-            tokens: vec![],
-            current: 0,
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self::from_results(Box::new(tokens.into_iter().map(Ok)))
+    }
+
+    /// A parser scanning `scanner`'s source as it goes. Scan errors are
+    /// set aside for [`Parser::take_scan_errors`] and the bad input skipped.
+    pub fn from_scanner(scanner: Scanner) -> Self {
+        Self::from_results(Box::new(scanner))
+    }
+
+    fn from_results(tokens: Box<dyn Iterator<Item = Result<Token, LoxError>>>) -> Self {
+        let mut parser = Self {
+            tokens,
+            current: Token::default(),
+            previous: Token::default(),
+            ahead: VecDeque::new(),
+            consumed: vec![],
+            checkpoints: 0,
+            compat: Compat::default(),
+            scan_errors: vec![],
+            stack: StackGuard::new(),
+        };
+        parser.current = parser.next_token();
+        parser
+    }
+
+    /// Under [`Compat::Jlox`], `var x;` means `var x = nil;` and syntax
+    /// jlox lacks is an error.
+    pub fn set_compat(&mut self, compat: Compat) {
+        self.compat = compat;
+    }
+
+    /// Rejects `token`, which starts an extension, when parsing reference
+    /// Lox.
+    fn extension(&self, token: &Token) -> ParseResult<()> {
+        if self.compat == Compat::Jlox {
+            return Err(ParserError::new(
+                "P0007",
+                token.clone(),
+                format!("'{}' isn't part of reference Lox.", token.lexeme),
+            ));
         }
+        Ok(())
     }
 
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+    pub fn take_scan_errors(&mut self) -> Vec<LoxError> {
+        std::mem::take(&mut self.scan_errors)
+    }
+
+    /// The next token from the source. Past the end, which only happens
+    /// when the tokens lack a final `Eof`, it's an `Eof` of its own.
+    fn next_token(&mut self) -> Token {
+        if let Some(token) = self.ahead.pop_front() {
+            return token;
+        }
+        self.pull()
+    }
+
+    fn pull(&mut self) -> Token {
+        loop {
+            match self.tokens.next() {
+                Some(Ok(token)) => return token,
+                Some(Err(error)) => self.scan_errors.push(error),
+                None => {
+                    return Token {
+                        token_type: Eof,
+                        line: self.current.line,
+                        source: self.current.source,
+                        ..Token::default()
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops the recursive descent before deeply nested input overflows
+    /// the stack.
+    fn check_depth(&self) -> ParseResult<()> {
+        if self.stack.exhausted() {
+            return Err(ParserError::new("P0006", self.peek().clone(), stack::TOO_DEEP.to_string()));
+        }
+        Ok(())
     }
 
     fn expression(&mut self) -> ParseResult<Expr> {
-        if self.matches(vec![Fun]) {
+        self.check_depth()?;
+        if self.matches(&[Fun]) {
+            self.extension(self.previous())?;
             return self.lambda();
         }
 
@@ -33,39 +134,73 @@ impl Parser {
 
     fn lambda(&mut self) -> ParseResult<Expr> {
         self.consume(LeftParen, "Expect '(' before lambda arguments.")?;
+        let (parameters, signature) = self.parameters()?;
+        self.consume(LeftBrace, "Expect '{' before lambda body.")?;
+        let body = self.block()?;
+        Ok(Expr::Lambda(next_expr_id(), parameters.into(), body.into(), signature))
+    }
+
+    /// Parses a parameter list after its `(`, and the return type after
+    /// the `)`. The signature is `None` when no types were written.
+    fn parameters(&mut self) -> ParseResult<(Vec<Token>, Option<Rc<Signature>>)> {
         let mut parameters = vec![];
+        let mut types = vec![];
         if !self.check(RightParen) {
             loop {
                 if parameters.len() >= 255 {
                     return Err(ParserError::new(
-                        self.peek(),
+                        "P0005",
+                        self.peek().clone(),
                         "Can't have more than 255 parameters.".to_string(),
                     ));
                 }
                 parameters.push(self.consume(Identifier, "Expect parameter name.")?);
+                types.push(self.type_annotation()?);
 
-                if !self.matches(vec![Comma]) {
+                if !self.matches(&[Comma]) {
                     break;
                 }
             }
         }
         self.consume(RightParen, "Expect ')' after parameters.")?;
-        self.consume(LeftBrace, "Expect '{' before lambda body.")?;
-        let body = self.block()?;
-        Ok(Expr::Lambda(parameters, Box::new(body)))
+        let returns = self.type_annotation()?;
+        if returns.is_none() && types.iter().all(Option::is_none) {
+            return Ok((parameters, None));
+        }
+        let signature = Signature {
+            params: types.into(),
+            returns,
+        };
+        Ok((parameters, Some(Rc::new(signature))))
+    }
+
+    /// Parses `: type` if it comes next.
+    fn type_annotation(&mut self) -> ParseResult<Option<Token>> {
+        if !self.matches(&[Colon]) {
+            return Ok(None);
+        }
+        self.extension(self.previous())?;
+        if self.matches(&[Identifier, Nil]) {
+            return Ok(Some(self.previous().clone()));
+        }
+        Err(ParserError::new("P0012", self.peek().clone(), "Expect type name.".to_string()))
     }
 
     fn assignment(&mut self) -> ParseResult<Expr> {
         let expr = self.or()?;
-        if self.matches(vec![Equal]) {
-            let equals = self.previous();
+        if self.matches(&[Equal]) {
+            let equals = self.previous().clone();
             let value = self.assignment()?;
 
-            if let Expr::Variable(name) = expr {
-                return Ok(Expr::Assign(name, Box::new(value)));
+            if let Expr::Variable(_, name) = &expr {
+                return Ok(Expr::Assign(next_expr_id(), name.clone(), Rc::new(value)));
+            }
+            if let Expr::Get(object, name) = &expr {
+                return Ok(Expr::Set(object.clone(), name.clone(), Rc::new(value)));
             }
 
             return Err(ParserError::new(
+                "P0004",
                 equals,
                 "Invalid assignment target.".to_string(),
             ));
@@ -75,10 +210,10 @@ impl Parser {
 
     fn or(&mut self) -> ParseResult<Expr> {
         let mut expr = self.and()?;
-        while self.matches(vec![Or]) {
-            let operator = self.previous();
+        while self.matches(&[Or]) {
+            let operator = self.previous().clone();
             let right = self.and()?;
-            expr = Expr::Logical(Box::new(expr), operator, Box::new(right));
+            expr = Expr::Logical(Rc::new(expr), operator, Rc::new(right));
         }
         Ok(expr)
     }
@@ -86,10 +221,10 @@ impl Parser {
     fn and(&mut self) -> ParseResult<Expr> {
         let mut expr = self.equality()?;
 
-        while self.matches(vec![And]) {
-            let operator = self.previous();
+        while self.matches(&[And]) {
+            let operator = self.previous().clone();
             let right = self.equality()?;
-            expr = Expr::Logical(Box::new(expr), operator, Box::new(right));
+            expr = Expr::Logical(Rc::new(expr), operator, Rc::new(right));
         }
 
         Ok(expr)
@@ -97,17 +232,17 @@ impl Parser {
 
     fn equality(&mut self) -> ParseResult<Expr> {
         let mut expr = self.comparison()?;
-        while self.matches(vec![BangEqual, EqualEqual]) {
-            let operator: Token = self.previous();
+        while self.matches(&[BangEqual, EqualEqual]) {
+            let operator: Token = self.previous().clone();
             let right: Expr = self.comparison()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = Expr::Binary(Rc::new(expr), operator, Rc::new(right));
         }
 
         Ok(expr)
     }
 
-    fn matches(&mut self, types: Vec<TokenType>) -> bool {
-        for t in types {
+    fn matches(&mut self, types: &[TokenType]) -> bool {
+        for &t in types {
             if self.check(t) {
                 self.advance();
                 return true;
@@ -123,31 +258,100 @@ impl Parser {
         self.peek().token_type == token_type
     }
 
-    fn advance(&mut self) -> Token {
+    /// Whether the token after the current one is of `token_type`.
+    fn check_next(&mut self, token_type: TokenType) -> bool {
+        self.peek_n(1).token_type == token_type
+    }
+
+    /// Whether the current token is the identifier `word`.
+    fn check_word(&self, word: &str) -> bool {
+        self.check(Identifier) && &*self.peek().lexeme == word
+    }
+
+    /// Whether the token after the current one is the identifier `word`,
+    /// for words like `in` that are only special in one place.
+    fn check_next_word(&mut self, word: &str) -> bool {
+        self.check_next(Identifier) && &*self.peek_n(1).lexeme == word
+    }
+
+    /// The token `k` places after the current one, which is `peek_n(0)`.
+    /// Past the end, it's the final `Eof`.
+    pub fn peek_n(&mut self, k: usize) -> &Token {
+        if k == 0 {
+            return &self.current;
+        }
+        while self.ahead.len() < k && self.ahead.back().unwrap_or(&self.current).token_type != Eof {
+            let token = self.pull();
+            self.ahead.push_back(token);
+        }
+        self.ahead.get(k - 1).or(self.ahead.back()).unwrap_or(&self.current)
+    }
+
+    /// Marks where the parser is, to try parsing one way and, if that
+    /// fails, [`Parser::rollback`] and try another. Each checkpoint must
+    /// be given back to `rollback` or [`Parser::commit`]; they nest.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.checkpoints += 1;
+        Checkpoint {
+            consumed: self.consumed.len(),
+            previous: self.previous.clone(),
+        }
+    }
+
+    /// Goes back to `checkpoint`, to parse the tokens since again.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        let current = std::mem::take(&mut self.current);
+        let mut replay = self.consumed.split_off(checkpoint.consumed);
+        replay.push(current);
+        for token in replay.into_iter().rev() {
+            self.ahead.push_front(token);
+        }
+        self.current = self.next_token();
+        self.previous = checkpoint.previous;
+        self.release();
+    }
+
+    /// Keeps what was parsed since `checkpoint`.
+    pub fn commit(&mut self, _checkpoint: Checkpoint) {
+        self.release();
+    }
+
+    fn release(&mut self) {
+        self.checkpoints -= 1;
+        if self.checkpoints == 0 {
+            self.consumed.clear();
+        }
+    }
+
+    fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
-            self.current += 1
+            let next = self.next_token();
+            self.previous = std::mem::replace(&mut self.current, next);
+            if self.checkpoints > 0 {
+                self.consumed.push(self.previous.clone());
+            }
         }
         self.previous()
     }
 
     fn is_at_end(&self) -> bool {
-        self.peek().token_type == Eof
+        self.current.token_type == Eof
     }
 
-    fn peek(&self) -> Token {
-        self.tokens.get(self.current).unwrap().clone()
+    fn peek(&self) -> &Token {
+        &self.current
     }
 
-    fn previous(&self) -> Token {
-        self.tokens.get(self.current - 1).unwrap().clone()
+    fn previous(&self) -> &Token {
+        &self.previous
     }
 
     fn comparison(&mut self) -> ParseResult<Expr> {
         let mut expr = self.term()?;
-        while self.matches(vec![Greater, GreaterEqual, Less, LessEqual, Percent]) {
-            let operator = self.previous();
+        while self.matches(&[Greater, GreaterEqual, Less, LessEqual]) {
+            let operator = self.previous().clone();
             let right = self.term()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = Expr::Binary(Rc::new(expr), operator, Rc::new(right));
         }
         Ok(expr)
     }
@@ -155,10 +359,10 @@ impl Parser {
     fn term(&mut self) -> ParseResult<Expr> {
         let mut expr = self.factor()?;
 
-        while self.matches(vec![Minus, Plus]) {
-            let operator = self.previous();
+        while self.matches(&[Minus, Plus]) {
+            let operator = self.previous().clone();
             let right = self.factor()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = Expr::Binary(Rc::new(expr), operator, Rc::new(right));
         }
 
         Ok(expr)
@@ -167,91 +371,171 @@ impl Parser {
     fn factor(&mut self) -> ParseResult<Expr> {
         let mut expr = self.unary()?;
 
-        while self.matches(vec![Slash, Star]) {
-            let operator = self.previous();
+        while self.matches(&[Slash, Star, Percent]) {
+            let operator = self.previous().clone();
             let right = self.unary()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = Expr::Binary(Rc::new(expr), operator, Rc::new(right));
         }
 
         Ok(expr)
     }
 
     fn unary(&mut self) -> ParseResult<Expr> {
-        if self.matches(vec![Bang, Minus]) {
-            let operator = self.previous();
+        if self.matches(&[Bang, Minus]) {
+            self.check_depth()?;
+            let operator = self.previous().clone();
             let right = self.unary()?;
-            return Ok(Expr::Unary(operator, Box::new(right)));
+            return Ok(Expr::Unary(operator, Rc::new(right)));
         }
 
         self.call()
     }
 
+    /// A primary expression followed by any chain of calls, indexes and
+    /// property accesses, like `grid[1][2].neighbors()[0]`. They all bind
+    /// tighter than any operator and apply left to right.
     fn call(&mut self) -> ParseResult<Expr> {
         let mut expr = self.primary()?;
 
         loop {
-            if self.matches(vec![LeftParen]) {
-                expr = self.finish_call(expr)?;
+            expr = if self.matches(&[LeftParen]) {
+                self.finish_call(expr)?
+            } else if self.matches(&[LeftBracket]) {
+                self.finish_index(expr)?
+            } else if self.matches(&[Dot]) {
+                self.finish_get(expr)?
             } else {
                 break;
-            }
+            };
         }
 
         Ok(expr)
     }
 
+    /// The arguments of a call, where `name: value` ones may follow the
+    /// positional ones to pass parameters by name.
     fn finish_call(&mut self, callee: Expr) -> ParseResult<Expr> {
         let mut arguments: Vec<Expr> = vec![];
+        let mut named: Vec<(Token, Expr)> = vec![];
         if !self.check(RightParen) {
             loop {
-                if arguments.len() >= 255 {
+                if arguments.len() + named.len() >= 255 {
+                    return Err(ParserError::new(
+                        "P0005",
+                        self.peek().clone(),
+                        "Can't have more than 255 arguments.".to_string(),
+                    ));
+                }
+                if self.check(Identifier) && self.check_next(Colon) {
+                    let name = self.advance().clone();
+                    self.extension(&name)?;
+                    if named.iter().any(|(other, _)| other.lexeme == name.lexeme) {
+                        return Err(ParserError::new("P0008", name, "Argument already given by this name.".to_string()));
+                    }
+                    self.advance();
+                    named.push((name, self.expression()?));
+                } else if !named.is_empty() {
                     return Err(ParserError::new(
-                        self.peek(),
-                        "Can't have more than 255 arguments".to_string(),
+                        "P0009",
+                        self.peek().clone(),
+                        "Expect named argument after a named argument.".to_string(),
                     ));
+                } else {
+                    arguments.push(self.expression()?);
                 }
-                arguments.push(self.expression()?);
-                if !self.matches(vec![Comma]) {
+                if !self.matches(&[Comma]) {
                     break;
                 }
+                if self.check(RightParen) {
+                    return Err(ParserError::new("P0013", self.peek().clone(), "Expect argument after ','.".to_string()));
+                }
             }
         }
         let paren = self.consume(RightParen, "Expect ')' after arguments.")?;
-        Ok(Expr::Call(Box::new(callee), paren, Box::new(arguments)))
+        Ok(Expr::Call(Rc::new(callee), paren, arguments.into(), named.into()))
+    }
+
+    fn finish_index(&mut self, object: Expr) -> ParseResult<Expr> {
+        let bracket = self.previous().clone();
+        self.extension(&bracket)?;
+        if self.check(RightBracket) {
+            return Err(ParserError::new("P0014", self.peek().clone(), "Expect index expression.".to_string()));
+        }
+        let index = self.expression()?;
+        self.consume(RightBracket, "Expect ']' after index.")?;
+        Ok(Expr::Index(Rc::new(object), bracket, Rc::new(index)))
+    }
+
+    fn finish_get(&mut self, object: Expr) -> ParseResult<Expr> {
+        let name = self.consume(Identifier, "Expect property name after '.'.")?;
+        Ok(Expr::Get(Rc::new(object), name))
     }
 
     fn primary(&mut self) -> ParseResult<Expr> {
-        if self.matches(vec![False]) {
+        if self.matches(&[False]) {
             return Ok(Expr::Literal(Literal::False));
         }
-        if self.matches(vec![True]) {
+        if self.matches(&[True]) {
             return Ok(Expr::Literal(Literal::True));
         }
-        if self.matches(vec![Nil]) {
+        if self.matches(&[Nil]) {
             return Ok(Expr::Literal(Literal::Nil));
         }
-        if self.matches(vec![Number, String]) {
-            let l = self.previous().literal.unwrap();
-            return Ok(Expr::Literal(l));
+        if self.matches(&[Number, String]) {
+            return match self.previous().literal.clone() {
+                Some(literal) => Ok(Expr::Literal(literal)),
+                None => Err(ParserError::new("P0001", self.previous().clone(), "Expect literal value.".to_string())),
+            };
         }
-        if self.matches(vec![LeftParen]) {
+        if self.matches(&[LeftParen]) {
             let expr = self.expression()?;
             self.consume(RightParen, "Expect ')' after expression")?;
-            return Ok(Expr::Grouping(Box::new(expr)));
+            return Ok(Expr::Grouping(Rc::new(expr)));
+        }
+        if self.matches(&[Identifier]) {
+            return Ok(Expr::Variable(next_expr_id(), self.previous().clone()));
         }
-        if self.matches(vec![Identifier]) {
-            return Ok(Expr::Variable(self.previous()));
+        if self.matches(&[LeftBrace]) {
+            return self.record();
         }
 
-        Ok(Expr::Empty)
+        Err(ParserError::new("P0001", self.peek().clone(), "Expect expression.".to_string()))
+    }
+
+    fn record(&mut self) -> ParseResult<Expr> {
+        let brace = self.previous().clone();
+        self.extension(&brace)?;
+        let mut fields: Vec<(Token, Expr)> = vec![];
+        if !self.check(RightBrace) {
+            loop {
+                let name = self.consume(Identifier, "Expect field name.")?;
+                if fields.iter().any(|(other, _)| other.lexeme == name.lexeme) {
+                    return Err(ParserError::new("P0008", name, "Record already has a field with this name.".to_string()));
+                }
+                self.consume(Colon, "Expect ':' after field name.")?;
+                fields.push((name, self.expression()?));
+                if !self.matches(&[Comma]) || self.check(RightBrace) {
+                    break;
+                }
+            }
+        }
+        self.consume(RightBrace, "Expect '}' after record fields.")?;
+        Ok(Expr::Record(brace, fields.into()))
     }
 
     fn consume(&mut self, t: TokenType, message: &str) -> Result<Token, ParserError> {
         if self.check(t) {
-            return Ok(self.advance());
+            return Ok(self.advance().clone());
         }
 
-        Err(ParserError::new(self.peek(), message.to_string()))
+        let code = match t {
+            Semicolon => "P0002",
+            RightParen | RightBracket | RightBrace => "P0003",
+            Identifier => "P0010",
+            String => "P0016",
+            _ => "P0011",
+        };
+        Err(ParserError::new(code, self.peek().clone(), message.to_string()))
     }
 
     pub fn synchronize(&mut self) {
@@ -261,7 +545,7 @@ impl Parser {
                 return;
             }
             match self.peek().token_type {
-                Class | For | Fun | If | Print | Return | Var | While => return,
+                Class | Enum | For | Fun | If | Print | Return | Var | While | With => return,
                 _ => (),
             }
             self.advance();
@@ -277,99 +561,200 @@ impl Parser {
         Ok(statements)
     }
 
-    fn declaration(&mut self) -> ParseResult<Stmt> {
-        if self.matches(vec![Fun]) {
-            if self.peek().token_type == LeftParen {
-                return self.function("lambda");
-            } else {
-                return self.function("function");
+    /// Parses a lone expression that makes up the whole source.
+    pub fn parse_expression(&mut self) -> ParseResult<Expr> {
+        let expr = self.expression()?;
+        if !self.is_at_end() {
+            return Err(ParserError::new("P0015", self.peek().clone(), "Expect end of expression.".to_string()));
+        }
+        Ok(expr)
+    }
+
+    /// Parses every declaration it can, skipping to the next statement
+    /// after each error instead of stopping at the first.
+    pub fn parse_collecting(&mut self) -> (Vec<Stmt>, Vec<LoxError>) {
+        let mut statements = vec![];
+        let mut errors = vec![];
+        while !self.is_at_end() {
+            match self.declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(err) => {
+                    errors.push(err.into());
+                    self.synchronize();
+                }
             }
         }
+        (statements, errors)
+    }
+
+    fn declaration(&mut self) -> ParseResult<Stmt> {
+        self.check_depth()?;
+        // `fun (` starts a lambda in an expression statement.
+        if self.check(Fun) && !self.check_next(LeftParen) {
+            let doc = self.peek().doc_comment();
+            self.advance();
+            return self.function("function", doc);
+        }
 
-        if self.matches(vec![Var]) {
+        if self.matches(&[Var]) {
             return self.var_declaration();
         }
-        self.statement()
-    }
 
-    fn function(&mut self, kind: &str) -> ParseResult<Stmt> {
-        let name = match kind {
-            "function" => self.consume(Identifier, &format!("Expect {} name.", kind))?,
-            "lambda" => Token::from_str(""),
-            _ => unimplemented!(),
-        };
-        self.consume(LeftParen, &format!("Expect '(' after {} name.", kind))?;
-        let mut parameters = vec![];
-        if !self.check(RightParen) {
-            loop {
-                if parameters.len() >= 255 {
-                    return Err(ParserError::new(
-                        self.peek(),
-                        "Can't have more than 255 parameters.".to_string(),
-                    ));
-                }
-                parameters.push(self.consume(Identifier, "Expect parameter name.")?);
+        if self.matches(&[Enum]) {
+            return self.enum_declaration();
+        }
 
-                if !self.matches(vec![Comma]) {
-                    break;
+        // Neither word is reserved, so `import(...)` still calls the native.
+        if self.check_word("import") && self.check_next(LeftBrace) {
+            self.advance();
+            return self.import_declaration();
+        }
+        if self.check_word("export") && (self.check_next(Fun) || self.check_next(Var) || self.check_next(Enum)) {
+            // Doc comments go before `export`, not between it and `fun`.
+            let doc = self.peek().doc_comment();
+            self.advance();
+            let declaration = match self.declaration()? {
+                Stmt::Function(name, id, params, body, signature, None) => {
+                    Stmt::Function(name, id, params, body, signature, doc)
                 }
+                declaration => declaration,
+            };
+            return Ok(Stmt::Export(Rc::new(declaration)));
+        }
+        self.statement()
+    }
+
+    fn import_declaration(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
+        self.consume(LeftBrace, "Expect '{' after 'import'.")?;
+        let mut names: Vec<Token> = vec![];
+        loop {
+            let name = self.consume(Identifier, "Expect name to import.")?;
+            if names.iter().any(|other| other.lexeme == name.lexeme) {
+                return Err(ParserError::new("P0008", name, "Name is already imported.".to_string()));
+            }
+            names.push(name);
+            // A trailing comma is allowed.
+            if !self.matches(&[Comma]) || self.check(RightBrace) {
+                break;
             }
         }
-        self.consume(RightParen, "Expect ')' after parameters.")?;
+        self.consume(RightBrace, "Expect '}' after imported names.")?;
+        if !self.check_word("from") {
+            return Err(ParserError::new("P0011", self.peek().clone(), "Expect 'from' after imported names.".to_string()));
+        }
+        self.advance();
+        let path = self.consume(String, "Expect module path.")?;
+        let Some(Literal::String(path)) = path.literal else {
+            return Err(ParserError::new("P0016", path, "Expect module path.".to_string()));
+        };
+        self.consume(Semicolon, "Expect ';' after import.")?;
+        Ok(Stmt::Import(keyword, names.into(), path))
+    }
+
+    fn function(&mut self, kind: &str, doc: Option<Rc<str>>) -> ParseResult<Stmt> {
+        let name = self.consume(Identifier, &format!("Expect {} name.", kind))?;
+        self.consume(LeftParen, &format!("Expect '(' after {} name.", kind))?;
+        let (parameters, signature) = self.parameters()?;
         self.consume(LeftBrace, &format!("Expect '{{' before {} body.", kind))?;
         let body = self.block()?;
-        Ok(Stmt::Function(name, parameters, Box::new(body)))
+        Ok(Stmt::Function(name, next_expr_id(), parameters.into(), body.into(), signature, doc))
     }
 
     fn var_declaration(&mut self) -> ParseResult<Stmt> {
         let name = self.consume(Identifier, "Expect variable name.")?;
+        let annotation = self.type_annotation()?;
 
         let mut initializer = None;
-        if self.matches(vec![Equal]) {
+        if self.matches(&[Equal]) {
             initializer = Some(self.expression()?)
+        } else if self.compat == Compat::Jlox {
+            initializer = Some(Expr::Literal(Literal::Nil));
         }
 
         self.consume(Semicolon, "Expect ';' after variable declaration.")?;
-        Ok(Stmt::Var(name, initializer))
+        Ok(Stmt::Var(name, annotation, initializer))
+    }
+
+    fn enum_declaration(&mut self) -> ParseResult<Stmt> {
+        self.extension(self.previous())?;
+        let name = self.consume(Identifier, "Expect enum name.")?;
+        self.consume(LeftBrace, "Expect '{' before enum body.")?;
+        let mut cases: Vec<Token> = vec![];
+        if !self.check(RightBrace) {
+            loop {
+                let case = self.consume(Identifier, "Expect enum case name.")?;
+                if cases.iter().any(|other| other.lexeme == case.lexeme) {
+                    return Err(ParserError::new("P0008", case, "Enum already has a case with this name.".to_string()));
+                }
+                cases.push(case);
+                // A trailing comma is allowed.
+                if !self.matches(&[Comma]) || self.check(RightBrace) {
+                    break;
+                }
+            }
+        }
+        self.consume(RightBrace, "Expect '}' after enum cases.")?;
+        Ok(Stmt::Enum(name, cases.into()))
     }
 
     fn statement(&mut self) -> ParseResult<Stmt> {
-        if self.matches(vec![For]) {
+        if self.matches(&[For]) {
             return self.for_statement();
         }
 
-        if self.matches(vec![Break]) {
+        if self.matches(&[Break]) {
             return self.break_statement();
         }
 
-        if self.matches(vec![If]) {
+        if self.matches(&[Continue]) {
+            return self.continue_statement();
+        }
+
+        if self.check(Identifier) && self.check_next(Colon) {
+            return self.labeled_statement();
+        }
+
+        if self.matches(&[If]) {
             return self.if_statement();
         }
 
-        if self.matches(vec![While]) {
+        if self.matches(&[Unless]) {
+            return self.unless_statement();
+        }
+
+        if self.matches(&[With]) {
+            return self.with_statement();
+        }
+
+        if self.matches(&[While]) {
             return self.while_statement();
         }
 
-        if self.matches(vec![Print]) {
+        if self.matches(&[Print]) {
             return self.print_statement();
         }
 
-        if self.matches(vec![Return]) {
+        if self.matches(&[Return]) {
             return self.return_statement();
         }
 
-        if self.matches(vec![LeftBrace]) {
-            return Ok(Stmt::Block(self.block()?));
+        if self.matches(&[LeftBrace]) {
+            return Ok(Stmt::Block(self.block()?.into()));
         }
         self.expression_statement()
     }
 
     fn for_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
         self.consume(LeftParen, "Expect '(' after 'for'.")?;
 
         let mut initializer: Option<Stmt> = None;
-        if self.matches(vec![Semicolon]) {
-        } else if self.matches(vec![Var]) {
+        if self.matches(&[Semicolon]) {
+        } else if self.matches(&[Var]) {
+            if self.check(Identifier) && self.check_next_word("in") {
+                return self.for_in_statement();
+            }
             initializer = Some(self.var_declaration()?);
         } else {
             initializer = Some(self.expression_statement()?);
@@ -386,62 +771,125 @@ impl Parser {
             increment = Some(self.expression()?);
         }
         self.consume(RightParen, "Expect ')' after for clauses.")?;
-        let mut body = self.statement()?;
-
-        if let Some(increment) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(increment)]);
-        }
-
-        if condition.is_none() {
-            condition = Some(Expr::Literal(Literal::True));
-        }
-
-        let condition = condition.unwrap();
-
-        body = Stmt::While(condition, Box::new(body));
+        let body = self.statement()?;
 
-        if let Some(initializer) = initializer {
-            body = Stmt::Block(vec![initializer, body]);
-        }
+        Ok(Stmt::For(
+            keyword,
+            initializer.map(Rc::new),
+            condition,
+            increment,
+            Rc::new(body),
+        ))
+    }
 
-        Ok(body)
+    /// The rest of `for (var name in iterable) body`, after `var`.
+    fn for_in_statement(&mut self) -> ParseResult<Stmt> {
+        let name = self.consume(Identifier, "Expect variable name.")?;
+        let keyword = self.advance().clone();
+        self.extension(&keyword)?;
+        let iterable = self.expression()?;
+        self.consume(RightParen, "Expect ')' after for clauses.")?;
+        let body = self.statement()?;
+        Ok(Stmt::ForIn(name, iterable, Rc::new(body)))
     }
 
     fn while_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
         self.consume(LeftParen, "expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(RightParen, "Expect ')' after condition.")?;
         let body = self.statement()?;
-        Ok(Stmt::While(condition, Box::new(body)))
+        Ok(Stmt::While(keyword, condition, Rc::new(body)))
     }
 
     fn break_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
+        self.extension(&keyword)?;
+        let label = self.matches(&[Identifier]).then(|| self.previous().clone());
         self.consume(Semicolon, "Expect ';' after break keyword.")?;
-        let token = Token::new(
-            TokenType::Break,
-            "break".to_string(),
-            None,
-            self.current as u32,
-        );
-        Ok(Stmt::Break(token))
+        Ok(Stmt::Break(keyword, label))
     }
 
+    fn continue_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
+        self.extension(&keyword)?;
+        let label = self.matches(&[Identifier]).then(|| self.previous().clone());
+        self.consume(Semicolon, "Expect ';' after continue keyword.")?;
+        Ok(Stmt::Continue(keyword, label))
+    }
+
+    fn labeled_statement(&mut self) -> ParseResult<Stmt> {
+        let label = self.advance().clone();
+        self.extension(self.peek())?;
+        self.advance();
+        let body = if self.matches(&[While]) {
+            self.while_statement()?
+        } else if self.matches(&[For]) {
+            self.for_statement()?
+        } else {
+            return Err(ParserError::new("P0017", self.peek().clone(), "Expect loop after label.".to_string()));
+        };
+        Ok(Stmt::Labeled(label, Rc::new(body)))
+    }
+
+    /// Parses an `if` along with any `else if` and `elif` arms after it
+    /// into one statement, so long chains don't nest.
     fn if_statement(&mut self) -> ParseResult<Stmt> {
-        self.consume(LeftParen, "Expect '(' after 'if'.")?;
+        let mut arms = vec![self.if_arm()?];
+        let mut else_branch = None;
+        loop {
+            if self.matches(&[Elif]) {
+                arms.push(self.if_arm()?);
+            } else if self.matches(&[Else]) {
+                if self.matches(&[If]) {
+                    arms.push(self.if_arm()?);
+                    continue;
+                }
+                else_branch = Some(Rc::new(self.statement()?));
+                break;
+            } else {
+                break;
+            }
+        }
+        Ok(Stmt::If(arms.into(), else_branch))
+    }
+
+    /// An arm of an `if`, `elif` or `unless`, after its keyword.
+    fn if_arm(&mut self) -> ParseResult<(Token, Expr, Stmt)> {
+        let keyword = self.previous().clone();
+        self.consume(LeftParen, &format!("Expect '(' after '{}'.", keyword.lexeme))?;
         let condition = self.expression()?;
         self.consume(RightParen, "Expect ')' after if condition.")?;
+        Ok((keyword, condition, self.statement()?))
+    }
 
-        let then_branch = self.statement()?;
+    /// Parses `unless (condition)` as an `if` on the negated condition.
+    fn unless_statement(&mut self) -> ParseResult<Stmt> {
+        let (keyword, condition, then_branch) = self.if_arm()?;
+        let not = Token {
+            token_type: Bang,
+            lexeme: Symbol::intern("!"),
+            ..keyword.clone()
+        };
+        let condition = Expr::Unary(not, Rc::new(Expr::Grouping(Rc::new(condition))));
         let mut else_branch = None;
-        if self.matches(vec![Else]) {
-            else_branch = Some(self.statement()?)
+        if self.matches(&[Else]) {
+            else_branch = Some(Rc::new(self.statement()?));
         }
+        Ok(Stmt::If([(keyword, condition, then_branch)].into(), else_branch))
+    }
 
-        Ok(Stmt::If(
-            condition,
-            Box::new(then_branch),
-            Box::new(else_branch),
-        ))
+    fn with_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
+        self.extension(&keyword)?;
+        self.consume(LeftParen, "Expect '(' after 'with'.")?;
+        self.consume(Var, "Expect 'var' after '('.")?;
+        let name = self.consume(Identifier, "Expect variable name.")?;
+        self.consume(Equal, "Expect '=' after variable name.")?;
+        let initializer = self.expression()?;
+        self.consume(RightParen, "Expect ')' after with initializer.")?;
+        let body = self.statement()?;
+        Ok(Stmt::With(keyword, name, initializer, Rc::new(body)))
     }
 
     fn block(&mut self) -> ParseResult<Vec<Stmt>> {
@@ -455,20 +903,23 @@ impl Parser {
     }
 
     fn print_statement(&mut self) -> ParseResult<Stmt> {
-        let value = self.expression()?;
+        let mut values = vec![self.expression()?];
+        while self.matches(&[Comma]) {
+            values.push(self.expression()?);
+        }
         self.consume(Semicolon, "Expected ';' after value.")?;
-        Ok(Stmt::Print(value))
+        Ok(Stmt::Print(values.into()))
     }
 
     fn return_statement(&mut self) -> ParseResult<Stmt> {
-        let keyword = self.previous();
+        let keyword = self.previous().clone();
         let mut value = None;
         if !self.check(Semicolon) {
             value = Some(self.expression()?);
         }
 
         self.consume(Semicolon, "Expect ';' after return value.")?;
-        Ok(Stmt::Return(keyword, Box::new(value)))
+        Ok(Stmt::Return(keyword, value))
     }
 
     fn expression_statement(&mut self) -> ParseResult<Stmt> {
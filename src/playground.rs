@@ -0,0 +1,84 @@
+use crate::error::LoxError;
+use crate::interpreter::Interpreter;
+use crate::json::Json;
+
+/// Runs `source` in a fresh interpreter with its output captured, for an
+/// in-browser playground. The result is an object with the printed
+/// `output`, a `diagnostics` array of every error with its `phase`,
+/// `message`, `line`, `column` and `length`, and the `status` `rlox` would
+/// exit with.
+pub fn run_source(source: &str) -> Json {
+    let mut interpreter = Interpreter::default();
+    interpreter.capture_output();
+    let status = match interpreter.run(source.to_string()) {
+        Ok(status) => status.exit_code(),
+        Err(_) => 70,
+    };
+    let diagnostics = interpreter.errors().iter().map(diagnostic).collect();
+    Json::object([
+        ("output", Json::from(interpreter.take_output())),
+        ("diagnostics", Json::Array(diagnostics)),
+        ("status", Json::Int(status as i64)),
+    ])
+}
+
+fn diagnostic(error: &LoxError) -> Json {
+    let span = error.span();
+    Json::object([
+        ("phase", Json::from(error.phase().name())),
+        ("message", Json::from(error.message())),
+        ("line", Json::Int(span.line as i64)),
+        ("column", Json::Int(span.column as i64)),
+        ("length", Json::Int(span.length as i64)),
+    ])
+}
+
+/// Exports for `wasm32-unknown-unknown`. Without `wasm-bindgen` the result
+/// crosses as a JSON string in linear memory:
+///
+/// ```js
+/// const bytes = new TextEncoder().encode(source);
+/// const ptr = exports.playground_alloc(bytes.length);
+/// new Uint8Array(exports.memory.buffer, ptr, bytes.length).set(bytes);
+/// const len = exports.playground_run(ptr, bytes.length);
+/// const out = new Uint8Array(exports.memory.buffer, exports.playground_result(), len);
+/// const result = JSON.parse(new TextDecoder().decode(out));
+/// ```
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use std::cell::RefCell;
+
+    thread_local! {
+        static RESULT: RefCell<String> = const { RefCell::new(String::new()) };
+    }
+
+    /// Reserves `len` bytes for the caller to write source into.
+    #[no_mangle]
+    pub extern "C" fn playground_alloc(len: usize) -> *mut u8 {
+        let mut buffer = Vec::<u8>::with_capacity(len);
+        let ptr = buffer.as_mut_ptr();
+        std::mem::forget(buffer);
+        ptr
+    }
+
+    /// Runs the `len` bytes of source at `ptr`, which must come from
+    /// `playground_alloc(len)` and are freed here, and returns the length
+    /// of the JSON result.
+    #[no_mangle]
+    pub extern "C" fn playground_run(ptr: *mut u8, len: usize) -> usize {
+        // SAFETY: the caller filled the buffer `playground_alloc(len)` made.
+        let bytes = unsafe { Vec::from_raw_parts(ptr, len, len) };
+        let source = String::from_utf8_lossy(&bytes);
+        let result = super::run_source(&source).to_string();
+        RESULT.with(|cell| {
+            *cell.borrow_mut() = result;
+            cell.borrow().len()
+        })
+    }
+
+    /// Where the last result is, valid until the next `playground_run`.
+    #[no_mangle]
+    pub extern "C" fn playground_result() -> *const u8 {
+        RESULT.with(|cell| cell.borrow().as_ptr())
+    }
+}
@@ -1,9 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::expr::Expr;
+use crate::error::LoxError;
+use crate::error_codes;
+use crate::expr::{Expr, ExprId};
 use crate::interpreter::Interpreter;
-use crate::stmt::Stmt;
-use crate::token::Token;
+use crate::stmt::{PendingLabel, Stmt};
+use crate::symbol::Symbol;
+use crate::token::{Literal, Token, TokenType};
+use crate::warning::{self, Warning};
 
 #[derive(Clone, PartialEq)]
 enum FunctionType {
@@ -11,221 +15,569 @@ enum FunctionType {
     Function,
 }
 
-pub struct Resolver {
-    pub interpreter: Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+/// What a collecting resolver gathers: every error and warning it would
+/// otherwise report, and each variable use paired with the declaration it
+/// refers to.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub errors: Vec<LoxError>,
+    pub warnings: Vec<Warning>,
+    pub references: Vec<(Token, Token)>,
+}
+
+/// Declarations seen so far, kept alongside `scopes` while collecting.
+#[derive(Default)]
+struct Collector {
+    resolution: Resolution,
+    scopes: Vec<HashMap<Symbol, Token>>,
+    globals: HashMap<Symbol, Token>,
+    global_uses: Vec<Token>,
+}
+
+/// A function being resolved, and the variables from outside it that it
+/// captures so far.
+struct FunctionScope {
+    /// The index in `scopes` of its parameters' scope.
+    base: usize,
+    captures: Vec<(Symbol, u32)>,
+}
+
+pub struct Resolver<'a> {
+    pub interpreter: &'a mut Interpreter,
+    scopes: Vec<HashMap<Symbol, bool>>,
+    /// The locals declared in each of `scopes` that nothing has read yet.
+    unread: Vec<HashMap<Symbol, Token>>,
+    /// The functions around the current statement, outermost first.
+    functions: Vec<FunctionScope>,
+    /// Every global defined before the program and declared at its top
+    /// level, once the top level has been reached.
+    globals: Option<HashSet<Symbol>>,
     current_function: FunctionType,
-    returned: bool
+    returned: bool,
+    /// The labels of the loops enclosing the current statement within its
+    /// function, `None` for unlabeled ones.
+    loops: Vec<Option<Symbol>>,
+    label: PendingLabel,
+    /// How many blocks deep the current statement is within its function.
+    nesting: usize,
+    collector: Option<Collector>,
 }
 
 pub trait Resolve<T> {
     fn resolve(&mut self, value: T);
 }
 
-impl Resolver {
-    pub fn new(interpreter: Interpreter) -> Self {
+impl<'a> Resolver<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
         Self {
             interpreter,
             scopes: vec![],
+            unread: vec![],
+            functions: vec![],
+            globals: None,
             current_function: FunctionType::None,
-            returned: false
+            returned: false,
+            loops: vec![],
+            label: PendingLabel::default(),
+            nesting: 0,
+            collector: None,
+        }
+    }
+
+    /// A resolver for tools: errors are collected rather than reported, and
+    /// [`Resolver::into_resolution`] hands them back with the references.
+    pub fn collecting(interpreter: &'a mut Interpreter) -> Self {
+        Self {
+            collector: Some(Collector::default()),
+            ..Self::new(interpreter)
+        }
+    }
+
+    /// Resolves an expression evaluated with `locals` declared in a scope
+    /// in front of the globals, as [`Interpreter::eval_expr`] does.
+    pub fn resolve_expression(&mut self, locals: &[Token], expr: &Expr) {
+        self.begin_scope();
+        for local in locals {
+            self.declare(local);
+            self.define(local);
+        }
+        self.resolve(expr);
+        self.end_scope();
+    }
+
+    /// Finishes collecting, linking uses of globals to their declarations
+    /// now that every top-level declaration has been seen.
+    pub fn into_resolution(self) -> Resolution {
+        let Some(mut collector) = self.collector else {
+            return Resolution::default();
+        };
+        for name in collector.global_uses {
+            if let Some(declaration) = collector.globals.get(&name.lexeme) {
+                collector.resolution.references.push((name, declaration.clone()));
+            }
+        }
+        collector.resolution
+    }
+
+    fn error(&mut self, code: &'static str, token: &Token, message: &str) {
+        let error = LoxError::Resolve(error_codes::get(code), token.clone(), message.into());
+        match &mut self.collector {
+            Some(collector) => collector.resolution.errors.push(error),
+            None => self.interpreter.report(error),
+        }
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        if !self.interpreter.warns_about(warning.rule) {
+            return;
+        }
+        match &mut self.collector {
+            Some(_) if self.interpreter.is_suppressed(&warning) => (),
+            Some(collector) => collector.resolution.warnings.push(warning),
+            None => self.interpreter.warn(warning),
         }
     }
 
     fn begin_scope(&mut self) {
-        self.scopes.push(HashMap::new())
+        self.scopes.push(HashMap::new());
+        self.unread.push(HashMap::new());
+        if let Some(collector) = &mut self.collector {
+            collector.scopes.push(HashMap::new());
+        }
     }
 
     fn end_scope(&mut self) {
         self.returned = false;
         self.scopes.pop();
+        let mut unread: Vec<Token> = self.unread.pop().unwrap_or_default().into_values().collect();
+        unread.sort_by_key(|name| (name.line, name.column));
+        for name in unread {
+            let message = format!("Local variable '{}' is never read.", name.lexeme);
+            self.warn(Warning::new(&name, warning::UNUSED_VARIABLE, message));
+        }
+        if let Some(collector) = &mut self.collector {
+            collector.scopes.pop();
+        }
     }
 
-    fn declare(&mut self, name: Token) {
-        if self.scopes.is_empty() {
+    fn declare(&mut self, name: &Token) {
+        if let Some(collector) = &mut self.collector {
+            match collector.scopes.last_mut() {
+                Some(scope) => scope.entry(name.lexeme).or_insert_with(|| name.clone()),
+                None => collector.globals.entry(name.lexeme).or_insert_with(|| name.clone()),
+            };
+        }
+        let Some(scope) = self.scopes.last_mut() else {
             return;
         };
-        let mut scope = self.scopes.pop().expect("Expected a HashMap.");
         if scope.contains_key(&name.lexeme) {
-            self.interpreter
-                .log_error(
-                    name,
-                    "Already a variable with this name in this scope.".to_string(),
-                )
-                .expect("There was an issue logging this error.");
+            self.error("R0003", name, "Already a variable with this name in this scope.");
             return;
         }
         scope.insert(name.lexeme, false);
-        self.scopes.push(scope);
+        let enclosing = &self.scopes[..self.scopes.len() - 1];
+        if enclosing.iter().any(|scope| scope.contains_key(&name.lexeme)) {
+            let message = format!("'{}' shadows a variable in an enclosing scope.", name.lexeme);
+            self.warn(Warning::new(name, warning::SHADOWING, message));
+        }
     }
 
-    fn define(&mut self, name: Token) {
-        if self.scopes.is_empty() {
+    /// A token standing in for a statement that doesn't keep one, to
+    /// place a warning about it, or `None` if there's no line to give it.
+    fn token_at(&self, token_type: TokenType, lexeme: &str, line: Option<u32>) -> Option<Token> {
+        Some(Token {
+            token_type,
+            lexeme: Symbol::intern(lexeme),
+            line: line?,
+            source: self.interpreter.current_source(),
+            ..Token::default()
+        })
+    }
+
+    /// Warns at `keyword` about a condition that's always true or always
+    /// false. An `infinite` loop's `true` is allowed, being how a loop left
+    /// by `break` is written.
+    fn check_condition(&mut self, keyword: &Token, condition: &Expr, infinite: bool) {
+        let mut inner = condition;
+        while let Expr::Grouping(expr) = inner {
+            inner = expr;
+        }
+        let Expr::Literal(value) = inner else {
             return;
+        };
+        if infinite && *value == Literal::True {
+            return;
+        }
+        let message = format!("The condition is always {}.", value);
+        self.warn(Warning::new(keyword, warning::CONSTANT_CONDITION, message));
+    }
+
+    /// Notes a local the program declares, rather than a parameter, to
+    /// warn about if nothing reads it. Names starting with `_` are meant
+    /// to go unread.
+    fn declare_unread(&mut self, name: &Token) {
+        if let Some(unread) = self.unread.last_mut() {
+            if !name.lexeme.as_str().starts_with('_') {
+                unread.insert(name.lexeme, name.clone());
+            }
+        }
+    }
+
+    /// Marks the local `name` refers to as read.
+    fn read(&mut self, name: &Token) {
+        let declared = self.scopes.iter().rposition(|scope| scope.contains_key(&name.lexeme));
+        if let Some(i) = declared {
+            self.unread[i].remove(&name.lexeme);
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme, true);
+        }
+    }
+
+    /// Notes the globals the top level `stmts` declare, before resolving
+    /// any of them, so functions can use globals declared after them.
+    fn declare_globals(&mut self, stmts: &[Stmt]) {
+        let interpreter = &self.interpreter;
+        let globals = self.globals.get_or_insert_with(|| interpreter.global_names());
+        for stmt in stmts {
+            globals.extend(stmt.declared().into_iter().map(|name| name.lexeme));
+        }
+    }
+
+    fn resolve_global(&mut self, name: &Token) {
+        if let Some(collector) = &mut self.collector {
+            collector.global_uses.push(name.clone());
+        }
+        let declared = self.globals.as_ref().is_none_or(|globals| globals.contains(&name.lexeme));
+        if !declared && self.interpreter.checks_globals() {
+            self.error("R0001", name, &format!("Undefined variable {}.", name.lexeme));
         }
-        let mut scope = self.scopes.pop().expect("Expected a HashMap.");
-        scope.insert(name.lexeme, true);
-        self.scopes.push(scope);
     }
 
-    fn resolve_local(&mut self, expr: Expr, name: Token) {
+    fn resolve_local(&mut self, id: ExprId, name: &Token) {
         if self.scopes.is_empty() {
+            self.resolve_global(name);
             return;
         }
 
         let mut i = self.scopes.len() - 1;
         loop {
-            if self.scopes.get(i).unwrap().contains_key(&name.lexeme) {
-                self.interpreter
-                    .resolve(expr.clone(), (self.scopes.len() - 1 - i) as u32);
+            if self.scopes[i].contains_key(&name.lexeme) {
+                let distance = self.capture(name, i);
+                self.interpreter.resolve(id, distance);
+                if let Some(collector) = &mut self.collector {
+                    if let Some(declaration) = collector.scopes[i].get(&name.lexeme) {
+                        let reference = (name.clone(), declaration.clone());
+                        collector.resolution.references.push(reference);
+                    }
+                }
+                return;
             }
             if i == 0 {
                 break;
             }
             i -= 1;
         }
+        self.resolve_global(name);
+    }
+
+    /// How many environments up from the current scope the variable
+    /// declared in `scopes[declared]` is found at run time. A variable
+    /// declared outside the current function is captured by it, and by
+    /// each function between, so it's found in the function's closure,
+    /// one past its parameters.
+    fn capture(&mut self, name: &Token, declared: usize) -> u32 {
+        let innermost = self.scopes.len() - 1;
+        let Some(outermost) = self.functions.iter().position(|function| function.base > declared) else {
+            return (innermost - declared) as u32;
+        };
+        let mut from = declared;
+        for function in &mut self.functions[outermost..] {
+            // The outermost capturer is declared in the scope before its
+            // parameters', and finds the variable where it's declared. The
+            // rest are declared inside the one before, and find it in that
+            // one's closure.
+            let distance = if from == declared {
+                function.base - 1 - declared
+            } else {
+                function.base - from
+            };
+            if !function.captures.iter().any(|&(captured, _)| captured == name.lexeme) {
+                function.captures.push((name.lexeme, distance as u32));
+            }
+            from = function.base;
+        }
+        (innermost - from + 1) as u32
+    }
+
+    /// Checks that a `break` or `continue` label names an enclosing loop.
+    /// Unlabeled ones outside a loop are left for the interpreter to report.
+    fn resolve_jump(&mut self, label: &Option<Token>) {
+        if let Some(label) = label {
+            if !self.loops.contains(&Some(label.lexeme)) {
+                self.error("R0005", label, &format!("No enclosing loop labeled '{}'.", label.lexeme));
+            }
+        }
     }
 
     fn resolve_function(
         &mut self,
-        params: Vec<Token>,
-        body: Box<Vec<Stmt>>,
+        id: ExprId,
+        params: &[Token],
+        body: &[Stmt],
         function_type: FunctionType,
     ) {
         let enclosing_function = self.current_function.clone();
         self.current_function = function_type;
+        if let Some(param) = params.get(warning::MAX_PARAMETERS) {
+            let message = format!("Functions shouldn't take more than {} parameters.", warning::MAX_PARAMETERS);
+            self.warn(Warning::new(param, warning::LONG_PARAMETER_LIST, message));
+        }
+        let nesting = std::mem::take(&mut self.nesting);
+        // A function body can't leave the loops around its definition.
+        let loops = std::mem::take(&mut self.loops);
         self.begin_scope();
-        for param in params {
-            self.declare(param.clone());
+        self.functions.push(FunctionScope {
+            base: self.scopes.len() - 1,
+            captures: vec![],
+        });
+        for param in params.iter() {
+            self.declare(param);
             self.define(param);
         }
-        self.resolve(*body);
+        self.resolve(body);
         self.end_scope();
+        if let Some(function) = self.functions.pop() {
+            self.interpreter.resolve_captures(id, function.captures);
+        }
+        self.loops = loops;
+        self.nesting = nesting;
         self.current_function = enclosing_function;
     }
 }
 
-impl Resolve<Vec<Stmt>> for Resolver {
-    fn resolve(&mut self, stmts: Vec<Stmt>) {
+impl Resolve<&[Stmt]> for Resolver<'_> {
+    fn resolve(&mut self, stmts: &[Stmt]) {
+        if self.scopes.is_empty() {
+            self.declare_globals(stmts);
+        }
         for stmt in stmts {
             self.resolve(stmt);
         }
     }
 }
 
-impl Resolve<Stmt> for Resolver {
-    fn resolve(&mut self, stmt: Stmt) {
+impl Resolve<&Stmt> for Resolver<'_> {
+    fn resolve(&mut self, stmt: &Stmt) {
         match stmt {
             Stmt::Block(stmts) => {
+                self.nesting += 1;
+                if self.nesting == warning::MAX_NESTING + 1 {
+                    if let Some(token) = self.token_at(TokenType::LeftBrace, "{", stmt.line()) {
+                        let message = format!("Blocks are nested more than {} deep.", warning::MAX_NESTING);
+                        self.warn(Warning::new(&token, warning::DEEP_NESTING, message));
+                    }
+                }
                 self.begin_scope();
-                self.resolve(stmts);
+                self.resolve(stmts.as_ref());
                 self.end_scope();
+                self.nesting -= 1;
             }
-            Stmt::Var(name, initializer) => {
+            Stmt::Var(name, _, initializer) => {
                 if self.returned {
-                    self.interpreter.log_error(name, "Unreachable code after return.".to_string()).expect("Unable to write to stderr.");
+                    self.error("R0008", name, "Unreachable code after return.");
                     return;
                 }
-                self.declare(name.clone());
+                self.declare(name);
+                self.declare_unread(name);
                 if let Some(expr) = initializer {
                     self.resolve(expr)
                 }
                 self.define(name);
             }
-            Stmt::Function(name, params, body) => {
-                self.declare(name.clone());
+            Stmt::Enum(name, _) => {
+                self.declare(name);
+                self.define(name);
+            }
+            Stmt::Import(_, names, _) => {
+                for name in names.iter() {
+                    self.declare(name);
+                    self.define(name);
+                }
+            }
+            Stmt::Export(declaration) => {
+                if let (false, Some(name)) = (self.scopes.is_empty(), declaration.declared().first()) {
+                    self.error("R0007", name, "Can only export from the top level.");
+                }
+                self.resolve(declaration.as_ref());
+            }
+            Stmt::Function(name, id, params, body, ..) => {
+                self.declare(name);
+                self.declare_unread(name);
                 self.define(name);
-                self.resolve_function(params, body, FunctionType::Function);
+                self.resolve_function(*id, params, body, FunctionType::Function);
             }
             Stmt::Expression(expression) => {
                 self.resolve(expression);
             }
-            Stmt::If(condition, then_branch, else_branch) => {
-                let (then_branch, else_branch) = (*then_branch, *else_branch);
-                self.resolve(condition);
-                self.resolve(then_branch);
-                if else_branch.is_some() {
-                    self.resolve(else_branch.unwrap());
+            Stmt::If(arms, else_branch) => {
+                // A return in one branch doesn't make the next unreachable.
+                for (keyword, condition, branch) in arms.iter() {
+                    self.check_condition(keyword, condition, false);
+                    self.resolve(condition);
+                    self.resolve(branch);
+                    self.returned = false;
+                }
+                if let Some(else_branch) = else_branch {
+                    self.resolve(else_branch.as_ref());
                 }
                 self.returned = false;
             }
-            Stmt::Print(expression) => {
-                self.resolve(expression);
-            }
+            Stmt::Print(values) => values.iter().for_each(|value| self.resolve(value)),
             Stmt::Return(keyword, value) => {
-                if self.current_function == FunctionType::None {
-                    self.interpreter
-                        .log_error(keyword, "Can't return from top-level code.".to_string())
-                        .expect("Unable to write to stderr.");
+                if self.current_function == FunctionType::None && !self.interpreter.allows_top_level_return() {
+                    self.error("R0004", keyword, "Can't return from top-level code.");
                     return;
                 }
-                let value = *value;
-                if value.is_some() {
-                    self.resolve(value.unwrap());
+                if let Some(value) = value.as_ref() {
+                    self.resolve(value);
                 }
                 self.returned = true;
             }
-            Stmt::While(condition, body) => {
+            Stmt::While(keyword, condition, body) => {
+                let label = self.label.take();
+                self.check_condition(keyword, condition, true);
                 self.resolve(condition);
-                self.resolve(*body);
+                self.loops.push(label);
+                self.resolve(body.as_ref());
+                self.loops.pop();
+            }
+            Stmt::For(keyword, initializer, condition, increment, body) => {
+                let label = self.label.take();
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.resolve(initializer.as_ref());
+                }
+                if let Some(condition) = condition {
+                    self.check_condition(keyword, condition, true);
+                    self.resolve(condition);
+                }
+                if let Some(increment) = increment {
+                    self.resolve(increment);
+                }
+                self.loops.push(label);
+                self.resolve(body.as_ref());
+                self.loops.pop();
+                self.end_scope();
             }
-            Stmt::Break(_) => (),
+            Stmt::ForIn(name, iterable, body) => {
+                let label = self.label.take();
+                self.resolve(iterable);
+                self.begin_scope();
+                self.declare(name);
+                self.define(name);
+                self.loops.push(label);
+                self.resolve(body.as_ref());
+                self.loops.pop();
+                self.end_scope();
+            }
+            Stmt::With(_, name, initializer, body) => {
+                self.resolve(initializer);
+                self.begin_scope();
+                self.declare(name);
+                self.define(name);
+                self.resolve(body.as_ref());
+                self.end_scope();
+            }
+            Stmt::Labeled(label, body) => {
+                if self.loops.contains(&Some(label.lexeme)) {
+                    self.error("R0006", label, &format!("Label '{}' is already in use.", label.lexeme));
+                }
+                self.label.set(label);
+                self.resolve(body.as_ref());
+            }
+            Stmt::Break(_, label) | Stmt::Continue(_, label) => self.resolve_jump(label),
         }
     }
 }
 
-impl Resolve<Expr> for Resolver {
-    fn resolve(&mut self, expr: Expr) {
+impl Resolve<&Expr> for Resolver<'_> {
+    fn resolve(&mut self, expr: &Expr) {
         match expr {
-            Expr::Variable(ref name) => {
+            Expr::Variable(id, name) => {
                 if self.returned {
-                    self.interpreter.log_error(name.clone(), "Unreachable code after a return.".to_string()).expect("Unable to write to stderr.");
+                    self.error("R0008", name, "Unreachable code after a return.");
                     return;
                 }
 
-                if !self.scopes.is_empty() {
-                    let scope = self.scopes.last().unwrap();
-                    match scope.get(&name.lexeme) {
-                        Some(false) => {
-                            self.interpreter
-                                .log_error(
-                                    name.clone(),
-                                    "Can't read local variable in its own initializer.".to_string(),
-                                )
-                                .expect("There was an error printing to stderr.");
-                        }
-                        _ => (),
-                    }
+                let in_initializer = self
+                    .scopes
+                    .last()
+                    .is_some_and(|scope| scope.get(&name.lexeme) == Some(&false));
+                if in_initializer {
+                    self.error("R0002", name, "Can't read local variable in its own initializer.");
                 }
-                self.resolve_local(expr.clone(), name.clone())
+                self.read(name);
+                self.resolve_local(*id, name)
             }
-            Expr::Assign(name, value) => {
-                let expr = Expr::Assign(name.clone(), value.clone());
-                self.resolve(*value);
-                self.resolve_local(expr, name);
+            Expr::Assign(id, name, value) => {
+                self.resolve(value.as_ref());
+                self.resolve_local(*id, name);
             }
-            Expr::Binary(left, _, right) => {
-                self.resolve(*left);
-                self.resolve(*right);
+            Expr::Binary(_, _, _) => {
+                // Walk left-leaning operator chains iteratively, mirroring
+                // `Interpreter::evaluate_binary`.
+                let mut leftmost = expr;
+                let mut rights = vec![];
+                while let Expr::Binary(left, _, right) = leftmost {
+                    rights.push(right);
+                    leftmost = left;
+                }
+                self.resolve(leftmost);
+                while let Some(right) = rights.pop() {
+                    self.resolve(right.as_ref());
+                }
             }
-            Expr::Call(callee, _, arguments) => {
-                self.resolve(*callee);
-                for argument in *arguments {
+            Expr::Call(callee, _, arguments, named) => {
+                self.resolve(callee.as_ref());
+                for argument in arguments.iter() {
                     self.resolve(argument);
                 }
+                for (_, value) in named.iter() {
+                    self.resolve(value);
+                }
+            }
+            Expr::Grouping(expression) | Expr::Get(expression, _) => {
+                self.resolve(expression.as_ref());
+            }
+            Expr::Index(object, _, index) => {
+                self.resolve(object.as_ref());
+                self.resolve(index.as_ref());
             }
-            Expr::Grouping(expression) => {
-                self.resolve(*expression);
+            Expr::Set(object, _, value) => {
+                self.resolve(value.as_ref());
+                self.resolve(object.as_ref());
+            }
+            Expr::Record(_, fields) => {
+                for (_, value) in fields.iter() {
+                    self.resolve(value);
+                }
             }
             Expr::Literal(_) => (),
             Expr::Logical(left, _, right) => {
-                self.resolve(*left);
-                self.resolve(*right);
+                self.resolve(left.as_ref());
+                self.resolve(right.as_ref());
             }
             Expr::Unary(_, right) => {
-                self.resolve(*right);
+                self.resolve(right.as_ref());
+            }
+            Expr::Lambda(id, params, body, _) => {
+                self.resolve_function(*id, params, body, FunctionType::Function);
             }
-            _ => (),
+            Expr::Empty => (),
         }
     }
 }
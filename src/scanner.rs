@@ -1,8 +1,15 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
+use crate::error::{LoxError, Span};
+use crate::error_codes;
+use crate::source_map::SourceId;
 use crate::token::Token;
 use crate::token::TokenType;
 use crate::token::Literal;
+use crate::token::Trivia;
+use crate::symbol::Symbol;
+use crate::warning::Suppressions;
 
 trait StringFuncs {
     fn substring(&self, start: usize, end: usize) -> &str;
@@ -15,69 +22,216 @@ impl StringFuncs for String {
     }
 
     fn char_at(&self, index: usize) -> char {
-        *self.as_bytes().get(index).unwrap() as char
+        self.as_bytes().get(index).map_or('\0', |&byte| byte as char)
     }
 }
 
+/// A `//` comment, kept so tools like the formatter can put it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub line: u32,
+    /// The comment's text, including the leading `//`.
+    pub text: String,
+    /// Whether code precedes the comment on its line.
+    pub trailing: bool,
+    /// The brace pair enclosing the comment, numbering pairs from 1 by
+    /// their opening brace; 0 is the top level.
+    pub block: usize,
+}
+
+/// The standard keywords, always reserved.
+pub const KEYWORDS: [(&str, TokenType); 17] = [
+    ("and", TokenType::And),
+    ("break", TokenType::Break),
+    ("class", TokenType::Class),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("true", TokenType::True),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+];
+
+/// The standard keyword spelled `word`, if there is one. A `match` rather
+/// than a lookup in [`KEYWORDS`], as it runs for every identifier.
+pub fn keyword(word: &str) -> Option<TokenType> {
+    Some(match word {
+        "and" => TokenType::And,
+        "break" => TokenType::Break,
+        "class" => TokenType::Class,
+        "else" => TokenType::Else,
+        "false" => TokenType::False,
+        "for" => TokenType::For,
+        "fun" => TokenType::Fun,
+        "if" => TokenType::If,
+        "nil" => TokenType::Nil,
+        "or" => TokenType::Or,
+        "print" => TokenType::Print,
+        "return" => TokenType::Return,
+        "super" => TokenType::Super,
+        "this" => TokenType::This,
+        "true" => TokenType::True,
+        "var" => TokenType::Var,
+        "while" => TokenType::While,
+        _ => return None,
+    })
+}
+
+/// Whether `token_type` is a keyword's, standard or extension.
+pub fn is_keyword(token_type: TokenType) -> bool {
+    KEYWORDS.iter().chain(&EXTENSION_KEYWORDS).any(|&(_, keyword)| keyword == token_type)
+}
+
+/// Keywords a program has to opt into with [`Scanner::add_keyword`], so
+/// scripts that already use these words as names keep working.
+pub const EXTENSION_KEYWORDS: [(&str, TokenType); 7] = [
+    ("const", TokenType::Const),
+    ("continue", TokenType::Continue),
+    ("elif", TokenType::Elif),
+    ("enum", TokenType::Enum),
+    ("match", TokenType::Match),
+    ("unless", TokenType::Unless),
+    ("with", TokenType::With),
+];
+
+/// The extension keyword spelled `word`, if there is one.
+pub fn extension_keyword(word: &str) -> Option<TokenType> {
+    EXTENSION_KEYWORDS
+        .iter()
+        .find(|(keyword, _)| *keyword == word)
+        .map(|&(_, token_type)| token_type)
+}
+
+/// Turns source into tokens. Used as an iterator it produces them one at a
+/// time, ending with `Eof`, and carries on past errors; `scan_tokens`
+/// gathers them all into `tokens` instead.
 pub struct Scanner {
     source: String,
     pub tokens: Vec<Token>,
+    pub comments: Vec<Comment>,
+    /// Lines holding nothing but whitespace.
+    pub blank_lines: Vec<u32>,
+    line_has_content: bool,
+    open_blocks: Vec<usize>,
+    block_count: usize,
     start: usize,
     current: usize,
     pub line: usize,
-    keywords: HashMap<String, TokenType>,
+    /// Where the current line begins in `source`, for token columns.
+    line_start: usize,
+    /// Words scanned differently from the standard keywords: extension
+    /// keywords that were added, and `None` for keywords that were
+    /// removed.
+    keywords: HashMap<String, Option<TokenType>>,
+    /// The token the last `scan_token` produced, if any.
+    pending: Option<Token>,
+    /// Trivia scanned since the last token, to lead the next one.
+    trivia: Vec<Trivia>,
+    last_token_line: Option<u32>,
+    /// Whether the final `Eof` has been handed out.
+    done: bool,
+    /// The file being scanned, stamped on every token.
+    source_id: SourceId,
+    suppressions: Suppressions,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
-        let keywords: HashMap<String, TokenType> = HashMap::from([
-            ("and".to_string(), TokenType::And),
-            ("break".to_string(), TokenType::Break),
-            ("class".to_string(), TokenType::Class),
-            ("else".to_string(), TokenType::Else),
-            ("false".to_string(), TokenType::False),
-            ("for".to_string(), TokenType::For),
-            ("fun".to_string(), TokenType::Fun),
-            ("if".to_string(), TokenType::If),
-            ("nil".to_string(), TokenType::Nil),
-            ("or".to_string(), TokenType::Or),
-            ("print".to_string(), TokenType::Print),
-            ("return".to_string(), TokenType::Return),
-            ("super".to_string(), TokenType::Super),
-            ("this".to_string(), TokenType::This),
-            ("true".to_string(), TokenType::True),
-            ("var".to_string(), TokenType::Var),
-            ("while".to_string(), TokenType::While)
-        ]);
         Self {
             source,
             tokens: vec![],
+            comments: vec![],
+            blank_lines: vec![],
+            line_has_content: false,
+            open_blocks: vec![],
+            block_count: 0,
             start: 0,
             current: 0,
             line: 1,
-            keywords
+            line_start: 0,
+            keywords: HashMap::new(),
+            pending: None,
+            trivia: vec![],
+            last_token_line: None,
+            done: false,
+            source_id: SourceId::MAIN,
+            suppressions: Suppressions::default(),
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Result<(), std::io::Error> {
-        while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token()?;
+    /// Scans the whole source into `tokens`, returning the first error.
+    pub fn scan_tokens(&mut self) -> Result<(), LoxError> {
+        match self.scan_tokens_collecting().into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(()),
         }
-        self.tokens.push(Token {
-            token_type: TokenType::Eof,
-            lexeme: "".to_string(),
-            literal: None,
+    }
+
+    /// Scans the whole source into `tokens`, recording each error and
+    /// carrying on past it, for tools that want every problem at once.
+    pub fn scan_tokens_collecting(&mut self) -> Vec<LoxError> {
+        let mut tokens = std::mem::take(&mut self.tokens);
+        let mut errors = vec![];
+        for result in self.by_ref() {
+            match result {
+                Ok(token) => tokens.push(token),
+                Err(error) => errors.push(error),
+            }
+        }
+        self.tokens = tokens;
+        errors
+    }
+
+    /// Marks the tokens as coming from `source_id` rather than the main
+    /// script.
+    pub fn set_source(&mut self, source_id: SourceId) {
+        self.source_id = source_id;
+    }
+
+    /// Records the lines `// lox-ignore:` comments silence warnings on in
+    /// `suppressions`, which the caller keeps a handle to.
+    pub fn set_suppressions(&mut self, suppressions: Suppressions) {
+        self.suppressions = suppressions;
+    }
+
+    /// Makes `word` scan as `token_type` rather than as an identifier.
+    pub fn add_keyword(&mut self, word: &str, token_type: TokenType) {
+        self.keywords.insert(word.to_string(), Some(token_type));
+    }
+
+    /// Makes the keyword `word` scan as an identifier.
+    pub fn remove_keyword(&mut self, word: &str) {
+        self.keywords.insert(word.to_string(), None);
+    }
+
+    fn error(&self, code: &'static str, message: String) -> LoxError {
+        let span = Span {
+            source: self.source_id,
             line: self.line as u32,
-        });
-        Ok(())
+            column: (self.start - self.line_start.min(self.start)) as u32,
+            length: 1,
+        };
+        LoxError::Scan(error_codes::get(code), span, message.into())
+    }
+
+    /// The column `current` sits at on the current line.
+    pub fn column(&self) -> u32 {
+        (self.current - self.line_start.min(self.current)) as u32
     }
 
     pub fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
-    fn scan_token(&mut self) -> Result<(), std::io::Error> {
+    fn scan_token(&mut self) -> Result<(), LoxError> {
         let c = self.advance();
         match c {
             '(' => {
@@ -90,10 +244,21 @@ impl Scanner {
             }
             '{' => {
                 self.add_token(TokenType::LeftBrace, None);
+                self.block_count += 1;
+                self.open_blocks.push(self.block_count);
                 Ok(())
             }
             '}' => {
                 self.add_token(TokenType::RightBrace, None);
+                self.open_blocks.pop();
+                Ok(())
+            }
+            '[' => {
+                self.add_token(TokenType::LeftBracket, None);
+                Ok(())
+            }
+            ']' => {
+                self.add_token(TokenType::RightBracket, None);
                 Ok(())
             }
             ',' => {
@@ -120,6 +285,14 @@ impl Scanner {
                 self.add_token(TokenType::Star, None);
                 Ok(())
             }
+            '%' => {
+                self.add_token(TokenType::Percent, None);
+                Ok(())
+            }
+            ':' => {
+                self.add_token(TokenType::Colon, None);
+                Ok(())
+            }
             '!' => {
                 let token_type = if self.matches('=') {
                     TokenType::BangEqual
@@ -163,29 +336,42 @@ impl Scanner {
             '/' => {
                 if self.matches('/') {
                     while self.peek() != '\n' && !self.is_at_end() { self.advance(); }
+                    let line = self.line as u32;
+                    let trailing = self.last_token_line == Some(line);
+                    let text = &self.source[self.start..self.current];
+                    let silenced = if trailing { line } else { line + 1 };
+                    self.suppressions.add_comment(self.source_id, silenced, text);
+                    self.comments.push(Comment {
+                        line,
+                        text: text.trim_end().to_string(),
+                        trailing,
+                        block: self.open_blocks.last().copied().unwrap_or(0),
+                    });
+                    let text = self.source[self.start..self.current].to_string();
+                    self.trivia.push(Trivia::Comment(text));
+                    self.line_has_content = true;
                 } else {
                     self.add_token(TokenType::Slash, None);
                 }
                 Ok(())
             }
-            ' ' | '\r' | '\t' => Ok(()),
+            ' ' | '\r' | '\t' => {
+                self.add_whitespace();
+                Ok(())
+            }
             '\n' => {
+                self.add_whitespace();
+                if !self.line_has_content {
+                    self.blank_lines.push(self.line as u32);
+                }
+                self.line_has_content = false;
                 self.line += 1;
+                self.line_start = self.current;
                 Ok(())
             }
             '"' => {
                 self.string()
             }
-            'o' => {
-                if self.matches('r') {
-                    self.add_token(TokenType::Or, None);
-                }
-                Ok(())
-            }
-            '%' => {
-                self.add_token(TokenType::Percent, None);
-                Ok(())
-            }
 
             _ => {
                 if c.is_ascii_digit() {
@@ -193,8 +379,12 @@ impl Scanner {
                 } else if c.is_ascii_alphabetic() || c == '_' {
                     self.identifier()
                 } else {
+                    // Skip the rest of a multi-byte character so scanning
+                    // can carry on at a character boundary.
+                    let c = self.source[self.start..].chars().next().unwrap_or(c);
+                    self.current = self.start + c.len_utf8();
                     let message = format!("Unexpected character '{}'", c);
-                    Err(std::io::Error::new(std::io::ErrorKind::Other, message))
+                    Err(self.error("L0002", message))
                 }
             }
         }
@@ -208,8 +398,28 @@ impl Scanner {
 
     fn add_token(&mut self, token_type: TokenType, literal: Option<Literal>) {
         let lexeme = &self.source[self.start..self.current];
-        let token = Token::new(token_type, lexeme.to_string(), literal, self.line as u32);
-        self.tokens.push(token);
+        let mut token = Token::new(token_type, Symbol::intern(lexeme), literal, self.line as u32);
+        token.column = (self.start - self.line_start.min(self.start)) as u32;
+        token.source = self.source_id;
+        token.leading = self.take_trivia();
+        self.last_token_line = Some(token.line);
+        self.pending = Some(token);
+        self.line_has_content = true;
+    }
+
+    fn add_whitespace(&mut self) {
+        let text = &self.source[self.start..self.current];
+        match self.trivia.last_mut() {
+            Some(Trivia::Whitespace(whitespace)) => whitespace.push_str(text),
+            _ => self.trivia.push(Trivia::Whitespace(text.to_string())),
+        }
+    }
+
+    fn take_trivia(&mut self) -> Option<Rc<[Trivia]>> {
+        if self.trivia.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.trivia).into())
     }
 
     fn current_char(&self) -> char {
@@ -237,25 +447,28 @@ impl Scanner {
         self.source.char_at(self.current + 1)
     }
 
-    fn string(&mut self) -> Result<(), std::io::Error> {
+    fn string(&mut self) -> Result<(), LoxError> {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' { self.line += 1; }
+            if self.peek() == '\n' {
+                self.line += 1;
+                self.line_start = self.current + 1;
+            }
             self.advance();
         }
 
         if self.is_at_end() {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Unterminated string."))
+            return Err(self.error("L0001", "Unterminated string.".to_string()))
         }
 
         self.advance();
 
         let value = self.source.substring(self.start + 1, self.current - 1);
-        let literal = Literal::String(value.to_string());
+        let literal = Literal::from(value);
         self.add_token(TokenType::String, Some(literal));
         Ok(())
     }
 
-    fn number(&mut self) -> Result<(), std::io::Error> {
+    fn number(&mut self) -> Result<(), LoxError> {
         while self.peek().is_ascii_digit() { self.advance(); }
 
         if self.peek() == '.' && self.peek_next().is_ascii_digit() {
@@ -264,21 +477,56 @@ impl Scanner {
             while self.peek().is_ascii_digit() { self.advance(); }
         }
         let value = &self.source.substring(self.start, self.current);
-        let n: f64 = value.parse::<f64>().unwrap();
-        let literal = Literal::Number(n);
+        // Literals without a fraction are integers unless they're too big
+        // to fit one.
+        let literal = match value.parse::<i64>() {
+            Ok(i) => Literal::Int(i),
+            Err(_) => match value.parse::<f64>() {
+                Ok(n) => Literal::Number(n),
+                Err(_) => return Err(self.error("L0003", "Invalid number.".to_string())),
+            },
+        };
         self.add_token(TokenType::Number, Some(literal));
         Ok(())
     }
 
-    fn identifier(&mut self) -> Result<(), std::io::Error> {
-        while self.peek().is_ascii_alphanumeric() { self.advance(); }
+    fn identifier(&mut self) -> Result<(), LoxError> {
+        while self.peek().is_ascii_alphanumeric() || self.peek() == '_' { self.advance(); }
         let text = self.source.substring(self.start, self.current);
-        match self.keywords.get(text) {
-            Some(token_type) =>  {
-                self.add_token(*token_type, None)
+        let token_type = match self.keywords.get(text) {
+            Some(token_type) => *token_type,
+            None => keyword(text),
+        };
+        self.add_token(token_type.unwrap_or(TokenType::Identifier), None);
+        Ok(())
+    }
+}
+
+impl Iterator for Scanner {
+    type Item = Result<Token, LoxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.is_at_end() {
+            self.start = self.current;
+            if let Err(error) = self.scan_token() {
+                return Some(Err(error));
+            }
+            if let Some(token) = self.pending.take() {
+                return Some(Ok(token));
             }
-            _ => self.add_token(TokenType::Identifier, None)
         }
-        Ok(())
+        if self.done {
+            return None;
+        }
+        self.done = true;
+        Some(Ok(Token {
+            token_type: TokenType::Eof,
+            lexeme: Symbol::default(),
+            literal: None,
+            line: self.line as u32,
+            column: self.column(),
+            leading: self.take_trivia(),
+            source: self.source_id,
+        }))
     }
 }
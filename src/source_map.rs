@@ -0,0 +1,34 @@
+/// Which file a token came from, as registered in a [`SourceMap`]. The
+/// default is the main script or REPL, whose errors keep the plain
+/// `[line N]` form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SourceId(u32);
+
+impl SourceId {
+    pub const MAIN: SourceId = SourceId(0);
+}
+
+/// The files other than the main script that an interpreter has loaded,
+/// so errors from them can name the file.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    names: Vec<String>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a file by the name errors should show for it.
+    pub fn add(&mut self, name: &str) -> SourceId {
+        self.names.push(name.to_string());
+        SourceId(self.names.len() as u32)
+    }
+
+    /// The name `id` was registered with, or `None` for the main script.
+    pub fn name(&self, id: SourceId) -> Option<&str> {
+        let index = (id.0 as usize).checked_sub(1)?;
+        self.names.get(index).map(String::as_str)
+    }
+}
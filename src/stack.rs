@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::ParserError;
+use crate::token::Token;
+
+/// How much native stack a parse or a run may use before it stops with an
+/// error, unless [`set_budget`] says otherwise. It suits the 2 MiB stacks
+/// threads get by default.
+pub const DEFAULT_BUDGET: usize = 1024 * 1024;
+
+/// The error the parser, and every other walk over the syntax tree, gives
+/// once nesting has used up the budget.
+pub const TOO_DEEP: &str = "Too much nesting.";
+
+/// The [`TOO_DEEP`] error, under `code`, for a walk that ran out of stack
+/// at a node: at the node's token, or on its line for nodes that keep
+/// none.
+pub fn too_deep(code: &'static str, token: Option<&Token>, line: Option<u32>) -> ParserError {
+    let token = token.cloned().unwrap_or_else(|| Token {
+        line: line.unwrap_or_default(),
+        ..Token::default()
+    });
+    ParserError::new(code, token, TOO_DEEP.to_string())
+}
+
+static BUDGET: AtomicUsize = AtomicUsize::new(DEFAULT_BUDGET);
+
+/// Sets the stack budget for parses and runs started afterwards, for hosts
+/// that run Lox on a thread with a bigger stack.
+pub fn set_budget(bytes: usize) {
+    BUDGET.store(bytes, Ordering::Relaxed);
+}
+
+/// The budget [`set_budget`] last set.
+pub fn budget() -> usize {
+    BUDGET.load(Ordering::Relaxed)
+}
+
+/// Notices when recursion over deeply nested code has used up the stack
+/// budget, so it can fail with an error instead of overflowing the stack.
+#[derive(Debug, Clone, Copy)]
+pub struct StackGuard {
+    base: usize,
+    budget: usize,
+}
+
+impl Default for StackGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StackGuard {
+    /// Measures from the caller's frame.
+    pub fn new() -> Self {
+        Self {
+            base: stack_address(),
+            budget: budget(),
+        }
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.base.abs_diff(stack_address()) > self.budget
+    }
+}
+
+/// A [`StackGuard`] for the walks that print a program: they leave out
+/// what's nested too deeply and carry on, then fail with where the budget
+/// first ran out.
+#[derive(Debug, Default)]
+pub struct DepthLimit {
+    stack: StackGuard,
+    too_deep: RefCell<Option<ParserError>>,
+}
+
+impl DepthLimit {
+    pub fn new(stack: StackGuard) -> Self {
+        Self {
+            stack,
+            too_deep: RefCell::default(),
+        }
+    }
+
+    /// Whether the budget has run out, noting where if it's the first
+    /// time: at `token`, or on `line` for nodes that keep no token.
+    pub fn exhausted(&self, token: Option<&Token>, line: impl FnOnce() -> Option<u32>) -> bool {
+        if !self.stack.exhausted() {
+            return false;
+        }
+        self.too_deep.borrow_mut().get_or_insert_with(|| too_deep("P0006", token, line()));
+        true
+    }
+
+    /// `output`, or the error for where the budget first ran out.
+    pub fn finish<T>(&self, output: T) -> Result<T, ParserError> {
+        match self.too_deep.take() {
+            Some(err) => Err(err),
+            None => Ok(output),
+        }
+    }
+}
+
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
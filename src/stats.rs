@@ -0,0 +1,21 @@
+use std::fmt;
+
+/// Counters the tree-walker keeps while it runs, reported by `--stats`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub statements: u64,
+    pub expressions: u64,
+    pub calls: u64,
+    pub environments: u64,
+    pub peak_call_depth: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "statements executed:     {}", self.statements)?;
+        writeln!(f, "expressions evaluated:   {}", self.expressions)?;
+        writeln!(f, "function calls:          {}", self.calls)?;
+        writeln!(f, "environments allocated:  {}", self.environments)?;
+        write!(f, "peak call depth:         {}", self.peak_call_depth)
+    }
+}
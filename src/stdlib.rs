@@ -0,0 +1,694 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+#[cfg(feature = "stdlib-fs")]
+use std::fs;
+#[cfg(feature = "stdlib-exec")]
+use std::process::Command;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::callable::BoundFunction;
+use crate::datetime::DateTime;
+use crate::error::RuntimeException;
+use crate::interpreter::{self, Interpreter};
+use crate::iterator;
+use crate::native_function::NativeFunction;
+use crate::token::{format_number, Literal, Token};
+
+type Native = fn(&mut Interpreter, &[Literal]) -> Result<Literal, RuntimeException>;
+
+/// A group of natives that reach outside the interpreter. Each has a
+/// Cargo feature to compile it in, and [`Interpreter::restrict`] can leave
+/// it out at run time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// `exec()`, behind `stdlib-exec`.
+    Exec,
+    /// The `io` module, `absPath()`, and importing modules with
+    /// `import()` or an `import` statement, behind `stdlib-fs`.
+    Fs,
+    /// `httpGet()` and `httpPost()`, behind `stdlib-net`.
+    Net,
+    /// `clock()` and `now()`, behind `stdlib-time`.
+    Time,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 4] = [Capability::Exec, Capability::Fs, Capability::Net, Capability::Time];
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        match name {
+            "exec" => Some(Capability::Exec),
+            "fs" => Some(Capability::Fs),
+            "net" => Some(Capability::Net),
+            "time" => Some(Capability::Time),
+            _ => None,
+        }
+    }
+
+    /// Whether the capability's feature was compiled in.
+    pub fn is_compiled_in(self) -> bool {
+        match self {
+            Capability::Exec => cfg!(feature = "stdlib-exec"),
+            Capability::Fs => cfg!(feature = "stdlib-fs"),
+            Capability::Net => cfg!(feature = "stdlib-net"),
+            Capability::Time => cfg!(feature = "stdlib-time"),
+        }
+    }
+
+    /// The globals that give scripts this capability.
+    pub fn globals(self) -> &'static [&'static str] {
+        match self {
+            Capability::Exec => &["exec"],
+            Capability::Fs => &["io", "absPath", "import"],
+            Capability::Net => &["httpGet", "httpPost"],
+            Capability::Time => &["clock", "now"],
+        }
+    }
+}
+
+/// The standard library's modules, each a map of its members, defined as
+/// globals so natives are reached as `math.sqrt` rather than crowding the
+/// names scripts use.
+pub fn modules() -> Vec<(&'static str, Literal)> {
+    vec![
+        (
+            "math",
+            module(
+                "math",
+                &[
+                    ("abs", 1, abs),
+                    ("ceil", 1, ceil),
+                    ("floor", 1, floor),
+                    ("pow", 2, pow),
+                    ("random", 0, random),
+                    ("random", 1, random_below),
+                    ("sqrt", 1, sqrt),
+                    ("toFixed", 2, to_fixed),
+                    ("toPrecision", 2, to_precision),
+                ],
+                &[("pi", Literal::Number(std::f64::consts::PI))],
+            ),
+        ),
+        (
+            "bytes",
+            module("bytes", &[("decode", 2, decode), ("encode", 2, encode), ("len", 1, bytes_len)], &[]),
+        ),
+        (
+            "str",
+            module(
+                "str",
+                &[("len", 1, len), ("lower", 1, lower), ("split", 2, split), ("trim", 1, trim), ("upper", 1, upper)],
+                &[],
+            ),
+        ),
+        #[cfg(feature = "stdlib-fs")]
+        (
+            "io",
+            module(
+                "io",
+                &[
+                    ("isDir", 1, is_dir),
+                    ("isFile", 1, is_file),
+                    ("listDir", 1, list_dir),
+                    ("mkdir", 1, mkdir),
+                    ("readFile", 1, read_file),
+                    ("readFileBytes", 1, read_file_bytes),
+                    ("remove", 1, remove),
+                    ("writeFile", 2, write_file),
+                    ("writeFileBytes", 2, write_file_bytes),
+                ],
+                &[],
+            ),
+        ),
+    ]
+}
+
+/// Capability natives that aren't part of a module.
+pub fn capability_globals() -> Vec<(&'static str, Literal)> {
+    vec![
+        #[cfg(feature = "stdlib-fs")]
+        ("import", Literal::NativeFunction(NativeFunction::new("import", 1, import))),
+        #[cfg(feature = "stdlib-fs")]
+        ("absPath", Literal::NativeFunction(NativeFunction::new("absPath", 1, abs_path))),
+        #[cfg(feature = "stdlib-exec")]
+        ("exec", Literal::NativeFunction(NativeFunction::new("exec", 1, exec))),
+        #[cfg(feature = "stdlib-net")]
+        ("httpGet", Literal::NativeFunction(NativeFunction::new("httpGet", 1, http_get))),
+        #[cfg(feature = "stdlib-net")]
+        ("httpPost", Literal::NativeFunction(NativeFunction::new("httpPost", 2, http_post))),
+        #[cfg(feature = "stdlib-time")]
+        ("clock", Literal::NativeFunction(NativeFunction::new("clock", 0, crate::native_function::clock))),
+        #[cfg(feature = "stdlib-time")]
+        ("now", Literal::NativeFunction(NativeFunction::new("now", 0, now))),
+    ]
+}
+
+/// Natives that aren't part of a module and need no capability.
+pub fn globals() -> Vec<(&'static str, Literal)> {
+    vec![
+        ("formatTime", Literal::NativeFunction(NativeFunction::new("formatTime", 2, format_time))),
+        ("parseTime", Literal::NativeFunction(NativeFunction::new("parseTime", 2, parse_time))),
+        ("lines", Literal::NativeFunction(NativeFunction::new("lines", 0, lines))),
+        ("readAll", Literal::NativeFunction(NativeFunction::new("readAll", 0, read_all))),
+        ("bind", Literal::NativeFunction(NativeFunction::variadic("bind", 1, bind))),
+        ("curry", Literal::NativeFunction(NativeFunction::new("curry", 1, curry))),
+        ("memoize", Literal::NativeFunction(NativeFunction::new("memoize", 1, memoize))),
+        ("pathJoin", Literal::NativeFunction(NativeFunction::new("pathJoin", 2, path_join))),
+        ("dirname", Literal::NativeFunction(NativeFunction::new("dirname", 1, dirname))),
+        ("basename", Literal::NativeFunction(NativeFunction::new("basename", 1, basename))),
+        ("extension", Literal::NativeFunction(NativeFunction::new("extension", 1, extension))),
+    ]
+}
+
+/// A method on a primitive value: its name, the native it calls with the
+/// value as the first argument, and that native's arity.
+type Method = (&'static str, &'static str, u8, Native);
+
+const STRING_METHODS: [Method; 7] = [
+    ("encode", "bytes.encode", 2, encode),
+    ("len", "str.len", 1, len),
+    ("length", "str.len", 1, len),
+    ("lower", "str.lower", 1, lower),
+    ("split", "str.split", 2, split),
+    ("trim", "str.trim", 1, trim),
+    ("upper", "str.upper", 1, upper),
+];
+
+const NUMBER_METHODS: [Method; 7] = [
+    ("abs", "math.abs", 1, abs),
+    ("ceil", "math.ceil", 1, ceil),
+    ("floor", "math.floor", 1, floor),
+    ("pow", "math.pow", 2, pow),
+    ("sqrt", "math.sqrt", 1, sqrt),
+    ("toFixed", "math.toFixed", 2, to_fixed),
+    ("toPrecision", "math.toPrecision", 2, to_precision),
+];
+
+const BYTES_METHODS: [Method; 3] = [
+    ("decode", "bytes.decode", 2, decode),
+    ("len", "bytes.len", 1, bytes_len),
+    ("length", "bytes.len", 1, bytes_len),
+];
+
+/// Whether values like `receiver` have methods, so a missing one is an
+/// undefined property rather than a value without properties.
+pub fn has_methods(receiver: &Literal) -> bool {
+    !methods(receiver).is_empty()
+}
+
+fn methods(receiver: &Literal) -> &'static [Method] {
+    match receiver {
+        Literal::String(_) => &STRING_METHODS,
+        Literal::Int(_) | Literal::Number(_) => &NUMBER_METHODS,
+        Literal::Bytes(_) => &BYTES_METHODS,
+        _ => &[],
+    }
+}
+
+/// `receiver.name` for a string, number or bytes: the module native the
+/// method stands for, with `receiver` bound as its first argument, so
+/// `"a".upper()` is `str.upper("a")`.
+pub fn method(receiver: &Literal, name: &str) -> Option<NativeFunction> {
+    let &(_, native, arity, callable) = methods(receiver).iter().find(|(method, ..)| *method == name)?;
+    let receiver = receiver.clone();
+    Some(NativeFunction::new(native, arity - 1, move |interpreter, args| {
+        callable(interpreter, &[std::slice::from_ref(&receiver), args].concat())
+    }))
+}
+
+/// A module's map of members. A native listed twice with different
+/// arities is one native overloaded on how many arguments it's given.
+fn module(name: &str, natives: &[(&str, u8, Native)], values: &[(&str, Literal)]) -> Literal {
+    let mut members: BTreeMap<Rc<str>, Literal> = values
+        .iter()
+        .map(|(member, value)| (Rc::from(*member), value.clone()))
+        .collect();
+    for &(member, arity, callable) in natives {
+        let native = NativeFunction::new(&format!("{}.{}", name, member), arity, callable);
+        let native = match members.remove(member) {
+            Some(Literal::NativeFunction(first)) => first.overload(native),
+            _ => native,
+        };
+        members.insert(Rc::from(member), Literal::NativeFunction(native));
+    }
+    Literal::Map(Rc::new(members.into()))
+}
+
+fn error(code: &'static str, message: String) -> RuntimeException {
+    RuntimeException::base(code, Token::default(), message)
+}
+
+fn number(args: &[Literal], index: usize, native: &str) -> Result<f64, RuntimeException> {
+    args[index]
+        .as_f64()
+        .ok_or_else(|| error("E0018", format!("{} expects a number.", native)))
+}
+
+fn string<'a>(args: &'a [Literal], index: usize, native: &str) -> Result<&'a str, RuntimeException> {
+    match &args[index] {
+        Literal::String(s) => Ok(s),
+        _ => Err(error("E0018", format!("{} expects a string.", native))),
+    }
+}
+
+fn bytes<'a>(args: &'a [Literal], index: usize, native: &str) -> Result<&'a [u8], RuntimeException> {
+    match &args[index] {
+        Literal::Bytes(bytes) => Ok(bytes),
+        _ => Err(error("E0018", format!("{} expects bytes.", native))),
+    }
+}
+
+fn abs(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    match args[0] {
+        Literal::Int(i) => i.checked_abs().map(Literal::Int).ok_or_else(|| error("E0010", "Integer overflow.".to_string())),
+        _ => Ok(Literal::Number(number(args, 0, "math.abs")?.abs())),
+    }
+}
+
+fn ceil(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Number(number(args, 0, "math.ceil")?.ceil()))
+}
+
+fn floor(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Number(number(args, 0, "math.floor")?.floor()))
+}
+
+thread_local! {
+    /// The state of `math.random`'s xorshift generator, seeded differently
+    /// each run.
+    static RANDOM_STATE: std::cell::Cell<u64> = std::cell::Cell::new({
+        use std::hash::{BuildHasher, Hasher};
+        std::collections::hash_map::RandomState::new().build_hasher().finish() | 1
+    });
+}
+
+fn next_random() -> u64 {
+    RANDOM_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    })
+}
+
+/// `math.random()`: a number from 0 up to but not including 1.
+fn random(_interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Number((next_random() >> 11) as f64 / (1u64 << 53) as f64))
+}
+
+/// `math.random(max)`: an integer from 0 up to but not including `max`.
+fn random_below(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    match args[0] {
+        Literal::Int(max) if max > 0 => Ok(Literal::Int((next_random() % max as u64) as i64)),
+        _ => Err(error("E0018", "math.random expects a positive integer.".to_string())),
+    }
+}
+
+fn pow(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let base = number(args, 0, "math.pow")?;
+    Ok(Literal::Number(base.powf(number(args, 1, "math.pow")?)))
+}
+
+fn sqrt(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Number(number(args, 0, "math.sqrt")?.sqrt()))
+}
+
+/// `math.toFixed(n, digits)`, `n` as a string with exactly `digits`
+/// digits after the point. A value exactly halfway rounds away from
+/// zero, as JavaScript's does, where Rust's formatting would round it to
+/// even.
+fn to_fixed(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let n = number(args, 0, "math.toFixed")?;
+    let digits = digits(args, 0, "math.toFixed")?;
+    if !n.is_finite() {
+        return Ok(Literal::from(format_number(n)));
+    }
+    // Every finite number's decimal expansion ends within 1074 places.
+    let exact = format!("{:.1074}", n);
+    let dropped = &exact[exact.find('.').map_or(exact.len(), |point| point + 1 + digits)..];
+    let halfway = dropped.strip_prefix('5').is_some_and(|rest| rest.bytes().all(|digit| digit == b'0'));
+    let n = match halfway {
+        true if n > 0.0 => n.next_up(),
+        true => n.next_down(),
+        false => n,
+    };
+    Ok(Literal::from(format!("{:.*}", digits, n)))
+}
+
+/// `math.toPrecision(n, digits)`, `n` as a string rounded to `digits`
+/// significant digits, with an exponent when plain digits couldn't show
+/// it that way.
+fn to_precision(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let n = number(args, 0, "math.toPrecision")?;
+    let digits = digits(args, 1, "math.toPrecision")?;
+    if !n.is_finite() {
+        return Ok(Literal::from(format_number(n)));
+    }
+    // The exponent after rounding, which may be one more than before.
+    let scientific = format!("{:.*e}", digits - 1, n);
+    let exponent: i64 = scientific[scientific.find('e').unwrap_or(0) + 1..].parse().unwrap_or(0);
+    if exponent < -6 || exponent >= digits as i64 {
+        return Ok(Literal::from(scientific));
+    }
+    Ok(Literal::from(format!("{:.*}", (digits as i64 - 1 - exponent) as usize, n)))
+}
+
+/// A count of digits from 1 to 100, or from 0 for `toFixed`.
+fn digits(args: &[Literal], min: usize, native: &str) -> Result<usize, RuntimeException> {
+    match &args[1] {
+        Literal::Int(digits) if (min as i64..=100).contains(digits) => Ok(*digits as usize),
+        _ => Err(error("E0018", format!("{} expects a whole number of digits from {} to 100.", native, min))),
+    }
+}
+
+fn len(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Int(string(args, 0, "str.len")?.chars().count() as i64))
+}
+
+fn lower(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::from(string(args, 0, "str.lower")?.to_lowercase()))
+}
+
+fn trim(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::from(string(args, 0, "str.trim")?.trim()))
+}
+
+/// `str.split(text, separator)`, an iterator over the pieces of `text`
+/// between each `separator`.
+fn split(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let text = string(args, 0, "str.split")?;
+    let separator = string(args, 1, "str.split")?;
+    if separator.is_empty() {
+        return Err(error("E0018", "str.split expects a non-empty separator.".to_string()));
+    }
+    Ok(iterator::from_values(text.split(separator).map(Literal::from).collect()))
+}
+
+fn upper(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::from(string(args, 0, "str.upper")?.to_uppercase()))
+}
+
+fn bytes_len(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Int(bytes(args, 0, "bytes.len")?.len() as i64))
+}
+
+/// `bytes.encode(text, encoding)`, for "utf-8", "latin-1" or "ascii".
+fn encode(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let text = string(args, 0, "bytes.encode")?;
+    let limit = match string(args, 1, "bytes.encode")? {
+        "utf-8" => return Ok(Literal::Bytes(Rc::from(text.as_bytes()))),
+        "latin-1" => 0xff,
+        "ascii" => 0x7f,
+        encoding => return Err(error("E0020", format!("Unknown encoding '{}'.", encoding))),
+    };
+    text.chars()
+        .map(|c| u8::try_from(c).ok().filter(|&byte| byte <= limit))
+        .collect::<Option<Vec<u8>>>()
+        .map(|bytes| Literal::Bytes(Rc::from(bytes)))
+        .ok_or_else(|| error("E0020", format!("'{}' can't be encoded as {}.", text, args[1])))
+}
+
+/// `bytes.decode(data, encoding)`, the reverse of `bytes.encode`.
+fn decode(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let data = bytes(args, 0, "bytes.decode")?;
+    let invalid = |encoding: &str| error("E0020", format!("The bytes aren't valid {}.", encoding));
+    match string(args, 1, "bytes.decode")? {
+        "utf-8" => std::str::from_utf8(data).map(Literal::from).map_err(|_| invalid("utf-8")),
+        "latin-1" => Ok(Literal::from(data.iter().map(|&byte| byte as char).collect::<String>())),
+        "ascii" if data.is_ascii() => Ok(Literal::from(data.iter().map(|&byte| byte as char).collect::<String>())),
+        "ascii" => Err(invalid("ascii")),
+        encoding => Err(error("E0020", format!("Unknown encoding '{}'.", encoding))),
+    }
+}
+
+#[cfg(feature = "stdlib-fs")]
+fn read_file(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let path = string(args, 0, "io.readFile")?;
+    fs::read_to_string(path)
+        .map(Literal::from)
+        .map_err(|err| error("E0019", format!("Could not read {}: {}.", path, err)))
+}
+
+#[cfg(feature = "stdlib-fs")]
+fn write_file(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let path = string(args, 0, "io.writeFile")?;
+    fs::write(path, string(args, 1, "io.writeFile")?)
+        .map(|_| Literal::Nil)
+        .map_err(|err| error("E0019", format!("Could not write {}: {}.", path, err)))
+}
+
+/// `io.listDir(path)`, an iterator over the names in a directory, sorted.
+#[cfg(feature = "stdlib-fs")]
+fn list_dir(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let path = string(args, 0, "io.listDir")?;
+    let failed = |err: std::io::Error| error("E0019", format!("Could not list {}: {}.", path, err));
+    let mut names = fs::read_dir(path)
+        .map_err(failed)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(failed)?;
+    names.sort();
+    Ok(iterator::from_values(names.into_iter().map(Literal::from).collect()))
+}
+
+/// `io.mkdir(path)` makes a directory and any missing parents.
+#[cfg(feature = "stdlib-fs")]
+fn mkdir(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let path = string(args, 0, "io.mkdir")?;
+    fs::create_dir_all(path)
+        .map(|_| Literal::Nil)
+        .map_err(|err| error("E0019", format!("Could not make {}: {}.", path, err)))
+}
+
+/// `io.remove(path)` deletes a file, or a directory with everything in it.
+#[cfg(feature = "stdlib-fs")]
+fn remove(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let path = string(args, 0, "io.remove")?;
+    let removed = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        _ => fs::remove_file(path),
+    };
+    removed
+        .map(|_| Literal::Nil)
+        .map_err(|err| error("E0019", format!("Could not remove {}: {}.", path, err)))
+}
+
+#[cfg(feature = "stdlib-fs")]
+fn is_dir(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::from(Path::new(string(args, 0, "io.isDir")?).is_dir()))
+}
+
+#[cfg(feature = "stdlib-fs")]
+fn is_file(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::from(Path::new(string(args, 0, "io.isFile")?).is_file()))
+}
+
+#[cfg(feature = "stdlib-fs")]
+fn read_file_bytes(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let path = string(args, 0, "io.readFileBytes")?;
+    fs::read(path)
+        .map(|bytes| Literal::Bytes(Rc::from(bytes)))
+        .map_err(|err| error("E0019", format!("Could not read {}: {}.", path, err)))
+}
+
+#[cfg(feature = "stdlib-fs")]
+fn write_file_bytes(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let path = string(args, 0, "io.writeFileBytes")?;
+    fs::write(path, bytes(args, 1, "io.writeFileBytes")?)
+        .map(|_| Literal::Nil)
+        .map_err(|err| error("E0019", format!("Could not write {}: {}.", path, err)))
+}
+
+/// A response as a `{ status, body }` map. The response may only be as
+/// big as the memory the script has left.
+#[cfg(feature = "stdlib-net")]
+fn http(interpreter: &Interpreter, method: &str, url: &str, body: Option<&str>) -> Result<Literal, RuntimeException> {
+    let response = crate::http::request(method, url, body, interpreter.memory_left())
+        .map_err(|err| error("E0019", format!("Could not {} {}: {}.", method, url.escape_debug(), err)))?;
+    let members = BTreeMap::from([
+        (Rc::from("status"), Literal::Int(response.status as i64)),
+        (Rc::from("body"), Literal::from(response.body.as_str())),
+    ]);
+    Ok(Literal::Map(Rc::new(members.into())))
+}
+
+#[cfg(feature = "stdlib-net")]
+fn http_get(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    http(interpreter, "GET", string(args, 0, "httpGet")?, None)
+}
+
+#[cfg(feature = "stdlib-net")]
+fn http_post(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    http(interpreter, "POST", string(args, 0, "httpPost")?, Some(string(args, 1, "httpPost")?))
+}
+
+/// `exec(command)` runs `command` through the system shell and waits for
+/// it, giving `{ status, stdout, stderr }`. The status is `nil` when a
+/// signal ended the command.
+#[cfg(feature = "stdlib-exec")]
+fn exec(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let command = string(args, 0, "exec")?;
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let output = Command::new(shell)
+        .args([flag, command])
+        .output()
+        .map_err(|err| error("E0019", format!("Could not run {}: {}.", command, err)))?;
+    let members = BTreeMap::from([
+        (Rc::from("status"), output.status.code().map_or(Literal::Nil, |code| Literal::Int(code as i64))),
+        (Rc::from("stdout"), Literal::from(&*String::from_utf8_lossy(&output.stdout))),
+        (Rc::from("stderr"), Literal::from(&*String::from_utf8_lossy(&output.stderr))),
+    ]);
+    Ok(Literal::Map(Rc::new(members.into())))
+}
+
+/// `now()` as a map of the UTC calendar fields.
+#[cfg(feature = "stdlib-time")]
+fn now(interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let now = DateTime::from_millis(interpreter.unix_millis());
+    let members: BTreeMap<_, _> = [
+        ("year", now.year),
+        ("month", now.month as i64),
+        ("day", now.day as i64),
+        ("hour", now.hour as i64),
+        ("minute", now.minute as i64),
+        ("second", now.second as i64),
+    ]
+    .into_iter()
+    .map(|(name, value)| (Rc::from(name), Literal::Int(value)))
+    .collect();
+    Ok(Literal::Map(Rc::new(members.into())))
+}
+
+/// `formatTime(ms, format)` writes a `clock()` timestamp out in UTC.
+fn format_time(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let time = DateTime::from_millis(number(args, 0, "formatTime")?);
+    time.format(string(args, 1, "formatTime")?).map(Literal::from).map_err(|message| error("E0021", message))
+}
+
+/// `parseTime(text, format)` reads a UTC time back into a timestamp.
+fn parse_time(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let time = DateTime::parse(string(args, 0, "parseTime")?, string(args, 1, "parseTime")?)
+        .map_err(|message| error("E0021", message))?;
+    Ok(Literal::Number(time.to_millis()))
+}
+
+/// A path as a string, or `nil` for a part the path doesn't have.
+fn path_literal(path: Option<&OsStr>) -> Literal {
+    path.map_or(Literal::Nil, |path| Literal::from(path.to_string_lossy().as_ref()))
+}
+
+/// `bind(fn, a, b, ...)`, `fn` with its first arguments fixed as `a`,
+/// `b` and so on.
+fn bind(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let (arity, _) = interpreter::arity(&args[0]).ok_or_else(|| error("E0018", "bind expects a function.".to_string()))?;
+    let fixed = &args[1..];
+    // Any call with at least the fixed arguments will do.
+    if !(fixed.len()..=u8::MAX as usize).any(|given| interpreter::accepts(&args[0], given)) {
+        return Err(error("E0005", format!("Can't bind {} arguments to a function taking {}.", fixed.len(), arity)));
+    }
+    Ok(bound(args[0].clone(), fixed, false))
+}
+
+/// `curry(fn)`, `fn` taking its arguments a few at a time: each call with
+/// fewer than the rest returns a function waiting for the others.
+fn curry(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    interpreter::arity(&args[0]).ok_or_else(|| error("E0018", "curry expects a function.".to_string()))?;
+    Ok(bound(args[0].clone(), &[], true))
+}
+
+/// `memoize(fn)`, `fn` remembering what it returned for each list of
+/// arguments so it's only called once for each. Arguments are told apart
+/// as `==` does, so maps are remembered by identity.
+fn memoize(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let function = args[0].clone();
+    let (arity, variadic) = interpreter::arity(&function).ok_or_else(|| error("E0018", "memoize expects a function.".to_string()))?;
+    let cache: RefCell<HashMap<Vec<Literal>, Literal>> = RefCell::default();
+    let memoized = move |interpreter: &mut Interpreter, args: &[Literal]| {
+        if let Some(result) = cache.borrow().get(args) {
+            return Ok(result.clone());
+        }
+        let result = interpreter.call_value(&function, args.to_vec(), &Token::default())?;
+        cache.borrow_mut().insert(args.to_vec(), result.clone());
+        Ok(result)
+    };
+    let mut memoized = NativeFunction { variadic, ..NativeFunction::new("memoize", arity as u8, memoized) };
+    // Memoizing an overloaded native keeps its overloads.
+    if let Literal::NativeFunction(native) = &args[0] {
+        for overload in native.arities().skip(1) {
+            let (arity, variadic) = (overload.arity, overload.variadic);
+            let other = NativeFunction { arity, variadic, overloads: None, ..memoized.clone() };
+            memoized = memoized.overload(other);
+        }
+    }
+    Ok(Literal::NativeFunction(memoized))
+}
+
+fn bound(function: Literal, args: &[Literal], curried: bool) -> Literal {
+    Literal::Bound(Rc::new(BoundFunction { function, args: Rc::from(args), curried }))
+}
+
+/// `pathJoin(base, path)` puts `path` under `base` with the platform's
+/// separator. An absolute `path` replaces `base`.
+fn path_join(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let path = Path::new(string(args, 0, "pathJoin")?).join(string(args, 1, "pathJoin")?);
+    Ok(path_literal(Some(path.as_os_str())))
+}
+
+/// `dirname(path)`, the path without its last part, or `nil` for a root
+/// or a bare file name.
+fn dirname(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let parent = Path::new(string(args, 0, "dirname")?).parent();
+    Ok(path_literal(parent.filter(|parent| !parent.as_os_str().is_empty()).map(Path::as_os_str)))
+}
+
+/// `basename(path)`, the last part of the path.
+fn basename(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(path_literal(Path::new(string(args, 0, "basename")?).file_name()))
+}
+
+/// `extension(path)`, what follows the last `.` in the file name, without
+/// the dot.
+fn extension(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(path_literal(Path::new(string(args, 0, "extension")?).extension()))
+}
+
+/// `absPath(path)` resolves `path` against the working directory. It
+/// doesn't touch the file, so the path needn't exist.
+#[cfg(feature = "stdlib-fs")]
+fn abs_path(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let path = string(args, 0, "absPath")?;
+    std::path::absolute(path)
+        .map(|path| path_literal(Some(path.as_os_str())))
+        .map_err(|err| error("E0019", format!("Could not resolve {}: {}.", path, err)))
+}
+
+fn read_error(err: std::io::Error) -> RuntimeException {
+    error("E0019", format!("Could not read input: {}.", err))
+}
+
+/// `lines()`, an iterator over the lines of input, read as they're asked
+/// for.
+fn lines(_interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(iterator::lazy(|interpreter| {
+        interpreter
+            .read_line()
+            .map(|line| line.map(Literal::from))
+            .map_err(read_error)
+    }))
+}
+
+/// `readAll()`, the rest of the input as one string.
+fn read_all(interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    interpreter.read_all().map(Literal::from).map_err(read_error)
+}
+
+/// `import(name)`, the top-level names of the module `name` finds.
+#[cfg(feature = "stdlib-fs")]
+fn import(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let name = string(args, 0, "import")?;
+    interpreter.import_module(name)
+}
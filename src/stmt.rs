@@ -1,15 +1,132 @@
-use crate::expr::Expr;
-use crate::token::Token;
+use std::rc::Rc;
+
+use crate::expr::{Expr, ExprId};
+use crate::symbol::Symbol;
+use crate::token::{Literal, Token};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Stmt {
-    Block(Vec<Stmt>),
+    Block(Rc<[Stmt]>),
     Expression(Expr),
-    Function(Token, Vec<Token>, Box<Vec<Stmt>>),
-    Print(Expr),
-    Return(Token, Box<Option<Expr>>),
-    If(Expr, Box<Stmt>, Box<Option<Stmt>>),
-    While(Expr, Box<Stmt>),
-    Var(Token, Option<Expr>),
-    Break(Token),
+    /// A named function, with its doc comment if it has one. The ID tells
+    /// the declaration apart for what the resolver finds it captures.
+    Function(Token, ExprId, Rc<[Token]>, Rc<[Stmt]>, Option<Rc<Signature>>, Option<Rc<str>>),
+    /// `print a, b;`, which prints its values on one line, separated as
+    /// the interpreter was told to.
+    Print(Rc<[Expr]>),
+    Return(Token, Option<Expr>),
+    /// Each condition with the keyword starting its arm and the branch it
+    /// guards, tried in order, and the branch for when none holds. An
+    /// `else if` chain is one `If`.
+    If(Rc<[(Token, Expr, Stmt)]>, Option<Rc<Stmt>>),
+    While(Token, Expr, Rc<Stmt>),
+    For(Token, Option<Rc<Stmt>>, Option<Expr>, Option<Expr>, Rc<Stmt>),
+    /// `for (var name in iterable) body`, run once for each value the
+    /// iterable's `next` function gives.
+    ForIn(Token, Expr, Rc<Stmt>),
+    /// A variable, its declared type if any, and its initializer.
+    Var(Token, Option<Token>, Option<Expr>),
+    /// A `while` or `for` loop with a label, which `break` and `continue`
+    /// in nested loops can name.
+    Labeled(Token, Rc<Stmt>),
+    /// The keyword and the label of the loop to leave, if one was named.
+    Break(Token, Option<Token>),
+    Continue(Token, Option<Token>),
+    /// `with (var name = initializer) body`, which calls the value's
+    /// `close` function however the body is left.
+    With(Token, Token, Expr, Rc<Stmt>),
+    /// An `enum` and the names of its cases.
+    Enum(Token, Rc<[Token]>),
+    /// `import { names } from "path";`, which defines each name as the
+    /// module's member of that name.
+    Import(Token, Rc<[Token]>, Rc<str>),
+    /// A declaration a module offers its importers. A module that exports
+    /// nothing offers all its top-level names.
+    Export(Rc<Stmt>),
+}
+
+/// The types written on a function's parameters and result, for
+/// `--check`. Each is `None` where left out.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Signature {
+    pub params: Rc<[Option<Token>]>,
+    pub returns: Option<Token>,
+}
+
+/// The label of a [`Stmt::Labeled`], held from when it's reached until
+/// the loop it was written on starts and takes it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PendingLabel(Option<Symbol>);
+
+impl PendingLabel {
+    pub fn set(&mut self, label: &Token) {
+        self.0 = Some(label.lexeme);
+    }
+
+    pub fn take(&mut self) -> Option<Symbol> {
+        self.0.take()
+    }
+}
+
+impl Stmt {
+    /// The first source line the statement's tokens mention, if any.
+    pub fn line(&self) -> Option<u32> {
+        match self {
+            Stmt::Block(stmts) => stmts.iter().find_map(Stmt::line),
+            Stmt::Expression(expr) => expr.line(),
+            Stmt::Print(values) => values.iter().find_map(Expr::line),
+            Stmt::Function(name, ..)
+            | Stmt::Var(name, _, _)
+            | Stmt::Enum(name, _)
+            | Stmt::ForIn(name, _, _) => Some(name.line),
+            Stmt::Return(keyword, _)
+            | Stmt::Break(keyword, _)
+            | Stmt::Continue(keyword, _)
+            | Stmt::With(keyword, ..)
+            | Stmt::Import(keyword, ..)
+            | Stmt::While(keyword, ..)
+            | Stmt::For(keyword, ..) => {
+                Some(keyword.line)
+            }
+            Stmt::Labeled(label, _) => Some(label.line),
+            Stmt::Export(declaration) => declaration.line(),
+            Stmt::If(arms, _) => arms.first().map(|(keyword, ..)| keyword.line),
+        }
+    }
+
+    /// The names the statement declares in the scope it's in.
+    pub fn declared(&self) -> Vec<&Token> {
+        match self {
+            Stmt::Var(name, ..) | Stmt::Function(name, ..) | Stmt::Enum(name, _) => vec![name],
+            Stmt::Import(_, names, _) => names.iter().collect(),
+            Stmt::Export(declaration) => declaration.declared(),
+            _ => vec![],
+        }
+    }
+
+    /// Rewrites a `for` loop into the block and `while` it stands for. The
+    /// expression nodes are shared with the loop, so scope distances the
+    /// resolver recorded for one apply to the other.
+    pub fn lower_for(
+        keyword: &Token,
+        initializer: &Option<Rc<Stmt>>,
+        condition: &Option<Expr>,
+        increment: &Option<Expr>,
+        body: &Rc<Stmt>,
+    ) -> Stmt {
+        let body = match increment {
+            Some(increment) => Stmt::Block(
+                vec![(**body).clone(), Stmt::Expression(increment.clone())].into(),
+            ),
+            None => (**body).clone(),
+        };
+        let condition = condition
+            .clone()
+            .unwrap_or(Expr::Literal(Literal::True));
+        let body = Stmt::While(keyword.clone(), condition, Rc::new(body));
+        match initializer {
+            Some(initializer) => Stmt::Block(vec![(**initializer).clone(), body].into()),
+            None => body,
+        }
+    }
 }
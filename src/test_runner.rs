@@ -0,0 +1,262 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What a test script says it should do, read from comments in the
+/// Crafting Interpreters test format:
+///
+/// - `// expect: value` for each line the script prints,
+/// - `// expect runtime error: message` for an error that stops it,
+/// - `// Error at 'x': message` for a compile error on that line, or
+///   `// [line N] Error ...` for one reported on another line,
+/// - `// Warning at 'x': message` for a warning on that line,
+/// - `// stdin: text` for a line of input to feed the script,
+/// - `// stderr: text` for a line it writes to stderr besides errors, as
+///   reports like `--stats` do,
+/// - `// flags: --option ...` for options to run it with, like
+///   `--keywords=elif` for syntax that's off by default.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Expectations {
+    pub output: Vec<String>,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    pub stderr: Vec<String>,
+    pub runtime_error: Option<String>,
+    pub input: Vec<String>,
+    pub flags: Vec<String>,
+}
+
+impl Expectations {
+    pub fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+        for (index, line) in source.lines().enumerate() {
+            let Some(comment) = line.find("// ").map(|start| &line[start + 3..]) else {
+                continue;
+            };
+            if let Some(value) = comment.strip_prefix("expect: ") {
+                expectations.output.push(value.to_string());
+            } else if let Some(flags) = comment.strip_prefix("flags: ") {
+                expectations.flags.extend(flags.split_whitespace().map(str::to_string));
+            } else if let Some(text) = comment.strip_prefix("stdin: ") {
+                expectations.input.push(format!("{}\n", text));
+            } else if let Some(text) = comment.strip_prefix("stderr: ") {
+                expectations.stderr.push(text.to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expectations.runtime_error = Some(message.to_string());
+            } else if comment.starts_with("Error") {
+                expectations.errors.push(format!("[line {}] {}", index + 1, comment));
+            } else if comment.starts_with("Warning") {
+                expectations.warnings.push(format!("[line {}] {}", index + 1, comment));
+            } else if comment.starts_with("[line ") {
+                expectations.errors.push(comment.to_string());
+            }
+        }
+        expectations
+    }
+
+    /// The status the interpreter should exit with.
+    pub fn exit_code(&self) -> i32 {
+        if !self.errors.is_empty() {
+            65
+        } else if self.runtime_error.is_some() {
+            70
+        } else {
+            0
+        }
+    }
+
+    /// Compares a run against the expectations, describing each mismatch.
+    pub fn check(&self, stdout: &str, stderr: &str, exit_code: Option<i32>) -> Vec<String> {
+        let mut failures = vec![];
+        let output: Vec<&str> = stdout.lines().collect();
+        for (i, expected) in self.output.iter().enumerate() {
+            match output.get(i) {
+                Some(actual) if actual == expected => (),
+                Some(actual) => {
+                    failures.push(format!("Expected output '{}', got '{}'.", expected, actual))
+                }
+                None => failures.push(format!("Missing expected output '{}'.", expected)),
+            }
+        }
+        for extra in output.iter().skip(self.output.len()) {
+            failures.push(format!("Unexpected output '{}'.", extra));
+        }
+
+        let errors: Vec<&str> = stderr.lines().collect();
+        if let Some(expected) = &self.runtime_error {
+            match errors.first() {
+                Some(actual) if actual == expected => (),
+                Some(actual) => failures.push(format!(
+                    "Expected runtime error '{}', got '{}'.",
+                    expected, actual
+                )),
+                None => failures.push(format!("Missing runtime error '{}'.", expected)),
+            }
+        } else {
+            for expected in &self.errors {
+                if !errors.contains(&expected.as_str()) {
+                    failures.push(format!("Missing expected error '{}'.", expected));
+                }
+            }
+            for expected in &self.warnings {
+                if !errors.contains(&expected.as_str()) {
+                    failures.push(format!("Missing expected warning '{}'.", expected));
+                }
+            }
+            for expected in &self.stderr {
+                if !errors.contains(&expected.as_str()) {
+                    failures.push(format!("Missing expected stderr '{}'.", expected));
+                }
+            }
+            for actual in &errors {
+                let mut expected = self.errors.iter().chain(&self.warnings).chain(&self.stderr);
+                if !expected.any(|expected| expected == actual) {
+                    failures.push(format!("Unexpected error '{}'.", actual));
+                }
+            }
+        }
+
+        if exit_code != Some(self.exit_code()) {
+            failures.push(format!(
+                "Expected exit code {}, got {}.",
+                self.exit_code(),
+                exit_code.map_or("none".to_string(), |code| code.to_string())
+            ));
+        }
+        failures
+    }
+}
+
+/// The directories `rlox test` passes over, holding modules the tests
+/// import rather than tests of their own.
+pub const FIXTURES: &str = "fixtures";
+
+/// Runs every `*.lox` file under `path`, outside [`FIXTURES`] directories, in test mode with the interpreter
+/// at `program`, passing it `options`, and prints each failing script or
+/// `test()` block and a summary. Returns whether everything passed.
+///
+/// With `--coverage`, each script's coverage summary is printed too, and
+/// `--coverage=out.lcov` gathers every script's record into that file.
+pub fn run_tests(program: &Path, options: &[String], path: &Path) -> io::Result<bool> {
+    let mut scripts = vec![];
+    walk(path, &mut scripts, &|dir| dir.file_name().is_some_and(|name| name == FIXTURES))?;
+    scripts.sort();
+
+    let lcov_path = options.iter().find_map(|option| option.strip_prefix("--coverage="));
+    let part_path = lcov_path.map(|lcov_path| format!("{}.part", lcov_path));
+    let options: Vec<String> = options
+        .iter()
+        .map(|option| match (&part_path, option.starts_with("--coverage=")) {
+            (Some(part_path), true) => format!("--coverage={}", part_path),
+            _ => option.clone(),
+        })
+        .collect();
+    let mut lcov = String::new();
+
+    let (mut passed, mut failed) = (0, 0);
+    let (mut blocks_passed, mut blocks_failed) = (0, 0);
+    for script in &scripts {
+        let expectations = Expectations::parse(&fs::read_to_string(script)?);
+        // A user's ~/.loxrc mustn't change what the scripts do, though a
+        // --prelude given explicitly still applies.
+        let mut child = Command::new(program)
+            .arg("--no-prelude")
+            .args(&options)
+            .args(&expectations.flags)
+            .arg("--test")
+            .arg(script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // Dropping stdin closes it, so the script sees the end of input.
+        if let Some(mut stdin) = child.stdin.take() {
+            // A script that exits without reading breaks the pipe.
+            let _ = stdin.write_all(expectations.input.concat().as_bytes());
+        }
+        let output = child.wait_with_output()?;
+
+        if let Some(part_path) = &part_path {
+            if let Ok(record) = fs::read_to_string(part_path) {
+                lcov.push_str(&record);
+                fs::remove_file(part_path)?;
+            }
+        }
+
+        // Test blocks and coverage are reported on stderr, apart from
+        // errors.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let (reports, errors): (Vec<&str>, Vec<&str>) = stderr
+            .lines()
+            .partition(|line| line.starts_with("test: ") || line.starts_with("coverage: "));
+        for report in &reports {
+            if let Some(coverage) = report.strip_prefix("coverage: ") {
+                println!("{}: {}", script.display(), coverage);
+            }
+        }
+        let mut failures = vec![];
+        for report in reports {
+            if report.starts_with("test: ok ") {
+                blocks_passed += 1;
+            } else if let Some(failure) = report.strip_prefix("test: FAIL ") {
+                blocks_failed += 1;
+                failures.push(failure.to_string());
+            }
+        }
+        // A failed block is what makes the script exit with 1.
+        let exit_code = match output.status.code() {
+            Some(1) if !failures.is_empty() => Some(0),
+            code => code,
+        };
+        failures.extend(expectations.check(
+            &String::from_utf8_lossy(&output.stdout),
+            &errors.join("\n"),
+            exit_code,
+        ));
+
+        if failures.is_empty() {
+            passed += 1;
+        } else {
+            failed += 1;
+            println!("FAIL {}", script.display());
+            for failure in failures {
+                println!("     {}", failure);
+            }
+        }
+    }
+    print!("{} passed, {} failed", passed, failed);
+    if blocks_passed + blocks_failed > 0 {
+        print!(" ({} test blocks, {} failed)", blocks_passed + blocks_failed, blocks_failed);
+    }
+    println!();
+    if let Some(lcov_path) = lcov_path {
+        fs::write(lcov_path, lcov)?;
+    }
+    Ok(failed == 0)
+}
+
+/// Every `*.lox` file under `path`, or `path` itself if it's a file.
+pub fn collect_scripts(path: &Path, scripts: &mut Vec<PathBuf>) -> io::Result<()> {
+    walk(path, scripts, &|_| false)
+}
+
+/// Like [`collect_scripts`], leaving out the directories below `path`
+/// that `skip` picks.
+fn walk(path: &Path, scripts: &mut Vec<PathBuf>, skip: &dyn Fn(&Path) -> bool) -> io::Result<()> {
+    if path.is_file() {
+        scripts.push(path.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if !skip(&path) {
+                walk(&path, scripts, skip)?;
+            }
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            scripts.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::ast_json::{program_from_json, program_to_json};
+use crate::callable::Callable;
+use crate::error::RuntimeException;
+use crate::interpreter::Interpreter;
+use crate::json::Json;
+use crate::native_function::NativeFunction;
+use crate::stack;
+use crate::stdlib::Capability;
+use crate::token::{Literal, Token};
+
+/// A copy of a value that can cross to another thread. Only plain data
+/// can: functions close over environments that belong to one interpreter.
+#[derive(Debug, Clone)]
+pub enum Message {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Number(f64),
+    String(String),
+    Map(Vec<(String, Message)>),
+    Record(Vec<(String, Message)>),
+    Bytes(Vec<u8>),
+    Channel(Arc<Channel>),
+}
+
+impl Message {
+    pub fn from_literal(value: &Literal) -> Result<Message, RuntimeException> {
+        let fields = |members: &BTreeMap<Rc<str>, Literal>| {
+            members
+                .iter()
+                .map(|(name, value)| Ok((name.to_string(), Message::from_literal(value)?)))
+                .collect::<Result<Vec<_>, RuntimeException>>()
+        };
+        Ok(match value {
+            Literal::Nil => Message::Nil,
+            Literal::True => Message::Bool(true),
+            Literal::False => Message::Bool(false),
+            Literal::Int(i) => Message::Int(*i),
+            Literal::Number(n) => Message::Number(*n),
+            Literal::String(s) => Message::String(s.to_string()),
+            Literal::Map(members) => Message::Map(fields(members)?),
+            Literal::Record(members) => Message::Record(fields(members)?),
+            Literal::Bytes(bytes) => Message::Bytes(bytes.to_vec()),
+            Literal::Channel(channel) => Message::Channel(channel.clone()),
+            Literal::NativeFunction(_)
+            | Literal::LoxFunction(_)
+            | Literal::EnumCase(_)
+            | Literal::Bound(_)
+            | Literal::Thread(_) => {
+                return Err(error(
+                    "E0022",
+                    "Only numbers, strings, booleans, nil, bytes, channels, maps and records can be sent between threads.",
+                ))
+            }
+        })
+    }
+
+    pub fn into_literal(self) -> Literal {
+        let fields = |members: Vec<(String, Message)>| {
+            Rc::new(
+                members
+                    .into_iter()
+                    .map(|(name, value)| (Rc::from(name), value.into_literal()))
+                    .collect(),
+            )
+        };
+        match self {
+            Message::Nil => Literal::Nil,
+            Message::Bool(b) => Literal::from(b),
+            Message::Int(i) => Literal::Int(i),
+            Message::Number(n) => Literal::Number(n),
+            Message::String(s) => Literal::from(s.as_str()),
+            Message::Map(members) => Literal::Map(fields(members)),
+            Message::Record(members) => Literal::Record(fields(members)),
+            Message::Bytes(bytes) => Literal::Bytes(Rc::from(bytes)),
+            Message::Channel(channel) => Literal::Channel(channel),
+        }
+    }
+}
+
+/// An unbounded queue of messages that any thread can send to or wait on.
+/// Scripts hold channels as values, which can be sent to other threads
+/// like data; a channel is freed with the last value referring to it.
+#[derive(Debug, Default)]
+pub struct Channel {
+    queue: Mutex<VecDeque<Message>>,
+    ready: Condvar,
+}
+
+impl Channel {
+    fn queue(&self) -> Result<MutexGuard<'_, VecDeque<Message>>, RuntimeException> {
+        self.queue.lock().map_err(|_| poisoned())
+    }
+}
+
+/// A spawned thread, until it's joined. Unlike channels, threads stay
+/// with the interpreter that spawned them.
+#[derive(Debug)]
+pub struct Thread(RefCell<Option<JoinHandle<Result<Message, String>>>>);
+
+/// The threading natives, defined as globals.
+pub fn natives() -> Vec<(&'static str, Literal)> {
+    vec![
+        ("spawn", Literal::NativeFunction(NativeFunction::new("spawn", 2, spawn))),
+        ("join", Literal::NativeFunction(NativeFunction::new("join", 1, join))),
+        ("channel", Literal::NativeFunction(NativeFunction::new("channel", 0, channel))),
+        ("send", Literal::NativeFunction(NativeFunction::new("send", 2, send))),
+        (
+            "receive",
+            Literal::NativeFunction(
+                NativeFunction::new("receive", 1, receive).overload(NativeFunction::new("receive", 2, receive_within)),
+            ),
+        ),
+    ]
+}
+
+fn error(code: &'static str, message: &str) -> RuntimeException {
+    RuntimeException::base(code, Token::default(), message.to_string())
+}
+
+fn poisoned() -> RuntimeException {
+    error("E0022", "A thread failed while holding a channel.")
+}
+
+/// `spawn(fn, argument)` calls `fn` with a copy of `argument` on a new
+/// thread with its own interpreter. The function sees none of the
+/// spawning script's variables, and its interpreter has the same
+/// capabilities as this one and default settings otherwise.
+fn spawn(interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let Literal::LoxFunction(function) = &args[0] else {
+        return Err(error("E0018", "spawn expects a function."));
+    };
+    if !function.accepts(1) {
+        return Err(error("E0018", "spawn expects a function taking one argument."));
+    }
+    let declaration = program_to_json(&[function.declaration()]).map_err(|err| error(err.code.code, &err.message))?;
+    let name = function.name.to_string();
+    let argument = Message::from_literal(&args[1])?;
+    let allowed: Vec<Capability> = Capability::ALL
+        .into_iter()
+        .filter(|capability| interpreter.allows(*capability))
+        .collect();
+
+    let thread = thread::Builder::new()
+        .stack_size(stack::budget() + 16 * 1024 * 1024)
+        .spawn(move || run_spawned(&declaration, &name, argument, &allowed))
+        .map_err(|err| error("E0022", &format!("Could not spawn a thread: {}.", err)))?;
+    Ok(Literal::Thread(Rc::new(Thread(RefCell::new(Some(thread))))))
+}
+
+fn run_spawned(declaration: &Json, name: &str, argument: Message, allowed: &[Capability]) -> Result<Message, String> {
+    let mut interpreter = Interpreter::default();
+    interpreter.restrict(allowed);
+    // The function was checked against the spawning script's globals,
+    // which aren't defined here.
+    interpreter.set_late_binding(true);
+    let program = program_from_json(declaration)?;
+    interpreter.run_statements(program).map_err(|err| err.to_string())?;
+    let function = interpreter
+        .globals()
+        .into_iter()
+        .find_map(|(global, value)| match value {
+            Literal::LoxFunction(function) if global == name => Some(function),
+            _ => None,
+        })
+        .ok_or("The spawned function could not be defined.")?;
+    let result = interpreter
+        .call(function.as_ref(), &[argument.into_literal()])
+        .and_then(|result| Message::from_literal(&result));
+    result.map_err(|err| match err {
+        RuntimeException::Base(err) if err.token.line > 0 => format!("[line {}] {}", err.token.line, err.message),
+        RuntimeException::Base(err) => err.message.into(),
+        _ => "Unexpected break or continue.".to_string(),
+    })
+}
+
+/// Waits for a spawned thread and gives back a copy of what its function
+/// returned.
+fn join(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let Literal::Thread(thread) = &args[0] else {
+        return Err(error("E0018", "join expects a thread."));
+    };
+    let Some(thread) = thread.0.borrow_mut().take() else {
+        return Err(error("E0022", "That thread has already been joined."));
+    };
+    match thread.join() {
+        Ok(Ok(result)) => Ok(result.into_literal()),
+        Ok(Err(message)) => Err(error("E0022", &format!("Spawned thread failed: {}", message))),
+        Err(_) => Err(error("E0022", "Spawned thread panicked.")),
+    }
+}
+
+fn channel(_interpreter: &mut Interpreter, _args: &[Literal]) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Channel(Arc::default()))
+}
+
+fn find_channel<'a>(value: &'a Literal, native: &str) -> Result<&'a Channel, RuntimeException> {
+    match value {
+        Literal::Channel(channel) => Ok(channel),
+        _ => Err(error("E0018", &format!("{} expects a channel.", native))),
+    }
+}
+
+/// Queues a copy of a value on a channel without waiting.
+fn send(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let channel = find_channel(&args[0], "send")?;
+    let message = Message::from_literal(&args[1])?;
+    channel.queue()?.push_back(message);
+    channel.ready.notify_one();
+    Ok(Literal::Nil)
+}
+
+/// Takes the oldest value from a channel, waiting for one to be sent if
+/// there is none. Nothing notices when no thread is left to send, so this
+/// waits forever then; `receive(channel, seconds)` gives up instead.
+fn receive(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let channel = find_channel(&args[0], "receive")?;
+    let mut queue = channel.queue()?;
+    loop {
+        if let Some(message) = queue.pop_front() {
+            return Ok(message.into_literal());
+        }
+        queue = channel.ready.wait(queue).map_err(|_| poisoned())?;
+    }
+}
+
+/// `receive(channel, seconds)` waits at most `seconds` for a value, giving
+/// nil if none was sent in time.
+fn receive_within(_interpreter: &mut Interpreter, args: &[Literal]) -> Result<Literal, RuntimeException> {
+    let channel = find_channel(&args[0], "receive")?;
+    let Some(timeout) = args[1].as_f64().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()) else {
+        return Err(error("E0018", "receive expects a number of seconds that isn't negative."));
+    };
+    let queue = channel.queue()?;
+    let (mut queue, _) = channel
+        .ready
+        .wait_timeout_while(queue, timeout, |queue| queue.is_empty())
+        .map_err(|_| poisoned())?;
+    Ok(queue.pop_front().map_or(Literal::Nil, Message::into_literal))
+}
@@ -1,8 +1,17 @@
 use std::fmt;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::hash::Hash;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
 
+use crate::callable::BoundFunction;
 use crate::native_function::NativeFunction;
 use crate::lox_function::LoxFunction;
+use crate::source_map::SourceId;
+use crate::symbol::Symbol;
+use crate::threads::{Channel, Thread};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum TokenType {
@@ -11,6 +20,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -54,9 +66,42 @@ pub enum TokenType {
     Var,
     While,
 
+    // Extension keywords, reserved only when enabled
+    Const,
+    Continue,
+    Elif,
+    Enum,
+    Match,
+    Unless,
+    With,
+
     Eof,
 }
 
+impl TokenType {
+    pub const ALL: [TokenType; 51] = [
+        TokenType::LeftParen, TokenType::RightParen, TokenType::LeftBrace, TokenType::RightBrace,
+        TokenType::LeftBracket, TokenType::RightBracket,
+        TokenType::Colon, TokenType::Comma, TokenType::Dot, TokenType::Minus, TokenType::Plus, TokenType::Semicolon,
+        TokenType::Slash, TokenType::Star, TokenType::Percent, TokenType::Bang, TokenType::BangEqual,
+        TokenType::Equal, TokenType::EqualEqual, TokenType::Greater, TokenType::GreaterEqual,
+        TokenType::Less, TokenType::LessEqual, TokenType::Identifier, TokenType::String,
+        TokenType::Number, TokenType::And, TokenType::Break, TokenType::Class, TokenType::Else,
+        TokenType::False, TokenType::Fun, TokenType::For, TokenType::If, TokenType::Nil,
+        TokenType::Or, TokenType::Print, TokenType::Return, TokenType::Super, TokenType::This,
+        TokenType::True, TokenType::Var, TokenType::While, TokenType::Const, TokenType::Continue,
+        TokenType::Elif, TokenType::Enum, TokenType::Match, TokenType::Unless, TokenType::With,
+        TokenType::Eof,
+    ];
+
+    /// Looks a token type up by the name `Display` prints for it.
+    pub fn from_name(name: &str) -> Option<TokenType> {
+        TokenType::ALL
+            .into_iter()
+            .find(|token_type| token_type.to_string() == name)
+    }
+}
+
 impl fmt::Display for TokenType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -65,23 +110,176 @@ impl fmt::Display for TokenType {
 
 #[derive(Debug, Clone)]
 pub enum Literal {
+    Int(i64),
     Number(f64),
-    String(String),
+    String(Rc<str>),
     True,
     False,
     Nil,
     NativeFunction(NativeFunction),
-    LoxFunction(LoxFunction)
+    /// Shared so that copying a function value stays cheap.
+    LoxFunction(Rc<LoxFunction>),
+    /// A read-only map from names to values, like the one `globals()`
+    /// returns. Maps are equal only to themselves.
+    Map(Rc<Members>),
+    /// A `{ x: 1, y: 2 }` value. Unlike maps, records are equal when their
+    /// fields are.
+    Record(Rc<Members>),
+    /// A case of an `enum`, equal only to itself.
+    EnumCase(Rc<EnumCase>),
+    /// Binary data, like a file read with `io.readFileBytes`. Equal when
+    /// the contents are.
+    Bytes(Rc<[u8]>),
+    /// A function with some of its arguments already given, equal only to
+    /// itself.
+    Bound(Rc<BoundFunction>),
+    /// A queue between threads, from `channel()`, equal only to itself.
+    Channel(Arc<Channel>),
+    /// A thread from `spawn()`, equal only to itself.
+    Thread(Rc<Thread>),
+}
+
+/// The fields of a map or record. Reads go straight through to the map;
+/// `freeze` makes a frozen copy, after which assigning a field is an error.
+#[derive(Debug, Clone, Default)]
+pub struct Members {
+    fields: BTreeMap<Rc<str>, Literal>,
+    frozen: bool,
+}
+
+impl Members {
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// A copy of these members with them, and every map and record among
+    /// them, frozen.
+    pub fn frozen(&self) -> Members {
+        let fields = self.fields.iter().map(|(name, value)| (name.clone(), value.frozen())).collect();
+        Members { fields, frozen: true }
+    }
+
+    /// A thawed copy of the fields, to build a changed value from.
+    pub fn to_map(&self) -> BTreeMap<Rc<str>, Literal> {
+        self.fields.clone()
+    }
+}
+
+/// Whether a value is frozen doesn't change what it's equal to.
+impl PartialEq for Members {
+    fn eq(&self, other: &Self) -> bool {
+        self.fields == other.fields
+    }
+}
+
+impl Deref for Members {
+    type Target = BTreeMap<Rc<str>, Literal>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.fields
+    }
+}
+
+impl From<BTreeMap<Rc<str>, Literal>> for Members {
+    fn from(fields: BTreeMap<Rc<str>, Literal>) -> Self {
+        Members { fields, frozen: false }
+    }
+}
+
+impl FromIterator<(Rc<str>, Literal)> for Members {
+    fn from_iter<I: IntoIterator<Item = (Rc<str>, Literal)>>(iter: I) -> Self {
+        Members::from(iter.into_iter().collect::<BTreeMap<_, _>>())
+    }
+}
+
+/// One of the constants an `enum` declaration defines.
+#[derive(Debug)]
+pub struct EnumCase {
+    pub enum_name: Symbol,
+    pub name: Symbol,
+}
+
+/// The bit pattern numbers are compared and hashed by, so that `Eq` and
+/// `Hash` agree: `-0.0` folds into `0.0` and every NaN into one canonical
+/// NaN.
+fn number_bits(n: f64) -> u64 {
+    if n == 0.0 {
+        0.0f64.to_bits()
+    } else if n.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        n.to_bits()
+    }
+}
+
+/// How numbers print: the shortest digits that read back as the same
+/// float, switching to an exponent for the very large and very small
+/// rather than writing out every zero.
+pub fn format_number(n: f64) -> String {
+    if n != 0.0 && n.is_finite() && !(1e-6..1e21).contains(&n.abs()) {
+        format!("{:e}", n)
+    } else {
+        n.to_string()
+    }
+}
+
+/// The integer a float is exactly equal to, if there is one.
+fn exact_int(n: f64) -> Option<i64> {
+    let in_range = (-9_223_372_036_854_775_808.0..9_223_372_036_854_775_808.0).contains(&n);
+    (n.fract() == 0.0 && in_range).then_some(n as i64)
+}
+
+impl Literal {
+    /// This value with any map or record in it frozen; see `Members::frozen`.
+    pub fn frozen(&self) -> Literal {
+        match self {
+            Literal::Map(members) => Literal::Map(Rc::new(members.frozen())),
+            Literal::Record(members) => Literal::Record(Rc::new(members.frozen())),
+            other => other.clone(),
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Literal::Int(_) | Literal::Number(_))
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Literal::Int(i) => Some(*i as f64),
+            Literal::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Orders two numbers, comparing integers exactly and promoting to
+    /// floats otherwise.
+    pub fn compare_numbers(&self, other: &Literal) -> Option<Ordering> {
+        match (self, other) {
+            (Literal::Int(a), Literal::Int(b)) => Some(a.cmp(b)),
+            _ => self.as_f64()?.partial_cmp(&other.as_f64()?),
+        }
+    }
 }
 
 impl PartialEq for Literal {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Literal::Nil, Literal::Nil) | (Literal::True, Literal::True) | (Literal::False, Literal::False) => true,
-            (Literal::Number(a), Literal::Number(b)) => (*a as i64) == (*b as i64),
+            (Literal::Int(a), Literal::Int(b)) => a == b,
+            (Literal::Number(a), Literal::Number(b)) => number_bits(*a) == number_bits(*b),
+            (Literal::Int(a), Literal::Number(b)) | (Literal::Number(b), Literal::Int(a)) => {
+                exact_int(*b) == Some(*a)
+            }
             (Literal::String(a), Literal::String(b)) => a == b,
             (Literal::LoxFunction(f1), Literal::LoxFunction(f2)) => f1 == f2,
             (Literal::NativeFunction(f1), Literal::NativeFunction(f2)) => f1 == f2,
+            (Literal::Map(m1), Literal::Map(m2)) => Rc::ptr_eq(m1, m2),
+            (Literal::Record(r1), Literal::Record(r2)) => r1 == r2,
+            (Literal::EnumCase(c1), Literal::EnumCase(c2)) => Rc::ptr_eq(c1, c2),
+            (Literal::Bytes(b1), Literal::Bytes(b2)) => b1 == b2,
+            (Literal::Bound(b1), Literal::Bound(b2)) => Rc::ptr_eq(b1, b2),
+            (Literal::Channel(c1), Literal::Channel(c2)) => Arc::ptr_eq(c1, c2),
+            (Literal::Thread(t1), Literal::Thread(t2)) => Rc::ptr_eq(t1, t2),
             _ => false
         }
     }
@@ -91,12 +289,29 @@ impl Eq for Literal {}
 
 impl Hash for Literal {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // Integral floats hash like the equal `Int` so the two stay
+        // interchangeable as keys.
         match self {
-            Literal::Number(f) => {
-                let i = *f as i64;
-                i.hash(state);
+            Literal::Int(i) => i.hash(state),
+            Literal::Number(n) => match exact_int(*n) {
+                Some(i) => i.hash(state),
+                None => number_bits(*n).hash(state),
             },
-            _ => self.hash(state)
+            _ => std::mem::discriminant(self).hash(state),
+        }
+        match self {
+            Literal::Int(_) | Literal::Number(_) => (),
+            Literal::String(s) => s.hash(state),
+            Literal::NativeFunction(f) => f.id().hash(state),
+            Literal::LoxFunction(f) => f.id.hash(state),
+            Literal::Map(m) => Rc::as_ptr(m).hash(state),
+            Literal::Record(r) => r.iter().for_each(|field| field.hash(state)),
+            Literal::EnumCase(c) => Rc::as_ptr(c).hash(state),
+            Literal::Bytes(b) => b.hash(state),
+            Literal::Bound(b) => Rc::as_ptr(b).hash(state),
+            Literal::Channel(c) => Arc::as_ptr(c).hash(state),
+            Literal::Thread(t) => Rc::as_ptr(t).hash(state),
+            Literal::True | Literal::False | Literal::Nil => (),
         }
     }
 }
@@ -111,6 +326,12 @@ impl From<bool> for Literal {
     }
 }
 
+impl From<i64> for Literal {
+    fn from(v: i64) -> Self {
+        Self::Int(v)
+    }
+}
+
 impl From<f64> for Literal {
     fn from(v: f64) -> Self {
         Self::Number(v)
@@ -119,65 +340,153 @@ impl From<f64> for Literal {
 
 impl From<String> for Literal {
     fn from(v: String) -> Self {
-        Self::String(v)
+        Self::String(Rc::from(v))
     }
 }
 
-impl ToString for Literal {
-    fn to_string(&self) -> String {
+impl From<&str> for Literal {
+    fn from(v: &str) -> Self {
+        Self::String(Rc::from(v))
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Literal::Nil => "nil".to_string(),
-            Literal::True => "true".to_string(),
-            Literal::False => "false".to_string(),
-            Literal::String(s) => s.to_string(),
-            Literal::Number(n) => n.to_string(),
-            Literal::NativeFunction(_) => "<native fn>".to_string(),
-            Literal::LoxFunction(f) => format!("<fn {}>", f.name)
+            Literal::Nil => write!(f, "nil"),
+            Literal::True => write!(f, "true"),
+            Literal::False => write!(f, "false"),
+            Literal::String(s) => write!(f, "{}", s),
+            Literal::Int(i) => write!(f, "{}", i),
+            Literal::Number(n) => write!(f, "{}", format_number(*n)),
+            Literal::NativeFunction(_) => write!(f, "<native fn>"),
+            Literal::LoxFunction(function) => write!(f, "<fn {}>", function.name),
+            Literal::Map(map) | Literal::Record(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Literal::EnumCase(case) => write!(f, "{}.{}", case.enum_name, case.name),
+            Literal::Bytes(bytes) => write!(f, "<{} bytes>", bytes.len()),
+            Literal::Bound(bound) => write!(f, "<bound {}>", bound.function),
+            Literal::Channel(_) => write!(f, "<channel>"),
+            Literal::Thread(_) => write!(f, "<thread>"),
         }
     }
 }
 
+/// Source text between tokens that the grammar ignores.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Trivia {
+    /// Spaces, tabs and newlines, so blank lines show up as more than one
+    /// newline.
+    Whitespace(String),
+    /// A `//` comment up to the end of its line.
+    Comment(String),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Option<Literal>,
     pub line: u32,
+    /// Byte offset of the token within its line, counting from 0. Only the
+    /// scanner fills this in.
+    pub column: u32,
+    /// The trivia since the previous token, which for `Eof` is whatever
+    /// ends the file. Only the scanner fills this in.
+    pub leading: Option<Rc<[Trivia]>>,
+    /// The file the token was scanned from.
+    pub source: SourceId,
 }
 
 impl Default for Token {
     fn default() -> Self {
         Self {
             token_type: TokenType::Nil,
-            lexeme: "".to_string(),
+            lexeme: Symbol::default(),
             literal: None,
-            line: 0
+            line: 0,
+            column: 0,
+            leading: None,
+            source: SourceId::MAIN,
         }
     }
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, literal: Option<Literal>, line: u32) -> Self {
+    pub fn new(token_type: TokenType, lexeme: Symbol, literal: Option<Literal>, line: u32) -> Self {
         Self {
             token_type,
             lexeme,
             literal,
             line,
+            column: 0,
+            leading: None,
+            source: SourceId::MAIN,
         }
     }
 
-    pub fn from_str(lexeme: &str) -> Self {
-        Self::from_string(lexeme.to_string())
-    }
-
     pub fn from_string(lexeme: String) -> Self {
         Self {
             token_type: TokenType::Nil,
-            lexeme,
+            lexeme: Symbol::intern(&lexeme),
             literal: None,
-            line: 0
+            line: 0,
+            column: 0,
+            leading: None,
+            source: SourceId::MAIN,
+        }
+    }
+
+    /// The text of the `///` comments just before this token, without the
+    /// slashes, or `None` if there aren't any. A blank line or an ordinary
+    /// comment between them and the token ends the run.
+    pub fn doc_comment(&self) -> Option<Rc<str>> {
+        let mut lines: Vec<&str> = vec![];
+        for trivia in self.leading.iter().flat_map(|leading| leading.iter()) {
+            match trivia {
+                Trivia::Comment(text) if text.starts_with("///") && !text.starts_with("////") => {
+                    let line = &text[3..];
+                    lines.push(line.strip_prefix(' ').unwrap_or(line).trim_end());
+                }
+                Trivia::Comment(_) => lines.clear(),
+                Trivia::Whitespace(space) if space.matches('\n').count() > 1 => lines.clear(),
+                Trivia::Whitespace(_) => (),
+            }
+        }
+        (!lines.is_empty()).then(|| Rc::from(lines.join("\n")))
+    }
+
+    /// The comments just before this token.
+    pub fn leading_comments(&self) -> impl Iterator<Item = &str> {
+        self.leading.iter().flat_map(|leading| leading.iter()).filter_map(|trivia| match trivia {
+            Trivia::Comment(text) => Some(text.as_str()),
+            Trivia::Whitespace(_) => None,
+        })
+    }
+}
+
+/// The source the tokens were scanned from, put back together from their
+/// lexemes and trivia. Exact for sources that scanned without errors.
+pub fn source_text(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    for token in tokens {
+        for trivia in token.leading.iter().flat_map(|leading| leading.iter()) {
+            match trivia {
+                Trivia::Whitespace(whitespace) => text.push_str(whitespace),
+                Trivia::Comment(comment) => text.push_str(comment),
+            }
         }
+        text.push_str(&token.lexeme.to_string());
     }
+    text
 }
 
 impl fmt::Display for Token {
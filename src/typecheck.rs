@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::LoxError;
+use crate::error_codes;
+use crate::expr::Expr;
+use crate::stmt::{Signature, Stmt};
+use crate::symbol::Symbol;
+use crate::token::{Literal, Token, TokenType};
+
+/// The types an annotation can name. `Any` matches every value; it's what
+/// unannotated variables have, and what an expression gets when the
+/// checker can't tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Any,
+    Bool,
+    Function,
+    Nil,
+    Number,
+    Record,
+    String,
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "any" => Some(Type::Any),
+            "bool" => Some(Type::Bool),
+            "function" => Some(Type::Function),
+            "nil" => Some(Type::Nil),
+            "number" => Some(Type::Number),
+            "record" => Some(Type::Record),
+            "string" => Some(Type::String),
+            _ => None,
+        }
+    }
+
+    pub fn of(literal: &Literal) -> Type {
+        match literal {
+            Literal::Nil => Type::Nil,
+            Literal::True | Literal::False => Type::Bool,
+            Literal::Int(_) | Literal::Number(_) => Type::Number,
+            Literal::String(_) => Type::String,
+            Literal::NativeFunction(_) | Literal::LoxFunction(_) | Literal::Bound(_) => Type::Function,
+            Literal::Record(_) => Type::Record,
+            Literal::Map(_)
+            | Literal::EnumCase(_)
+            | Literal::Bytes(_)
+            | Literal::Channel(_)
+            | Literal::Thread(_) => Type::Any,
+        }
+    }
+
+    /// Whether a value of type `other` may go where `self` is expected.
+    pub fn accepts(self, other: Type) -> bool {
+        self == Type::Any || other == Type::Any || self == other
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::Any => "any",
+            Type::Bool => "bool",
+            Type::Function => "function",
+            Type::Nil => "nil",
+            Type::Number => "number",
+            Type::Record => "record",
+            Type::String => "string",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What a call to a declared function expects and gives back.
+struct FunctionType {
+    params: Vec<Type>,
+    returns: Type,
+}
+
+#[derive(Clone)]
+struct Binding {
+    declared: Type,
+    function: Option<Rc<FunctionType>>,
+}
+
+/// The `--check` pass. Annotations don't change how a program runs; this
+/// compares them against literals, operators and calls without running
+/// it. Anything it can't follow, such as natives or properties, is `any`,
+/// so unannotated code never fails the check.
+pub struct TypeChecker {
+    scopes: Vec<HashMap<Symbol, Binding>>,
+    /// The declared result of each enclosing function.
+    returns: Vec<Type>,
+    string_coercion: bool,
+    errors: Vec<LoxError>,
+}
+
+impl TypeChecker {
+    /// `string_coercion` says whether `+` turns a non-string operand into
+    /// a string, as the interpreter will.
+    pub fn new(string_coercion: bool) -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            returns: vec![],
+            string_coercion,
+            errors: vec![],
+        }
+    }
+
+    pub fn check(mut self, stmts: &[Stmt]) -> Vec<LoxError> {
+        self.block(stmts);
+        self.errors
+    }
+
+    fn error(&mut self, code: &'static str, token: &Token, message: String) {
+        self.errors.push(LoxError::Type(error_codes::get(code), token.clone(), message.into()));
+    }
+
+    fn mismatch(&mut self, token: &Token, expected: Type, found: Type, place: &str) {
+        if !expected.accepts(found) {
+            self.error("T0001", token, format!("Expected {} but found {}{}.", expected, found, place));
+        }
+    }
+
+    fn annotation(&mut self, annotation: &Option<Token>) -> Type {
+        let Some(type_name) = annotation else {
+            return Type::Any;
+        };
+        match Type::from_name(&type_name.lexeme) {
+            Some(declared) => declared,
+            None => {
+                self.error("T0002", type_name, format!("Unknown type '{}'.", type_name.lexeme));
+                Type::Any
+            }
+        }
+    }
+
+    fn function_type(&mut self, arity: usize, signature: Option<&Signature>) -> FunctionType {
+        let params = match signature {
+            Some(signature) => signature.params.iter().map(|param| self.annotation(param)).collect(),
+            None => vec![Type::Any; arity],
+        };
+        let returns = match signature {
+            Some(signature) => self.annotation(&signature.returns),
+            None => Type::Any,
+        };
+        FunctionType { params, returns }
+    }
+
+    fn declare(&mut self, name: &Token, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme, binding);
+        }
+    }
+
+    fn look_up(&self, name: &Token) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name.lexeme))
+    }
+
+    /// Checks statements in a scope of their own. Functions declared in it
+    /// are known from the start, so calls ahead of the declaration are
+    /// checked too.
+    fn block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            if let Stmt::Function(name, _, params, _, signature, _) = stmt {
+                let function = self.function_type(params.len(), signature.as_deref());
+                let binding = Binding {
+                    declared: Type::Function,
+                    function: Some(Rc::new(function)),
+                };
+                self.declare(name, binding);
+            }
+        }
+        stmts.iter().for_each(|stmt| self.stmt(stmt));
+    }
+
+    fn scoped(&mut self, check: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        check(self);
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block(stmts) => self.scoped(|checker| checker.block(stmts)),
+            Stmt::Expression(expr) => {
+                self.expr(expr);
+            }
+            Stmt::Print(values) => values.iter().for_each(|value| {
+                self.expr(value);
+            }),
+            Stmt::Var(name, annotation, initializer) => {
+                let declared = self.annotation(annotation);
+                if let Some(initializer) = initializer {
+                    let found = self.expr(initializer);
+                    self.mismatch(name, declared, found, "");
+                }
+                let binding = Binding {
+                    declared,
+                    function: None,
+                };
+                self.declare(name, binding);
+            }
+            Stmt::Function(name, _, params, body, ..) => {
+                let function = self
+                    .look_up(name)
+                    .and_then(|binding| binding.function.clone())
+                    .unwrap_or_else(|| Rc::new(self.function_type(params.len(), None)));
+                self.function(params, body, &function);
+            }
+            Stmt::Return(keyword, value) => {
+                let found = match value {
+                    Some(value) => self.expr(value),
+                    None => Type::Nil,
+                };
+                if let Some(&expected) = self.returns.last() {
+                    self.mismatch(keyword, expected, found, "");
+                }
+            }
+            Stmt::If(arms, else_branch) => {
+                for (_, condition, branch) in arms.iter() {
+                    self.expr(condition);
+                    self.stmt(branch);
+                }
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::While(_, condition, body) => {
+                self.expr(condition);
+                self.stmt(body);
+            }
+            Stmt::For(_, initializer, condition, increment, body) => self.scoped(|checker| {
+                if let Some(initializer) = initializer {
+                    checker.stmt(initializer);
+                }
+                condition.iter().chain(increment).for_each(|expr| {
+                    checker.expr(expr);
+                });
+                checker.stmt(body);
+            }),
+            Stmt::Labeled(_, body) => self.stmt(body),
+            Stmt::ForIn(name, iterable, body) | Stmt::With(_, name, iterable, body) => {
+                self.expr(iterable);
+                self.scoped(|checker| {
+                    let binding = Binding {
+                        declared: Type::Any,
+                        function: None,
+                    };
+                    checker.declare(name, binding);
+                    checker.stmt(body);
+                });
+            }
+            Stmt::Enum(name, _) => {
+                let binding = Binding {
+                    declared: Type::Any,
+                    function: None,
+                };
+                self.declare(name, binding);
+            }
+            Stmt::Import(_, names, _) => {
+                for name in names.iter() {
+                    let binding = Binding {
+                        declared: Type::Any,
+                        function: None,
+                    };
+                    self.declare(name, binding);
+                }
+            }
+            Stmt::Export(declaration) => self.stmt(declaration),
+            Stmt::Break(..) | Stmt::Continue(..) => (),
+        }
+    }
+
+    fn function(&mut self, params: &[Token], body: &[Stmt], function: &FunctionType) {
+        self.returns.push(function.returns);
+        self.scoped(|checker| {
+            for (param, &declared) in params.iter().zip(&function.params) {
+                let binding = Binding {
+                    declared,
+                    function: None,
+                };
+                checker.declare(param, binding);
+            }
+            checker.block(body);
+        });
+        self.returns.pop();
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal(literal) => Type::of(literal),
+            Expr::Grouping(inner) => self.expr(inner),
+            Expr::Variable(_, name) => self.look_up(name).map_or(Type::Any, |binding| binding.declared),
+            Expr::Assign(_, name, value) => {
+                let found = self.expr(value);
+                if let Some(declared) = self.look_up(name).map(|binding| binding.declared) {
+                    self.mismatch(name, declared, found, "");
+                }
+                found
+            }
+            Expr::Unary(operator, operand) => {
+                let operand = self.expr(operand);
+                if operator.token_type == TokenType::Bang {
+                    return Type::Bool;
+                }
+                if !Type::Number.accepts(operand) {
+                    self.error("T0003", operator, "Operand must be a number.".to_string());
+                }
+                Type::Number
+            }
+            Expr::Binary(left, operator, right) => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                self.binary(operator, left, right)
+            }
+            Expr::Logical(left, _, right) => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                if left == right {
+                    left
+                } else {
+                    Type::Any
+                }
+            }
+            Expr::Call(callee, paren, arguments, named) => self.call(callee, paren, arguments, named),
+            Expr::Get(object, _) => {
+                self.expr(object);
+                Type::Any
+            }
+            Expr::Set(object, _, value) => {
+                self.expr(object);
+                self.expr(value)
+            }
+            Expr::Index(object, _, index) => {
+                self.expr(object);
+                self.expr(index);
+                Type::Any
+            }
+            Expr::Record(_, fields) => {
+                for (_, value) in fields.iter() {
+                    self.expr(value);
+                }
+                Type::Record
+            }
+            Expr::Lambda(_, params, body, signature) => {
+                let function = self.function_type(params.len(), signature.as_deref());
+                self.function(params, body, &function);
+                Type::Function
+            }
+            Expr::Empty => Type::Any,
+        }
+    }
+
+    /// The type of a binary operation, reporting operands the interpreter
+    /// would reject whatever values the `any` ones turn out to have.
+    fn binary(&mut self, operator: &Token, left: Type, right: Type) -> Type {
+        use TokenType::*;
+
+        let numbers = Type::Number.accepts(left) && Type::Number.accepts(right);
+        let strings = Type::String.accepts(left) && Type::String.accepts(right);
+        let string_side = left == Type::String || right == Type::String;
+        match operator.token_type {
+            EqualEqual | BangEqual => Type::Bool,
+            Star if string_side => {
+                let count = if left == Type::String { right } else { left };
+                if !Type::Number.accepts(count) {
+                    self.error("T0003", operator, "A string can only be repeated by a number.".to_string());
+                }
+                Type::String
+            }
+            Plus => {
+                let coerced = self.string_coercion
+                    && (Type::String.accepts(left) || Type::String.accepts(right));
+                if !numbers && !strings && !coerced {
+                    self.error("T0003", operator, "Operands must be two numbers or two strings.".to_string());
+                }
+                if left == Type::Number && right == Type::Number {
+                    Type::Number
+                } else if (left == Type::String && right == Type::String) || (string_side && self.string_coercion) {
+                    Type::String
+                } else {
+                    Type::Any
+                }
+            }
+            Greater | GreaterEqual | Less | LessEqual => {
+                if !numbers {
+                    self.error("T0003", operator, "Operands must be numbers.".to_string());
+                }
+                Type::Bool
+            }
+            _ => {
+                if !numbers {
+                    self.error("T0003", operator, "Operands must be numbers.".to_string());
+                }
+                // `*` on an `any` operand might still repeat a string.
+                if operator.token_type == Star && (left == Type::Any || right == Type::Any) {
+                    Type::Any
+                } else {
+                    Type::Number
+                }
+            }
+        }
+    }
+
+    /// Checks a call's arguments against the function's parameters. Named
+    /// arguments are only counted, as function types keep no names.
+    fn call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr], named: &[(Token, Expr)]) -> Type {
+        let callee_type = self.expr(callee);
+        let found: Vec<Type> = arguments.iter().map(|argument| self.expr(argument)).collect();
+        named.iter().for_each(|(_, value)| {
+            self.expr(value);
+        });
+        if !Type::Function.accepts(callee_type) {
+            self.error("T0004", paren, "Can only call functions and classes.".to_string());
+            return Type::Any;
+        }
+        let function = match callee {
+            Expr::Variable(_, name) => self.look_up(name).and_then(|binding| binding.function.clone()),
+            _ => None,
+        };
+        let Some(function) = function else {
+            return Type::Any;
+        };
+        if function.params.len() != found.len() + named.len() {
+            let given = found.len() + named.len();
+            let message = format!("Expected {} arguments but got {}.", function.params.len(), given);
+            self.error("T0005", paren, message);
+            return function.returns;
+        }
+        for (i, (&expected, &found)) in function.params.iter().zip(&found).enumerate() {
+            self.mismatch(paren, expected, found, &format!(" for argument {}", i + 1));
+        }
+        function.returns
+    }
+}
@@ -0,0 +1,556 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::bytecode::{Closure, FunctionProto, OpCode, Upvalue, Value, VmNative};
+use crate::error::RuntimeError;
+use crate::interpreter::{repeat_string, Compat, Truthiness};
+#[cfg(feature = "stdlib-time")]
+use crate::native_function::unix_millis;
+use crate::symbol::Symbol;
+use crate::token::Token;
+
+const FRAMES_MAX: usize = 100_000;
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base: usize,
+    /// Back-edges taken by the loops running in this frame, as the loop's
+    /// start, its `Loop` instruction and the count, outermost first.
+    loops: Vec<(usize, usize, u64)>,
+}
+
+/// A clox-style stack machine executing code produced by
+/// [`Compiler`](crate::compiler::Compiler). Globals persist between calls
+/// to `run`, so the REPL keeps its definitions.
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Symbol, Value>,
+    /// Natives only the tree-walker has, so using one here is reported as
+    /// unsupported rather than undefined.
+    unsupported: HashSet<Symbol>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Where `print` writes when output is captured instead of going to
+    /// stdout.
+    output: Option<String>,
+    compat: Compat,
+    truthiness: Truthiness,
+    string_coercion: bool,
+    /// Stands in for the system clock when set.
+    clock: Option<Rc<dyn Fn() -> f64>>,
+    max_loop_iterations: Option<u64>,
+    /// The value of the last expression statement the script ran outside
+    /// any function.
+    result: Value,
+}
+
+type VmResult<T> = Result<T, RuntimeError>;
+
+type NativeFn = fn(&Vm, &[Value]) -> Value;
+
+const NATIVES: &[(&str, u8, NativeFn)] = &[
+    #[cfg(feature = "stdlib-time")]
+    ("clock", 0, clock),
+];
+
+impl Default for Vm {
+    fn default() -> Self {
+        let mut vm = Self {
+            stack: vec![],
+            frames: vec![],
+            globals: HashMap::new(),
+            unsupported: HashSet::new(),
+            open_upvalues: vec![],
+            output: None,
+            compat: Compat::default(),
+            truthiness: Truthiness::default(),
+            string_coercion: true,
+            clock: None,
+            max_loop_iterations: None,
+            result: Value::Nil,
+        };
+        for &(name, arity, function) in NATIVES {
+            vm.define_native(name, arity, function);
+        }
+        vm
+    }
+}
+
+#[cfg(feature = "stdlib-time")]
+fn clock(vm: &Vm, _args: &[Value]) -> Value {
+    Value::Number(vm.clock.as_ref().map_or_else(unix_millis, |clock| clock()))
+}
+
+impl Vm {
+    /// Only division by zero and printing differ by dialect here; the
+    /// rest is settled by the parser or the other settings.
+    pub fn set_compat(&mut self, compat: Compat) {
+        self.compat = compat;
+    }
+
+    /// See [`Interpreter::set_truthiness`](crate::interpreter::Interpreter::set_truthiness).
+    pub fn set_truthiness(&mut self, truthiness: Truthiness) {
+        self.truthiness = truthiness;
+    }
+
+    pub fn set_string_coercion(&mut self, string_coercion: bool) {
+        self.string_coercion = string_coercion;
+    }
+
+    fn is_truthy(&self, value: &Value) -> bool {
+        match value {
+            Value::Int(0) if self.truthiness == Truthiness::Script => false,
+            Value::Number(n) if self.truthiness == Truthiness::Script => *n != 0.0,
+            Value::String(s) if self.truthiness == Truthiness::Script => !s.is_empty(),
+            value => value.is_truthy(),
+        }
+    }
+
+    fn define_native(&mut self, name: &str, arity: u8, function: NativeFn) {
+        let name = Symbol::intern(name);
+        let native = VmNative {
+            name,
+            arity,
+            function,
+        };
+        self.globals.insert(name, Value::Native(native));
+    }
+
+    /// See [`Interpreter::set_clock`](crate::interpreter::Interpreter::set_clock).
+    pub fn set_clock(&mut self, clock: Rc<dyn Fn() -> f64>) {
+        self.clock = Some(clock);
+    }
+
+    pub fn globals(&self) -> &HashMap<Symbol, Value> {
+        &self.globals
+    }
+
+    /// The value of the last expression statement the last script ran
+    /// outside any function, or nil.
+    pub fn take_result(&mut self) -> Value {
+        std::mem::replace(&mut self.result, Value::Nil)
+    }
+
+    /// How many times any one loop may go around before it's stopped with
+    /// an error, or `None` for no limit.
+    pub fn set_max_loop_iterations(&mut self, max_loop_iterations: Option<u64>) {
+        self.max_loop_iterations = max_loop_iterations;
+    }
+
+    pub fn set_globals(&mut self, globals: HashMap<Symbol, Value>) {
+        self.globals = globals;
+    }
+
+    pub fn remove_global(&mut self, name: Symbol) {
+        self.globals.remove(&name);
+    }
+
+    pub fn set_unsupported(&mut self, names: HashSet<Symbol>) {
+        self.unsupported = names;
+    }
+
+    /// Collects printed lines for [`Vm::take_output`] rather than writing
+    /// them to stdout.
+    pub fn capture_output(&mut self) {
+        self.output = Some(String::new());
+    }
+
+    pub fn take_output(&mut self) -> String {
+        self.output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn run(&mut self, script: Rc<FunctionProto>) -> VmResult<()> {
+        let closure = Rc::new(Closure {
+            function: script,
+            upvalues: vec![],
+        });
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.result = Value::Nil;
+        self.stack.push(Value::Closure(closure.clone()));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: 0,
+            loops: vec![],
+        });
+        let result = self.execute();
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn error(&self, code: &'static str, message: String) -> RuntimeError {
+        let line = self
+            .frames
+            .last()
+            .map(|frame| {
+                let lines = &frame.closure.function.chunk.lines;
+                lines[frame.ip.saturating_sub(1)]
+            })
+            .unwrap_or(0);
+        let token = Token {
+            line,
+            ..Token::default()
+        };
+        RuntimeError::new(code, token, message)
+    }
+
+    /// Counts a jump back to `start` from the `Loop` just read, failing once
+    /// that loop has gone around more than `limit` times. Loops that don't
+    /// enclose this one have finished, so their counts are dropped.
+    fn count_iteration(&mut self, start: usize, limit: u64) -> VmResult<()> {
+        let frame = self.frames.last_mut().expect("Expected a call frame.");
+        let end = frame.ip - 1;
+        frame.loops.retain(|&(outer_start, outer_end, _)| outer_start <= start && outer_end >= end);
+        let count = match frame.loops.last_mut() {
+            Some((loop_start, _, count)) if *loop_start == start => {
+                *count += 1;
+                *count
+            }
+            _ => {
+                frame.loops.push((start, end, 1));
+                1
+            }
+        };
+        if count > limit {
+            return Err(self.error("E0015", format!("Loop exceeded {} iterations.", limit)));
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("VM stack underflow.")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("Expected a call frame.")
+    }
+
+    fn constant(&self, index: u16) -> Value {
+        self.frame().closure.function.chunk.constants[index as usize].clone()
+    }
+
+    fn global_name(&self, index: u16) -> Symbol {
+        self.frame().closure.function.chunk.names[index as usize]
+    }
+
+    fn undefined(&self, name: Symbol) -> RuntimeError {
+        match self.unsupported.contains(&name) {
+            true => self.error("C0001", format!("{} isn't supported by the VM.", name)),
+            false => self.error("E0001", format!("Undefined variable {}.", name)),
+        }
+    }
+
+    fn execute(&mut self) -> VmResult<()> {
+        loop {
+            let op = {
+                let frame = self.frames.last_mut().expect("Expected a call frame.");
+                let op = frame.closure.function.chunk.code[frame.ip];
+                frame.ip += 1;
+                op
+            };
+
+            match op {
+                OpCode::Constant(index) => {
+                    let value = self.constant(index);
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::PopResult => self.result = self.pop(),
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[self.frame().base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    let index = self.frame().base + slot as usize;
+                    self.stack[index] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal(index) => {
+                    let name = self.global_name(index);
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(self.undefined(name)),
+                    }
+                }
+                OpCode::DefineGlobal(index) => {
+                    let name = self.global_name(index);
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal(index) => {
+                    let name = self.global_name(index);
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.undefined(name)),
+                    }
+                }
+                OpCode::GetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(Value::Bool(a == b));
+                }
+                OpCode::Greater | OpCode::Less | OpCode::Subtract | OpCode::Multiply
+                | OpCode::Divide | OpCode::Modulo => self.arithmetic(op)?,
+                OpCode::Add => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let value = match (a, b) {
+                        (Value::String(a), Value::String(b)) => {
+                            Value::String(Rc::from(format!("{}{}", a, b)))
+                        }
+                        (Value::String(a), b) if self.string_coercion => {
+                            Value::String(Rc::from(format!("{}{}", a, b)))
+                        }
+                        (a, Value::String(b)) if self.string_coercion => {
+                            Value::String(Rc::from(format!("{}{}", a, b)))
+                        }
+                        (a, b) if a.as_f64().is_some() && b.as_f64().is_some() => {
+                            self.numeric(op, a, b)?
+                        }
+                        _ => {
+                            return Err(self.error(
+                                "E0002",
+                                "Operands must be two numbers or two strings.".to_string(),
+                            ))
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!self.is_truthy(&value)));
+                }
+                OpCode::Negate => match self.pop() {
+                    Value::Int(i) => match i.checked_neg() {
+                        Some(i) => self.stack.push(Value::Int(i)),
+                        None => return Err(self.error("E0010", "Integer overflow.".to_string())),
+                    },
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    _ => return Err(self.error("E0002", "Operand must be a number.".to_string())),
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    let value = match value {
+                        Value::Number(n) => self.compat.non_finite_name(n).map(str::to_string),
+                        _ => None,
+                    }
+                    .unwrap_or_else(|| value.to_string());
+                    match &mut self.output {
+                        Some(output) => {
+                            let _ = writeln!(output, "{}", value);
+                        }
+                        None => println!("{}", value),
+                    }
+                }
+                OpCode::Jump(target) => {
+                    self.frames.last_mut().expect("Expected a call frame.").ip = target as usize;
+                }
+                OpCode::JumpIfFalse(target) => {
+                    if !self.is_truthy(self.peek(0)) {
+                        self.frames.last_mut().expect("Expected a call frame.").ip =
+                            target as usize;
+                    }
+                }
+                OpCode::Loop(target) => {
+                    if let Some(limit) = self.max_loop_iterations {
+                        self.count_iteration(target as usize, limit)?;
+                    }
+                    self.frames.last_mut().expect("Expected a call frame.").ip = target as usize;
+                }
+                OpCode::Call(arg_count) => self.call_value(arg_count as usize)?,
+                OpCode::Closure(index) => {
+                    let function = match self.constant(index) {
+                        Value::Function(function) => function,
+                        _ => return Err(self.error("E0030", "Expected a function constant.".to_string())),
+                    };
+                    let base = self.frame().base;
+                    let mut upvalues = Vec::with_capacity(function.upvalues.len());
+                    for descriptor in function.upvalues.iter() {
+                        if descriptor.is_local {
+                            upvalues.push(self.capture_upvalue(base + descriptor.index as usize));
+                        } else {
+                            upvalues.push(self.frame().closure.upvalues[descriptor.index as usize].clone());
+                        }
+                    }
+                    let closure = Closure { function, upvalues };
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::CloseUpvalues(slot) => {
+                    self.close_upvalues(self.frame().base + slot as usize);
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("Expected a call frame.");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, op: OpCode) -> VmResult<()> {
+        let b = self.pop();
+        let a = self.pop();
+        if let (OpCode::Multiply, Value::String(s), count) | (OpCode::Multiply, count, Value::String(s)) =
+            (op, &a, &b)
+        {
+            let Some(count) = count.as_f64() else {
+                return Err(self.error("E0002", "A string can only be repeated by a number.".to_string()));
+            };
+            let repeated = repeat_string(s, count).map_err(|message| self.error("E0002", message))?;
+            self.stack.push(Value::String(Rc::from(repeated)));
+            return Ok(());
+        }
+        if a.as_f64().is_none() || b.as_f64().is_none() {
+            return Err(self.error("E0002", "Operands must be numbers.".to_string()));
+        }
+        let value = self.numeric(op, a, b)?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Applies a numeric operator with the same integer/float promotion
+    /// rules as the tree-walker.
+    fn numeric(&self, op: OpCode, a: Value, b: Value) -> VmResult<Value> {
+        let divisor_is_zero = matches!(op, OpCode::Divide | OpCode::Modulo) && b.as_f64() == Some(0.0);
+        if divisor_is_zero && self.compat != Compat::Jlox {
+            return Err(self.error("E0003", "Cannot divide by zero".to_string()));
+        }
+        if let (Value::Int(a), Value::Int(b), false) = (&a, &b, divisor_is_zero) {
+            let (a, b) = (*a, *b);
+            let result = match op {
+                OpCode::Greater => return Ok(Value::Bool(a > b)),
+                OpCode::Less => return Ok(Value::Bool(a < b)),
+                OpCode::Add => a.checked_add(b),
+                OpCode::Subtract => a.checked_sub(b),
+                OpCode::Multiply => a.checked_mul(b),
+                // The one quotient that doesn't fit is an overflow, not a float.
+                OpCode::Divide if b == -1 => a.checked_neg(),
+                OpCode::Divide if a % b == 0 => Some(a / b),
+                OpCode::Divide => return Ok(Value::Number(a as f64 / b as f64)),
+                // `MIN % -1` is 0 even though `MIN / -1` overflows.
+                _ => Some(a.wrapping_rem(b)),
+            };
+            return result
+                .map(Value::Int)
+                .ok_or_else(|| self.error("E0010", "Integer overflow.".to_string()));
+        }
+        let (a, b) = (a.as_f64().unwrap_or_default(), b.as_f64().unwrap_or_default());
+        Ok(match op {
+            OpCode::Greater => Value::Bool(a > b),
+            OpCode::Less => Value::Bool(a < b),
+            OpCode::Add => Value::Number(a + b),
+            OpCode::Subtract => Value::Number(a - b),
+            OpCode::Multiply => Value::Number(a * b),
+            OpCode::Divide => Value::Number(a / b),
+            _ => Value::Number(a % b),
+        })
+    }
+
+    fn call_value(&mut self, arg_count: usize) -> VmResult<()> {
+        let callee = self.peek(arg_count).clone();
+        match callee {
+            Value::Closure(closure) => {
+                let arity = closure.function.arity as usize;
+                if arg_count != arity {
+                    let message = format!("Expected {} arguments but got {}.", arity, arg_count);
+                    return Err(self.error("E0005", message));
+                }
+                if self.frames.len() >= FRAMES_MAX {
+                    return Err(self.error("E0008", "Stack overflow.".to_string()));
+                }
+                self.frames.push(CallFrame {
+                    closure,
+                    ip: 0,
+                    base: self.stack.len() - arg_count - 1,
+                    loops: vec![],
+                });
+                Ok(())
+            }
+            Value::Native(native) => {
+                if arg_count != native.arity as usize {
+                    let message =
+                        format!("Expected {} arguments but got {}.", native.arity, arg_count);
+                    return Err(self.error("E0005", message));
+                }
+                let base = self.stack.len() - arg_count - 1;
+                let result = (native.function)(self, &self.stack[base + 1..]);
+                self.stack.truncate(base);
+                self.stack.push(result);
+                Ok(())
+            }
+            _ => Err(self.error("E0004", "Can only call functions and classes.".to_string())),
+        }
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in self.open_upvalues.iter() {
+            if matches!(&*upvalue.borrow(), Upvalue::Open(open) if *open == slot) {
+                return upvalue.clone();
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Moves every captured variable living at or above `from` off the
+    /// stack and into its upvalue.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match &*upvalue.borrow() {
+                Upvalue::Open(slot) => *slot,
+                Upvalue::Closed(_) => return false,
+            };
+            if slot < from {
+                return true;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+}
@@ -0,0 +1,162 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::Phase;
+use crate::source_map::{SourceId, SourceMap};
+use crate::token::Token;
+
+/// A local variable that's declared but never read.
+pub const UNUSED_VARIABLE: &str = "unused-variable";
+/// A local declared with the name of one in an enclosing scope.
+pub const SHADOWING: &str = "shadowing";
+/// An `if`, `while` or `for` condition that's a literal, except the
+/// `while (true)` of a loop left with `break`.
+pub const CONSTANT_CONDITION: &str = "constant-condition";
+/// A function taking more than [`MAX_PARAMETERS`] parameters.
+pub const LONG_PARAMETER_LIST: &str = "long-parameter-list";
+/// Blocks nested more than [`MAX_NESTING`] deep within a function.
+pub const DEEP_NESTING: &str = "deep-nesting";
+
+/// `==` or `!=` between values of different types, other than nil.
+pub const MIXED_EQUALITY: &str = "mixed-equality";
+/// A number or other non-string joined to a string with `+`.
+pub const IMPLICIT_CONVERSION: &str = "implicit-conversion";
+/// nil joined to a string with `+`.
+pub const NIL_OPERAND: &str = "nil-operand";
+
+/// Every rule, for `rlox lint` and `--enable`/`--disable` to name. The
+/// last three are checked as the program runs, so `rlox lint` never
+/// reports them.
+pub const RULES: [&str; 8] = [
+    UNUSED_VARIABLE,
+    SHADOWING,
+    CONSTANT_CONDITION,
+    LONG_PARAMETER_LIST,
+    DEEP_NESTING,
+    MIXED_EQUALITY,
+    IMPLICIT_CONVERSION,
+    NIL_OPERAND,
+];
+
+pub const MAX_PARAMETERS: usize = 5;
+pub const MAX_NESTING: usize = 4;
+
+/// The rule called `name`, as `RULES` spells it.
+pub fn rule(name: &str) -> Option<&'static str> {
+    RULES.into_iter().find(|rule| *rule == name)
+}
+
+/// Something legal but likely a mistake. Unlike an error it doesn't stop
+/// the program, unless warnings are made errors with
+/// [`Interpreter::set_warnings`](crate::interpreter::Interpreter::set_warnings).
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub token: Token,
+    /// The name `// lox-ignore:` comments turn it off with.
+    pub rule: &'static str,
+    pub message: String,
+    /// `Resolve` for the resolver's warnings, and `Runtime` for those
+    /// given as the program runs.
+    pub phase: Phase,
+}
+
+impl Warning {
+    pub fn new(token: &Token, rule: &'static str, message: String) -> Self {
+        Self { token: token.clone(), rule, message, phase: Phase::Resolve }
+    }
+
+    pub fn runtime(token: &Token, rule: &'static str, message: String) -> Self {
+        Self { token: token.clone(), rule, message, phase: Phase::Runtime }
+    }
+
+    /// The code `rlox explain` describes the warning under, which it's
+    /// reported with as an error under `--strict`.
+    pub fn code(&self) -> &'static str {
+        match self.rule {
+            UNUSED_VARIABLE => "R0009",
+            SHADOWING => "R0010",
+            CONSTANT_CONDITION => "R0011",
+            LONG_PARAMETER_LIST => "R0012",
+            DEEP_NESTING => "R0013",
+            MIXED_EQUALITY => "E0012",
+            IMPLICIT_CONVERSION => "E0013",
+            NIL_OPERAND => "E0014",
+            _ => unreachable!("every rule has a code"),
+        }
+    }
+
+    /// The warning as reported on stderr, placed like
+    /// [`LoxError::render`](crate::error::LoxError::render) places errors.
+    pub fn render(&self, sources: &SourceMap) -> String {
+        match sources.name(self.token.source) {
+            Some(file) => format!("[{}:{}] Warning at '{}': {}", file, self.token.line, self.token.lexeme, self.message),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] Warning at '{}': {}", self.token.line, self.token.lexeme, self.message)
+    }
+}
+
+/// How the interpreter treats the warnings the resolver finds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Warnings {
+    /// Dropped, as Lox has no warnings.
+    #[default]
+    Off,
+    /// Printed to stderr, and the program still runs.
+    On,
+    /// Reported as errors, stopping the program.
+    Strict,
+}
+
+impl Warnings {
+    pub fn from_name(name: &str) -> Option<Warnings> {
+        match name {
+            "off" => Some(Warnings::Off),
+            "on" => Some(Warnings::On),
+            "strict" => Some(Warnings::Strict),
+            _ => None,
+        }
+    }
+}
+
+/// The lines `// lox-ignore: rule, rule` comments silence warnings on, and
+/// the rules they silence there; a bare `// lox-ignore` silences them all.
+/// A comment after code covers its own line, and one on a line of its own
+/// covers the next. The scanner fills it in as it meets the comments, and
+/// the interpreter, holding another handle to the same lines, reads it
+/// back.
+#[derive(Debug, Clone, Default)]
+pub struct Suppressions(Rc<RefCell<HashMap<Line, Vec<String>>>>);
+
+type Line = (SourceId, u32);
+
+impl Suppressions {
+    /// Reads a comment's text, starting at its `//`, and notes what it
+    /// silences on `line` if it's a `lox-ignore` comment.
+    pub fn add_comment(&self, source: SourceId, line: u32, comment: &str) {
+        let Some(rest) = comment.trim_start_matches('/').trim().strip_prefix("lox-ignore") else {
+            return;
+        };
+        let rules: Vec<String> = match rest.trim().strip_prefix(':') {
+            Some(rules) => rules.split(',').map(|rule| rule.trim().to_string()).filter(|rule| !rule.is_empty()).collect(),
+            None if rest.trim().is_empty() => vec!["all".to_string()],
+            None => return,
+        };
+        self.0.borrow_mut().entry((source, line)).or_default().extend(rules);
+    }
+
+    pub fn is_suppressed(&self, warning: &Warning) -> bool {
+        let token = &warning.token;
+        self.0
+            .borrow()
+            .get(&(token.source, token.line))
+            .is_some_and(|rules| rules.iter().any(|rule| rule == "all" || rule == warning.rule))
+    }
+}
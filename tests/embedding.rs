@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use treewalk::interpreter::{Backend, Interpreter, Snapshot};
+use treewalk::json::Json;
+use treewalk::token::Literal;
+
+#[test]
+fn hooks_fire_once_per_event() {
+    let mut interpreter = Interpreter::default();
+    interpreter.capture_output();
+    let calls = Rc::new(RefCell::new(vec![]));
+    let returns = Rc::new(RefCell::new(vec![]));
+    let statements = Rc::new(RefCell::new(0));
+    let errors = Rc::new(RefCell::new(vec![]));
+
+    let log = calls.clone();
+    interpreter.on_call(Box::new(move |_, callee, args, span| {
+        log.borrow_mut().push((callee.to_string(), args.to_vec(), span.line));
+    }));
+    let log = returns.clone();
+    interpreter.on_return(Box::new(move |_, callee, value, _| {
+        log.borrow_mut().push((callee.to_string(), value.clone()));
+    }));
+    let count = statements.clone();
+    interpreter.on_statement(Box::new(move |_, _, _| *count.borrow_mut() += 1));
+    let log = errors.clone();
+    interpreter.on_error(Box::new(move |error| log.borrow_mut().push(error.message().to_string())));
+
+    interpreter.eval_str("fun f(x) {\n  return x + 1;\n}\nf(1);\nf(2);").unwrap();
+    assert_eq!(
+        *calls.borrow(),
+        [
+            ("<fn f>".to_string(), vec![Literal::Int(1)], 4),
+            ("<fn f>".to_string(), vec![Literal::Int(2)], 5),
+        ]
+    );
+    assert_eq!(
+        *returns.borrow(),
+        [("<fn f>".to_string(), Literal::Int(2)), ("<fn f>".to_string(), Literal::Int(3))]
+    );
+    // The declaration, both calls and the return each call runs.
+    assert_eq!(*statements.borrow(), 5);
+    assert!(errors.borrow().is_empty());
+
+    assert!(interpreter.eval_str("f(nil);").is_err());
+    assert_eq!(*errors.borrow(), ["Operands must be two numbers or two strings."]);
+    // A call that fails doesn't return.
+    assert_eq!(calls.borrow().len(), 3);
+    assert_eq!(returns.borrow().len(), 2);
+}
+
+#[test]
+fn restoring_a_snapshot_undoes_global_changes() {
+    for backend in [Backend::TreeWalk, Backend::Vm] {
+        let mut interpreter = Interpreter::default();
+        interpreter.set_backend(backend);
+        interpreter.capture_output();
+        interpreter.eval_str("var a = 1;").unwrap();
+        let snapshot = interpreter.snapshot();
+        assert_eq!(snapshot.to_json().to_string(), r#"{"a":1}"#);
+
+        interpreter.eval_str("a = 2; var b = 3;").unwrap();
+        interpreter.restore(snapshot.clone());
+        interpreter.eval_str("print a;").unwrap();
+        assert_eq!(interpreter.take_output(), "1\n");
+        assert!(interpreter.eval_str("print b;").is_err());
+
+        // The same snapshot can be restored again.
+        interpreter.eval_str("a = 4;").unwrap();
+        interpreter.restore(snapshot.clone());
+        interpreter.eval_str("print a;").unwrap();
+        assert_eq!(interpreter.take_output(), "1\n");
+
+        // So can one read back from JSON.
+        let json = Json::parse(r#"{"a":"from json"}"#).unwrap();
+        interpreter.restore(Snapshot::from_json(&json).unwrap());
+        interpreter.eval_str("print a;").unwrap();
+        assert_eq!(interpreter.take_output(), "from json\n");
+    }
+}
+
+#[cfg(feature = "stdlib-time")]
+#[test]
+fn a_replaced_clock_makes_times_reproducible() {
+    for backend in [Backend::TreeWalk, Backend::Vm] {
+        let mut interpreter = Interpreter::default();
+        interpreter.set_backend(backend);
+        let ticks = Rc::new(RefCell::new(0.0));
+        let clock = ticks.clone();
+        interpreter.set_clock(Box::new(move || {
+            *clock.borrow_mut() += 1000.0;
+            1_709_211_909_000.0 + *clock.borrow()
+        }));
+        assert_eq!(interpreter.eval_str("clock();").unwrap(), Literal::Number(1_709_211_910_000.0));
+        assert_eq!(interpreter.eval_str("clock();").unwrap(), Literal::Number(1_709_211_911_000.0));
+    }
+
+    let mut interpreter = Interpreter::default();
+    interpreter.set_clock(Box::new(|| 1_709_211_909_000.0));
+    let now = interpreter.eval_str("var t = now(); formatTime(clock(), \"%Y-%m-%d %H:%M:%S\");");
+    assert_eq!(now.unwrap(), Literal::from("2024-02-29 13:05:09"));
+    assert_eq!(interpreter.eval_str("t.year * 100 + t.month;").unwrap(), Literal::Int(202402));
+}
+
+#[test]
+fn globals_lists_every_global_by_name() {
+    for backend in [Backend::TreeWalk, Backend::Vm] {
+        let mut interpreter = Interpreter::default();
+        interpreter.set_backend(backend);
+        interpreter.eval_str("var zeta = 1; var alpha = \"a\"; var mid = nil;").unwrap();
+        let globals = interpreter.globals();
+        let names: Vec<_> = globals.iter().map(|&(name, _)| name).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+        assert!(names.contains(&"globals"));
+
+        let user: Vec<_> = globals
+            .into_iter()
+            .filter(|(name, _)| ["alpha", "mid", "zeta"].contains(name))
+            .collect();
+        assert_eq!(
+            user,
+            [("alpha", Literal::from("a")), ("mid", Literal::Nil), ("zeta", Literal::Int(1))]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use treewalk::error::RuntimeException;
+use treewalk::interpreter::{Backend, Interpreter};
+use treewalk::token::Literal;
+
+fn error_message(result: Result<Literal, RuntimeException>) -> String {
+    match result {
+        Err(RuntimeException::Base(error)) => error.message.to_string(),
+        other => panic!("expected an error, got {:?}", other),
+    }
+}
+
+#[test]
+fn eval_expr_binds_arguments() {
+    let mut interpreter = Interpreter::default();
+    let bindings = [("x", Literal::Int(2)), ("y", Literal::Int(3))];
+    assert_eq!(interpreter.eval_expr("x * y + 1", &bindings).unwrap(), Literal::Int(7));
+    // The bindings can differ from one call to the next.
+    assert_eq!(interpreter.eval_expr("x", &[("x", Literal::from("a"))]).unwrap(), Literal::from("a"));
+    assert_eq!(interpreter.eval_expr("globals != nil", &[]).unwrap(), Literal::True);
+}
+
+#[test]
+fn eval_expr_lambdas_capture_bindings() {
+    let mut interpreter = Interpreter::default();
+    let bindings = [("n", Literal::Int(10))];
+    let value = interpreter.eval_expr("(fun (x) { return (fun () { return x + n; })(); })(5)", &bindings);
+    assert_eq!(value.unwrap(), Literal::Int(15));
+}
+
+#[test]
+fn eval_expr_gives_back_errors() {
+    let mut interpreter = Interpreter::default();
+    assert_eq!(error_message(interpreter.eval_expr("1 +", &[])), "Expect expression.");
+    assert_eq!(error_message(interpreter.eval_expr("\"open", &[])), "Unterminated string.");
+    assert_eq!(
+        error_message(interpreter.eval_expr("(fun () { var a = a; return a; })()", &[])),
+        "Can't read local variable in its own initializer."
+    );
+    assert_eq!(error_message(interpreter.eval_expr("missing", &[])), "Undefined variable missing.");
+    assert_eq!(error_message(interpreter.eval_expr("1 + nil", &[])), "Operands must be two numbers or two strings.");
+}
+
+#[test]
+fn closures_outlive_the_programs_that_declared_others() {
+    let mut interpreter = Interpreter::default();
+    interpreter
+        .eval_str("{ var zz = 1; { { fun g(x) { return zz + x; } g(1); } } }")
+        .unwrap();
+    // Freed declarations must not lend their captures to new functions.
+    for _ in 0..100 {
+        assert_eq!(interpreter.eval_expr("(fun (x) { return x; })(5)", &[]).unwrap(), Literal::Int(5));
+    }
+}
+
+#[test]
+fn eval_str_gives_back_the_last_value() {
+    let mut interpreter = Interpreter::default();
+    assert_eq!(interpreter.eval_str("var a = 2; a * 3;").unwrap(), Literal::Int(6));
+    assert_eq!(interpreter.eval_str("a;").unwrap(), Literal::Int(2));
+    assert_eq!(interpreter.eval_str("fun f() { return 1; } f(); var b = 0;").unwrap(), Literal::Int(1));
+    assert_eq!(interpreter.eval_str("fun g() {}").unwrap(), Literal::Nil);
+}
+
+#[test]
+fn eval_str_gives_back_the_first_error() {
+    let mut interpreter = Interpreter::default();
+    interpreter.capture_output();
+    assert_eq!(interpreter.eval_str("a +;").unwrap_err().to_string(), "[line 1] Error at ';': Expect expression.");
+    let error = interpreter.eval_str("print 1; nil();").unwrap_err();
+    assert_eq!(error.to_string(), "Can only call functions and classes.\n[line 1]");
+    // A failed run doesn't leave its value for the next one.
+    assert_eq!(interpreter.eval_str("var c = 1;").unwrap(), Literal::Nil);
+}
+
+#[test]
+fn vm_globals_stay_declared_for_later_runs() {
+    let mut interpreter = Interpreter::default();
+    interpreter.set_backend(Backend::Vm);
+    interpreter.capture_output();
+    interpreter.eval_str("var a = 1; fun f() { return a; }").unwrap();
+    interpreter.eval_str("a = 2; print f();").unwrap();
+    assert_eq!(interpreter.take_output(), "2\n");
+}
+
+#[test]
+fn eval_str_gives_back_the_vms_last_value() {
+    let mut interpreter = Interpreter::default();
+    interpreter.set_backend(Backend::Vm);
+    assert_eq!(interpreter.eval_str("var a = 2; a * 3;").unwrap(), Literal::Int(6));
+    assert_eq!(interpreter.eval_str("fun f() { a; } f(); \"s\";").unwrap(), Literal::from("s"));
+    assert_eq!(interpreter.eval_str("{ a + 0.5; }").unwrap(), Literal::Number(2.5));
+    assert_eq!(interpreter.eval_str("f;").unwrap(), Literal::Nil);
+    assert_eq!(interpreter.eval_str("var b = 1;").unwrap(), Literal::Nil);
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "stdlib-net")]
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use treewalk::http::{request, Response};
+
+/// Answers one request on `listener` with a 200 and `body`.
+fn serve(listener: TcpListener, body: &'static str) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut head = [0; 1024];
+        let _ = stream.read(&mut head).unwrap();
+        let response = format!("HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = stream.write_all(response.as_bytes());
+    })
+}
+
+#[test]
+fn responses_within_the_limit_are_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let server = serve(listener, "hello");
+    let response = request("GET", &url, None, Some(1000)).unwrap();
+    assert_eq!(
+        response,
+        Response {
+            status: 200,
+            body: "hello".to_string()
+        }
+    );
+    server.join().unwrap();
+}
+
+#[test]
+fn responses_over_the_limit_fail() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let server = serve(listener, "a body longer than the limit");
+    let error = request("GET", &url, None, Some(16)).unwrap_err();
+    assert_eq!(error.to_string(), "the response is over the 16 bytes of memory left");
+    server.join().unwrap();
+}
+
+#[test]
+fn bracketed_ipv6_hosts_connect() {
+    // Not every machine has IPv6 loopback.
+    let Ok(listener) = TcpListener::bind("[::1]:0") else {
+        return;
+    };
+    let port = listener.local_addr().unwrap().port();
+    let server = serve(listener, "over IPv6");
+    let response = request("GET", &format!("http://[::1]:{}/path", port), None, None).unwrap();
+    assert_eq!(response.body, "over IPv6");
+    server.join().unwrap();
+}
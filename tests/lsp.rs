@@ -0,0 +1,80 @@
+use treewalk::json::Json;
+use treewalk::lsp::Server;
+
+const URI: &str = "file:///test.lox";
+
+/// Opens a document holding `text`, giving back the diagnostics published
+/// for it.
+fn open(server: &mut Server, text: &str) -> Json {
+    let message = Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/didOpen")),
+        (
+            "params",
+            Json::object([(
+                "textDocument",
+                Json::object([("uri", Json::from(URI)), ("text", Json::from(text))]),
+            )]),
+        ),
+    ]);
+    server.handle(&message).remove(0)
+}
+
+fn request(server: &mut Server, method: &str, params: Vec<(&str, Json)>) -> Json {
+    let mut fields = vec![("textDocument", Json::object([("uri", Json::from(URI))]))];
+    fields.extend(params);
+    let params = Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect());
+    let message = Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("id", Json::Int(1)),
+        ("method", Json::from(method)),
+        ("params", params),
+    ]);
+    let reply = server.handle(&message).remove(0);
+    reply.get("result").cloned().unwrap()
+}
+
+/// The line and character a range starts at.
+fn start(range: &Json) -> (i64, i64) {
+    let start = range.get("start").unwrap();
+    (
+        start.get("line").and_then(Json::as_i64).unwrap(),
+        start.get("character").and_then(Json::as_i64).unwrap(),
+    )
+}
+
+#[test]
+fn diagnostics_count_utf16_units() {
+    let mut server = Server::default();
+    let published = open(&mut server, "print \"é\" + ;");
+    let diagnostics = published.get("params").and_then(|params| params.get("diagnostics")).unwrap();
+    let range = diagnostics.as_array().unwrap()[0].get("range").unwrap();
+    assert_eq!(start(range), (0, 12));
+}
+
+#[test]
+fn definitions_count_utf16_units() {
+    let mut server = Server::default();
+    open(&mut server, "{\n  var q = \"😀\"; print \"é\" + q;\n}");
+    // "😀" is two UTF-16 units and four bytes, "é" one unit and two bytes.
+    let position = Json::object([("line", Json::Int(1)), ("character", Json::Int(28))]);
+    let location = request(&mut server, "textDocument/definition", vec![("position", position)]);
+    assert_eq!(start(location.get("range").unwrap()), (1, 6));
+
+    // The same column counted in bytes falls past the use.
+    let position = Json::object([("line", Json::Int(1)), ("character", Json::Int(31))]);
+    let location = request(&mut server, "textDocument/definition", vec![("position", position)]);
+    assert_eq!(location, Json::Null);
+}
+
+#[test]
+fn symbols_count_utf16_units() {
+    let mut server = Server::default();
+    open(&mut server, "var s = \"é\"; fun f() {}");
+    let symbols = request(&mut server, "textDocument/documentSymbol", vec![]);
+    let symbols = symbols.as_array().unwrap();
+    let f = symbols[1].get("location").and_then(|location| location.get("range")).unwrap();
+    assert_eq!(start(f), (0, 17));
+    let end = f.get("end").and_then(|end| end.get("character")).and_then(Json::as_i64);
+    assert_eq!(end, Some(18));
+}